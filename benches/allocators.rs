@@ -0,0 +1,44 @@
+//! Replays the workloads of [`wgpu_allocators::bench`] against every allocator in the crate.
+//!
+//! Run with `cargo bench --features bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wgpu_allocators::{
+    bench::Workload,
+    Allocator,
+    Buddy,
+    DoubleStack,
+    FreeList,
+    Offset,
+    Pool,
+    Ring,
+    Stack,
+    Tlsf,
+};
+
+/// The seed of every workload, fixed so that runs are comparable.
+const SEED: u64 = 0x5eed;
+
+fn bench_allocator<A: Allocator>(c: &mut Criterion, name: &str) {
+    for workload in Workload::all(SEED) {
+        let mut group = c.benchmark_group(workload.name);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &workload, |b, workload| {
+            b.iter(|| workload.run::<A>());
+        });
+        group.finish();
+    }
+}
+
+fn allocators(c: &mut Criterion) {
+    bench_allocator::<Stack>(c, "stack");
+    bench_allocator::<DoubleStack>(c, "double-stack");
+    bench_allocator::<FreeList>(c, "free-list");
+    bench_allocator::<Buddy>(c, "buddy");
+    bench_allocator::<Tlsf>(c, "tlsf");
+    bench_allocator::<Offset>(c, "offset");
+    bench_allocator::<Pool>(c, "pool");
+    bench_allocator::<Ring>(c, "ring");
+}
+
+criterion_group!(benches, allocators);
+criterion_main!(benches);
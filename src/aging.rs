@@ -0,0 +1,121 @@
+//! Tracking of when allocations were last touched, for finding cold data.
+//!
+//! Aging is opt-in; see [`HeapArena::enable_aging`](crate::HeapArena::enable_aging). Once it is
+//! enabled, an arena remembers the last frame in which each of its allocations was written and
+//! the last frame in which it was bound, and can report the allocations that have gone untouched
+//! for some number of frames. These are the candidates for eviction or demotion when memory runs
+//! low.
+
+use wgpu::BufferAddress;
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::arena::{ArenaKey, Relocation};
+
+/// A frame number, counted by [`HeapArena::begin_frame`](crate::HeapArena::begin_frame).
+pub type Frame = u64;
+
+/// What is known about the age of a single allocation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocationAge {
+    /// The label given with [`HeapArena::set_label`](crate::HeapArena::set_label), if any.
+    pub label: Option<String>,
+    /// The frame in which the allocation was made.
+    pub allocated: Frame,
+    /// The last frame in which the allocation was written or uploaded to, if any.
+    pub last_written: Option<Frame>,
+    /// The last frame in which a slice or binding of the allocation was taken, if any.
+    pub last_bound: Option<Frame>,
+}
+
+impl AllocationAge {
+    /// The last frame in which the allocation was made, written, or bound.
+    pub fn last_touched(&self) -> Frame {
+        self.allocated.max(self.last_written.unwrap_or(0)).max(self.last_bound.unwrap_or(0))
+    }
+}
+
+/// An entry in the report returned by
+/// [`HeapArena::cold_allocations`](crate::HeapArena::cold_allocations).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColdAllocation {
+    pub arena_key: ArenaKey,
+    pub range_in_heap: Range<BufferAddress>,
+    pub age: AllocationAge,
+    /// The number of frames since the allocation was last touched.
+    pub idle_frames: Frame,
+}
+
+impl ColdAllocation {
+    /// The size, in bytes, of the allocation.
+    pub fn size(&self) -> BufferAddress {
+        self.range_in_heap.end - self.range_in_heap.start
+    }
+}
+
+/// The ages of every allocation in an arena, keyed by heap and then by offset within the heap.
+///
+/// This is a [`BTreeMap`] so that reports come out in a stable order.
+#[derive(Debug, Default)]
+pub(crate) struct AgeTracker {
+    ages: BTreeMap<(ArenaKey, BufferAddress), (BufferAddress, AllocationAge)>,
+}
+
+impl AgeTracker {
+    pub(crate) fn insert(&mut self, key: ArenaKey, range: Range<BufferAddress>, frame: Frame) {
+        let age = AllocationAge { allocated: frame, ..Default::default() };
+        self.ages.insert((key, range.start), (range.end, age));
+    }
+
+    pub(crate) fn remove(&mut self, key: ArenaKey, range: Range<BufferAddress>) {
+        self.ages.remove(&(key, range.start));
+    }
+
+    /// Forgets every allocation in a heap whose key is not kept by `keep`.
+    pub(crate) fn retain_heaps(&mut self, keep: impl Fn(ArenaKey) -> bool) {
+        self.ages.retain(|(key, _), _| keep(*key));
+    }
+
+    /// Moves the age of each allocation in `relocations` from its old key and range to its new
+    /// ones.
+    pub(crate) fn relocate(&mut self, relocations: &[Relocation]) {
+        // Every age is taken out before any is put back, as the new key and range of one
+        // allocation may be the old ones of another.
+        let ages: Vec<_> = relocations
+            .iter()
+            .filter_map(|Relocation { from, to }| {
+                let key = (from.arena_key, from.range_in_heap.start);
+                let (_, age) = self.ages.remove(&key)?;
+
+                Some((to, age))
+            })
+            .collect();
+        for (to, age) in ages {
+            let key = (to.arena_key, to.range_in_heap.start);
+            self.ages.insert(key, (to.range_in_heap.end, age));
+        }
+    }
+
+    pub(crate) fn get_mut(
+        &mut self,
+        key: ArenaKey,
+        range: Range<BufferAddress>,
+    ) -> Option<&mut AllocationAge> {
+        self.ages.get_mut(&(key, range.start)).map(|(_, age)| age)
+    }
+
+    pub(crate) fn cold(&self, now: Frame, min_idle_frames: Frame) -> Vec<ColdAllocation> {
+        self.ages
+            .iter()
+            .filter_map(|((key, start), (end, age))| {
+                let idle_frames = now.saturating_sub(age.last_touched());
+                (idle_frames >= min_idle_frames).then(|| ColdAllocation {
+                    arena_key: *key,
+                    range_in_heap: *start..*end,
+                    age: age.clone(),
+                    idle_frames,
+                })
+            })
+            .collect()
+    }
+}
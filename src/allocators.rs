@@ -1,8 +1,17 @@
 use wgpu::BufferAddress;
 
-use std::ops::Range;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    ops::Range,
+};
 
-use crate::{Allocator, Heap, NonZeroBufferAddress};
+use crate::{
+    queue::Serial,
+    sizes::{align_up, combine_alignments},
+    AllocError,
+    Allocator,
+    NonZeroBufferAddress,
+};
 
 /// A bump allocator with support for deallocations in reverse allocation order.
 ///
@@ -11,45 +20,1564 @@ use crate::{Allocator, Heap, NonZeroBufferAddress};
 /// deallocations. While this completely takes fragmentation out of the equation, it is generally
 /// only suited for allocations of a known quantity that live forever; otherwise, stack allocation
 /// quickly leads to leaked resources and wasted memory.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stack {
     pointer: BufferAddress,
+    /// The value of `pointer` before each live allocation was made, from oldest to newest, so
+    /// that deallocating one also frees its alignment padding.
+    previous_pointers: Vec<BufferAddress>,
+    /// The size, in bytes, of the managed memory.
+    size: BufferAddress,
 }
 
 impl Allocator for Stack {
-    fn new(heap: &Heap) -> Self {
-        Self { pointer: heap.size.get() }
+    /// Creates a new `Stack` that manages `size` bytes, independently of any [`Heap`](crate::Heap).
+    ///
+    /// This is useful for running the allocator over memory that isn't owned by a `Heap`, such as
+    /// with [`RawHeap`](crate::RawHeap).
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        Self { pointer: size.get(), previous_pointers: Vec::new(), size: size.get() }
     }
 
     fn alloc(
         &mut self,
         size: NonZeroBufferAddress,
         alignment: NonZeroBufferAddress,
-    ) -> Option<Range<BufferAddress>> {
-        self.pointer = self.pointer.checked_sub(size.get())? & create_alignment_bitmask(alignment);
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // The highest offset at which `size` bytes still fit below the pointer, rounded down to
+        // `alignment`. Rounding down from an in-bounds offset can never leave the heap, and unlike
+        // a bitmask, this works for alignments that aren't powers of two.
+        let start = self.pointer.checked_sub(size.get()).ok_or(AllocError::OutOfMemory)?;
+        self.previous_pointers.push(self.pointer);
+        self.pointer = start - start % alignment.get();
 
-        Some(self.pointer..(self.pointer + size.get()))
+        Ok(self.pointer..(self.pointer + size.get()))
     }
 
-    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), ()> {
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
         if range.start == self.pointer {
             // Because, during normal operation, no two overlapping allocations will ever exist, we
             // know that, if a range from a given allocation begins at `self.pointer`, it must be
             // the most recent allocation. We don't even need to check the end of the range.
 
-            self.pointer = range.end;
+            // Note: the pointer is restored to where it was rather than to `range.end`, which may
+            // be below it by the alignment padding of the allocation.
+            self.pointer = self.previous_pointers.pop().ok_or(AllocError::OutOfOrder)?;
 
             Ok(())
         } else {
             // The given range does not represent the most recent allocation, so it cannot be
             // deallocated yet.
-            Err(())
+            Err(AllocError::OutOfOrder)
         }
     }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        Some(self.pointer)
+    }
+
+    fn reset(&mut self) -> bool {
+        self.pointer = self.size;
+        self.previous_pointers.clear();
+
+        true
+    }
+}
+
+/// Two stacks growing toward each other from opposite ends of the same memory.
+///
+/// The *bottom* stack grows up from the start of the heap and the *top* stack grows down from its
+/// end, and either can take whatever the other leaves free. The classic use is per-frame
+/// allocation: long-lived data is allocated from the bottom, transient data from the top, and the
+/// top is cleared with [`Self::reset_top`] at the end of every frame without touching the bottom.
+///
+/// [`Allocator::alloc`] allocates from the bottom; [`Self::alloc_top`] allocates from the top.
+/// Within each stack, deallocations must be made in reverse allocation order, as with [`Stack`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct DoubleStack {
+    /// The end of the bottom stack, which grows up from 0.
+    bottom: BufferAddress,
+    /// The start of the top stack, which grows down from `size`.
+    top: BufferAddress,
+    /// The value of `bottom` before each live allocation of the bottom stack was made, as with
+    /// [`Stack`].
+    previous_bottoms: Vec<BufferAddress>,
+    /// The value of `top` before each live allocation of the top stack was made.
+    previous_tops: Vec<BufferAddress>,
+    /// The size, in bytes, of the managed memory.
+    size: BufferAddress,
+}
+
+impl DoubleStack {
+    /// Allocates `size` bytes aligned to `alignment` from the bottom stack.
+    ///
+    /// This is what [`Allocator::alloc`] does.
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::OutOfMemory`] if the allocation would run into the top
+    /// stack.
+    pub fn alloc_bottom(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        let start = align_up(self.bottom, alignment).ok_or(AllocError::OutOfMemory)?;
+        let end = start.checked_add(size.get()).filter(|&end| end <= self.top);
+        let end = end.ok_or(AllocError::OutOfMemory)?;
+        self.previous_bottoms.push(self.bottom);
+        self.bottom = end;
+
+        Ok(start..self.bottom)
+    }
+
+    /// Allocates `size` bytes aligned to `alignment` from the top stack.
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::OutOfMemory`] if the allocation would run into the bottom
+    /// stack.
+    pub fn alloc_top(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // As with `Stack`, rounding down from an in-bounds offset can never leave the heap.
+        let start = self.top.checked_sub(size.get()).ok_or(AllocError::OutOfMemory)?;
+        let start = start - start % alignment.get();
+        if start < self.bottom {
+            return Err(AllocError::OutOfMemory);
+        }
+        self.previous_tops.push(self.top);
+        self.top = start;
+
+        Ok(start..(start + size.get()))
+    }
+
+    /// Frees every allocation of the bottom stack at once.
+    pub fn reset_bottom(&mut self) {
+        self.bottom = 0;
+        self.previous_bottoms.clear();
+    }
+
+    /// Frees every allocation of the top stack at once.
+    pub fn reset_top(&mut self) {
+        self.top = self.size;
+        self.previous_tops.clear();
+    }
+
+    /// The number of bytes used by the bottom stack, including alignment padding.
+    pub fn bottom_used(&self) -> BufferAddress {
+        self.bottom
+    }
+
+    /// The number of bytes used by the top stack, including alignment padding.
+    pub fn top_used(&self) -> BufferAddress {
+        self.size - self.top
+    }
+}
+
+impl Allocator for DoubleStack {
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        Self {
+            bottom: 0,
+            top: size.get(),
+            previous_bottoms: Vec::new(),
+            previous_tops: Vec::new(),
+            size: size.get(),
+        }
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.alloc_bottom(size, alignment)
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        if range.start >= range.end || range.end > self.size {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+
+        // As with `Stack`, only the most recent allocation of either stack touches its pointer,
+        // which is restored along with the alignment padding of the allocation.
+        if range.start == self.top {
+            self.top = self.previous_tops.pop().ok_or(AllocError::OutOfOrder)?;
+        } else if range.end == self.bottom {
+            self.bottom = self.previous_bottoms.pop().ok_or(AllocError::OutOfOrder)?;
+        } else {
+            return Err(AllocError::OutOfOrder);
+        }
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        Some(self.top - self.bottom)
+    }
+
+    fn reset(&mut self) -> bool {
+        self.reset_bottom();
+        self.reset_top();
+
+        true
+    }
+}
+
+/// A general-purpose allocator that places each allocation in a free block chosen by a
+/// [`FitStrategy`].
+///
+/// The free list keeps the unallocated regions of the heap as a list of blocks sorted by address.
+/// Allocating searches the list for a block that can hold the allocation once aligned&mdash;by
+/// default the *best fit*, the smallest such block&mdash;and splits it, and deallocating returns a
+/// range to the list and coalesces it with any adjacent free blocks. Unlike [`Stack`], allocations
+/// may be freed in any order, which makes this suitable for long-lived data with arbitrary
+/// lifetimes, at the cost of a linear search per operation.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct FreeList {
+    /// The free blocks, sorted by address. No two blocks overlap or are adjacent.
+    free_blocks: Vec<Range<BufferAddress>>,
+    /// The size, in bytes, of the managed memory.
+    size: BufferAddress,
+    strategy: FitStrategy,
+    /// The end of the most recent allocation, from which [`FitStrategy::NextFit`] resumes its
+    /// search.
+    cursor: BufferAddress,
+}
+
+/// How a [`FreeList`] chooses among the free blocks that can hold an allocation.
+///
+/// Ties are always broken in favor of the lowest address.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub enum FitStrategy {
+    /// The first block that fits, searching from the start of the heap.
+    ///
+    /// This stops searching early, so it is the fastest strategy, but it tends to collect small
+    /// fragments near the start of the heap.
+    FirstFit,
+    /// The first block that fits, searching from the end of the previous allocation and wrapping
+    /// around.
+    ///
+    /// This spreads allocations evenly over the heap, which suits workloads whose allocations
+    /// have similar lifetimes, such as streaming.
+    NextFit,
+    /// The smallest block that fits, which leaves the largest blocks intact for large
+    /// allocations.
+    #[default]
+    BestFit,
+    /// The largest block that fits, which leaves remainders that are large enough to be useful.
+    WorstFit,
+}
+
+impl FreeList {
+    /// Creates a new `FreeList` that manages `size` bytes and places allocations according to
+    /// `strategy`.
+    pub fn with_strategy(size: NonZeroBufferAddress, strategy: FitStrategy) -> Self {
+        Self {
+            free_blocks: std::iter::once(0..size.get()).collect(),
+            size: size.get(),
+            strategy,
+            cursor: 0,
+        }
+    }
+
+    /// The strategy with which allocations are placed.
+    pub fn strategy(&self) -> FitStrategy {
+        self.strategy
+    }
+
+    /// The total number of free bytes.
+    pub fn free_bytes(&self) -> BufferAddress {
+        self.free_blocks.iter().map(|block| block.end - block.start).sum()
+    }
+
+    /// The number of free blocks, which is a measure of fragmentation.
+    pub fn free_block_count(&self) -> usize {
+        self.free_blocks.len()
+    }
+}
+
+impl Allocator for FreeList {
+    /// Creates a new `FreeList` that manages `size` bytes, independently of any
+    /// [`Heap`](crate::Heap).
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        Self::with_strategy(size, FitStrategy::default())
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // The index of the block at `index` and the start of the allocation within it, if it fits.
+        let fit = |index: usize| {
+            let block = &self.free_blocks[index];
+            let start = align_up(block.start, alignment)?;
+            (start.checked_add(size.get())? <= block.end).then_some((index, start))
+        };
+        let block_size = |&(index, _): &(usize, BufferAddress)| {
+            self.free_blocks[index].end - self.free_blocks[index].start
+        };
+        let count = self.free_blocks.len();
+        // Note: `min_by_key` returns the first of several equal minimums, so ties are broken in
+        // favor of the lowest address.
+        let (index, start) = match self.strategy {
+            FitStrategy::FirstFit => (0..count).find_map(fit),
+            FitStrategy::NextFit => {
+                // The first block that ends after the cursor, which may also contain it.
+                let first = self.free_blocks.partition_point(|block| block.end <= self.cursor);
+                (first..count).chain(0..first).find_map(fit)
+            }
+            FitStrategy::BestFit => (0..count).filter_map(fit).min_by_key(block_size),
+            FitStrategy::WorstFit => {
+                (0..count).filter_map(fit).min_by_key(|fit| std::cmp::Reverse(block_size(fit)))
+            }
+        }
+        .ok_or(AllocError::OutOfMemory)?;
+
+        let block = self.free_blocks[index].clone();
+        let range = start..(start + size.get());
+
+        // Replace the block with whatever is left on either side of the allocation.
+        let remainders = [block.start..range.start, range.end..block.end];
+        self.free_blocks.splice(
+            index..=index,
+            remainders.into_iter().filter(|remainder| remainder.start < remainder.end),
+        );
+        self.cursor = range.end;
+
+        Ok(range)
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        if range.start >= range.end || range.end > self.size {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+
+        // The index of the first free block that begins at or after the start of `range`.
+        let index = self.free_blocks.partition_point(|block| block.start < range.start);
+        let previous = index.checked_sub(1).map(|index| self.free_blocks[index].clone());
+        let next = self.free_blocks.get(index).cloned();
+
+        // A range that overlaps a free block was never allocated (or has already been freed).
+        if previous.as_ref().is_some_and(|previous| previous.end > range.start)
+            || next.as_ref().is_some_and(|next| next.start < range.end)
+        {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+
+        let touches_previous = previous.is_some_and(|previous| previous.end == range.start);
+        let touches_next = next.is_some_and(|next| range.end == next.start);
+        match (touches_previous, touches_next) {
+            (true, true) => {
+                self.free_blocks[index - 1].end = self.free_blocks[index].end;
+                self.free_blocks.remove(index);
+            }
+            (true, false) => self.free_blocks[index - 1].end = range.end,
+            (false, true) => self.free_blocks[index].start = range.start,
+            (false, false) => self.free_blocks.insert(index, range),
+        }
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        Some(self.free_blocks.iter().map(|block| block.end - block.start).max().unwrap_or(0))
+    }
+
+    fn reset(&mut self) -> bool {
+        self.free_blocks = std::iter::once(0..self.size).collect();
+        self.cursor = 0;
+
+        true
+    }
+
+    fn grow(&mut self, new_size: NonZeroBufferAddress) -> bool {
+        let new_size = new_size.get();
+        if new_size < self.size {
+            return false;
+        }
+
+        // The new memory is coalesced with a free block at the old end, if there is one.
+        match self.free_blocks.last_mut() {
+            Some(last) if last.end == self.size => last.end = new_size,
+            _ if new_size > self.size => self.free_blocks.push(self.size..new_size),
+            _ => {}
+        }
+        self.size = new_size;
+
+        true
+    }
+}
+
+/// An allocator that splits its memory into power-of-two blocks and merges them back on free.
+///
+/// Every allocation is given a block whose size is the smallest power of two that holds both its
+/// size and its alignment (and at least [`Self::MIN_BLOCK_SIZE`]). A larger free block is split
+/// in halves&mdash;*buddies*&mdash;until a block of the right size is obtained, and a freed block
+/// is merged with its buddy whenever the buddy is also free. Both operations take *O(log n)* time,
+/// and because blocks are always aligned to their own size, fragmentation is bounded; the cost is
+/// that each allocation wastes up to half of its block.
+///
+/// Alignments must be powers of two. A heap whose size is not a power of two is managed as several
+/// independent top-level blocks, and up to `MIN_BLOCK_SIZE - 1` bytes at its end go unused.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Buddy {
+    /// The offsets of the free blocks of each order, where a block of order `n` is
+    /// `MIN_BLOCK_SIZE << n` bytes in size.
+    free_blocks: Vec<BTreeSet<BufferAddress>>,
+    /// The order of the block backing each live allocation, keyed by offset.
+    allocated: BTreeMap<BufferAddress, usize>,
+    /// The offset and order of each top-level block, from largest to smallest. Blocks are never
+    /// merged beyond these.
+    roots: Vec<(BufferAddress, usize)>,
+}
+
+impl Buddy {
+    /// The size, in bytes, of the smallest block handed out.
+    pub const MIN_BLOCK_SIZE: BufferAddress = 16;
+
+    fn block_size(order: usize) -> BufferAddress {
+        Self::MIN_BLOCK_SIZE << order
+    }
+
+    /// The order of the top-level block containing `offset`.
+    fn root_order(&self, offset: BufferAddress) -> usize {
+        self.roots
+            .iter()
+            .find(|&&(start, order)| (start..(start + Self::block_size(order))).contains(&offset))
+            .map_or(0, |&(_, order)| order)
+    }
+}
+
+impl Allocator for Buddy {
+    /// Creates a new `Buddy` that manages `size` bytes, independently of any [`Heap`](crate::Heap).
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        let usable = size.get() & !(Self::MIN_BLOCK_SIZE - 1);
+        let order_count = match usable {
+            0 => 0,
+            _ => (usable.ilog2() - Self::MIN_BLOCK_SIZE.ilog2()) as usize + 1,
+        };
+        let mut buddy = Self {
+            free_blocks: vec![BTreeSet::new(); order_count],
+            allocated: BTreeMap::new(),
+            roots: Vec::new(),
+        };
+
+        // Lay out top-level blocks from largest to smallest so that each is aligned to its size.
+        let mut offset = 0;
+        for order in (0..order_count).rev() {
+            if usable & Self::block_size(order) != 0 {
+                buddy.roots.push((offset, order));
+                buddy.free_blocks[order].insert(offset);
+                offset += Self::block_size(order);
+            }
+        }
+
+        buddy
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // Blocks are aligned to their own size, which is always a power of two.
+        if !alignment.is_power_of_two() {
+            return Err(AllocError::AlignmentNotSupported { alignment });
+        }
+        let block_size = size
+            .get()
+            .max(alignment.get())
+            .max(Self::MIN_BLOCK_SIZE)
+            .checked_next_power_of_two()
+            .ok_or(AllocError::OutOfMemory)?;
+        let order = (block_size.ilog2() - Self::MIN_BLOCK_SIZE.ilog2()) as usize;
+
+        let mut found = (order..self.free_blocks.len())
+            .find(|&order| !self.free_blocks[order].is_empty())
+            .ok_or(AllocError::OutOfMemory)?;
+        // SAFETY: `found` was chosen because its free list is nonempty.
+        let offset = unsafe { self.free_blocks[found].pop_first().unwrap_unchecked() };
+
+        // Split the block until it is of the right order, freeing the upper half each time.
+        while found > order {
+            found -= 1;
+            self.free_blocks[found].insert(offset + Self::block_size(found));
+        }
+        self.allocated.insert(offset, order);
+
+        Ok(offset..(offset + size.get()))
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        let mut order = self.allocated.remove(&range.start).ok_or(AllocError::NotOwnedByAllocator)?;
+        let mut offset = range.start;
+
+        let root_order = self.root_order(offset);
+        while order < root_order {
+            let buddy = offset ^ Self::block_size(order);
+            if !self.free_blocks[order].remove(&buddy) {
+                break;
+            }
+            offset = offset.min(buddy);
+            order += 1;
+        }
+        self.free_blocks[order].insert(offset);
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        let order = self.free_blocks.iter().rposition(|blocks| !blocks.is_empty());
+
+        Some(order.map_or(0, Self::block_size))
+    }
+
+    fn reset(&mut self) -> bool {
+        for free_blocks in self.free_blocks.iter_mut() {
+            free_blocks.clear();
+        }
+        self.allocated.clear();
+        for &(offset, order) in self.roots.iter() {
+            self.free_blocks[order].insert(offset);
+        }
+
+        true
+    }
+}
+
+/// A two-level segregated fit (TLSF) allocator, with constant-time allocation and deallocation.
+///
+/// Free blocks are kept in segregated lists indexed by two levels of size class: the first level
+/// is the position of the leftmost 1 bit of the block size, and the second level divides each
+/// first-level class linearly into [`Self::SECOND_LEVEL_COUNT`] subclasses. Two levels of bitmaps
+/// record which lists are nonempty, so finding a list whose every block is large enough for an
+/// allocation (a *good fit*) takes a fixed number of bit scans, regardless of how many blocks
+/// exist. Freed blocks are immediately coalesced with free neighbors.
+///
+/// This makes `Tlsf` suited to real-time use, where allocation must have a hard upper bound on its
+/// cost. Blocks begin and end at multiples of [`Self::GRANULARITY`], so an alignment that isn't a
+/// multiple of it is honored by aligning to the least common multiple of the two.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tlsf {
+    /// Every block, free or allocated. Slots of blocks that have been merged away are recycled
+    /// through [`Self::unused_slots`].
+    blocks: Vec<TlsfBlock>,
+    unused_slots: Vec<usize>,
+    /// Bit `i` is set if any list in first-level class `i` is nonempty.
+    first_level_bitmap: u64,
+    /// Bit `j` of element `i` is set if the list for classes `(i, j)` is nonempty.
+    #[cfg_attr(feature = "snapshot", serde(with = "long_array"))]
+    second_level_bitmaps: [u16; 64],
+    /// The first block of the free list for each pair of classes.
+    #[cfg_attr(feature = "snapshot", serde(with = "long_array"))]
+    free_heads: [[Option<usize>; Tlsf::SECOND_LEVEL_COUNT]; 64],
+    /// The block backing each live allocation, keyed by offset.
+    allocated: HashMap<BufferAddress, usize>,
+    /// The number of bytes managed, which excludes any remainder smaller than
+    /// [`Self::GRANULARITY`].
+    usable: BufferAddress,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+struct TlsfBlock {
+    offset: BufferAddress,
+    size: BufferAddress,
+    is_free: bool,
+    /// The blocks immediately before and after this one in memory.
+    prev_physical: Option<usize>,
+    next_physical: Option<usize>,
+    /// The neighbors of this block in its free list, if it is free.
+    prev_free: Option<usize>,
+    next_free: Option<usize>,
+}
+
+impl Tlsf {
+    /// The number of second-level subclasses per first-level class.
+    pub const SECOND_LEVEL_COUNT: usize = 16;
+
+    /// The granularity, in bytes, of block sizes and offsets.
+    ///
+    /// This equals [`Self::SECOND_LEVEL_COUNT`] so that even the smallest first-level class can be
+    /// divided evenly into second-level subclasses.
+    pub const GRANULARITY: BufferAddress = Self::SECOND_LEVEL_COUNT as BufferAddress;
+
+    /// Frees a single block covering all of the managed memory, which must not be covered by any
+    /// other block.
+    fn insert_whole_block(&mut self) {
+        if self.usable > 0 {
+            let block = self.new_block(0, self.usable, None, None);
+            self.insert_free(block);
+        }
+    }
+
+    /// The classes of the free list that a block of `size` bytes belongs in.
+    fn classes(size: BufferAddress) -> (usize, usize) {
+        let first = size.ilog2() as usize;
+        let shift = first - Self::SECOND_LEVEL_COUNT.ilog2() as usize;
+        let second = (size >> shift) as usize - Self::SECOND_LEVEL_COUNT;
+
+        (first, second)
+    }
+
+    /// The classes of the first free list whose every block can hold `size` bytes, or `None` if
+    /// there is no such list.
+    fn find_suitable(&self, size: BufferAddress) -> Option<(usize, usize)> {
+        // Round `size` up to the next second-level boundary so that every block in the resulting
+        // list is at least `size` bytes.
+        let first = size.ilog2() as usize;
+        let shift = first - Self::SECOND_LEVEL_COUNT.ilog2() as usize;
+        let (first, second) = Self::classes(size.checked_add((1 << shift) - 1)?);
+
+        let second_map = u32::from(self.second_level_bitmaps[first]) & (!0 << second);
+        if second_map != 0 {
+            return Some((first, second_map.trailing_zeros() as usize));
+        }
+
+        let larger_classes = (!0u64).checked_shl(first as u32 + 1).unwrap_or(0);
+        let first_map = self.first_level_bitmap & larger_classes;
+        if first_map == 0 {
+            return None;
+        }
+        let first = first_map.trailing_zeros() as usize;
+
+        Some((first, self.second_level_bitmaps[first].trailing_zeros() as usize))
+    }
+
+    fn new_block(
+        &mut self,
+        offset: BufferAddress,
+        size: BufferAddress,
+        prev_physical: Option<usize>,
+        next_physical: Option<usize>,
+    ) -> usize {
+        let block = TlsfBlock {
+            offset,
+            size,
+            is_free: false,
+            prev_physical,
+            next_physical,
+            prev_free: None,
+            next_free: None,
+        };
+
+        match self.unused_slots.pop() {
+            Some(index) => {
+                self.blocks[index] = block;
+                index
+            }
+            None => {
+                self.blocks.push(block);
+                self.blocks.len() - 1
+            }
+        }
+    }
+
+    fn insert_free(&mut self, index: usize) {
+        let (first, second) = Self::classes(self.blocks[index].size);
+        let head = self.free_heads[first][second];
+
+        let block = &mut self.blocks[index];
+        block.is_free = true;
+        block.prev_free = None;
+        block.next_free = head;
+        if let Some(head) = head {
+            self.blocks[head].prev_free = Some(index);
+        }
+        self.free_heads[first][second] = Some(index);
+        self.first_level_bitmap |= 1 << first;
+        self.second_level_bitmaps[first] |= 1 << second;
+    }
+
+    fn remove_free(&mut self, index: usize) {
+        let (first, second) = Self::classes(self.blocks[index].size);
+        let block = &mut self.blocks[index];
+        block.is_free = false;
+        let (prev, next) = (block.prev_free.take(), block.next_free.take());
+
+        match prev {
+            Some(prev) => self.blocks[prev].next_free = next,
+            None => self.free_heads[first][second] = next,
+        }
+        if let Some(next) = next {
+            self.blocks[next].prev_free = prev;
+        }
+
+        if self.free_heads[first][second].is_none() {
+            self.second_level_bitmaps[first] &= !(1 << second);
+            if self.second_level_bitmaps[first] == 0 {
+                self.first_level_bitmap &= !(1 << first);
+            }
+        }
+    }
+
+    /// Splits the first `size` bytes of block `index` off into a block of their own, and returns
+    /// the index of the block holding the remainder.
+    fn split(&mut self, index: usize, size: BufferAddress) -> usize {
+        let block = &self.blocks[index];
+        let (offset, remainder) = (block.offset + size, block.size - size);
+        let next_physical = block.next_physical;
+
+        let rest = self.new_block(offset, remainder, Some(index), next_physical);
+        if let Some(next) = next_physical {
+            self.blocks[next].prev_physical = Some(rest);
+        }
+        self.blocks[index].next_physical = Some(rest);
+        self.blocks[index].size = size;
+
+        rest
+    }
+
+    /// Merges block `next`, which must directly follow block `index` in memory, into it.
+    fn merge(&mut self, index: usize, next: usize) {
+        let TlsfBlock { size, next_physical, .. } = self.blocks[next];
+        self.blocks[index].size += size;
+        self.blocks[index].next_physical = next_physical;
+        if let Some(after) = next_physical {
+            self.blocks[after].prev_physical = Some(index);
+        }
+        self.unused_slots.push(next);
+    }
+}
+
+impl Allocator for Tlsf {
+    /// Creates a new `Tlsf` that manages `size` bytes, independently of any [`Heap`](crate::Heap).
+    ///
+    /// Up to `GRANULARITY - 1` bytes at the end of the memory go unused.
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        let mut tlsf = Self {
+            blocks: Vec::new(),
+            unused_slots: Vec::new(),
+            first_level_bitmap: 0,
+            second_level_bitmaps: [0; 64],
+            free_heads: [[None; Self::SECOND_LEVEL_COUNT]; 64],
+            allocated: HashMap::new(),
+            usable: size.get() & !(Self::GRANULARITY - 1),
+        };
+        tlsf.insert_whole_block();
+
+        tlsf
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        let granularity = NonZeroBufferAddress::new(Self::GRANULARITY).unwrap();
+        let block_size = align_up(size.get(), granularity).ok_or(AllocError::OutOfMemory)?;
+        // Note: aligning to a multiple of the granularity keeps every block at a multiple of it,
+        // which the classes of the free lists rely on.
+        let alignment = combine_alignments(alignment, granularity);
+        // Blocks are already aligned to the granularity, so only larger alignments can require
+        // padding.
+        let padding = alignment.get() - Self::GRANULARITY;
+
+        let (first, second) = block_size
+            .checked_add(padding)
+            .and_then(|size| self.find_suitable(size))
+            .ok_or(AllocError::OutOfMemory)?;
+        let mut index = self.free_heads[first][second].ok_or(AllocError::OutOfMemory)?;
+        self.remove_free(index);
+
+        let offset = self.blocks[index].offset;
+        // Note: the block is at least `padding` bytes larger than needed, so this can't overflow.
+        let aligned_offset = align_up(offset, alignment).unwrap();
+        if aligned_offset > offset {
+            let padding_block = index;
+            index = self.split(padding_block, aligned_offset - offset);
+            self.insert_free(padding_block);
+        }
+        if self.blocks[index].size - block_size >= Self::GRANULARITY {
+            let rest = self.split(index, block_size);
+            self.insert_free(rest);
+        }
+        self.allocated.insert(aligned_offset, index);
+
+        Ok(aligned_offset..(aligned_offset + size.get()))
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        let mut index = self.allocated.remove(&range.start).ok_or(AllocError::NotOwnedByAllocator)?;
+
+        let prev = self.blocks[index].prev_physical.filter(|&prev| self.blocks[prev].is_free);
+        if let Some(prev) = prev {
+            self.remove_free(prev);
+            self.merge(prev, index);
+            index = prev;
+        }
+        let next = self.blocks[index].next_physical.filter(|&next| self.blocks[next].is_free);
+        if let Some(next) = next {
+            self.remove_free(next);
+            self.merge(index, next);
+        }
+        self.insert_free(index);
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        if self.first_level_bitmap == 0 {
+            return Some(0);
+        }
+        // Only the largest nonempty list can hold the largest block, but it must be searched.
+        let first = self.first_level_bitmap.ilog2() as usize;
+        let second = self.second_level_bitmaps[first].ilog2() as usize;
+
+        let mut largest = 0;
+        let mut next = self.free_heads[first][second];
+        while let Some(index) = next {
+            largest = largest.max(self.blocks[index].size);
+            next = self.blocks[index].next_free;
+        }
+
+        Some(largest)
+    }
+
+    fn reset(&mut self) -> bool {
+        self.blocks.clear();
+        self.unused_slots.clear();
+        self.first_level_bitmap = 0;
+        self.second_level_bitmaps = [0; 64];
+        self.free_heads = [[None; Self::SECOND_LEVEL_COUNT]; 64];
+        self.allocated.clear();
+        self.insert_whole_block();
+
+        true
+    }
+}
+
+/// A port of Sebastian Aaltonen's [OffsetAllocator], with constant-time allocation and
+/// deallocation and little metadata.
+///
+/// Free blocks are kept in [`Self::BIN_COUNT`] lists, or *bins*, indexed by their size encoded as
+/// a tiny floating-point number with a 5-bit exponent and a 3-bit mantissa. Bin sizes thus grow
+/// geometrically, with 8 linearly spaced bins per power of two, which bounds the memory wasted by
+/// rounding to one eighth. Two levels of bitmaps record which bins are nonempty, so finding a bin
+/// whose every block is large enough for an allocation takes two bit scans. Freed blocks are
+/// immediately coalesced with free neighbors.
+///
+/// Like [`Tlsf`], this is suited to real-time use, but it places blocks at byte granularity and
+/// honors any alignment, which makes it a good general-purpose choice for GPU suballocation.
+/// Because bins rather than blocks are searched, an allocation can fail even though a free block
+/// could hold it, if that block shares its bin with smaller ones. Allocations larger than the
+/// largest bin, of about 15 GiB, always fail.
+///
+/// [OffsetAllocator]: https://github.com/sebbbi/OffsetAllocator
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Offset {
+    /// Every block, free or allocated. Slots of blocks that have been merged away are recycled
+    /// through [`Self::unused_slots`].
+    nodes: Vec<OffsetNode>,
+    unused_slots: Vec<usize>,
+    /// Bit `i` is set if any bin in group `i` of 8 bins is nonempty.
+    used_groups: u32,
+    /// Bit `j` of element `i` is set if bin `8 * i + j` is nonempty.
+    used_bins: [u8; Offset::BIN_COUNT / 8],
+    /// The first block of the free list of each bin.
+    #[cfg_attr(feature = "snapshot", serde(with = "long_array"))]
+    bin_heads: [Option<usize>; Offset::BIN_COUNT],
+    /// The block backing each live allocation, keyed by offset.
+    allocated: HashMap<BufferAddress, usize>,
+    /// The size, in bytes, of the managed memory.
+    size: BufferAddress,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+struct OffsetNode {
+    offset: BufferAddress,
+    size: BufferAddress,
+    is_free: bool,
+    /// The blocks immediately before and after this one in memory.
+    prev_neighbor: Option<usize>,
+    next_neighbor: Option<usize>,
+    /// The neighbors of this block in its bin, if it is free.
+    prev_in_bin: Option<usize>,
+    next_in_bin: Option<usize>,
 }
 
-fn create_alignment_bitmask(alignment: NonZeroBufferAddress) -> u64 {
-    // SAFETY: `alignment` is a nonzero unsigned integer, so its value must be greater than or equal
-    // to 1. Thus, subtracting one will never result in underflow.
-    !unsafe { alignment.get().unchecked_sub(1) }
+impl Offset {
+    /// The number of bins.
+    pub const BIN_COUNT: usize = 256;
+
+    const MANTISSA_BITS: u32 = 3;
+    const MANTISSA_VALUE: BufferAddress = 1 << Self::MANTISSA_BITS;
+    const MANTISSA_MASK: BufferAddress = Self::MANTISSA_VALUE - 1;
+
+    /// The bin of a block of `size` bytes, rounding down, so that the block is at least as large
+    /// as the bin size.
+    fn bin_rounding_down(size: BufferAddress) -> usize {
+        let (exponent, mantissa) = Self::split_size(size);
+        let bin = (exponent << Self::MANTISSA_BITS) | mantissa;
+
+        // Note: blocks too large for the last bin still belong in it.
+        (bin as usize).min(Self::BIN_COUNT - 1)
+    }
+
+    /// The first bin whose every block can hold `size` bytes, or `None` if there is none.
+    fn bin_rounding_up(size: BufferAddress) -> Option<usize> {
+        let (exponent, mut mantissa) = Self::split_size(size);
+        if exponent > 0 && size & ((1 << (exponent - 1)) - 1) != 0 {
+            mantissa += 1;
+        }
+        // Note: this is an addition rather than a bitwise or so that a mantissa that overflows
+        // carries into the exponent.
+        let bin = ((exponent << Self::MANTISSA_BITS) + mantissa) as usize;
+
+        (bin < Self::BIN_COUNT).then_some(bin)
+    }
+
+    /// The exponent and truncated mantissa of `size` as a floating-point number.
+    fn split_size(size: BufferAddress) -> (BufferAddress, BufferAddress) {
+        if size < Self::MANTISSA_VALUE {
+            // Sizes below the implicit leading 1 are denormals, stored as is.
+            return (0, size);
+        }
+        let mantissa_start = size.ilog2() - Self::MANTISSA_BITS;
+
+        (BufferAddress::from(mantissa_start) + 1, (size >> mantissa_start) & Self::MANTISSA_MASK)
+    }
+
+    /// The first nonempty bin at or after `bin`.
+    fn find_nonempty(&self, bin: usize) -> Option<usize> {
+        let (group, index) = (bin / 8, bin % 8);
+        let bins = self.used_bins[group] & (!0 << index);
+        if bins != 0 {
+            return Some(group * 8 + bins.trailing_zeros() as usize);
+        }
+
+        let groups = self.used_groups & (!0u32).checked_shl(group as u32 + 1).unwrap_or(0);
+        if groups == 0 {
+            return None;
+        }
+        let group = groups.trailing_zeros() as usize;
+
+        Some(group * 8 + self.used_bins[group].trailing_zeros() as usize)
+    }
+
+    /// The first free block in a bin before `end` that can hold `size` bytes aligned to
+    /// `alignment`.
+    ///
+    /// Blocks in these bins may be too small to leave room for any padding, but can still hold
+    /// the allocation if they happen to need little of it, such as every block of an empty heap.
+    /// Unlike the rest of the allocator, this takes a linear search.
+    fn find_aligned_fit(
+        &self,
+        size: BufferAddress,
+        alignment: NonZeroBufferAddress,
+        end: usize,
+    ) -> Option<usize> {
+        let mut bin = Self::bin_rounding_up(size)?;
+        while let Some(nonempty) = self.find_nonempty(bin).filter(|&nonempty| nonempty < end) {
+            let mut next = self.bin_heads[nonempty];
+            while let Some(index) = next {
+                let OffsetNode { offset, size: block_size, next_in_bin, .. } = self.nodes[index];
+                // Note: `offset + block_size` is within the heap, so this can't overflow.
+                if align_up(offset, alignment).unwrap() + size <= offset + block_size {
+                    return Some(index);
+                }
+                next = next_in_bin;
+            }
+            bin = nonempty + 1;
+            if bin == Self::BIN_COUNT {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Frees a single block covering all of the managed memory, which must not be covered by any
+    /// other block.
+    fn insert_whole_block(&mut self) {
+        let node = self.new_node(0, self.size, None, None);
+        self.insert_free(node);
+    }
+
+    fn new_node(
+        &mut self,
+        offset: BufferAddress,
+        size: BufferAddress,
+        prev_neighbor: Option<usize>,
+        next_neighbor: Option<usize>,
+    ) -> usize {
+        let node = OffsetNode {
+            offset,
+            size,
+            is_free: false,
+            prev_neighbor,
+            next_neighbor,
+            prev_in_bin: None,
+            next_in_bin: None,
+        };
+
+        match self.unused_slots.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn insert_free(&mut self, index: usize) {
+        let bin = Self::bin_rounding_down(self.nodes[index].size);
+        let head = self.bin_heads[bin];
+
+        let node = &mut self.nodes[index];
+        node.is_free = true;
+        node.prev_in_bin = None;
+        node.next_in_bin = head;
+        if let Some(head) = head {
+            self.nodes[head].prev_in_bin = Some(index);
+        }
+        self.bin_heads[bin] = Some(index);
+        self.used_groups |= 1 << (bin / 8);
+        self.used_bins[bin / 8] |= 1 << (bin % 8);
+    }
+
+    fn remove_free(&mut self, index: usize) {
+        let bin = Self::bin_rounding_down(self.nodes[index].size);
+        let node = &mut self.nodes[index];
+        node.is_free = false;
+        let (prev, next) = (node.prev_in_bin.take(), node.next_in_bin.take());
+
+        match prev {
+            Some(prev) => self.nodes[prev].next_in_bin = next,
+            None => self.bin_heads[bin] = next,
+        }
+        if let Some(next) = next {
+            self.nodes[next].prev_in_bin = prev;
+        }
+
+        if self.bin_heads[bin].is_none() {
+            self.used_bins[bin / 8] &= !(1 << (bin % 8));
+            if self.used_bins[bin / 8] == 0 {
+                self.used_groups &= !(1 << (bin / 8));
+            }
+        }
+    }
+
+    /// Splits the first `size` bytes of block `index` off into a block of their own, and returns
+    /// the index of the block holding the remainder.
+    fn split(&mut self, index: usize, size: BufferAddress) -> usize {
+        let node = &self.nodes[index];
+        let (offset, remainder) = (node.offset + size, node.size - size);
+        let next_neighbor = node.next_neighbor;
+
+        let rest = self.new_node(offset, remainder, Some(index), next_neighbor);
+        if let Some(next) = next_neighbor {
+            self.nodes[next].prev_neighbor = Some(rest);
+        }
+        self.nodes[index].next_neighbor = Some(rest);
+        self.nodes[index].size = size;
+
+        rest
+    }
+
+    /// Merges block `next`, which must directly follow block `index` in memory, into it.
+    fn merge(&mut self, index: usize, next: usize) {
+        let OffsetNode { size, next_neighbor, .. } = self.nodes[next];
+        self.nodes[index].size += size;
+        self.nodes[index].next_neighbor = next_neighbor;
+        if let Some(after) = next_neighbor {
+            self.nodes[after].prev_neighbor = Some(index);
+        }
+        self.unused_slots.push(next);
+    }
+}
+
+impl Allocator for Offset {
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        let mut offset = Self {
+            nodes: Vec::new(),
+            unused_slots: Vec::new(),
+            used_groups: 0,
+            used_bins: [0; Self::BIN_COUNT / 8],
+            bin_heads: [None; Self::BIN_COUNT],
+            allocated: HashMap::new(),
+            size: size.get(),
+        };
+        offset.insert_whole_block();
+
+        offset
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // Any block with room for `alignment - 1` bytes of padding can hold the allocation
+        // wherever it begins.
+        let padded_bin = size.get().checked_add(alignment.get() - 1).and_then(Self::bin_rounding_up);
+        let mut index = match padded_bin.and_then(|bin| self.find_nonempty(bin)) {
+            Some(bin) => self.bin_heads[bin].ok_or(AllocError::OutOfMemory)?,
+            None => self
+                .find_aligned_fit(size.get(), alignment, padded_bin.unwrap_or(Self::BIN_COUNT))
+                .ok_or(AllocError::OutOfMemory)?,
+        };
+        self.remove_free(index);
+
+        let offset = self.nodes[index].offset;
+        // Note: the block is at least `alignment - 1` bytes larger than needed, so this can't
+        // overflow.
+        let aligned_offset = align_up(offset, alignment).unwrap();
+        if aligned_offset > offset {
+            let padding = index;
+            index = self.split(padding, aligned_offset - offset);
+            self.insert_free(padding);
+        }
+        if self.nodes[index].size > size.get() {
+            let rest = self.split(index, size.get());
+            self.insert_free(rest);
+        }
+        self.allocated.insert(aligned_offset, index);
+
+        Ok(aligned_offset..(aligned_offset + size.get()))
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        let mut index = self.allocated.remove(&range.start).ok_or(AllocError::NotOwnedByAllocator)?;
+
+        let prev = self.nodes[index].prev_neighbor.filter(|&prev| self.nodes[prev].is_free);
+        if let Some(prev) = prev {
+            self.remove_free(prev);
+            self.merge(prev, index);
+            index = prev;
+        }
+        let next = self.nodes[index].next_neighbor.filter(|&next| self.nodes[next].is_free);
+        if let Some(next) = next {
+            self.remove_free(next);
+            self.merge(index, next);
+        }
+        self.insert_free(index);
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        if self.used_groups == 0 {
+            return Some(0);
+        }
+        // Only the largest nonempty bin can hold the largest block, but it must be searched.
+        let group = self.used_groups.ilog2() as usize;
+        let bin = group * 8 + self.used_bins[group].ilog2() as usize;
+
+        let mut largest = 0;
+        let mut next = self.bin_heads[bin];
+        while let Some(index) = next {
+            largest = largest.max(self.nodes[index].size);
+            next = self.nodes[index].next_in_bin;
+        }
+
+        Some(largest)
+    }
+
+    fn reset(&mut self) -> bool {
+        self.nodes.clear();
+        self.unused_slots.clear();
+        self.used_groups = 0;
+        self.used_bins = [0; Self::BIN_COUNT / 8];
+        self.bin_heads = [None; Self::BIN_COUNT];
+        self.allocated.clear();
+        self.insert_whole_block();
+
+        true
+    }
+}
+
+/// A bump allocator that wraps around its memory, for transient per-frame data.
+///
+/// Allocations are made one after another, wrapping back to the start of the memory when the end
+/// is reached, and are freed in bulk rather than individually: [`Self::mark_frame`] closes off the
+/// allocations made so far under a fence value&mdash;typically the [`Serial`] of the submission
+/// that uses them&mdash;and [`Self::free_up_to`] releases every frame whose fence has passed. This
+/// is the standard pattern for streaming uniform and vertex data that lives for a single frame.
+///
+/// [`Allocator::dealloc`] always fails, as allocations cannot be freed individually.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ring {
+    size: BufferAddress,
+    /// The offset at which the next allocation begins searching.
+    head: BufferAddress,
+    /// The number of bytes between the oldest live allocation and [`Self::head`], including any
+    /// space skipped over when wrapping.
+    used: BufferAddress,
+    /// The total number of bytes ever consumed, which only increases.
+    consumed: BufferAddress,
+    /// The fence and value of [`Self::consumed`] at every call to [`Self::mark_frame`] whose frame
+    /// has yet to be freed, oldest first.
+    frames: VecDeque<(Serial, BufferAddress)>,
+}
+
+impl Ring {
+    /// Marks every allocation made since the previous call as belonging to the frame `fence`.
+    ///
+    /// Fences must not decrease from one call to the next.
+    pub fn mark_frame(&mut self, fence: Serial) {
+        self.frames.push_back((fence, self.consumed));
+    }
+
+    /// Frees the allocations of every frame whose fence is no greater than `fence`.
+    pub fn free_up_to(&mut self, fence: Serial) {
+        while let Some(&(frame_fence, consumed)) = self.frames.front() {
+            if frame_fence > fence {
+                break;
+            }
+            self.frames.pop_front();
+            self.used = self.consumed - consumed;
+        }
+    }
+
+    /// The number of bytes currently in use, including space skipped over when wrapping.
+    pub fn used_bytes(&self) -> BufferAddress {
+        self.used
+    }
+}
+
+impl Allocator for Ring {
+    /// Creates a new `Ring` that manages `size` bytes, independently of any [`Heap`](crate::Heap).
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        Self { size: size.get(), head: 0, used: 0, consumed: 0, frames: VecDeque::new() }
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        if self.used == self.size {
+            return Err(AllocError::OutOfMemory);
+        }
+        if self.used == 0 {
+            // Nothing is live, so start over from the beginning to keep allocations packed.
+            self.head = 0;
+        }
+        let tail = (self.head + self.size - self.used) % self.size;
+
+        // Free memory is either one run from the head to the tail or, if the live allocations
+        // don't wrap around, two runs: from the head to the end and from the start to the tail.
+        let wraps = self.head >= tail;
+        let end = if wraps { self.size } else { tail };
+        let start_at_head = align_up(self.head, alignment).filter(|start| {
+            start.checked_add(size.get()).is_some_and(|range_end| range_end <= end)
+        });
+        let start = match start_at_head {
+            Some(start) => start,
+            None if wraps && size.get() <= tail => 0,
+            None => return Err(AllocError::OutOfMemory),
+        };
+        let range = start..(start + size.get());
+
+        let consumed = match start >= self.head {
+            true => range.end - self.head,
+            false => (self.size - self.head) + range.end,
+        };
+        self.head = range.end % self.size;
+        self.used += consumed;
+        self.consumed += consumed;
+
+        Ok(range)
+    }
+
+    unsafe fn dealloc(&mut self, _range: Range<BufferAddress>) -> Result<(), AllocError> {
+        Err(AllocError::Unsupported)
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        if self.used == 0 {
+            return Some(self.size);
+        }
+        let tail = (self.head + self.size - self.used) % self.size;
+
+        Some(match self.head >= tail {
+            true => (self.size - self.head).max(tail),
+            false => tail - self.head,
+        })
+    }
+
+    fn reset(&mut self) -> bool {
+        self.head = 0;
+        self.used = 0;
+        self.frames.clear();
+
+        true
+    }
+}
+
+/// An allocator of identically-sized slots, for many small allocations of one size.
+///
+/// Memory is divided into slots of a fixed size and alignment, chosen at construction, and a bitmap
+/// tracks which are free. Allocation takes the lowest free slot and deallocation returns it, so
+/// both are fast and there is no fragmentation; the cost is that requests larger than a slot, or
+/// more strictly aligned than one, always fail. This suits per-object uniform data, where thousands
+/// of blocks of the same size are allocated and freed.
+///
+/// [`Allocator::with_capacity`] uses slots of [`Self::DEFAULT_BLOCK_SIZE`] bytes, aligned to the same.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pool {
+    block_size: BufferAddress,
+    /// The distance between the starts of consecutive slots.
+    stride: BufferAddress,
+    slot_count: usize,
+    /// One bit per slot, set if the slot is free.
+    free_slots: Vec<u64>,
+}
+
+impl Pool {
+    /// The slot size used by [`Allocator::with_capacity`], which matches the minimum uniform buffer offset
+    /// alignment of most devices.
+    pub const DEFAULT_BLOCK_SIZE: BufferAddress = 256;
+
+    /// Creates a new `Pool` that divides `size` bytes into slots of `block_size` bytes, each
+    /// aligned to `alignment`, independently of any [`Heap`](crate::Heap).
+    pub fn with_block_size(
+        size: NonZeroBufferAddress,
+        block_size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Self {
+        let stride = align_up(block_size.get(), alignment).expect("block size is too large");
+        let slot_count = (size.get() / stride) as usize;
+
+        Self { block_size: block_size.get(), stride, slot_count, free_slots: all_free(slot_count) }
+    }
+
+    /// The size, in bytes, of each slot.
+    pub fn block_size(&self) -> BufferAddress {
+        self.block_size
+    }
+
+    /// The total number of slots.
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// The number of slots not currently allocated.
+    pub fn free_slot_count(&self) -> usize {
+        self.free_slots.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+impl Allocator for Pool {
+    /// Creates a new `Pool` that divides `size` bytes into slots of
+    /// [`Self::DEFAULT_BLOCK_SIZE`] bytes.
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        let block_size = NonZeroBufferAddress::new(Self::DEFAULT_BLOCK_SIZE).unwrap();
+
+        Self::with_block_size(size, block_size, block_size)
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        // Every slot begins at a multiple of the stride, so any alignment dividing it is honored.
+        if !self.stride.is_multiple_of(alignment.get()) {
+            return Err(AllocError::AlignmentNotSupported { alignment });
+        }
+        if size.get() > self.block_size {
+            return Err(AllocError::OutOfMemory);
+        }
+
+        let word_index = self
+            .free_slots
+            .iter()
+            .position(|&word| word != 0)
+            .ok_or(AllocError::OutOfMemory)?;
+        let bit_index = self.free_slots[word_index].trailing_zeros() as usize;
+        self.free_slots[word_index] &= !(1 << bit_index);
+
+        let start = ((word_index * 64 + bit_index) as BufferAddress) * self.stride;
+        Ok(start..(start + size.get()))
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        if !range.start.is_multiple_of(self.stride) || range.end - range.start > self.block_size {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+        let slot = (range.start / self.stride) as usize;
+        if slot >= self.slot_count {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+
+        let (word, bit) = (&mut self.free_slots[slot / 64], 1 << (slot % 64));
+        if *word & bit != 0 {
+            // The slot is already free.
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+        *word |= bit;
+
+        Ok(())
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        Some(match self.free_slots.iter().any(|&word| word != 0) {
+            true => self.block_size,
+            false => 0,
+        })
+    }
+
+    fn reset(&mut self) -> bool {
+        self.free_slots = all_free(self.slot_count);
+
+        true
+    }
+
+    fn grow(&mut self, new_size: NonZeroBufferAddress) -> bool {
+        let slot_count = (new_size.get() / self.stride) as usize;
+        if slot_count < self.slot_count {
+            return false;
+        }
+
+        self.free_slots.resize(slot_count.div_ceil(64), 0);
+        for slot in self.slot_count..slot_count {
+            self.free_slots[slot / 64] |= 1 << (slot % 64);
+        }
+        self.slot_count = slot_count;
+
+        true
+    }
+}
+
+/// Another allocator whose every allocation is aligned to at least `ALIGN` bytes.
+///
+/// The alignment passed to [`Allocator::alloc`] is combined with `ALIGN`, so that callers needing
+/// a fixed alignment, such as the 256 bytes required of dynamic uniform buffer offsets by most
+/// devices, don't have to pass it on every call; see also [`Self::alloc_aligned`]. Everything else
+/// is forwarded to the wrapped allocator.
+///
+/// An `ALIGN` of 0 is rejected at compile time.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "snapshot", derive(serde::Serialize, serde::Deserialize))]
+pub struct Aligned<const ALIGN: u64, A> {
+    inner: A,
+}
+
+impl<const ALIGN: u64, A> Aligned<ALIGN, A> {
+    /// The alignment, in bytes, of every allocation.
+    pub const ALIGNMENT: NonZeroBufferAddress = match NonZeroBufferAddress::new(ALIGN) {
+        Some(alignment) => alignment,
+        None => panic!("`Aligned` requires a nonzero alignment"),
+    };
+
+    /// Wraps `inner`, which may already have made allocations.
+    pub fn wrap(inner: A) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<const ALIGN: u64, A: Allocator> Aligned<ALIGN, A> {
+    /// Allocates `size` bytes aligned to [`Self::ALIGNMENT`].
+    ///
+    /// # Errors
+    ///
+    /// This fails as with [`Allocator::alloc`].
+    pub fn alloc_aligned(
+        &mut self,
+        size: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.inner.alloc(size, Self::ALIGNMENT)
+    }
+}
+
+impl<const ALIGN: u64, A: Allocator> Allocator for Aligned<ALIGN, A> {
+    fn with_capacity(size: NonZeroBufferAddress) -> Self {
+        Self::wrap(A::with_capacity(size))
+    }
+
+    fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.inner.alloc(size, combine_alignments(alignment, Self::ALIGNMENT))
+    }
+
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        self.inner.dealloc(range)
+    }
+
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        self.inner.largest_free_block()
+    }
+
+    fn reset(&mut self) -> bool {
+        self.inner.reset()
+    }
+
+    fn grow(&mut self, new_size: NonZeroBufferAddress) -> bool {
+        self.inner.grow(new_size)
+    }
+}
+
+/// The bitmap of a [`Pool`] of `slot_count` slots that are all free.
+fn all_free(slot_count: usize) -> Vec<u64> {
+    let mut free_slots = vec![!0; slot_count.div_ceil(64)];
+    if !slot_count.is_multiple_of(64) {
+        // Clear the bits past the last slot so that they are never handed out.
+        free_slots[slot_count / 64] = (1 << (slot_count % 64)) - 1;
+    }
+
+    free_slots
+}
+
+/// Serialization of arrays longer than the 32 elements that serde supports, as sequences.
+#[cfg(feature = "snapshot")]
+mod long_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S, T, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        array.as_slice().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D, T, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let elements = Vec::<T>::deserialize(deserializer)?;
+        let len = elements.len();
+
+        elements.try_into().map_err(|_| D::Error::invalid_length(len, &"an array of fixed length"))
+    }
 }
@@ -1,48 +1,115 @@
 use wgpu::BufferAddress;
 
-use std::ops::{Index, IndexMut, Range};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::BTreeMap,
+    ops::{Deref, Index, IndexMut, Range},
+    sync::{Arc, Mutex},
+};
 
-use crate::{Allocator, Heap, HeapUsages, NonZeroBufferAddress};
-
-/// A user-provided function that calculates the size, in bytes, of a new heap given a
-/// [`NewHeapSizeContext`].
-type CalculateNewHeapSize = fn(NewHeapSizeContext) -> NonZeroBufferAddress;
+#[cfg(feature = "log")]
+use crate::observer::LogObserver;
+#[cfg(feature = "snapshot")]
+use crate::snapshot::{ArenaSnapshot, HeapSnapshot, PoolSnapshot};
+#[cfg(feature = "track-allocs")]
+use crate::tracking::{AllocTracker, LiveAllocation};
+#[cfg(doc)]
+use crate::Heap;
+use crate::{
+    aging::{AgeTracker, AllocationAge, ColdAllocation, Frame},
+    backing::{GpuBacking, HeapBacking, Wgpu},
+    compaction::{
+        self,
+        Admission,
+        BudgetTracker,
+        CompactionBudget,
+        CompactionProgress,
+        CompactionReport,
+    },
+    diagnostics::{AllocDiagnostics, HeapDiagnostics, Suggestion},
+    governor,
+    growth::GrowthPolicy,
+    guard::{self, CanaryViolation, GuardBands, Guarded, CANARY},
+    metrics::{FrameCounters, Metrics, PoolMetrics},
+    observer::AllocationObserver,
+    queue::{Completion, Hazards, Serial, TransferContext},
+    sizes::{self, classify_size, combine_alignments},
+    stats::{ArenaStats, HeapStats, Stats, TagStats},
+    typed::ArrayLayout,
+    AllocError,
+    AsyncWriteError,
+    BindingError,
+    CopyError,
+    Allocator,
+    upload::{BudgetExceeded, UploadPath, UploadPolicy, UploadStrategy},
+    watermark::{self, Crossing, WatermarkEvent, WatermarkId, Watermarks},
+    HeapDescriptor,
+    HeapUsages,
+    pad_for_copy,
+    NonZeroBufferAddress,
+};
 
 /// Context for calculating the size, in bytes, of a new heap.
 ///
-/// Such a context is passed to a [`CalculateNewHeapSize`].
+/// Such a context is passed to a [`GrowthPolicy`].
+#[derive(Clone, Copy, Debug)]
 pub struct NewHeapSizeContext {
     /// The size, in bytes, of the first allocation to be made on the new heap.
     ///
-    /// The [`CalculateNewHeapSize`] that this context is passed to must produce a size greater than
-    /// or equal to this value.
+    /// The [`GrowthPolicy`] that this context is passed to must produce a size greater than or
+    /// equal to this value, or else the allocation fails with
+    /// [`AllocError::SizeTooLargeForArena`].
     pub first_alloc_size: NonZeroBufferAddress,
+    /// The number of heaps already in the pool that the new heap will be added to.
+    pub heap_count: usize,
+    /// The size, in bytes, of the most recently created heap in that pool, if any.
+    pub last_heap_size: Option<NonZeroBufferAddress>,
 }
 
-fn classify_size(size: NonZeroBufferAddress) -> usize {
-    let size = size.get();
+impl NewHeapSizeContext {
+    /// The context for a new heap in a pool of `heaps` whose first allocation is `size` bytes.
+    fn new<H: HeapBacking, A>(heaps: &[(H, A)], size: NonZeroBufferAddress) -> Self {
+        Self {
+            first_alloc_size: size,
+            heap_count: heaps.len(),
+            last_heap_size: heaps.last().map(|(heap, _)| heap.size()),
+        }
+    }
+}
 
-    // This tells us how many zeros are on the left-side of the binary representation of `size`, but
-    // it *also* tells us how many bits are *not* leading zeros&mdash;we just have to subtract this
-    // value from the total number of bits in `size`.
-    let leading_zeros = size.leading_zeros();
-    let total_bits = 8 * std::mem::size_of_val(&size);
-    // SAFETY: The number of leading zeros in `size` cannot exceed the total number of bits.
-    let not_leading_zeros = unsafe {
-        // Note: it's OK to cast `leading_zeros` to `usize` as it can't possibly overflow `usize` on
-        // any system&mdash;we're not dealing with 512-bit integers here.
-        total_bits.unchecked_sub(leading_zeros as usize)
-    };
+/// The alignment that allocations with `usage` must have to be bound at a dynamic offset on a
+/// device with `limits`.
+fn min_binding_alignment(usage: HeapUsages, limits: &wgpu::Limits) -> NonZeroBufferAddress {
+    let mut alignment = 1;
+    if usage.contains(HeapUsages::UNIFORM) {
+        alignment = alignment.max(limits.min_uniform_buffer_offset_alignment.into());
+    }
+    if usage.contains(HeapUsages::STORAGE) {
+        alignment = alignment.max(limits.min_storage_buffer_offset_alignment.into());
+    }
+
+    NonZeroBufferAddress::new(alignment).unwrap_or(NonZeroBufferAddress::MIN)
+}
 
-    // If `not_leading_zeros` is the number of bits that aren't leading zeros, then
-    // `not_leading_zeros` must be the zero-based index of the leftmost 1 bit.
-    // SAFETY: `size` is based on a `NonZeroBufferAddress`, so it must be nonzero.
-    unsafe { not_leading_zeros.unchecked_sub(1) }
+/// The debug label of the heap at `index_in_pool` in the pool for `size_class`.
+fn heap_label(prefix: &str, size_class: usize, index_in_pool: usize) -> String {
+    match sizes::pool_index(size_class) {
+        None => format!("{prefix}-tiny-heap{index_in_pool}"),
+        Some(_) => format!("{prefix}-pool{size_class}-heap{index_in_pool}"),
+    }
 }
 
-impl<A> Default for SizePool<A> {
+impl<A, B: GpuBacking> Default for SizePool<A, B> {
     fn default() -> Self {
-        Self(Vec::new())
+        Self {
+            heaps: Vec::new(),
+            occupancy: Vec::new(),
+            generations: Vec::new(),
+            next_allocation_generation: 0,
+            metrics: Cell::default(),
+            last_reset: 0,
+        }
     }
 }
 
@@ -58,210 +125,3131 @@ impl<A> Default for SizePool<A> {
 /// of size 1 to 4,096 bytes (exclusive). Another way of thinking about this is that it contains
 /// heaps and allocators from size classes 0 to 11 (inclusive).
 #[derive(Debug)]
-struct SizePool<A>(Vec<(Heap, A)>);
+struct SizePool<A, B: GpuBacking = Wgpu> {
+    /// The heaps and allocators in this pool, in order of creation.
+    heaps: Vec<(B::Heap, A)>,
+    /// The live allocations in each heap, in the same order as [`Self::heaps`].
+    occupancy: Vec<HeapOccupancy>,
+    /// The generation of each slot that has ever held a heap, which is bumped whenever the heap
+    /// in the slot is destroyed or replaced so that [`ArenaKey`]s made for it go stale.
+    ///
+    /// This is never shorter than [`Self::heaps`], and never shrinks.
+    generations: Vec<u32>,
+    /// The generation of the next allocation in this pool (see [`Allocation::generation`]).
+    ///
+    /// This is not reset along with the pool, so that allocations made after a reset can be told
+    /// apart from those made before it.
+    next_allocation_generation: u32,
+    /// Cumulative counters for this pool.
+    ///
+    /// This is a [`Cell`] so that copies recorded through `&self` methods can be counted.
+    metrics: Cell<PoolMetrics>,
+    /// The epoch of the arena as of the last reset of this pool.
+    last_reset: u64,
+}
+
+impl<A, B: GpuBacking> SizePool<A, B> {
+    fn record(&self, f: impl FnOnce(&mut PoolMetrics)) {
+        update_cell(&self.metrics, f);
+    }
+
+    /// The key of the heap at `index_in_pool`, as of its current generation.
+    fn key(&self, size_class: usize, index_in_pool: usize) -> ArenaKey {
+        ArenaKey { size_class, index_in_pool, generation: self.generations[index_in_pool] }
+    }
+
+    /// The position of the heap at `key`.
+    ///
+    /// This fails with [`AllocError::StaleKey`] if the heap that `key` was made for has since been
+    /// destroyed or moved, or with [`AllocError::NotOwnedByAllocator`] if this pool never had a
+    /// heap at its position.
+    fn index_of(&self, key: ArenaKey) -> Result<usize, AllocError> {
+        match self.generations.get(key.index_in_pool) {
+            Some(&generation) if generation != key.generation => Err(AllocError::StaleKey),
+            _ if key.index_in_pool < self.heaps.len() => Ok(key.index_in_pool),
+            _ => Err(AllocError::NotOwnedByAllocator),
+        }
+    }
+
+    /// Marks the slot at `index_in_pool` as holding a different heap, or none at all.
+    fn bump_generation(&mut self, index_in_pool: usize) {
+        let generation = &mut self.generations[index_in_pool];
+        *generation = generation.wrapping_add(1);
+    }
+
+    /// Records that `range` was allocated in the heap at `index_in_pool`, returning the generation
+    /// of the new allocation.
+    fn record_alloc(&mut self, index_in_pool: usize, range: Range<BufferAddress>) -> u32 {
+        let generation = self.next_allocation_generation;
+        self.next_allocation_generation = generation.wrapping_add(1);
+        self.record_alloc_with_generation(index_in_pool, range, generation);
+
+        generation
+    }
+
+    /// Like [`Self::record_alloc`], but with the generation given instead of taken from
+    /// [`Self::next_allocation_generation`].
+    fn record_alloc_with_generation(
+        &mut self,
+        index_in_pool: usize,
+        range: Range<BufferAddress>,
+        generation: u32,
+    ) {
+        let size = range.end - range.start;
+        let occupancy = &mut self.occupancy[index_in_pool];
+        occupancy.ranges.insert(range.start, range.end);
+        occupancy.generations.insert(range.start, generation);
+        occupancy.bytes += size;
+        occupancy.high_water_mark = occupancy.high_water_mark.max(occupancy.bytes);
+        self.record(|metrics| metrics.bytes_allocated += size);
+    }
+
+    /// The generation of the live allocation that starts at `start` in the heap at
+    /// `index_in_pool`.
+    fn generation_at(&self, index_in_pool: usize, start: BufferAddress) -> u32 {
+        self.occupancy[index_in_pool].generations[&start]
+    }
+
+    /// Records that `range` was freed in the heap at `index_in_pool`, returning its tag, if any.
+    fn record_dealloc(
+        &mut self,
+        index_in_pool: usize,
+        range: Range<BufferAddress>,
+    ) -> Option<&'static str> {
+        let size = range.end - range.start;
+        let occupancy = &mut self.occupancy[index_in_pool];
+        occupancy.ranges.remove(&range.start);
+        occupancy.generations.remove(&range.start);
+        let tag = occupancy.tags.remove(&range.start);
+        occupancy.bytes -= size;
+        self.record(|metrics| metrics.bytes_freed += size);
+
+        tag
+    }
+}
+
+impl<A: Allocator, B: GpuBacking> SizePool<A, B> {
+    /// Frees every allocation in this pool at once, as of the arena epoch `epoch`.
+    fn reset(&mut self, epoch: u64) {
+        for (heap, allocator) in self.heaps.iter_mut() {
+            if !allocator.reset() {
+                *allocator = A::new(heap);
+            }
+        }
+        for occupancy in self.occupancy.iter_mut() {
+            // The high-water mark is kept, as it describes the history of the heap.
+            occupancy.ranges.clear();
+            occupancy.generations.clear();
+            occupancy.tags.clear();
+            occupancy.bytes = 0;
+        }
+        self.record(|metrics| metrics.bytes_freed = metrics.bytes_allocated);
+        self.last_reset = epoch;
+    }
+}
+
+/// The live allocations of a single heap in a [`SizePool`].
+#[derive(Clone, Debug, Default)]
+struct HeapOccupancy {
+    /// The range of every live allocation, as a map from start to end.
+    ranges: BTreeMap<BufferAddress, BufferAddress>,
+    /// The total size, in bytes, of the live allocations.
+    bytes: BufferAddress,
+    /// The most bytes that were ever live at once.
+    high_water_mark: BufferAddress,
+    /// The tag of every live allocation made with one, keyed by start.
+    tags: BTreeMap<BufferAddress, &'static str>,
+    /// The generation of every live allocation, keyed by start.
+    generations: BTreeMap<BufferAddress, u32>,
+}
+
+impl HeapOccupancy {
+    /// The number of bytes not covered by any live allocation in a heap of `heap_size` bytes.
+    fn free_bytes(&self, heap_size: NonZeroBufferAddress) -> BufferAddress {
+        heap_size.get() - self.bytes
+    }
+}
+
+/// The pool for `size_class` among `tiny_pool` and `size_pools`, which are the fields of a
+/// [`HeapArena`]. Any missing pools up to and including it are created.
+fn pool_or_insert<'a, A, B: GpuBacking>(
+    tiny_pool: &'a mut SizePool<A, B>,
+    size_pools: &'a mut Vec<SizePool<A, B>>,
+    size_class: usize,
+) -> &'a mut SizePool<A, B> {
+    let Some(index) = sizes::pool_index(size_class) else {
+        return tiny_pool;
+    };
+    if size_pools.len() <= index {
+        size_pools.resize_with(index + 1, SizePool::default);
+    }
+
+    &mut size_pools[index]
+}
+
+fn update_cell<T: Copy>(cell: &Cell<T>, f: impl FnOnce(&mut T)) {
+    let mut value = cell.get();
+    f(&mut value);
+    cell.set(value);
+}
 
 impl<A> HeapArena<A> {
     /// Creates a new `HeapArena`.
     ///
-    /// The closure `calc_new_heap_size` largely determines the performance characteristics of the
-    /// returned arena. In general, to increase performance&mdash;by decreasing the number of
-    /// buffer allocations&mdash;at the cost of increased memory usage, one should return larger
-    /// heap sizes. Conversely, to save memory at the cost of decreased performance, one should
-    /// return heap sizes equal to, or slightly greater than, the initial requested in-heap
-    /// allocation size.
+    /// The `growth_policy` largely determines the performance characteristics of the returned
+    /// arena. In general, to increase performance&mdash;by decreasing the number of buffer
+    /// allocations&mdash;at the cost of increased memory usage, it should produce larger heap
+    /// sizes. Conversely, to save memory at the cost of decreased performance, it should produce
+    /// heap sizes equal to, or slightly greater than, the initial requested in-heap allocation
+    /// size. See the [`growth`](crate::growth) module for the built-in policies.
     pub fn new(
         usage: HeapUsages,
-        calc_new_heap_size: CalculateNewHeapSize,
+        growth_policy: impl GrowthPolicy + Send + 'static,
+    ) -> Self {
+        Self::with_backing(usage, growth_policy)
+    }
+
+    /// Creates a new `HeapArena` whose allocations can be bound at dynamic offsets on a device
+    /// with `limits`.
+    ///
+    /// This is like [`Self::new`], but with a minimum alignment (see [`Self::set_min_alignment`])
+    /// of [`wgpu::Limits::min_uniform_buffer_offset_alignment`] if `usage` contains
+    /// [`HeapUsages::UNIFORM`], [`wgpu::Limits::min_storage_buffer_offset_alignment`] if it
+    /// contains [`HeapUsages::STORAGE`], or the larger of the two if it contains both.
+    pub fn with_limits(
+        usage: HeapUsages,
+        growth_policy: impl GrowthPolicy + Send + 'static,
+        limits: &wgpu::Limits,
+    ) -> Self {
+        let mut arena = Self::new(usage, growth_policy);
+        arena.set_min_alignment(min_binding_alignment(usage, limits));
+
+        arena
+    }
+
+    /// Reads back the guard bands of every allocation that has them and reports each band that
+    /// no longer holds only canaries, ordered by heap and then by offset.
+    ///
+    /// Canaries reach the GPU buffer only once flushed, so every allocation made since the last
+    /// flush must be flushed, and the flush submitted, beforehand. This blocks until the GPU is
+    /// idle. See the [`guard`] module.
+    pub fn verify_canaries(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<CanaryViolation> {
+        // Each heap is read back up to the end of its last guarded allocation, which is a multiple
+        // of `COPY_BUFFER_ALIGNMENT` as guarded allocations begin and end on one.
+        let mut read_ends: BTreeMap<ArenaKey, BufferAddress> = BTreeMap::new();
+        for (arena_key, _, guarded) in self.guard_bands.armed() {
+            let end = read_ends.entry(arena_key).or_default();
+            *end = (*end).max(guarded.padded_end);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("wgpu-allocators canary readback"),
+        });
+        let readbacks: BTreeMap<ArenaKey, wgpu::Buffer> = read_ends
+            .into_iter()
+            .map(|(arena_key, end)| {
+                let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("wgpu-allocators canary readback"),
+                    size: end,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                });
+                let (heap, _) = &self[arena_key];
+                encoder.copy_buffer_to_buffer(&heap.gpu_buffer, 0, &readback_buffer, 0, end);
+
+                (arena_key, readback_buffer)
+            })
+            .collect();
+        queue.submit(Some(encoder.finish()));
+        for readback_buffer in readbacks.values() {
+            readback_buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        }
+        device.poll(wgpu::Maintain::Wait);
+
+        let mut violations = Vec::new();
+        for (arena_key, readback_buffer) in readbacks.iter() {
+            let contents = readback_buffer.slice(..).get_mapped_range();
+            let guarded = self.guard_bands.armed().filter(|(key, _, _)| key == arena_key);
+            for (_, padded_start, guarded) in guarded {
+                for (side, band) in guarded.bands(padded_start) {
+                    // Note: these casts can't truncate as the readback buffer was mapped.
+                    let bytes = &contents[(band.start as usize)..(band.end as usize)];
+                    let mut corrupted = (band.start..band.end)
+                        .zip(bytes)
+                        .filter(|&(_, &byte)| byte != CANARY)
+                        .map(|(offset, _)| offset);
+                    if let Some(first_corrupted) = corrupted.next() {
+                        let index_in_pool = arena_key.index_in_pool;
+                        let pool = self.pool(arena_key.size_class);
+                        violations.push(CanaryViolation {
+                            allocation: Allocation {
+                                arena_key: *arena_key,
+                                range_in_heap: guarded.range_in_heap.clone(),
+                                generation: pool.generation_at(index_in_pool, padded_start),
+                            },
+                            side,
+                            first_corrupted,
+                            corrupted_bytes: 1 + corrupted.count() as u64,
+                        });
+                    }
+                }
+            }
+            drop(contents);
+            readback_buffer.unmap();
+        }
+
+        violations
+    }
+}
+
+impl<A, B: GpuBacking> HeapArena<A, B> {
+    /// Creates a new `HeapArena` whose heaps are created through `B`.
+    ///
+    /// See [`HeapArena::new`] for the role of `growth_policy`.
+    pub fn with_backing(
+        usage: HeapUsages,
+        growth_policy: impl GrowthPolicy + Send + 'static,
     ) -> Self {
         Self {
             tiny_pool: SizePool::default(),
             size_pools: Vec::new(),
             usage,
-            calc_new_heap_size,
+            growth_policy: BoxedGrowthPolicy(Box::new(growth_policy)),
+            frame_counters: Cell::default(),
+            upload_policy: UploadPolicy::default(),
+            upload_strategy: UploadStrategy::default(),
+            reserved_bytes: 0,
+            heap_observer: None,
+            allocation_observer: None,
+            budget: None,
+            eviction_handler: None,
+            deterministic: false,
+            pending_uploads: Vec::new(),
+            frame: 0,
+            aging: None,
+            hazards: None,
+            empty_heap_policy: EmptyHeapPolicy::default(),
+            heap_growth: HeapGrowth::default(),
+            min_heap_size: None,
+            tiny_alloc_policy: TinyAllocPolicy::default(),
+            heap_label_prefix: None,
+            min_alignment: NonZeroBufferAddress::MIN,
+            guard_size: None,
+            guard_bands: GuardBands::default(),
+            retiring: Vec::new(),
+            preemption_policy: PreemptionPolicy::default(),
+            evictable: Vec::new(),
+            displaced: Vec::new(),
+            released: Arc::default(),
+            compaction: None,
+            epoch: 0,
+            watermarks: Watermarks::default(),
+            #[cfg(feature = "track-allocs")]
+            tracker: AllocTracker::default(),
+        }
+    }
+
+    /// Installs a callback that is invoked whenever this arena creates or destroys a heap,
+    /// replacing any previous one.
+    ///
+    /// This is useful for logging and telemetry, and for asserting in tests that no heaps are
+    /// created after some warm-up period.
+    pub fn set_heap_observer(&mut self, observer: impl FnMut(&HeapEvent) + Send + 'static) {
+        self.heap_observer = Some(HeapObserver(Box::new(observer)));
+    }
+
+    /// Removes the callback installed by [`Self::set_heap_observer`], if any.
+    pub fn clear_heap_observer(&mut self) {
+        self.heap_observer = None;
+    }
+
+    /// The total size, in bytes, of every heap in this arena.
+    pub fn reserved_bytes(&self) -> BufferAddress {
+        self.reserved_bytes
+    }
+
+    /// Limits the total size of the heaps of this arena to `max_bytes`; see [`Self::set_budget`].
+    pub fn with_budget(mut self, max_bytes: BufferAddress) -> Self {
+        self.set_budget(Some(max_bytes));
+
+        self
+    }
+
+    /// The most bytes that the heaps of this arena may reserve, if limited.
+    pub fn budget(&self) -> Option<BufferAddress> {
+        self.budget
+    }
+
+    /// Limits the total size of the heaps of this arena to `budget` bytes, or lifts the limit if
+    /// `budget` is `None`.
+    ///
+    /// Once the limit is reached, allocations that would need a new heap fail with
+    /// [`AllocError::BudgetExceeded`] after giving the eviction handler, if any, a chance to make
+    /// room (see [`Self::set_eviction_handler`]). Heaps that already exist are left alone even if
+    /// they exceed a new budget.
+    pub fn set_budget(&mut self, budget: Option<BufferAddress>) {
+        self.budget = budget;
+        self.check_watermarks();
+    }
+
+    /// The number of bytes by which the heaps of this arena may grow before exceeding its budget,
+    /// if it has one.
+    fn budget_headroom(&self) -> Option<BufferAddress> {
+        self.budget.map(|budget| budget.saturating_sub(self.reserved_bytes))
+    }
+
+    /// The total size, in bytes, of the live allocations of this arena, including their alignment
+    /// padding and guard bands.
+    pub fn allocated_bytes(&self) -> BufferAddress {
+        std::iter::once(&self.tiny_pool)
+            .chain(self.size_pools.iter())
+            .flat_map(|pool| pool.occupancy.iter())
+            .map(|occupancy| occupancy.bytes)
+            .sum()
+    }
+
+    /// The number of bytes that the usage of this arena is measured against: its budget if it has
+    /// one, or else the memory reserved by its heaps.
+    fn usage_capacity(&self) -> BufferAddress {
+        self.budget.unwrap_or(self.reserved_bytes)
+    }
+
+    /// Registers a callback that is invoked whenever the usage of this arena rises above
+    /// `threshold`, such as 0.9 for 90%.
+    ///
+    /// The callback is not invoked again until usage has fallen back to `threshold` or below, and
+    /// is not invoked at all if usage is already above `threshold` until that has happened. It
+    /// can't access the arena, so it typically signals the code that owns the arena to start
+    /// evicting. See the [`watermark`] module for how usage is measured.
+    pub fn on_usage_above(
+        &mut self,
+        threshold: f64,
+        callback: impl FnMut(&WatermarkEvent) + Send + 'static,
+    ) -> WatermarkId {
+        let usage = watermark::usage(self.allocated_bytes(), self.usage_capacity());
+
+        self.watermarks.insert(threshold, Crossing::Rising, Box::new(callback), usage)
+    }
+
+    /// Registers a callback that is invoked whenever the usage of this arena falls from above
+    /// `threshold` to `threshold` or below.
+    ///
+    /// This is the counterpart of [`Self::on_usage_above`], typically used to stop evicting.
+    pub fn on_usage_below(
+        &mut self,
+        threshold: f64,
+        callback: impl FnMut(&WatermarkEvent) + Send + 'static,
+    ) -> WatermarkId {
+        let usage = watermark::usage(self.allocated_bytes(), self.usage_capacity());
+
+        self.watermarks.insert(threshold, Crossing::Falling, Box::new(callback), usage)
+    }
+
+    /// Removes the callback registered as `id`, returning whether there was one.
+    pub fn remove_watermark(&mut self, id: WatermarkId) -> bool {
+        self.watermarks.remove(id)
+    }
+
+    /// Invokes the callbacks of the watermarks crossed since this was last called.
+    fn check_watermarks(&mut self) {
+        if self.watermarks.is_empty() {
+            return;
+        }
+
+        let allocated_bytes = self.allocated_bytes();
+        let capacity = self.usage_capacity();
+        self.watermarks.update(allocated_bytes, capacity);
+    }
+
+    /// Installs a callback that is invoked whenever an allocation would exceed the budget of this
+    /// arena, replacing any previous one.
+    ///
+    /// The callback receives the arena and the size of the heap that could not be created. It may
+    /// free allocations (such as those of cached data that can be recreated later) and return
+    /// `true` for the allocation to be tried again, or return `false` to give up. It is called
+    /// repeatedly until the allocation succeeds or it gives up, so it must eventually return
+    /// `false` once there is nothing left to evict.
+    ///
+    /// Freed memory can only be reused by later allocations of the same size class, unless the
+    /// [`EmptyHeapPolicy`] releases emptied heaps.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this arena is deterministic (see [`Self::set_deterministic`]).
+    pub fn set_eviction_handler(
+        &mut self,
+        handler: impl FnMut(&mut Self, NonZeroBufferAddress) -> bool + Send + 'static,
+    ) {
+        assert!(!self.deterministic, "deterministic arenas can't have an eviction handler");
+        self.eviction_handler = Some(EvictionHandler(Box::new(handler)));
+    }
+
+    /// Removes the callback installed by [`Self::set_eviction_handler`], if any.
+    pub fn clear_eviction_handler(&mut self) {
+        self.eviction_handler = None;
+    }
+
+    /// Whether placement in this arena depends only on the operations performed on it, as set by
+    /// [`Self::set_deterministic`].
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Makes placement in this arena depend only on the operations performed on it, or lets it
+    /// depend on the rest of the process again, as described under
+    /// [Determinism](Self#determinism).
+    ///
+    /// While deterministic, new heaps are never enlarged under the consolidation pressure of the
+    /// [`governor`], and no eviction handler can be installed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `deterministic` is `true` and an eviction handler is installed.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        assert!(
+            !deterministic || self.eviction_handler.is_none(),
+            "deterministic arenas can't have an eviction handler",
+        );
+        self.deterministic = deterministic;
+    }
+
+    fn notify_heap_event(&mut self, kind: HeapEventKind, size: NonZeroBufferAddress) {
+        match kind {
+            HeapEventKind::Created => self.reserved_bytes += size.get(),
+            HeapEventKind::Destroyed => self.reserved_bytes -= size.get(),
+            HeapEventKind::Grown { previous_size } => {
+                self.reserved_bytes += size.get() - previous_size.get();
+            }
+        }
+
+        let event = HeapEvent {
+            kind,
+            size,
+            size_class: classify_size(size),
+            total_reserved: self.reserved_bytes,
+        };
+        if let Some(HeapObserver(observer)) = self.heap_observer.as_mut() {
+            observer(&event);
+        }
+        self.observe(|observer| observer.heap_event(&event));
+    }
+
+    /// Installs an observer of the lifecycle of the heaps and allocations of this arena,
+    /// replacing any previous one.
+    ///
+    /// See the [`observer`](crate::observer) module.
+    pub fn set_allocation_observer(&mut self, observer: impl AllocationObserver + Send + 'static) {
+        self.allocation_observer = Some(BoxedAllocationObserver(RefCell::new(Box::new(observer))));
+    }
+
+    /// Removes the observer installed by [`Self::set_allocation_observer`], if any.
+    pub fn clear_allocation_observer(&mut self) {
+        self.allocation_observer = None;
+    }
+
+    /// Passes the observer installed by [`Self::set_allocation_observer`], if any, to `f`, as well
+    /// as the logger if the `log` feature is enabled.
+    fn observe(&self, f: impl Fn(&mut dyn AllocationObserver)) {
+        #[cfg(feature = "log")]
+        f(&mut LogObserver);
+        if let Some(BoxedAllocationObserver(observer)) = self.allocation_observer.as_ref() {
+            f(&mut **observer.borrow_mut());
+        }
+    }
+
+    /// The policy that decides what happens to heaps emptied by [`Self::dealloc`].
+    pub fn empty_heap_policy(&self) -> EmptyHeapPolicy {
+        self.empty_heap_policy
+    }
+
+    /// Replaces the policy that decides what happens to heaps emptied by [`Self::dealloc`].
+    ///
+    /// This only affects subsequent deallocations; heaps that are already empty are left alone.
+    pub fn set_empty_heap_policy(&mut self, policy: EmptyHeapPolicy) {
+        self.empty_heap_policy = policy;
+    }
+
+    /// Whether [`Self::alloc_or_grow`] may grow heaps rather than create new ones.
+    pub fn heap_growth(&self) -> HeapGrowth {
+        self.heap_growth
+    }
+
+    /// Replaces the setting that decides whether [`Self::alloc_or_grow`] may grow heaps rather
+    /// than create new ones.
+    pub fn set_heap_growth(&mut self, growth: HeapGrowth) {
+        self.heap_growth = growth;
+    }
+
+    /// The least size, in bytes, of new heaps in this arena, if any.
+    pub fn min_heap_size(&self) -> Option<NonZeroBufferAddress> {
+        self.min_heap_size
+    }
+
+    /// Makes every heap created from now on at least `size` bytes, however small a heap the
+    /// growth policy asks for, or stops doing so if `size` is `None`.
+    ///
+    /// Heaps larger than the device allows are never created; see
+    /// [`AllocError::SizeTooLargeForArena`].
+    pub fn set_min_heap_size(&mut self, size: Option<NonZeroBufferAddress>) {
+        self.min_heap_size = size;
+    }
+
+    /// The policy that decides how new heaps for tiny allocations are sized.
+    pub fn tiny_alloc_policy(&self) -> TinyAllocPolicy {
+        self.tiny_alloc_policy
+    }
+
+    /// Replaces the policy that decides how new heaps for tiny allocations are sized.
+    ///
+    /// This only affects heaps created from now on.
+    pub fn set_tiny_alloc_policy(&mut self, policy: TinyAllocPolicy) {
+        self.tiny_alloc_policy = policy;
+    }
+
+    /// The prefix of the debug labels of new heaps in this arena, if any.
+    pub fn heap_label_prefix(&self) -> Option<&str> {
+        self.heap_label_prefix.as_deref()
+    }
+
+    /// Gives every heap created from now on a debug label that starts with `prefix`, so that its
+    /// buffers can be told apart in graphics debuggers such as RenderDoc and PIX.
+    ///
+    /// Heaps are labeled `{prefix}-pool{size_class}-heap{index}`, such as
+    /// `arena-uniform-pool12-heap3`, or `{prefix}-tiny-heap{index}` for the pool of tiny heaps.
+    /// See [`HeapDescriptor::label`] for how the labels of staging buffers are derived.
+    pub fn set_heap_label_prefix(&mut self, prefix: impl Into<String>) {
+        self.heap_label_prefix = Some(prefix.into());
+    }
+
+    /// The alignment that every allocation in this arena has at least.
+    pub fn min_alignment(&self) -> NonZeroBufferAddress {
+        self.min_alignment
+    }
+
+    /// Aligns every allocation made from now on to `alignment`, in addition to the alignment
+    /// requested for it.
+    ///
+    /// Allocations must be aligned to the device's offset alignment limit to be bound at a dynamic
+    /// offset; [`Self::with_limits`] sets this accordingly.
+    pub fn set_min_alignment(&mut self, alignment: NonZeroBufferAddress) {
+        self.min_alignment = alignment;
+    }
+
+    /// The least size, in bytes, of the guard bands around new allocations, if enabled.
+    pub fn guard_size(&self) -> Option<NonZeroBufferAddress> {
+        self.guard_size
+    }
+
+    /// Pads every allocation made from now on with guard bands of at least `guard_size` bytes on
+    /// either side, or stops padding them if `guard_size` is `None`.
+    ///
+    /// Guarded allocations are aligned to at least [`wgpu::COPY_BUFFER_ALIGNMENT`], and their
+    /// guard bands count as allocated in [`Self::stats`]. Allocations made earlier keep their
+    /// guard bands, or lack of them, until they are freed. See the [`guard`] module.
+    pub fn set_guard_size(&mut self, guard_size: Option<NonZeroBufferAddress>) {
+        self.guard_size = guard_size;
+    }
+
+    /// The usage of every heap in this arena.
+    pub fn usage(&self) -> HeapUsages {
+        self.usage
+    }
+
+    /// The policy that decides how [`Self::upload`] uploads data.
+    pub fn upload_policy(&self) -> UploadPolicy {
+        self.upload_policy
+    }
+
+    /// Replaces the policy that decides how [`Self::upload`] uploads data.
+    ///
+    /// This takes effect immediately, including for the remainder of the current frame.
+    pub fn set_upload_policy(&mut self, policy: UploadPolicy) {
+        self.upload_policy = policy;
+    }
+
+    /// The strategy by which new heaps in this arena upload data.
+    pub fn upload_strategy(&self) -> UploadStrategy {
+        self.upload_strategy
+    }
+
+    /// Replaces the strategy by which new heaps in this arena upload data.
+    ///
+    /// Existing heaps keep the strategy they were created with. With
+    /// [`UploadStrategy::QueueWrite`], allocations in new heaps must be written with
+    /// [`Self::upload`] rather than [`Self::write`].
+    pub fn set_upload_strategy(&mut self, strategy: UploadStrategy) {
+        self.upload_strategy = strategy;
+    }
+
+    /// Marks the start of a new frame, resetting the counters returned by
+    /// [`Self::frame_counters`].
+    pub fn begin_frame(&mut self) {
+        self.frame_counters.set(FrameCounters::default());
+        self.frame += 1;
+    }
+
+    /// The number of calls to [`Self::begin_frame`] so far.
+    pub fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    /// Starts tracking when each allocation is written and bound.
+    ///
+    /// Only allocations made after this is called are tracked. This has no effect if aging is
+    /// already enabled.
+    pub fn enable_aging(&mut self) {
+        self.aging.get_or_insert_with(RefCell::default);
+    }
+
+    /// Stops tracking allocation ages and forgets all ages tracked so far.
+    pub fn disable_aging(&mut self) {
+        self.aging = None;
+    }
+
+    /// Starts tracking which allocations each submission made through a [`ManagedQueue`] uses,
+    /// so that [`Self::dealloc`] can refuse to free them before the submission has finished.
+    ///
+    /// An allocation is used by a submission if it was flushed, or a slice or binding of it was
+    /// taken, since this arena was last passed to [`ManagedQueue::submit`]. This has no effect if
+    /// hazard tracking is already enabled.
+    ///
+    /// [`ManagedQueue`]: crate::ManagedQueue
+    /// [`ManagedQueue::submit`]: crate::ManagedQueue::submit
+    pub fn enable_hazard_tracking(&mut self) {
+        self.hazards.get_or_insert_with(RefCell::default);
+    }
+
+    /// Stops tracking the allocations used by submissions and forgets all uses tracked so far.
+    pub fn disable_hazard_tracking(&mut self) {
+        self.hazards = None;
+    }
+
+    /// The serial of the latest unfinished submission that uses `allocation`, or `None` if there
+    /// is none or hazard tracking is disabled.
+    pub fn busy_until(&self, allocation: &Allocation) -> Option<Serial> {
+        let mut hazards = self.hazards.as_ref()?.borrow_mut();
+
+        hazards.busy_until(allocation.arena_key, &allocation.range_in_heap)
+    }
+
+    fn record_use(&self, key: ArenaKey, range: Range<BufferAddress>) {
+        if let Some(hazards) = self.hazards.as_ref() {
+            hazards.borrow_mut().record(key, range);
+        }
+    }
+
+    /// Assigns `serial` to every use recorded since the last submission.
+    pub(crate) fn record_submission(&self, serial: Serial, completion: &Completion) {
+        if let Some(hazards) = self.hazards.as_ref() {
+            hazards.borrow_mut().submit(serial, completion);
         }
     }
+
+    /// Gives `allocation` a label to identify it by in [`Self::cold_allocations`].
+    ///
+    /// This has no effect if aging is disabled or `allocation` is not tracked.
+    pub fn set_label(&self, allocation: &Allocation, label: impl Into<String>) {
+        self.touch(allocation, |age, _| age.label = Some(label.into()));
+    }
+
+    /// The age of `allocation`, or `None` if aging is disabled or `allocation` is not tracked.
+    pub fn age(&self, allocation: &Allocation) -> Option<AllocationAge> {
+        let mut aging = self.aging.as_ref()?.borrow_mut();
+        let key = allocation.arena_key;
+
+        aging.get_mut(key, allocation.range_in_heap.clone()).map(|age| age.clone())
+    }
+
+    /// Stops tracking the age of `allocation`.
+    ///
+    /// This should be called when an allocation is freed through its [`Allocator`] so that it
+    /// doesn't linger in [`Self::cold_allocations`].
+    pub fn forget_age(&self, allocation: &Allocation) {
+        if let Some(aging) = self.aging.as_ref() {
+            let key = allocation.arena_key;
+            aging.borrow_mut().remove(key, allocation.range_in_heap.clone());
+        }
+    }
+
+    /// Reports every tracked allocation that has not been made, written, or bound for at least
+    /// `min_idle_frames` frames, ordered by heap and then by offset.
+    ///
+    /// The report is empty if aging is disabled.
+    pub fn cold_allocations(&self, min_idle_frames: Frame) -> Vec<ColdAllocation> {
+        self.aging
+            .as_ref()
+            .map_or_else(Vec::new, |aging| aging.borrow().cold(self.frame, min_idle_frames))
+    }
+
+    /// Gives `allocation` a tag to identify it by in [`Self::dump_live_allocations`].
+    ///
+    /// This has no effect if `allocation` is not live.
+    #[cfg(feature = "track-allocs")]
+    pub fn tag_allocation(&mut self, allocation: &Allocation, tag: impl Into<String>) {
+        self.tracker.tag(allocation, tag.into());
+    }
+
+    /// Reports every allocation that has been made but not freed, ordered by heap and then by
+    /// offset.
+    ///
+    /// Allocations queued by [`Self::dealloc_deferred`] or released by dropped
+    /// [`OwnedAllocation`]s are still live until they are actually freed.
+    #[cfg(feature = "track-allocs")]
+    pub fn dump_live_allocations(&self) -> Vec<&LiveAllocation> {
+        self.tracker.live().collect()
+    }
+
+    fn touch(&self, allocation: &Allocation, f: impl FnOnce(&mut AllocationAge, Frame)) {
+        if let Some(aging) = self.aging.as_ref() {
+            let key = allocation.arena_key;
+            if let Some(age) = aging.borrow_mut().get_mut(key, allocation.range_in_heap.clone()) {
+                f(age, self.frame);
+            }
+        }
+    }
+
+    fn record_written(&self, allocation: &Allocation) {
+        self.touch(allocation, |age, frame| age.last_written = Some(frame));
+    }
+
+    fn record_bound(&self, allocation: &Allocation) {
+        self.touch(allocation, |age, frame| age.last_bound = Some(frame));
+        self.record_use(allocation.arena_key, allocation.range_in_heap.clone());
+    }
+
+    /// The operations performed on this arena since the last call to [`Self::begin_frame`].
+    pub fn frame_counters(&self) -> FrameCounters {
+        self.frame_counters.get()
+    }
+
+    fn record_frame(&self, f: impl FnOnce(&mut FrameCounters)) {
+        update_cell(&self.frame_counters, f);
+    }
+}
+
+/// A collection of [`Heap`]s unified by a single allocation interface.
+///
+/// Heaps are created through the [`GpuBacking`] `B`, which is [`Wgpu`] unless given otherwise.
+/// Arenas of other backings can allocate and deallocate, but only arenas of [`Heap`]s can bind,
+/// write, and flush their allocations.
+///
+/// In particular, this collection is an *arena*&mdash;allocations can be returned with
+/// [`dealloc`](Self::dealloc), but heaps are only destroyed as permitted by the
+/// [`EmptyHeapPolicy`]. The remaining heaps are simultaneously deallocated when the arena itself
+/// is dropped.
+///
+/// # Determinism
+///
+/// Heaps within a pool are always searched in a fixed order, and no placement decision depends on
+/// hashing, pointer values, or the addresses that buffers happen to land at. Placement can still
+/// depend on more than the operations performed on the arena, though:
+///
+/// - The [`Allocator`] and the [`GrowthPolicy`] passed to [`HeapArena::new`] may not be
+///   deterministic themselves, although all of those provided by this crate are.
+/// - The callback installed with [`set_eviction_handler`](Self::set_eviction_handler) decides
+///   what to free, and may do so by timing or by hashing.
+/// - Once a [buffer ceiling](crate::governor::set_buffer_ceiling) is set, new heaps are enlarged
+///   (see [`governor::consolidation_factor`]) by how many buffers every arena in the process,
+///   on any thread, has.
+///
+/// An arena made deterministic with [`set_deterministic`](Self::set_deterministic) refuses an
+/// eviction handler and never enlarges heaps for the governor. Given the same sequence of
+/// operations and deterministic allocators and growth policies, two such arenas create the same
+/// heaps, in the same order, and return the same [`Allocation`]s, whatever the machine or the
+/// backend, so that a recorded sequence can be replayed and its layouts compared across machines.
+///
+/// Some operations can still fail for reasons outside of the arena, after which the sequences of
+/// two arenas differ: [`alloc`](Self::alloc) fails with [`AllocError::HeapCreationFailed`] once
+/// the buffer ceiling is reached, and, with hazard tracking, [`dealloc`](Self::dealloc) fails with
+/// [`AllocError::InUse`] depending on how far the GPU has come. A
+/// [`SharedHeapArena`](crate::SharedHeapArena) is never deterministic as a whole, as which of its
+/// shards serves an allocation depends on a hash of the ID of the calling thread and on which
+/// shards are locked at the time.
+#[derive(Debug)]
+pub struct HeapArena<A, B: GpuBacking = Wgpu> {
+    /// A [`SizePool`] for heaps and allocators of size 1 to 4,096 bytes (inclusive).
+    ///
+    /// This is separated from [`Self::size_pools`] as it seemed silly to allocate pools for size
+    /// classes of 0, 1, 2, etc., which represent very small heaps that should probably never be
+    /// created in practice.
+    tiny_pool: SizePool<A, B>,
+    /// The size pools of heaps and allocators that make up this arena's backing storage.
+    ///
+    /// See [`SizePool`] for details on how a size pool is laid out internally.
+    ///
+    /// This field orders pools from lowest to highest size class, beginning at 12. Therefore, index
+    /// 0 is for heaps of size 4,096 to 8,192 bytes (exclusive), index 1 is for heaps of size 8,192
+    /// to 16,384 bytes (exclusive), and so on.
+    size_pools: Vec<SizePool<A, B>>,
+    /// The usage for all heaps within this arena.
+    usage: HeapUsages,
+    /// Decides the size of each new heap.
+    growth_policy: BoxedGrowthPolicy,
+    /// The operations performed on this arena during the current frame.
+    frame_counters: Cell<FrameCounters>,
+    /// The policy that decides how [`Self::upload`] uploads data.
+    upload_policy: UploadPolicy,
+    /// The strategy by which new heaps upload data.
+    upload_strategy: UploadStrategy,
+    /// The total size, in bytes, of every heap in this arena.
+    reserved_bytes: BufferAddress,
+    /// The callback installed by [`Self::set_heap_observer`].
+    heap_observer: Option<HeapObserver>,
+    /// The observer installed by [`Self::set_allocation_observer`].
+    ///
+    /// This is a [`RefCell`] so that flushes through `&self` methods can be observed.
+    allocation_observer: Option<BoxedAllocationObserver>,
+    /// The most bytes that the heaps of this arena may reserve, set by [`Self::set_budget`].
+    budget: Option<BufferAddress>,
+    /// The callback installed by [`Self::set_eviction_handler`].
+    eviction_handler: Option<EvictionHandler<A, B>>,
+    /// Whether placement depends only on the operations performed on this arena, as set by
+    /// [`Self::set_deterministic`].
+    deterministic: bool,
+    /// Uploads deferred by [`Self::defer_upload`], from highest to lowest priority.
+    ///
+    /// Uploads of equal priority are kept in the order in which they were deferred.
+    pending_uploads: Vec<PendingUpload>,
+    /// The number of calls to [`Self::begin_frame`] so far.
+    frame: Frame,
+    /// The ages of allocations, if enabled with [`Self::enable_aging`].
+    ///
+    /// This is a [`RefCell`] so that slices and bindings taken through `&self` can be recorded.
+    aging: Option<RefCell<AgeTracker>>,
+    /// The allocations used by unfinished submissions, if enabled with
+    /// [`Self::enable_hazard_tracking`].
+    ///
+    /// This is a [`RefCell`] for the same reason as [`Self::aging`].
+    hazards: Option<RefCell<Hazards>>,
+    /// The policy that decides what happens to heaps emptied by [`Self::dealloc`].
+    empty_heap_policy: EmptyHeapPolicy,
+    /// Whether [`Self::alloc_or_grow`] may grow heaps.
+    heap_growth: HeapGrowth,
+    /// The least size, in bytes, of new heaps, set by [`Self::set_min_heap_size`].
+    min_heap_size: Option<NonZeroBufferAddress>,
+    /// How new heaps in [`Self::tiny_pool`] are sized.
+    tiny_alloc_policy: TinyAllocPolicy,
+    /// The prefix of the debug labels of new heaps, set by [`Self::set_heap_label_prefix`].
+    heap_label_prefix: Option<String>,
+    /// The alignment that every allocation has at least, set by [`Self::set_min_alignment`].
+    min_alignment: NonZeroBufferAddress,
+    /// The least size, in bytes, of the guard bands around new allocations, set by
+    /// [`Self::set_guard_size`].
+    guard_size: Option<NonZeroBufferAddress>,
+    /// Every live allocation with guard bands.
+    guard_bands: GuardBands,
+    /// Allocations queued by [`Self::dealloc_deferred`], with the fence after which they may be
+    /// freed by [`Self::retire_completed`].
+    retiring: Vec<(Serial, Allocation)>,
+    /// Whether [`Self::alloc_desc`] may free evictable allocations to stay within the budget.
+    preemption_policy: PreemptionPolicy,
+    /// Every live allocation made with [`AllocDesc::evictable`], with its priority, in the order
+    /// in which they were made.
+    evictable: Vec<(Priority, Allocation)>,
+    /// Allocations freed to make room for allocations of higher priority, to be collected by
+    /// [`Self::take_displaced`].
+    displaced: Vec<Displaced>,
+    /// Allocations whose [`OwnedAllocation`] has been dropped, to be freed by [`Self::reclaim`].
+    released: ReleaseQueue,
+    /// The progress of the compaction begun by [`Self::compact_incremental`], if it has yet to
+    /// finish.
+    compaction: Option<CompactionProgress>,
+    /// The number of calls to [`Self::reset_all`] and [`Self::reset_pool`] so far.
+    ///
+    /// Allocations released from an epoch before the last reset of their pool were already freed
+    /// by the reset, so they are ignored by [`Self::reclaim`].
+    epoch: u64,
+    /// The callbacks registered with [`Self::on_usage_above`] and [`Self::on_usage_below`].
+    watermarks: Watermarks,
+    /// Every live allocation, for catching invalid deallocations and finding leaks.
+    #[cfg(feature = "track-allocs")]
+    tracker: AllocTracker,
+}
+
+/// The allocations released by dropped [`OwnedAllocation`]s, tagged with the epoch of the arena at
+/// the time they were made.
+type ReleaseQueue = Arc<Mutex<Vec<(u64, Allocation)>>>;
+
+/// What a [`HeapArena`] does with a heap once [`HeapArena::dealloc`] leaves it without any live
+/// allocations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum EmptyHeapPolicy {
+    /// Keep every heap alive for reuse until the arena is dropped.
+    #[default]
+    Keep,
+    /// Destroy empty heaps at the end of their pool, returning their memory to the device.
+    ///
+    /// Empty heaps followed by a live heap in the same pool are kept, as removing them would
+    /// change the [`ArenaKey`] of every later heap. They are destroyed once they become trailing.
+    ReleaseTrailing,
+}
+
+/// How important an allocation is, for deciding which allocations may displace which once a
+/// [`HeapArena`] runs out of budget (see [`PreemptionPolicy`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Data that is cheap to recreate or merely prefetched, such as distant levels of detail.
+    Low,
+    #[default]
+    Normal,
+    /// Data that is needed right away, such as the visible level of detail of a streamed mesh.
+    High,
+}
+
+/// What [`HeapArena::alloc_desc`] does when an allocation would exceed the budget of the arena
+/// and the eviction handler, if any, has given up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PreemptionPolicy {
+    /// Fail with [`AllocError::BudgetExceeded`], whatever the priority of the allocation.
+    #[default]
+    Deny,
+    /// Free allocations made with [`AllocDesc::evictable`] of a lower [`Priority`] than the
+    /// allocation, lowest priority first and oldest first among equals, until it fits.
+    ///
+    /// Only allocations whose memory could be reused by the allocation are freed: those in the
+    /// same pool, or any if the [`EmptyHeapPolicy`] releases emptied heaps. Even so, an
+    /// allocation may be freed without making enough room. Allocations that can't be freed, such
+    /// as those still used by a submission (see [`HeapArena::enable_hazard_tracking`]), are
+    /// skipped.
+    Evict,
+}
+
+/// An allocation freed by [`HeapArena::alloc_desc`] to make room for one of higher priority, as
+/// returned by [`HeapArena::take_displaced`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Displaced {
+    /// The allocation, which is no longer live.
+    pub allocation: Allocation,
+    /// The priority that the allocation was made with.
+    pub priority: Priority,
+    /// The priority of the allocation that displaced it.
+    pub displaced_by: Priority,
+}
+
+/// Whether [`HeapArena::alloc_or_grow`] grows a heap when an allocation doesn't fit in any
+/// existing one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HeapGrowth {
+    /// Always create a new heap.
+    #[default]
+    Never,
+    /// Grow the most recently created heap of the pool, doubling its size or more as needed, as
+    /// long as it doesn't exceed the given size in bytes.
+    ///
+    /// A new heap is still created if the heap can't grow (see [`Heap::can_grow`]), its allocator
+    /// doesn't support growth (see [`Allocator::grow`]), or the allocation doesn't fit even once
+    /// grown.
+    UpTo(NonZeroBufferAddress),
+}
+
+/// How a [`HeapArena`] sizes new heaps for tiny allocations, those of size classes below
+/// [`sizes::FIRST_POOLED_SIZE_CLASS`], which all share the pool of tiny heaps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TinyAllocPolicy {
+    /// Size heaps for tiny allocations by the growth policy, like those of any other pool.
+    #[default]
+    Unbatched,
+    /// Pack tiny allocations into shared heaps of the given size in bytes, whatever size the
+    /// growth policy would give them.
+    ///
+    /// Growth policies that size heaps after the allocation that creates them otherwise create a
+    /// buffer per tiny allocation, such as per uniform of a UI widget. A new heap is still only
+    /// created once no existing tiny heap has room, and is never smaller than the allocation, nor
+    /// than [`HeapArena::min_heap_size`].
+    Batched { heap_size: NonZeroBufferAddress },
+}
+
+/// An upload deferred by [`HeapArena::defer_upload`].
+#[derive(Debug)]
+struct PendingUpload {
+    allocation: Allocation,
+    contents: Vec<u8>,
+    priority: u32,
+}
+
+/// Whether a [`HeapEvent`] is for the creation, destruction, or growth of a heap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HeapEventKind {
+    Created,
+    Destroyed,
+    /// The heap was grown by [`HeapArena::alloc_or_grow`] from the given size, in bytes, to
+    /// [`HeapEvent::size`].
+    Grown { previous_size: NonZeroBufferAddress },
+}
+
+/// Describes the creation, destruction, or growth of a heap by a [`HeapArena`].
+///
+/// Such an event is passed to the callback installed by [`HeapArena::set_heap_observer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapEvent {
+    pub kind: HeapEventKind,
+    /// The size, in bytes, of the heap.
+    pub size: NonZeroBufferAddress,
+    /// The size class of the heap, which is the position of the leftmost 1 bit in the binary
+    /// representation of [`Self::size`].
+    pub size_class: usize,
+    /// The total size, in bytes, of every heap in the arena after this event.
+    pub total_reserved: BufferAddress,
+}
+
+/// The callback installed by [`HeapArena::set_heap_observer`].
+struct HeapObserver(Box<dyn FnMut(&HeapEvent) + Send>);
+
+/// The observer installed by [`HeapArena::set_allocation_observer`].
+struct BoxedAllocationObserver(RefCell<Box<dyn AllocationObserver + Send>>);
+
+/// What a [`HeapArena`] needs to know to create a heap, gathered so that its pools can be
+/// borrowed alongside.
+struct NewHeapSettings {
+    usage: HeapUsages,
+    upload_strategy: UploadStrategy,
+    label_prefix: Option<String>,
+    budget: Option<BufferAddress>,
+    reserved_bytes: BufferAddress,
+    min_heap_size: Option<NonZeroBufferAddress>,
+    tiny_alloc_policy: TinyAllocPolicy,
+    /// Whether new heaps ignore the consolidation pressure of the [`governor`].
+    deterministic: bool,
+}
+
+/// Fails if creating a heap of `heap_size` bytes in an arena that has reserved `reserved_bytes`
+/// would exceed `budget`.
+fn check_budget(
+    budget: Option<BufferAddress>,
+    reserved_bytes: BufferAddress,
+    heap_size: NonZeroBufferAddress,
+) -> Result<(), AllocError> {
+    match budget {
+        Some(budget) if reserved_bytes + heap_size.get() > budget => {
+            Err(AllocError::BudgetExceeded { heap_size, budget })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The callback installed by [`HeapArena::set_eviction_handler`].
+#[allow(clippy::type_complexity)]
+struct EvictionHandler<A, B: GpuBacking = Wgpu>(
+    Box<dyn FnMut(&mut HeapArena<A, B>, NonZeroBufferAddress) -> bool + Send>,
+);
+
+impl<A, B: GpuBacking> std::fmt::Debug for EvictionHandler<A, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("EvictionHandler")
+    }
+}
+
+/// The [`GrowthPolicy`] of a [`HeapArena`].
+struct BoxedGrowthPolicy(Box<dyn GrowthPolicy + Send>);
+
+impl std::fmt::Debug for BoxedGrowthPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("GrowthPolicy")
+    }
+}
+
+impl std::fmt::Debug for BoxedAllocationObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("AllocationObserver")
+    }
+}
+
+impl std::fmt::Debug for HeapObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("HeapObserver")
+    }
+}
+
+impl<A, B: GpuBacking> Drop for HeapArena<A, B> {
+    fn drop(&mut self) {
+        if self.heap_observer.is_none()
+            && self.allocation_observer.is_none()
+            && !cfg!(feature = "log")
+        {
+            return;
+        }
+
+        let sizes: Vec<NonZeroBufferAddress> = std::iter::once(&self.tiny_pool)
+            .chain(self.size_pools.iter())
+            .flat_map(|pool| pool.heaps.iter().map(|(heap, _)| heap.size()))
+            .collect();
+        for size in sizes {
+            self.notify_heap_event(HeapEventKind::Destroyed, size);
+        }
+    }
+}
+
+impl<A: Allocator, B: GpuBacking> HeapArena<A, B> {
+    /// Frees every allocation in this arena at once, while keeping its heaps alive for reuse.
+    ///
+    /// Every allocator is reset with [`Allocator::reset`], or else replaced with a fresh one from
+    /// [`Allocator::new`], so all existing [`Allocation`]s become invalid; pending uploads and
+    /// tracked ages are discarded along with them. This is much cheaper than dropping the arena
+    /// and creating a new one, which would destroy and recreate every buffer.
+    pub fn reset_all(&mut self) {
+        self.epoch += 1;
+        for pool in std::iter::once(&mut self.tiny_pool).chain(self.size_pools.iter_mut()) {
+            pool.reset(self.epoch);
+        }
+        self.pending_uploads.clear();
+        if let Some(aging) = self.aging.as_mut() {
+            *aging.get_mut() = AgeTracker::default();
+        }
+        if let Some(hazards) = self.hazards.as_mut() {
+            *hazards.get_mut() = Hazards::default();
+        }
+        self.retiring.clear();
+        self.evictable.clear();
+        self.released.lock().unwrap().clear();
+        self.guard_bands.clear();
+        #[cfg(feature = "track-allocs")]
+        self.tracker.clear();
+        self.check_watermarks();
+    }
+
+    /// Frees every allocation in the pool of `size_class` at once, as [`Self::reset_all`] does
+    /// for the whole arena, while leaving the other pools alone.
+    ///
+    /// Size classes below 12 share the pool of tiny heaps, which is reset as a whole. Nothing
+    /// happens if there is no pool for `size_class` yet.
+    pub fn reset_pool(&mut self, size_class: usize) {
+        if sizes::pool_index(size_class).is_some_and(|index| self.size_pools.len() <= index) {
+            return;
+        }
+        self.epoch += 1;
+        let epoch = self.epoch;
+        self.pool_or_insert(size_class).reset(epoch);
+
+        let in_pool = |key: ArenaKey| sizes::share_pool(key.size_class, size_class);
+        self.pending_uploads.retain(|upload| !in_pool(upload.allocation.arena_key));
+        if let Some(aging) = self.aging.as_mut() {
+            aging.get_mut().retain_heaps(|key| !in_pool(key));
+        }
+        if let Some(hazards) = self.hazards.as_mut() {
+            hazards.get_mut().retain_heaps(|key| !in_pool(key));
+        }
+        self.retiring.retain(|(_, allocation)| !in_pool(allocation.arena_key));
+        self.evictable.retain(|(_, allocation)| !in_pool(allocation.arena_key));
+        self.released.lock().unwrap().retain(|(_, allocation)| !in_pool(allocation.arena_key));
+        self.guard_bands.retain_heaps(|key| !in_pool(key));
+        #[cfg(feature = "track-allocs")]
+        self.tracker.retain_heaps(|key| !in_pool(key));
+        self.check_watermarks();
+    }
+
+    /// Queues `allocation` to be freed once `fence` has completed, as reported to
+    /// [`Self::retire_completed`].
+    ///
+    /// `fence` is typically the [`Serial`] of the last submission that uses `allocation`, or the
+    /// index of the last frame that does. Until then, the memory of `allocation` is not reused,
+    /// so commands that are still executing can keep reading it.
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must have been returned by [`Self::alloc`] on this arena and must not have
+    /// been freed or queued to be freed already. It must not be used by any submission or frame
+    /// after `fence`.
+    pub unsafe fn dealloc_deferred(&mut self, allocation: Allocation, fence: Serial) {
+        self.retiring.push((fence, allocation));
+    }
+
+    /// Frees every allocation queued by [`Self::dealloc_deferred`] with a fence no greater than
+    /// `completed`, returning how many were freed.
+    ///
+    /// Allocations that can't be freed yet, such as those of a [`Stack`](crate::Stack) that are
+    /// not on top or those still used by a submission, stay queued until a later call.
+    pub fn retire_completed(&mut self, completed: Serial) -> usize {
+        let (mut due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retiring)
+            .into_iter()
+            .partition(|&(fence, _)| fence <= completed);
+        self.retiring = pending;
+
+        // Freeing one allocation may allow another to be freed, so keep going until no progress is
+        // made.
+        let mut retired = 0;
+        loop {
+            let count = due.len();
+            due.retain(|(_, allocation)| {
+                // SAFETY: Each allocation was queued exactly once by `dealloc_deferred`, whose
+                // caller guarantees that the GPU is done with it once its fence has completed.
+                unsafe { self.dealloc(allocation.clone()) }.is_err()
+            });
+            retired += count - due.len();
+            if due.len() == count {
+                break;
+            }
+        }
+        self.retiring.extend(due);
+
+        retired
+    }
+
+    /// The number of allocations queued by [`Self::dealloc_deferred`] that have not been freed
+    /// yet.
+    pub fn retiring_count(&self) -> usize {
+        self.retiring.len()
+    }
+
+    /// Like [`Self::alloc`], but returns an [`OwnedAllocation`] that returns itself to this arena
+    /// when dropped.
+    ///
+    /// Allocations released so far are reclaimed first, as with [`Self::reclaim`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`].
+    pub fn alloc_owned(
+        &mut self,
+        device: &B::Device,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<OwnedAllocation, AllocError> {
+        self.reclaim();
+
+        Ok(OwnedAllocation {
+            allocation: Some(self.alloc(device, size, alignment)?),
+            epoch: self.epoch,
+            released: Arc::clone(&self.released),
+        })
+    }
+
+    /// Frees every allocation whose [`OwnedAllocation`] has been dropped, returning how many were
+    /// freed.
+    ///
+    /// Dropping a handle only queues its allocation, as the arena can't be borrowed mutably from
+    /// [`Drop`]; the memory becomes available again once this is called. Allocations that the
+    /// allocator refuses to free, such as those of a [`Stack`](crate::Stack) that are not on top,
+    /// stay queued until a later call.
+    pub fn reclaim(&mut self) -> usize {
+        let mut queued = std::mem::take(&mut *self.released.lock().unwrap());
+        queued.retain(|(epoch, allocation)| {
+            *epoch >= self.pool(allocation.arena_key.size_class).last_reset
+        });
+
+        // Freeing one allocation may allow another to be freed, so keep going until no progress is
+        // made.
+        let mut reclaimed = 0;
+        loop {
+            let count = queued.len();
+            queued.retain(|(_, allocation)| {
+                // SAFETY: Each allocation was made by this arena since its pool was last reset, and
+                // was queued exactly once, when its owner was dropped.
+                unsafe { self.dealloc(allocation.clone()) }.is_err()
+            });
+            reclaimed += count - queued.len();
+            if queued.len() == count {
+                break;
+            }
+        }
+        self.released.lock().unwrap().extend(queued);
+
+        reclaimed
+    }
+
+    pub fn unmap(&self) {
+        for (heap, _) in self.tiny_pool.heaps.iter() {
+            B::unmap(heap);
+        }
+        for pool in self.size_pools.iter() {
+            for (heap, _) in pool.heaps.iter() {
+                B::unmap(heap);
+            }
+        }
+    }
+
+    /// Requests that the staging buffers of every heap in this arena be mapped for writing again.
+    ///
+    /// See [`GpuBacking::map`].
+    pub fn remap(&self) {
+        for (heap, _) in self.tiny_pool.heaps.iter() {
+            B::map(heap);
+        }
+        for pool in self.size_pools.iter() {
+            for (heap, _) in pool.heaps.iter() {
+                B::map(heap);
+            }
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `alignment`, creating a new heap if none of the existing
+    /// heaps of its size class can hold the allocation.
+    ///
+    /// # Errors
+    ///
+    /// Besides the errors of the allocator itself, this fails with
+    /// [`AllocError::SizeTooLargeForArena`] if the allocation doesn't fit in the heap that would be
+    /// created for it, or [`AllocError::HeapCreationFailed`] if that heap can't be created.
+    pub fn alloc(
+        &mut self,
+        device: &B::Device,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        loop {
+            let (error, heap_size) = match self.alloc_within_budget(device, size, alignment) {
+                Err(error @ AllocError::BudgetExceeded { heap_size, .. }) => (error, heap_size),
+                result => return result,
+            };
+            let Some(EvictionHandler(mut handler)) = self.eviction_handler.take() else {
+                return Err(error);
+            };
+            let retry = handler(self, heap_size);
+            // Note: the handler may have installed a replacement for itself, which is kept.
+            self.eviction_handler.get_or_insert(EvictionHandler(handler));
+            if !retry {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Like [`Self::alloc`], but the allocation is described by `desc`, and is accounted under
+    /// its tag, if any, in [`Self::stats`] until it is freed.
+    ///
+    /// With the `track-allocs` feature, the tag is also given to the allocation as if by
+    /// [`Self::tag_allocation`].
+    ///
+    /// If the allocation would exceed the budget even after the eviction handler has given up,
+    /// evictable allocations of lower priority may be freed to make room for it, as decided by the
+    /// [`PreemptionPolicy`]. Those are reported by [`Self::take_displaced`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`].
+    pub fn alloc_desc(
+        &mut self,
+        device: &B::Device,
+        desc: AllocDesc,
+    ) -> Result<Allocation, AllocError> {
+        let allocation = self.alloc_preempting(device, &desc)?;
+        if desc.evictable {
+            self.evictable.push((desc.priority, allocation.clone()));
+        }
+        if let Some(tag) = desc.tag {
+            let padded_start = self
+                .guard_bands
+                .padded_range(allocation.arena_key, &allocation.range_in_heap)
+                .map_or(allocation.range_in_heap.start, |padded_range| padded_range.start);
+            let key = allocation.arena_key;
+            // Note: the allocation was just made, so its pool exists.
+            let pool = self.get_pool_mut(key.size_class).unwrap();
+            pool.occupancy[key.index_in_pool].tags.insert(padded_start, tag);
+            #[cfg(feature = "track-allocs")]
+            self.tracker.tag(&allocation, tag.to_owned());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Like [`Self::alloc`], but frees evictable allocations of lower priority than `desc` if the
+    /// budget is exceeded, as decided by the [`PreemptionPolicy`].
+    fn alloc_preempting(
+        &mut self,
+        device: &B::Device,
+        desc: &AllocDesc,
+    ) -> Result<Allocation, AllocError> {
+        let mut result = self.alloc(device, desc.size, desc.alignment);
+        if self.preemption_policy == PreemptionPolicy::Deny {
+            return result;
+        }
+
+        // Note: freed memory can only be reused by allocations of the same pool, unless emptied
+        // heaps are released, as in `CachedArena`.
+        let alignment = combine_alignments(desc.alignment, self.min_alignment);
+        let (padded_size, _, _) = self.padded_request(desc.size, alignment);
+        let size_class = classify_size(padded_size);
+        let releases_heaps = self.empty_heap_policy == EmptyHeapPolicy::ReleaseTrailing;
+        let mut victims: Vec<(Priority, Allocation)> = self
+            .evictable
+            .iter()
+            .filter(|(priority, allocation)| {
+                *priority < desc.priority
+                    && (releases_heaps
+                        || sizes::share_pool(allocation.arena_key.size_class, size_class))
+            })
+            .cloned()
+            .collect();
+        // Note: the sort is stable, so older allocations of equal priority come first.
+        victims.sort_by_key(|(priority, _)| *priority);
+
+        let mut victims = victims.into_iter();
+        while matches!(result, Err(AllocError::BudgetExceeded { .. })) {
+            let Some((priority, allocation)) = victims.next() else {
+                break;
+            };
+            // SAFETY: The caller of `set_preemption_policy` guarantees that evictable allocations
+            // may be freed whenever an allocation is made.
+            if unsafe { self.dealloc(allocation.clone()) }.is_err() {
+                continue;
+            }
+            self.displaced.push(Displaced { allocation, priority, displaced_by: desc.priority });
+            result = self.alloc_within_budget(device, desc.size, desc.alignment);
+        }
+
+        result
+    }
+
+    /// The policy that decides whether [`Self::alloc_desc`] may free evictable allocations.
+    pub fn preemption_policy(&self) -> PreemptionPolicy {
+        self.preemption_policy
+    }
+
+    /// Replaces the policy that decides whether [`Self::alloc_desc`] may free evictable
+    /// allocations.
+    ///
+    /// # Safety
+    ///
+    /// With [`PreemptionPolicy::Evict`], every allocation made with [`AllocDesc::evictable`] may
+    /// be freed by any later call to [`Self::alloc_desc`], so it must no longer be in use by the
+    /// GPU whenever that is called, as with [`Self::dealloc`]. Enabling hazard tracking keeps
+    /// allocations used by unfinished submissions from being freed.
+    pub unsafe fn set_preemption_policy(&mut self, policy: PreemptionPolicy) {
+        self.preemption_policy = policy;
+    }
+
+    /// Takes the allocations freed so far to make room for allocations of higher priority.
+    ///
+    /// Their owners must stop using them, and may recreate their contents once there is room.
+    pub fn take_displaced(&mut self) -> Vec<Displaced> {
+        std::mem::take(&mut self.displaced)
+    }
+
+    /// Like [`Self::alloc`], but without invoking the eviction handler.
+    fn alloc_within_budget(
+        &mut self,
+        device: &B::Device,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        let alignment = combine_alignments(alignment, self.min_alignment);
+        let (padded_size, alignment, front) = self.padded_request(size, alignment);
+        let size_class = classify_size(padded_size);
+        let settings = self.new_heap_settings();
+        // Note: the pool is borrowed field by field so that the growth policy can be borrowed
+        // alongside it.
+        let pool = pool_or_insert(&mut self.tiny_pool, &mut self.size_pools, size_class);
+
+        let heap_count = pool.heaps.len();
+        let allocation = Self::alloc_in_pool(
+            device,
+            pool,
+            padded_size,
+            size_class,
+            alignment,
+            &settings,
+            &*self.growth_policy.0,
+        );
+        let new_heap_size = pool.heaps[heap_count..].last().map(|(heap, _)| heap.size());
+
+        if let Some(new_heap_size) = new_heap_size {
+            self.notify_heap_event(HeapEventKind::Created, new_heap_size);
+        }
+        let allocation = self.strip_guard_bands(allocation?, size, front);
+        self.record_alloc(&allocation);
+
+        Ok(allocation)
+    }
+
+    /// The settings of the heaps that this arena would create now.
+    fn new_heap_settings(&self) -> NewHeapSettings {
+        NewHeapSettings {
+            usage: self.usage,
+            upload_strategy: self.upload_strategy,
+            label_prefix: self.heap_label_prefix.clone(),
+            budget: self.budget,
+            reserved_bytes: self.reserved_bytes,
+            min_heap_size: self.min_heap_size,
+            tiny_alloc_policy: self.tiny_alloc_policy,
+            deterministic: self.deterministic,
+        }
+    }
+
+    /// Records that `allocation` was made.
+    fn record_alloc(&mut self, allocation: &Allocation) {
+        self.record_frame(|counters| counters.allocations += 1);
+        if let Some(aging) = self.aging.as_mut() {
+            let key = allocation.arena_key;
+            aging.get_mut().insert(key, allocation.range_in_heap.clone(), self.frame);
+        }
+        #[cfg(feature = "track-allocs")]
+        self.tracker.insert(allocation);
+        self.observe(|observer| observer.allocated(allocation));
+        self.check_watermarks();
+    }
+
+    /// The size and alignment to request of an allocator for an allocation of `size` bytes
+    /// aligned to `alignment`, along with the size of its front guard band if guard bands are
+    /// enabled.
+    fn padded_request(
+        &self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> (NonZeroBufferAddress, NonZeroBufferAddress, Option<BufferAddress>) {
+        let Some(guard_size) = self.guard_size else {
+            return (size, alignment, None);
+        };
+        let copy_alignment = NonZeroBufferAddress::new(wgpu::COPY_BUFFER_ALIGNMENT).unwrap();
+        let alignment = combine_alignments(alignment, copy_alignment);
+        let (front, back) = guard::padding(size.get(), alignment.get(), guard_size.get());
+
+        (size.saturating_add(front + back), alignment, Some(front))
+    }
+
+    /// The allocation of `size` bytes within `padded`, an allocation requested as described by
+    /// [`Self::padded_request`] whose front guard band is `front` bytes, if any.
+    ///
+    /// Canaries are written into both guard bands if the heap has mapped staging memory.
+    fn strip_guard_bands(
+        &mut self,
+        padded: Allocation,
+        size: NonZeroBufferAddress,
+        front: Option<BufferAddress>,
+    ) -> Allocation {
+        let Some(front) = front else {
+            return padded;
+        };
+        let Allocation { arena_key, range_in_heap: padded_range, generation } = padded;
+        let start = padded_range.start + front;
+        let mut guarded = Guarded {
+            padded_end: padded_range.end,
+            range_in_heap: start..(start + size.get()),
+            armed: false,
+        };
+
+        let (heap, _) = &self[arena_key];
+        guarded.armed = guarded.bands(padded_range.start).into_iter().all(|(_, band)| {
+            let canaries = vec![CANARY; (band.end - band.start) as usize];
+            B::write(heap, band, &canaries).is_ok()
+        });
+        let range_in_heap = guarded.range_in_heap.clone();
+        self.guard_bands.insert(arena_key, padded_range.start, guarded);
+
+        Allocation { arena_key, range_in_heap, generation }
+    }
+
+    /// The pool for `size_class`, which is created if it doesn't exist yet.
+    fn pool_or_insert(&mut self, size_class: usize) -> &mut SizePool<A, B> {
+        pool_or_insert(&mut self.tiny_pool, &mut self.size_pools, size_class)
+    }
+
+    /// Allocates space for an array of `count` values of type `T`, with the size and alignment
+    /// given by [`ArrayLayout::of`] for the usage of this arena.
+    ///
+    /// The array can then be written with [`Self::write_slice`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`].
+    pub fn alloc_for<T>(
+        &mut self,
+        device: &B::Device,
+        count: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        let layout = ArrayLayout::of::<T>(self.usage, count);
+
+        self.alloc(device, layout.size, layout.alignment)
+    }
+
+    fn alloc_in_pool(
+        device: &B::Device,
+        pool: &mut SizePool<A, B>,
+        size: NonZeroBufferAddress,
+        size_class: usize,
+        alignment: NonZeroBufferAddress,
+        settings: &NewHeapSettings,
+        growth_policy: &dyn GrowthPolicy,
+    ) -> Result<Allocation, AllocError> {
+        if let Some(allocation) = Self::alloc_in_existing_heap(pool, size, size_class, alignment) {
+            return Ok(allocation);
+        }
+
+        // None of the existing heaps can hold our allocation, so we'll have to create a new one.
+
+        let context = NewHeapSizeContext::new(&pool.heaps, size);
+        let max_heap_size = B::max_heap_size(device);
+        let heap_size =
+            Self::new_heap_size(growth_policy, settings, size_class, context, max_heap_size)?;
+        if heap_size.get() > max_heap_size {
+            return Err(AllocError::SizeTooLargeForArena { size, heap_size });
+        }
+        check_budget(settings.budget, settings.reserved_bytes, heap_size)?;
+        let label = settings
+            .label_prefix
+            .as_deref()
+            .map(|prefix| heap_label(prefix, size_class, pool.heaps.len()));
+        let descriptor = HeapDescriptor {
+            label: label.as_deref(),
+            upload_strategy: settings.upload_strategy,
+            ..HeapDescriptor::new(heap_size, settings.usage)
+        };
+        if !governor::has_headroom(descriptor.buffer_count()) {
+            return Err(AllocError::HeapCreationFailed { heap_size });
+        }
+        let (_, allocator) = pool.expand(device, &descriptor);
+        // The heap is kept even if the allocation fails, as it may serve later allocations.
+        let range_in_heap = allocator.alloc(size, alignment)?;
+        // Note: we just appended to this pool, so its length must be nonzero.
+        let generation = pool.record_alloc(pool.heaps.len() - 1, range_in_heap.clone());
+
+        Ok(Allocation {
+            // Note: we just appended to this pool, so its length must be nonzero.
+            arena_key: pool.key(size_class, pool.heaps.len() - 1),
+            range_in_heap,
+            generation,
+        })
+    }
+
+    /// Tries to make an allocation in one of the existing heaps of `pool`, best fit first.
+    ///
+    /// See [`SizePool::fitting_heaps`].
+    fn alloc_in_existing_heap(
+        pool: &mut SizePool<A, B>,
+        size: NonZeroBufferAddress,
+        size_class: usize,
+        alignment: NonZeroBufferAddress,
+    ) -> Option<Allocation> {
+        for index_in_pool in pool.fitting_heaps(size) {
+            let (_, allocator) = &mut pool.heaps[index_in_pool];
+            if let Ok(range_in_heap) = allocator.alloc(size, alignment) {
+                let generation = pool.record_alloc(index_in_pool, range_in_heap.clone());
+
+                return Some(Allocation {
+                    arena_key: pool.key(size_class, index_in_pool),
+                    range_in_heap,
+                    generation,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Returns `allocation` to the heap it was made in.
+    ///
+    /// Any upload of `allocation` deferred with [`Self::defer_upload`] is discarded, and its age
+    /// is forgotten. If the heap is left without live allocations, it is then handled according
+    /// to the [`EmptyHeapPolicy`].
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::NotOwnedByAllocator`] if `allocation` is not exactly a live
+    /// allocation of this arena, such as if it was freed already or covers only part of one, with
+    /// [`AllocError::StaleKey`] if its heap has since been destroyed or moved by
+    /// [`Self::compact`], with [`AllocError::InUse`] if hazard tracking is enabled and a
+    /// submission that uses `allocation` has yet to finish (see
+    /// [`Self::enable_hazard_tracking`]), or with the error of the heap's allocator if it refuses
+    /// to free it. In any case, nothing is changed.
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must have been returned by [`Self::alloc`] on this arena, must not have been
+    /// freed already, and must no longer be in use by the GPU.
+    pub unsafe fn dealloc(&mut self, allocation: Allocation) -> Result<(), AllocError> {
+        let Allocation { arena_key, range_in_heap, generation } = allocation;
+        let padded_range = self
+            .guard_bands
+            .padded_range(arena_key, &range_in_heap)
+            .unwrap_or_else(|| range_in_heap.clone());
+        let pool = match arena_key.size_class.checked_sub(12) {
+            None => &mut self.tiny_pool,
+            Some(index) => {
+                self.size_pools.get_mut(index).ok_or(AllocError::NotOwnedByAllocator)?
+            }
+        };
+        let index_in_pool = pool.index_of(arena_key)?;
+        #[cfg(feature = "track-allocs")]
+        self.tracker.check_dealloc(arena_key, &range_in_heap);
+        // Only a live allocation of exactly this range and generation is freed. Otherwise, this
+        // one was freed already (and perhaps its range reused by another), or it was cut short.
+        let occupancy = &pool.occupancy[index_in_pool];
+        let is_live = occupancy.ranges.get(&padded_range.start) == Some(&padded_range.end)
+            && occupancy.generations.get(&padded_range.start) == Some(&generation);
+        if !is_live {
+            return Err(AllocError::NotOwnedByAllocator);
+        }
+        let (_, allocator) = &mut pool.heaps[index_in_pool];
+        if let Some(hazards) = self.hazards.as_mut() {
+            if let Some(serial) = hazards.get_mut().busy_until(arena_key, &range_in_heap) {
+                return Err(AllocError::InUse { serial });
+            }
+        }
+        // SAFETY: The caller guarantees that `range_in_heap`, and so the padded range around it,
+        // is live in this heap.
+        unsafe { allocator.dealloc(padded_range.clone()) }?;
+        #[cfg(feature = "track-allocs")]
+        self.tracker.remove(arena_key, &range_in_heap);
+        self.guard_bands.remove(arena_key, padded_range.start);
+
+        pool.record_dealloc(arena_key.index_in_pool, padded_range);
+        self.record_frame(|counters| counters.deallocations += 1);
+        self.observe(|observer| {
+            let range_in_heap = range_in_heap.clone();
+            observer.deallocated(&Allocation { arena_key, range_in_heap, generation });
+        });
+
+        self.pending_uploads.retain(|upload| {
+            let allocation = &upload.allocation;
+            allocation.arena_key != arena_key || allocation.range_in_heap != range_in_heap
+        });
+        self.evictable.retain(|(_, allocation)| {
+            allocation.arena_key != arena_key || allocation.range_in_heap != range_in_heap
+        });
+        if let Some(hazards) = self.hazards.as_mut() {
+            hazards.get_mut().remove_pending(arena_key, &range_in_heap);
+        }
+        if let Some(aging) = self.aging.as_mut() {
+            aging.get_mut().remove(arena_key, range_in_heap);
+        }
+        if self.empty_heap_policy == EmptyHeapPolicy::ReleaseTrailing {
+            self.release_trailing_heaps(arena_key.size_class);
+        }
+        self.check_watermarks();
+
+        Ok(())
+    }
+
+    /// Destroys the empty heaps at the end of the pool for `size_class`.
+    fn release_trailing_heaps(&mut self, size_class: usize) {
+        let Some(pool) = self.get_pool_mut(size_class) else {
+            return;
+        };
+
+        let mut released = Vec::new();
+        while pool.occupancy.last().is_some_and(|occupancy| occupancy.ranges.is_empty()) {
+            pool.occupancy.pop();
+            // SAFETY: `heaps` and `occupancy` have the same length.
+            let (heap, _) = unsafe { pool.heaps.pop().unwrap_unchecked() };
+            pool.bump_generation(pool.heaps.len());
+            pool.record(|metrics| metrics.heaps_destroyed += 1);
+            released.push(heap.size());
+        }
+        for size in released {
+            self.notify_heap_event(HeapEventKind::Destroyed, size);
+        }
+    }
+}
+
+impl<A: Allocator> HeapArena<A> {
+    /// Like [`Self::alloc`], but if the allocation doesn't fit in any existing heap, first tries
+    /// to grow a heap as permitted by [`Self::heap_growth`], recording the copy of its contents
+    /// into `encoder`.
+    ///
+    /// `encoder` must be submitted before any other commands that use the grown heap. See
+    /// [`Heap::grow`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`].
+    pub fn alloc_or_grow(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        let HeapGrowth::UpTo(max_heap_size) = self.heap_growth else {
+            return self.alloc(device, size, alignment);
+        };
+        let alignment = combine_alignments(alignment, self.min_alignment);
+        let (padded_size, alignment, front) = self.padded_request(size, alignment);
+
+        let size_class = classify_size(padded_size);
+        let max_growth = self.budget_headroom();
+        let pool = self.pool_or_insert(size_class);
+        let existing = Self::alloc_in_existing_heap(pool, padded_size, size_class, alignment);
+        if let Some(allocation) = existing {
+            let allocation = self.strip_guard_bands(allocation, size, front);
+            self.record_alloc(&allocation);
+
+            return Ok(allocation);
+        }
+
+        let growth =
+            pool.grow_last(device, encoder, padded_size, alignment, max_heap_size, max_growth);
+        let Some(growth) = growth else {
+            return self.alloc(device, size, alignment);
+        };
+        // Growing a heap in place keeps its allocations, so its key stays the same.
+        let arena_key = pool.key(size_class, growth.index_in_pool);
+        let allocation = growth.range_in_heap.map(|range_in_heap| Allocation {
+            arena_key,
+            generation: pool.generation_at(growth.index_in_pool, range_in_heap.start),
+            range_in_heap,
+        });
+        let kind = HeapEventKind::Grown { previous_size: growth.previous_size };
+        self.notify_heap_event(kind, growth.new_size);
+
+        match allocation {
+            Some(allocation) => {
+                let allocation = self.strip_guard_bands(allocation, size, front);
+                self.record_alloc(&allocation);
+
+                Ok(allocation)
+            }
+            None => self.alloc(device, size, alignment),
+        }
+    }
+
+    /// Allocates room for `contents` aligned to `alignment` and uploads `contents` into it, much
+    /// like [`wgpu::util::DeviceExt::create_buffer_init`] but suballocated.
+    ///
+    /// The allocation is padded with zeros to [`wgpu::COPY_BUFFER_ALIGNMENT`]. `contents` are
+    /// written into the staging memory of its heap and flushed with a copy recorded into
+    /// `encoder`, or handed to `queue` if the heap has no staging memory (see
+    /// [`UploadStrategy::QueueWrite`]). Unlike [`Self::upload`], this is not subject to
+    /// [`UploadPolicy::per_frame_budget`], though it is counted against it.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `contents` is empty.
+    pub fn alloc_with_data(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        contents: &[u8],
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        let contents = pad_for_copy(contents);
+        let size = NonZeroBufferAddress::new(contents.len() as BufferAddress)
+            .expect("cannot allocate empty contents");
+        let allocation = self.alloc(device, size, alignment)?;
+
+        let (heap, _) = &self[allocation.arena_key];
+        match heap.upload_strategy() {
+            UploadStrategy::Staging | UploadStrategy::Windowed { .. } => {
+                self.write_and_flush(encoder, &allocation, &contents);
+            }
+            UploadStrategy::QueueWrite => {
+                heap.write_via(queue, allocation.range_in_heap.clone(), &contents);
+                self.record_written(&allocation);
+            }
+        }
+        self.record_frame(|counters| counters.bytes_uploaded += size.get());
+
+        Ok(allocation)
+    }
+
+    /// Like [`Self::alloc_with_data`], but waits for the staging memory of the heap of the
+    /// allocation to be mapped, as with [`Heap::ensure_mapped`], rather than panicking if it
+    /// isn't.
+    ///
+    /// Unlike `alloc_with_data`, `contents` are not flushed here; they are written into staging
+    /// memory and flushed by the next [`Self::flush_dirty`]. Heaps without staging memory hand
+    /// `contents` to `queue` as before, and heaps with a staging window (see
+    /// [`UploadStrategy::Windowed`]) are written without waiting.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc`]. If the staging memory can't be mapped, the allocation is freed again
+    /// and nothing is written.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `contents` is empty.
+    pub async fn alloc_and_write_async(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        contents: &[u8],
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Allocation, AsyncWriteError> {
+        let contents = pad_for_copy(contents);
+        let size = NonZeroBufferAddress::new(contents.len() as BufferAddress)
+            .expect("cannot allocate empty contents");
+        let allocation = self.alloc(device, size, alignment)?;
+
+        let (heap, _) = &self[allocation.arena_key];
+        match heap.upload_strategy() {
+            UploadStrategy::Staging => {
+                if let Err(error) = heap.ensure_mapped(device).await {
+                    // SAFETY: the allocation was just made and has not been handed out.
+                    let _ = unsafe { self.dealloc(allocation) };
+                    return Err(error.into());
+                }
+                self.write(&allocation, &contents);
+            }
+            // Note: writes through a staging window needn't wait for it to be mapped.
+            UploadStrategy::Windowed { .. } => self.write(&allocation, &contents),
+            UploadStrategy::QueueWrite => {
+                heap.write_via(queue, allocation.range_in_heap.clone(), &contents);
+                self.record_written(&allocation);
+            }
+        }
+        self.record_frame(|counters| counters.bytes_uploaded += size.get());
+
+        Ok(allocation)
+    }
+}
+
+impl<A, B: GpuBacking> HeapArena<A, B> {
+    /// The size of the heap that `growth_policy`, as overridden by `settings`, would create in
+    /// the pool of `size_class` in the situation described by `context`, on a device whose
+    /// heaps may be no larger than `max_heap_size` bytes.
+    fn new_heap_size(
+        growth_policy: &dyn GrowthPolicy,
+        settings: &NewHeapSettings,
+        size_class: usize,
+        context: NewHeapSizeContext,
+        max_heap_size: BufferAddress,
+    ) -> Result<NonZeroBufferAddress, AllocError> {
+        let size = context.first_alloc_size;
+        let new_heap_size = match settings.tiny_alloc_policy {
+            TinyAllocPolicy::Batched { heap_size } if sizes::pool_index(size_class).is_none() => {
+                heap_size.max(size)
+            }
+            _ => growth_policy.new_heap_size(context),
+        };
+        if new_heap_size < size {
+            return Err(AllocError::SizeTooLargeForArena { size, heap_size: new_heap_size });
+        }
+        let new_heap_size = new_heap_size.max(settings.min_heap_size.unwrap_or(new_heap_size));
+
+        // As the process approaches its buffer ceiling, create fewer, larger heaps, but never one
+        // that the device or the budget would refuse when the unconsolidated heap would do.
+        // Deterministic arenas don't, as the pressure depends on the rest of the process.
+        let consolidation_factor =
+            if settings.deterministic { 1 } else { governor::consolidation_factor() };
+        let budget_headroom = settings
+            .budget
+            .map_or(BufferAddress::MAX, |budget| budget.saturating_sub(settings.reserved_bytes));
+        let consolidated = new_heap_size
+            .saturating_mul(NonZeroBufferAddress::new(consolidation_factor).unwrap())
+            .get()
+            .min(max_heap_size)
+            .min(budget_headroom);
+
+        Ok(NonZeroBufferAddress::new(consolidated).map_or(new_heap_size, |consolidated| {
+            consolidated.max(new_heap_size)
+        }))
+    }
+
+    /// The size class that an allocation of `size` bytes maps to, as with
+    /// [`sizes::classify_size`].
+    ///
+    /// This is the position of the leftmost 1 bit in `size`. Allocations of size classes below
+    /// [`sizes::FIRST_POOLED_SIZE_CLASS`] share a single pool of tiny heaps.
+    pub fn size_class(size: NonZeroBufferAddress) -> usize {
+        classify_size(size)
+    }
+
+    /// Predicts where [`Self::alloc`] would place an allocation of `size` bytes aligned to
+    /// `alignment`, without allocating anything.
+    ///
+    /// The prediction holds until the arena or the buffer governor is next modified. Existing
+    /// heaps are probed by trying the allocation on a clone of their allocator.
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::SizeTooLargeForArena`] if a new heap would be needed but
+    /// the growth policy doesn't leave room for the allocation, or with
+    /// [`AllocError::BudgetExceeded`] if that heap would exceed the budget of this arena.
+    pub fn placement(
+        &self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Placement, AllocError>
+    where
+        A: Allocator + Clone,
+    {
+        let alignment = combine_alignments(alignment, self.min_alignment);
+        let (padded_size, alignment, front) = self.padded_request(size, alignment);
+        let size_class = classify_size(padded_size);
+        let pool = self.get_pool(size_class);
+        let heaps: &[(B::Heap, A)] = pool.map_or(&[], |pool| &pool.heaps);
+
+        // Note: this must search heaps in the same order as `alloc_in_pool`.
+        let fitting_heaps = pool.map_or_else(Vec::new, |pool| pool.fitting_heaps(padded_size));
+        let existing = fitting_heaps.into_iter().find_map(|index_in_pool| {
+            let (_, allocator) = &heaps[index_in_pool];
+            let padded_range = allocator.clone().alloc(padded_size, alignment).ok()?;
+            let start = padded_range.start + front.unwrap_or(0);
+            let range_in_heap = start..(start + size.get());
+
+            let pool = pool?;
+            Some(Allocation {
+                arena_key: pool.key(size_class, index_in_pool),
+                range_in_heap,
+                generation: pool.next_allocation_generation,
+            })
+        });
+
+        if let Some(allocation) = existing {
+            return Ok(Placement::Existing(allocation));
+        }
+        let context = NewHeapSizeContext::new(heaps, padded_size);
+        let settings = self.new_heap_settings();
+        // Note: without a device, the size of the heap is only limited by the budget.
+        let heap_size = Self::new_heap_size(
+            &*self.growth_policy.0,
+            &settings,
+            size_class,
+            context,
+            BufferAddress::MAX,
+        )?;
+        check_budget(self.budget, self.reserved_bytes, heap_size)?;
+
+        Ok(Placement::NewHeap { size_class, heap_size })
+    }
+}
+
+impl<A: Allocator, B: GpuBacking> HeapArena<A, B> {
+    /// Reports on whether an allocation of `size` bytes aligned to `alignment` fits in the
+    /// existing heaps of this arena and, if not, what could be done about it.
+    ///
+    /// Whether an allocation fits is judged conservatively from the largest free block of each
+    /// heap (see [`Allocator::largest_free_block`]), allowing for worst-case alignment padding.
+    pub fn diagnose(
+        &self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> AllocDiagnostics {
+        let size_class = classify_size(size);
+        let heaps: &[(B::Heap, A)] = self.get_pool(size_class).map_or(&[], |pool| &pool.heaps);
+
+        let mut diagnostics = AllocDiagnostics {
+            size,
+            alignment,
+            size_class,
+            heaps: heaps
+                .iter()
+                .map(|(heap, allocator)| HeapDiagnostics {
+                    size: heap.size(),
+                    largest_free_block: allocator.largest_free_block(),
+                })
+                .collect(),
+            reserved_bytes: self.reserved_bytes,
+            live_buffers: governor::live_buffer_count(),
+            buffer_ceiling: governor::buffer_ceiling(),
+            suggestion: Suggestion::None,
+        };
+        diagnostics.suggest();
+
+        diagnostics
+    }
+}
+
+impl<A: Allocator, B: GpuBacking> HeapArena<A, B> {
+    /// Takes a snapshot of the memory usage of every heap in this arena.
+    ///
+    /// See [`ArenaStats::total`] for the usage of the arena as a whole.
+    pub fn stats(&self) -> ArenaStats {
+        let heaps = self
+            .pools()
+            .flat_map(|(size_class, pool)| {
+                pool.heaps.iter().zip(pool.occupancy.iter()).enumerate().map(
+                    move |(index_in_pool, ((heap, allocator), occupancy))| HeapStats {
+                        arena_key: pool.key(size_class, index_in_pool),
+                        size: heap.size(),
+                        stats: Stats {
+                            bytes_allocated: occupancy.bytes,
+                            bytes_free: occupancy.free_bytes(heap.size()),
+                            largest_free_block: allocator.largest_free_block(),
+                            allocation_count: occupancy.ranges.len(),
+                            high_water_mark: occupancy.high_water_mark,
+                        },
+                    },
+                )
+            })
+            .collect();
+        let mut tags: BTreeMap<&'static str, TagStats> = BTreeMap::new();
+        for occupancy in self.pools().flat_map(|(_, pool)| pool.occupancy.iter()) {
+            for (start, &tag) in occupancy.tags.iter() {
+                let tag_stats = tags.entry(tag).or_default();
+                tag_stats.bytes_allocated += occupancy.ranges[start] - start;
+                tag_stats.allocation_count += 1;
+            }
+        }
+
+        ArenaStats { heaps, tags }
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<A: Allocator> HeapArena<A> {
+    /// Captures the logical state of this arena: its heaps, the state of their allocators, and
+    /// their live allocations, but not their contents.
+    ///
+    /// See the [`snapshot`](crate::snapshot) module.
+    pub fn snapshot(&self) -> ArenaSnapshot<A>
+    where
+        A: Clone,
+    {
+        let pools = self
+            .pools()
+            .filter(|(_, pool)| !pool.generations.is_empty())
+            .map(|(size_class, pool)| PoolSnapshot {
+                size_class,
+                heaps: pool
+                    .heaps
+                    .iter()
+                    .zip(pool.occupancy.iter())
+                    .map(|((heap, allocator), occupancy)| HeapSnapshot {
+                        size: heap.size(),
+                        allocator: allocator.clone(),
+                        allocations: occupancy
+                            .ranges
+                            .iter()
+                            .map(|(&start, &end)| start..end)
+                            .collect(),
+                        generations: occupancy
+                            .ranges
+                            .keys()
+                            .map(|start| occupancy.generations[start])
+                            .collect(),
+                        high_water_mark: occupancy.high_water_mark,
+                    })
+                    .collect(),
+                generations: pool.generations.clone(),
+                next_allocation_generation: pool.next_allocation_generation,
+            })
+            .collect();
+
+        ArenaSnapshot {
+            usage: self.usage,
+            upload_strategy: self.upload_strategy,
+            min_alignment: self.min_alignment,
+            pools,
+        }
+    }
+
+    /// Recreates an arena from `snapshot`, with a new heap on `device` in place of each heap
+    /// that was captured.
+    ///
+    /// The allocator of each heap is restored as it was, and its allocations are live again with
+    /// the same generations, so the [`Allocation`]s and [`ArenaKey`]s made by the original arena
+    /// are valid in the returned one, which places later allocations exactly as the original
+    /// would have. The heaps are zeroed, as their contents are not captured. As growth policies
+    /// can't be serialized, `growth_policy` decides the size of any heaps created afterwards.
+    pub fn restore(
+        snapshot: ArenaSnapshot<A>,
+        device: &wgpu::Device,
+        growth_policy: impl GrowthPolicy + Send + 'static,
+    ) -> Self {
+        let mut arena = Self::new(snapshot.usage, growth_policy);
+        arena.set_upload_strategy(snapshot.upload_strategy);
+        arena.set_min_alignment(snapshot.min_alignment);
+        for PoolSnapshot { size_class, heaps, generations, next_allocation_generation } in
+            snapshot.pools
+        {
+            let pool = pool_or_insert(&mut arena.tiny_pool, &mut arena.size_pools, size_class);
+            // Note: new heaps only add slot generations past the end of these.
+            pool.generations = generations;
+            pool.next_allocation_generation = next_allocation_generation;
+            for HeapSnapshot { size, allocator, allocations, generations, high_water_mark } in heaps
+            {
+                let descriptor = HeapDescriptor {
+                    upload_strategy: snapshot.upload_strategy,
+                    ..HeapDescriptor::new(size, snapshot.usage)
+                };
+                pool.expand(device, &descriptor).1 = allocator;
+                // Note: we just appended to this pool, so its length must be nonzero.
+                let index_in_pool = pool.heaps.len() - 1;
+                for (range, generation) in allocations.into_iter().zip(generations) {
+                    pool.record_alloc_with_generation(index_in_pool, range.clone(), generation);
+                    #[cfg(feature = "track-allocs")]
+                    arena.tracker.insert(&Allocation {
+                        arena_key: pool.key(size_class, index_in_pool),
+                        range_in_heap: range,
+                        generation,
+                    });
+                }
+                let occupancy = &mut pool.occupancy[index_in_pool];
+                occupancy.high_water_mark = occupancy.high_water_mark.max(high_water_mark);
+                arena.reserved_bytes += size.get();
+            }
+        }
+
+        arena
+    }
+}
+
+impl<A: Allocator + Clone> HeapArena<A> {
+    /// Moves live allocations out of sparsely used heaps and into others in the same pool, then
+    /// destroys every heap left empty, returning a report of what was done, including where each
+    /// affected allocation went.
+    ///
+    /// Heaps are evacuated from the least to the most used, and only if all of their allocations
+    /// fit elsewhere; allocations are moved into the most used heaps that can hold them. Contents
+    /// are copied on the GPU by commands recorded into `encoder`, which must be submitted before
+    /// the moved allocations are used, and in staging memory on the CPU. Destroying a heap shifts
+    /// the [`ArenaKey`] of every later heap in its pool, so allocations in those heaps are
+    /// reported as relocated too, with the same range.
+    ///
+    /// Only heaps whose contents can be copied out take part: heaps with usage
+    /// [`HeapUsages::MAP_READ`] and heaps whose staging memory is not mapped are left alone.
+    /// Allocations that don't begin on a multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`] are never
+    /// moved, and moved allocations keep the alignment of their old offset up to
+    /// [`MAX_RELOCATION_ALIGNMENT`].
+    ///
+    /// Pending uploads, allocation ages, allocations queued by [`Self::dealloc_deferred`], and
+    /// allocations released by dropped [`OwnedAllocation`]s follow their allocations. Live
+    /// `OwnedAllocation`s and any other copies of relocated [`Allocation`]s held by the caller
+    /// must be replaced with the new ones.
+    ///
+    /// # Safety
+    ///
+    /// The GPU must be done with every allocation in this arena, and with every heap that may be
+    /// destroyed.
+    pub unsafe fn compact(&mut self, encoder: &mut wgpu::CommandEncoder) -> CompactionReport {
+        // Note: this finishes any incremental compaction in progress, too.
+        self.compaction = None;
+        let mut budget = BudgetTracker::new(CompactionBudget::default());
+        // SAFETY: The caller upholds the same contract.
+        let (mut report, _) = unsafe { self.compact_pools(encoder, 0, &mut budget) };
+        report.progress = 1.0;
+
+        report
+    }
+
+    /// Like [`Self::compact`], but moves no more than `budget` allows, resuming the compaction
+    /// that the previous call left unfinished, if any.
+    ///
+    /// Calling this once per frame works off fragmentation over many frames without a hitch.
+    /// The returned report describes this call only, except for [`CompactionReport::progress`],
+    /// which estimates how much of the whole compaction is done; once it is complete, the next
+    /// call starts a new one.
+    ///
+    /// Pools are compacted one after another, from the lowest size class to the highest, and
+    /// heaps are evacuated whole, so a call stops before the first heap whose evacuation would
+    /// exceed what is left of `budget`. See [`CompactionBudget`] for heaps too large for it.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::compact`].
+    pub unsafe fn compact_incremental(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        budget: CompactionBudget,
+    ) -> CompactionReport {
+        let progress = self.compaction.take().unwrap_or_default();
+        let mut budget = BudgetTracker::new(budget);
+        // SAFETY: The caller upholds the same contract.
+        let (mut report, stopped_at) =
+            unsafe { self.compact_pools(encoder, progress.next_pool, &mut budget) };
+        match stopped_at {
+            Some(next_pool) => {
+                let progress = CompactionProgress {
+                    next_pool,
+                    bytes_moved: progress.bytes_moved + report.bytes_moved,
+                };
+                report.progress = self.estimate_progress(&progress);
+                self.compaction = Some(progress);
+            }
+            None => report.progress = 1.0,
+        }
+
+        report
+    }
+
+    /// The estimated fraction of the compaction begun by [`Self::compact_incremental`] that is
+    /// done, from 0 to 1, or `None` if there is none in progress.
+    pub fn compaction_progress(&self) -> Option<f64> {
+        self.compaction.as_ref().map(|progress| self.estimate_progress(progress))
+    }
+
+    /// Estimates the fraction of an incremental compaction that is done from the bytes it has
+    /// moved and those that compacting the pools it has yet to get to would move.
+    fn estimate_progress(&self, progress: &CompactionProgress) -> f64 {
+        let remaining: BufferAddress = self
+            .pools()
+            .skip(progress.next_pool)
+            .map(|(_, pool)| pool.compaction_estimate())
+            .sum();
+        // Note: the compaction isn't done, so some work must remain even if none is estimated.
+        let total = progress.bytes_moved + remaining.max(1);
+
+        progress.bytes_moved as f64 / total as f64
+    }
+
+    /// Compacts the pools from the `first_pool`th on, as described by [`Self::compact`], until
+    /// `budget` runs out, returning a report and, if it ran out, the index of the pool to resume
+    /// from.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::compact`].
+    unsafe fn compact_pools(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        first_pool: usize,
+        budget: &mut BudgetTracker,
+    ) -> (CompactionReport, Option<usize>) {
+        let started = compaction::now();
+        let mut report = CompactionReport::default();
+        let mut destroyed = Vec::new();
+        let mut stopped_at = None;
+        let pools = std::iter::once(&mut self.tiny_pool).chain(self.size_pools.iter_mut());
+        for (index, pool) in pools.enumerate().skip(first_pool) {
+            let (pool_destroyed, finished) = pool.compact(encoder, budget, &mut report);
+            destroyed.extend(pool_destroyed);
+            if !finished {
+                stopped_at = Some(index);
+                break;
+            }
+        }
+
+        for size in destroyed {
+            report.heaps_destroyed += 1;
+            report.bytes_reclaimed += size.get();
+            self.notify_heap_event(HeapEventKind::Destroyed, size);
+        }
+        // Pools move padded ranges, guard bands and all, so relocations are reported without them.
+        let relocations = self.guard_bands.relocate(std::mem::take(&mut report.relocations));
+        let relocate = |allocation: &mut Allocation| {
+            let relocation = relocations.iter().find(|relocation| relocation.from == *allocation);
+            if let Some(relocation) = relocation {
+                *allocation = relocation.to.clone();
+            }
+        };
+        for upload in self.pending_uploads.iter_mut() {
+            relocate(&mut upload.allocation);
+        }
+        for (_, allocation) in self.retiring.iter_mut() {
+            relocate(allocation);
+        }
+        for (_, allocation) in self.evictable.iter_mut() {
+            relocate(allocation);
+        }
+        for (_, allocation) in self.released.lock().unwrap().iter_mut() {
+            relocate(allocation);
+        }
+        if let Some(aging) = self.aging.as_mut() {
+            aging.get_mut().relocate(&relocations);
+        }
+        if let Some(hazards) = self.hazards.as_mut() {
+            hazards.get_mut().relocate(&relocations);
+        }
+        #[cfg(feature = "track-allocs")]
+        self.tracker.relocate(&relocations);
+        self.observe(|observer| observer.compacted(&relocations));
+
+        report.relocations = relocations;
+        report.remaining = self.stats().total();
+        report.duration = started.map(|started| started.elapsed());
+
+        (report, stopped_at)
+    }
+}
+
+/// The largest alignment, in bytes, that [`HeapArena::compact`] preserves.
+///
+/// This is the default minimum uniform and storage buffer offset alignment of wgpu.
+pub const MAX_RELOCATION_ALIGNMENT: BufferAddress = 256;
+
+/// An allocation moved by [`HeapArena::compact`].
+#[derive(Debug)]
+pub struct Relocation {
+    pub from: Allocation,
+    pub to: Allocation,
+}
+
+/// Where an allocation would be placed, as predicted by [`HeapArena::placement`].
+#[derive(Debug)]
+pub enum Placement {
+    /// The allocation would be made in an existing heap, as described.
+    Existing(Allocation),
+    /// No existing heap can hold the allocation, so a new heap would be created.
+    NewHeap {
+        size_class: usize,
+        /// The size, in bytes, of the new heap.
+        heap_size: NonZeroBufferAddress,
+    },
+}
+
+impl<A: Allocator, B: GpuBacking> SizePool<A, B> {
+    /// The indices of the heaps in this pool that may have room for `size` bytes, in the order
+    /// they should be tried.
+    ///
+    /// Each heap is judged by its largest free block if its allocator keeps track of it (see
+    /// [`Allocator::largest_free_block`]), or by its free bytes otherwise. Heaps with less room
+    /// than `size` are skipped, and of the rest, the one with the least room is tried first so that
+    /// larger free blocks are kept for larger allocations. Ties go to the newest heap.
+    fn fitting_heaps(&self, size: NonZeroBufferAddress) -> Vec<usize> {
+        let mut fitting: Vec<_> = self
+            .heaps
+            .iter()
+            .zip(self.occupancy.iter())
+            .enumerate()
+            .filter_map(|(index_in_pool, ((heap, allocator), occupancy))| {
+                let room = allocator
+                    .largest_free_block()
+                    .unwrap_or_else(|| occupancy.free_bytes(heap.size()));
+
+                (room >= size.get()).then_some((room, Reverse(index_in_pool)))
+            })
+            .collect();
+        fitting.sort_unstable();
+
+        fitting.into_iter().map(|(_, Reverse(index_in_pool))| index_in_pool).collect()
+    }
+
+    fn expand(&mut self, device: &B::Device, descriptor: &HeapDescriptor) -> &mut (B::Heap, A) {
+        let heap = B::create_heap(device, descriptor);
+        let allocator = A::new(&heap);
+        self.heaps.push((heap, allocator));
+        self.occupancy.push(HeapOccupancy::default());
+        if self.generations.len() < self.heaps.len() {
+            self.generations.push(0);
+        }
+        self.record(|metrics| metrics.heaps_created += 1);
+
+        // SAFETY: We just pushed a new heap/allocator pair.
+        unsafe { self.heaps.last_mut().unwrap_unchecked() }
+    }
+
 }
 
-/// A collection of [`Heap`]s unified by a single infallible allocation interface.
-///
-/// In particular, this collection is an *arena*&mdash;new heaps can be allocated, but existing
-/// heaps cannot be selectively deallocated. The heaps contained within this arena are
-/// simultaneously deallocated when the arena itself is dropped.
-#[derive(Debug)]
-pub struct HeapArena<A> {
-    /// A [`SizePool`] for heaps and allocators of size 1 to 4,096 bytes (inclusive).
-    ///
-    /// This is separated from [`Self::size_pools`] as it seemed silly to allocate pools for size
-    /// classes of 0, 1, 2, etc., which represent very small heaps that should probably never be
-    /// created in practice.
-    tiny_pool: SizePool<A>,
-    /// The size pools of heaps and allocators that make up this arena's backing storage.
-    ///
-    /// See [`SizePool`] for details on how a size pool is laid out internally.
+impl<A: Allocator> SizePool<A> {
+    /// Grows the last heap in this pool so that it can hold an allocation of `size` bytes aligned
+    /// to `alignment`, without exceeding `max_heap_size` bytes or growing by more than
+    /// `max_growth` bytes, and tries to make the allocation in it.
     ///
-    /// This field orders pools from lowest to highest size class, beginning at 12. Therefore, index
-    /// 0 is for heaps of size 4,096 to 8,192 bytes (exclusive), index 1 is for heaps of size 8,192
-    /// to 16,384 bytes (exclusive), and so on.
-    size_pools: Vec<SizePool<A>>,
-    /// The usage for all heaps within this arena.
-    usage: HeapUsages,
-    /// Calculates the size of a new heap created by [`Self::expand`].
-    calc_new_heap_size: CalculateNewHeapSize,
-}
+    /// This returns `None` if the heap was not grown.
+    fn grow_last(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+        max_heap_size: NonZeroBufferAddress,
+        max_growth: Option<BufferAddress>,
+    ) -> Option<HeapGrowthOutcome> {
+        let index_in_pool = self.heaps.len().checked_sub(1)?;
+        let (heap, allocator) = &mut self.heaps[index_in_pool];
+        if !heap.can_grow() {
+            return None;
+        }
 
-impl<A: Allocator> HeapArena<A> {
-    pub fn unmap(&self) {
-        for (heap, _) in self.tiny_pool.0.iter() {
-            heap.unmap();
+        // Double the heap, or more if that still wouldn't leave room for the allocation at the old
+        // end.
+        let previous_size = heap.size();
+        let needed = previous_size
+            .get()
+            .checked_next_multiple_of(alignment.get())?
+            .checked_add(size.get())?
+            .next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        let needed = NonZeroBufferAddress::new(needed)?;
+        let new_size = previous_size.saturating_mul(NonZeroBufferAddress::new(2)?).max(needed);
+        let new_size = new_size.min(max_heap_size);
+        let exceeds_budget = |max_growth| new_size.get() - previous_size.get() > max_growth;
+        if new_size <= previous_size
+            || max_growth.is_some_and(exceeds_budget)
+            || !allocator.grow(new_size)
+        {
+            return None;
         }
-        for pool in self.size_pools.iter() {
-            for (heap, _) in pool.0.iter() {
-                heap.unmap();
-            }
+        heap.grow(device, encoder, new_size);
+
+        // The heap stays grown even if the allocation doesn't fit, as its allocator already is.
+        let range_in_heap = allocator.alloc(size, alignment).ok();
+        self.record(|metrics| metrics.heaps_grown += 1);
+        if let Some(range_in_heap) = range_in_heap.clone() {
+            self.record_alloc(index_in_pool, range_in_heap);
         }
+
+        Some(HeapGrowthOutcome { index_in_pool, range_in_heap, previous_size, new_size })
     }
+}
 
-    pub fn alloc(
+impl<A: Allocator + Clone> SizePool<A> {
+    /// Compacts this pool as described by [`HeapArena::compact`] until `budget` runs out,
+    /// recording relocations, moves, and copies in `report`.
+    ///
+    /// This returns the sizes of the heaps that were destroyed, and whether the pool was compacted
+    /// completely rather than stopped by `budget`.
+    fn compact(
         &mut self,
-        device: &wgpu::Device,
-        size: NonZeroBufferAddress,
-        alignment: NonZeroBufferAddress,
-    ) -> Allocation {
-        let size_class = classify_size(size);
-        let pool = if size_class < 12 {
-            &mut self.tiny_pool
-        } else {
-            // SAFETY: `size_class` is at least 12, so this will never underflow.
-            let index = unsafe { size_class.unchecked_sub(12) };
+        encoder: &mut wgpu::CommandEncoder,
+        budget: &mut BudgetTracker,
+        report: &mut CompactionReport,
+    ) -> (Vec<NonZeroBufferAddress>, bool) {
+        let heap_count = self.heaps.len();
+        let mut by_usage: Vec<usize> =
+            (0..heap_count).filter(|&index| self.heaps[index].0.can_copy_out()).collect();
+        by_usage.sort_by_key(|&index| self.occupancy[index].bytes);
 
-            let min_len = index + 1;
-            if self.size_pools.len() < min_len {
-                self.size_pools.resize_with(min_len, || SizePool::default());
+        let mut finished = true;
+        let mut evacuated = vec![false; heap_count];
+        // Heaps that allocations were moved into, which must not be evacuated in turn.
+        let mut received = vec![false; heap_count];
+        // Moves, as the source heap and range, the destination heap and range, and the generation
+        // of the moved allocation.
+        let mut moves = Vec::new();
+        for &source in by_usage.iter() {
+            if received[source] {
+                continue;
             }
 
-            &mut self.size_pools[index]
-        };
+            let strategy = self.heaps[source].0.upload_strategy();
+            let mut destinations: Vec<(usize, A)> = by_usage
+                .iter()
+                .rev()
+                .filter(|&&index| {
+                    index != source
+                        && !evacuated[index]
+                        && self.heaps[index].0.upload_strategy() == strategy
+                })
+                .map(|&index| (index, self.heaps[index].1.clone()))
+                .collect();
 
-        Self::alloc_in_pool(
-            device,
-            pool,
-            size,
-            size_class,
-            alignment,
-            self.usage,
-            self.calc_new_heap_size,
-        )
-    }
+            // Place larger allocations first, as they are the hardest to fit.
+            let mut ranges: Vec<Range<BufferAddress>> =
+                self.occupancy[source].ranges.iter().map(|(&start, &end)| start..end).collect();
+            ranges.sort_by_key(|range| std::cmp::Reverse(range.end - range.start));
+            let planned: Option<Vec<_>> = ranges
+                .into_iter()
+                .map(|range| {
+                    if !range.start.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+                        return None;
+                    }
+                    let size = NonZeroBufferAddress::new(range.end - range.start)?;
+                    let alignment = relocation_alignment(range.start);
+                    destinations.iter_mut().find_map(|(destination, allocator)| {
+                        let new_range = allocator.alloc(size, alignment).ok()?;
 
-    fn alloc_in_pool(
-        device: &wgpu::Device,
-        pool: &mut SizePool<A>,
-        size: NonZeroBufferAddress,
-        size_class: usize,
-        alignment: NonZeroBufferAddress,
-        heap_usage: HeapUsages,
-        calc_new_heap_size: CalculateNewHeapSize,
-    ) -> Allocation {
-        for (index_in_pool, (_, allocator)) in pool
-            .0
-            .iter_mut()
-            .rev()
-            .enumerate()
-        {
-            if let Some(range_in_heap) = allocator.alloc(size, alignment) {
-                return Allocation {
-                    arena_key: ArenaKey { size_class, index_in_pool },
-                    range_in_heap,
-                };
+                        Some((range.clone(), *destination, new_range))
+                    })
+                })
+                .collect();
+            let Some(planned) = planned else {
+                continue;
+            };
+            let bytes = planned.iter().map(|(range, _, _)| range.end - range.start).sum();
+            match budget.admit(planned.len(), bytes) {
+                Admission::Admitted => {}
+                Admission::Refused => continue,
+                Admission::Deferred => {
+                    finished = false;
+                    break;
+                }
             }
+
+            for (destination, allocator) in destinations {
+                self.heaps[destination].1 = allocator;
+            }
+            for (range, destination, new_range) in planned {
+                self.heaps[source].0.copy_to_heap(
+                    encoder,
+                    range.clone(),
+                    &self.heaps[destination].0,
+                    new_range.start,
+                );
+                self.record(|metrics| metrics.copies_recorded += 1);
+                report.copies_recorded += 1;
+                report.allocations_moved += 1;
+                report.bytes_moved += range.end - range.start;
+                // Moved allocations keep their generation, as they are still the same allocations.
+                let generation = self.generation_at(source, range.start);
+                let tag = self.record_dealloc(source, range.clone());
+                self.record_alloc(destination, new_range.clone());
+                let occupancy = &mut self.occupancy[destination];
+                occupancy.generations.insert(new_range.start, generation);
+                if let Some(tag) = tag {
+                    occupancy.tags.insert(new_range.start, tag);
+                }
+                received[destination] = true;
+                moves.push((source, range, destination, new_range, generation));
+            }
+            evacuated[source] = true;
         }
 
-        // None of the existing heaps can hold our allocation, so we'll have to create a new one.
+        // Destroy every empty heap and shift the rest down to fill the gaps.
+        let mut new_indices = Vec::with_capacity(heap_count);
+        let mut destroyed = Vec::new();
+        let mut kept = 0;
+        for occupancy in self.occupancy.iter() {
+            new_indices.push(kept);
+            if !occupancy.ranges.is_empty() {
+                kept += 1;
+            }
+        }
+        // Every slot from the first destroyed heap onwards ends up with a different heap, or none.
+        let old_generations = self.generations.clone();
+        let first_destroyed =
+            self.occupancy.iter().position(|occupancy| occupancy.ranges.is_empty());
+        for index in first_destroyed.unwrap_or(heap_count)..heap_count {
+            self.bump_generation(index);
+        }
+        // Note: the tiny pool holds several size classes, so the size class of each allocation is
+        // found from its size, as `HeapArena::alloc` does.
+        let allocation =
+            |generations: &[u32], index_in_pool, range: Range<BufferAddress>, generation| {
+                Allocation {
+                    arena_key: ArenaKey {
+                        // SAFETY: Allocations are never empty.
+                        size_class: classify_size(unsafe {
+                            NonZeroBufferAddress::new_unchecked(range.end - range.start)
+                        }),
+                        index_in_pool,
+                        generation: generations[index_in_pool],
+                    },
+                    range_in_heap: range,
+                    generation,
+                }
+            };
+        for (index, occupancy) in self.occupancy.iter().enumerate() {
+            if occupancy.ranges.is_empty() || new_indices[index] == index {
+                continue;
+            }
+            for (&start, &end) in occupancy.ranges.iter() {
+                // Allocations that were moved here are reported below.
+                if moves.iter().any(|(_, _, destination, new_range, _)| {
+                    *destination == index && new_range.start == start
+                }) {
+                    continue;
+                }
+                let generation = occupancy.generations[&start];
+                report.relocations.push(Relocation {
+                    from: allocation(&old_generations, index, start..end, generation),
+                    to: allocation(&self.generations, new_indices[index], start..end, generation),
+                });
+            }
+        }
+        for (source, range, destination, new_range, generation) in moves {
+            let new_index = new_indices[destination];
+            report.relocations.push(Relocation {
+                from: allocation(&old_generations, source, range, generation),
+                to: allocation(&self.generations, new_index, new_range, generation),
+            });
+        }
 
-        let new_heap_size = (calc_new_heap_size)(NewHeapSizeContext {
-            first_alloc_size: size,
-        });
-        if new_heap_size < size {
-            panic!(
-                "heap size is too small; must be able to store first allocation of size {} bytes",
-                size.get(),
-            );
+        let heaps = std::mem::take(&mut self.heaps);
+        let occupancy = std::mem::take(&mut self.occupancy);
+        for ((heap, allocator), occupancy) in heaps.into_iter().zip(occupancy) {
+            if occupancy.ranges.is_empty() {
+                destroyed.push(heap.size());
+            } else {
+                self.heaps.push((heap, allocator));
+                self.occupancy.push(occupancy);
+            }
         }
+        self.record(|metrics| metrics.heaps_destroyed += destroyed.len() as u64);
+
+        (destroyed, finished)
+    }
 
-        let (_, allocator) = pool.expand(device, new_heap_size, heap_usage);
-        let range_in_heap = allocator.alloc(size, alignment).unwrap();
+    /// Estimates the number of bytes that compacting this pool would move: those of the least
+    /// used heaps whose allocations fit in the free space of the heaps used more.
+    fn compaction_estimate(&self) -> BufferAddress {
+        let mut heaps: Vec<(BufferAddress, BufferAddress)> = self
+            .heaps
+            .iter()
+            .zip(self.occupancy.iter())
+            .filter(|((heap, _), _)| heap.can_copy_out())
+            .map(|((heap, _), occupancy)| (occupancy.bytes, occupancy.free_bytes(heap.size())))
+            .collect();
+        heaps.sort_unstable();
 
-        Allocation {
-            arena_key: ArenaKey {
-                size_class,
-                // SAFETY: We just appended to this pool, so its length must be nonzero.
-                index_in_pool: unsafe { pool.0.len().unchecked_sub(1) },
-            },
-            range_in_heap,
+        let mut free_after: BufferAddress = heaps.iter().map(|&(_, free)| free).sum();
+        let mut moved = 0;
+        for (bytes, free) in heaps {
+            free_after -= free;
+            if moved + bytes > free_after {
+                break;
+            }
+            moved += bytes;
         }
+
+        moved
     }
 }
 
-impl<A: Allocator> SizePool<A> {
-    fn expand(
-        &mut self,
-        device: &wgpu::Device,
-        new_heap_size: NonZeroBufferAddress,
-        usage: HeapUsages,
-    ) -> &mut (Heap, A) {
-        let heap = Heap::new(device, new_heap_size, usage);
-        let allocator = A::new(&heap);
-        self.0.push((heap, allocator));
+/// The alignment that an allocation starting at `start` keeps when moved by
+/// [`HeapArena::compact`].
+fn relocation_alignment(start: BufferAddress) -> NonZeroBufferAddress {
+    let alignment = 1 << start.trailing_zeros().min(MAX_RELOCATION_ALIGNMENT.trailing_zeros());
 
-        // SAFETY: We just pushed a new heap/allocator pair.
-        unsafe { self.0.last_mut().unwrap_unchecked() }
+    NonZeroBufferAddress::new(alignment).unwrap()
+}
+
+/// The result of [`SizePool::grow_last`].
+struct HeapGrowthOutcome {
+    index_in_pool: usize,
+    /// The allocation made in the grown heap, if it fit.
+    range_in_heap: Option<Range<BufferAddress>>,
+    previous_size: NonZeroBufferAddress,
+    new_size: NonZeroBufferAddress,
+}
+
+/// The size, alignment, tag, and priority of an allocation to be made by
+/// [`HeapArena::alloc_desc`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AllocDesc {
+    pub size: NonZeroBufferAddress,
+    pub alignment: NonZeroBufferAddress,
+    /// The category, such as `"meshes"` or `"uniforms"`, that the allocation is accounted under
+    /// in [`ArenaStats::tags`], if any.
+    pub tag: Option<&'static str>,
+    /// The priority of the allocation, which decides the allocations it may displace, and by
+    /// which it may be displaced if it is evictable.
+    pub priority: Priority,
+    /// Whether the allocation may be freed to make room for allocations of higher priority (see
+    /// [`PreemptionPolicy::Evict`]).
+    pub evictable: bool,
+}
+
+impl AllocDesc {
+    /// Describes an untagged, unevictable allocation of `size` bytes aligned to `alignment`, of
+    /// normal priority.
+    pub fn new(size: NonZeroBufferAddress, alignment: NonZeroBufferAddress) -> Self {
+        Self { size, alignment, tag: None, priority: Priority::default(), evictable: false }
     }
 }
 
-#[derive(Debug)]
+/// A region of a heap in a [`HeapArena`], as returned by [`HeapArena::alloc`].
+///
+/// Allocations are plain data that can be cloned, compared, and hashed, so they can be stored in
+/// ECS components or used as map keys. Cloning an allocation does not allocate anything; it must
+/// still be freed exactly once.
+///
+/// Once an allocation is freed, its memory may be given to a later allocation with the same heap
+/// and range, but never with the same [`Self::generation`]. In debug builds, writing, binding,
+/// slicing, or flushing through an allocation that is no longer live panics (see
+/// [`HeapArena::is_live`]).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Allocation {
     pub arena_key: ArenaKey,
     /// The result from [`Allocator::alloc`]. To be used with the heap represented by
     /// [`Self::arena_key`].
     pub range_in_heap: Range<BufferAddress>,
+    /// The number of allocations made in the pool of this allocation before it, which tells it
+    /// apart from earlier and later allocations of the same range.
+    ///
+    /// This is kept when the allocation is moved by [`HeapArena::compact`]. Parts of an allocation
+    /// that are written or bound on their own share its generation.
+    pub generation: u32,
+}
+
+impl Allocation {
+    /// The offset, in bytes, of this allocation within its heap.
+    pub fn offset(&self) -> BufferAddress {
+        self.range_in_heap.start
+    }
+
+    /// The size, in bytes, of this allocation.
+    pub fn size(&self) -> BufferAddress {
+        self.range_in_heap.end - self.range_in_heap.start
+    }
+}
+
+/// What is known about an [`Allocation`] and the heap that holds it, as returned by
+/// [`HeapArena::allocation_info`].
+///
+/// This is meant for tooling, such as debug overlays of where allocations live, that would
+/// otherwise have to look up heaps by indexing the arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AllocationInfo {
+    /// The size, in bytes, of the heap that holds the allocation.
+    pub heap_size: NonZeroBufferAddress,
+    /// The size class of the heap, as with [`ArenaKey::size_class`].
+    pub size_class: usize,
+    /// The position of the heap within the pool of its size class, as with
+    /// [`ArenaKey::index_in_pool`].
+    pub heap_index: usize,
+    /// The offset, in bytes, of the allocation within its heap.
+    pub offset: BufferAddress,
+    /// The size, in bytes, of the allocation.
+    pub size: BufferAddress,
+}
+
+/// An [`Allocation`] that is returned to its [`HeapArena`] when dropped.
+///
+/// This is created by [`HeapArena::alloc_owned`] and dereferences to the allocation itself, so it
+/// can be passed to every method that takes one. It doesn't borrow the arena, so any number of
+/// handles can be held while the arena is used. The GPU must be done with an allocation before its
+/// handle is dropped, just as with [`HeapArena::dealloc`].
+#[derive(Debug)]
+pub struct OwnedAllocation {
+    /// The allocation, which is only `None` after [`Self::into_inner`].
+    allocation: Option<Allocation>,
+    epoch: u64,
+    released: ReleaseQueue,
+}
+
+impl OwnedAllocation {
+    /// Unwraps the allocation so that it is no longer freed on drop.
+    pub fn into_inner(mut self) -> Allocation {
+        // SAFETY: `allocation` is only taken here, which consumes `self`.
+        unsafe { self.allocation.take().unwrap_unchecked() }
+    }
+}
+
+impl Deref for OwnedAllocation {
+    type Target = Allocation;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `allocation` is only taken by `into_inner`, which consumes `self`.
+        unsafe { self.allocation.as_ref().unwrap_unchecked() }
+    }
+}
+
+impl Drop for OwnedAllocation {
+    fn drop(&mut self) {
+        if let Some(allocation) = self.allocation.take() {
+            // A poisoned queue means a panic elsewhere; leaking the allocation is harmless then.
+            if let Ok(mut released) = self.released.lock() {
+                released.push((self.epoch, allocation));
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Identifies a heap within a [`HeapArena`], which can be looked up by indexing the arena.
+///
+/// Keys are cheap to copy, so they can be stored alongside allocations and used any number of
+/// times. Each key also records the generation of the heap it was made for, so once that heap is
+/// destroyed, or moved by [`HeapArena::compact`], the key goes stale: [`HeapArena::get`] returns
+/// `None` for it and [`HeapArena::dealloc`] fails with [`AllocError::StaleKey`], even if another
+/// heap has since taken its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ArenaKey {
     size_class: usize,
     index_in_pool: usize,
+    generation: u32,
 }
 
-impl<A> Index<ArenaKey> for HeapArena<A> {
-    type Output = (Heap, A);
+impl ArenaKey {
+    /// The size class of the allocation that this key was made for.
+    ///
+    /// Heaps of size classes below 12 share the pool of tiny heaps.
+    pub fn size_class(&self) -> usize {
+        self.size_class
+    }
 
-    fn index(&self, key: ArenaKey) -> &Self::Output {
-        if key.size_class < 12 {
-            &self.tiny_pool.0[key.index_in_pool]
-        } else {
-            // SAFETY: `size_class` is at least 12, so this will never underflow.
-            let pool = &self.size_pools[unsafe { key.size_class.unchecked_sub(12) }];
+    /// The position of the heap within its pool, in order of creation.
+    pub fn index_in_pool(&self) -> usize {
+        self.index_in_pool
+    }
 
-            &pool.0[key.index_in_pool]
+    /// The number of times that the position of the heap within its pool had been given up by a
+    /// previous heap when this key was made.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// This key with the size class of its pool in place of that of its allocation, so that the
+    /// keys of a tiny heap made for allocations of different size classes compare equal.
+    ///
+    /// The pool of tiny heaps is given size class 0, as in [`HeapArena::snapshot`].
+    #[cfg(feature = "track-allocs")]
+    pub(crate) fn heap_key(self) -> Self {
+        match sizes::pool_index(self.size_class) {
+            Some(_) => self,
+            None => Self { size_class: 0, ..self },
         }
     }
 }
 
-impl<A> IndexMut<ArenaKey> for HeapArena<A> {
-    fn index_mut(&mut self, key: ArenaKey) -> &mut Self::Output {
-        if key.size_class < 12 {
-            &mut self.tiny_pool.0[key.index_in_pool]
-        } else {
-            // SAFETY: `size_class` is at least 12, so this will never underflow.
-            let pool = &mut self.size_pools[unsafe {
-                key.size_class.unchecked_sub(12)
-            }];
+impl<A, B: GpuBacking> HeapArena<A, B> {
+    /// Every heap in this arena, together with its allocator, ordered by size class and then by
+    /// creation.
+    pub fn heaps(&self) -> impl Iterator<Item = &(B::Heap, A)> {
+        std::iter::once(&self.tiny_pool)
+            .chain(self.size_pools.iter())
+            .flat_map(|pool| pool.heaps.iter())
+    }
+
+    /// Like [`Self::heaps`], but yields mutable references.
+    ///
+    /// As with [`IndexMut`], allocations made or freed directly through an allocator are not
+    /// seen by the arena.
+    pub fn heaps_mut(&mut self) -> impl Iterator<Item = &mut (B::Heap, A)> {
+        std::iter::once(&mut self.tiny_pool)
+            .chain(self.size_pools.iter_mut())
+            .flat_map(|pool| pool.heaps.iter_mut())
+    }
+
+    /// Every pool along with its size class, from the lowest size class to the highest.
+    ///
+    /// The pool of tiny heaps is reported as size class 0.
+    fn pools(&self) -> impl Iterator<Item = (usize, &SizePool<A, B>)> {
+        std::iter::once((0, &self.tiny_pool))
+            .chain(self.size_pools.iter().enumerate().map(|(index, pool)| (index + 12, pool)))
+    }
+
+    /// Calls `f` with the size class and the heaps of every pool that has any, from the lowest
+    /// size class to the highest.
+    ///
+    /// The pool of tiny heaps is reported as size class 0.
+    pub fn for_each_pool(&self, mut f: impl FnMut(usize, &[(B::Heap, A)])) {
+        for (size_class, pool) in self.pools() {
+            if !pool.heaps.is_empty() {
+                f(size_class, &pool.heaps);
+            }
+        }
+    }
+
+    /// The pool for `size_class`, if it exists.
+    fn get_pool(&self, size_class: usize) -> Option<&SizePool<A, B>> {
+        match size_class.checked_sub(12) {
+            None => Some(&self.tiny_pool),
+            Some(index) => self.size_pools.get(index),
+        }
+    }
+
+    /// The pool for `size_class`, if it exists.
+    fn get_pool_mut(&mut self, size_class: usize) -> Option<&mut SizePool<A, B>> {
+        match size_class.checked_sub(12) {
+            None => Some(&mut self.tiny_pool),
+            Some(index) => self.size_pools.get_mut(index),
+        }
+    }
+
+    /// The pool for `size_class`, which must exist.
+    fn pool(&self, size_class: usize) -> &SizePool<A, B> {
+        self.get_pool(size_class).expect("no pool for size class")
+    }
+
+    /// The heap at `key` and its allocator, or `None` if there is no such heap, such as if `key`
+    /// belongs to another arena.
+    pub fn get(&self, key: ArenaKey) -> Option<&(B::Heap, A)> {
+        let pool = self.get_pool(key.size_class)?;
+
+        pool.heaps.get(pool.index_of(key).ok()?)
+    }
+
+    /// The heap at `key` and its allocator, or `None` if there is no such heap, such as if `key`
+    /// belongs to another arena.
+    pub fn get_mut(&mut self, key: ArenaKey) -> Option<&mut (B::Heap, A)> {
+        let pool = self.get_pool_mut(key.size_class)?;
+        let index_in_pool = pool.index_of(key).ok()?;
+
+        pool.heaps.get_mut(index_in_pool)
+    }
+
+    /// The heap that holds `allocation`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `allocation` was not made by this arena or its key is stale, as
+    /// indexing it with [`Allocation::arena_key`] does.
+    pub fn heap_for(&self, allocation: &Allocation) -> &B::Heap {
+        &self[allocation.arena_key].0
+    }
+
+    /// The heap that holds `allocation`, or `None` if there is no such heap, such as if its key
+    /// is stale or it was made by another arena.
+    pub fn try_heap_for(&self, allocation: &Allocation) -> Option<&B::Heap> {
+        self.get(allocation.arena_key).map(|(heap, _)| heap)
+    }
 
-            &mut pool.0[key.index_in_pool]
+    /// Determines if `allocation`, or the allocation that it is a part of, has yet to be freed.
+    ///
+    /// This is false once the allocation is freed, even if a later allocation has since been
+    /// given the same range, as that allocation has a different [`Allocation::generation`].
+    pub fn is_live(&self, allocation: &Allocation) -> bool {
+        let key = allocation.arena_key;
+        let range = &allocation.range_in_heap;
+        let Some(pool) = self.get_pool(key.size_class) else {
+            return false;
+        };
+        let Ok(index_in_pool) = pool.index_of(key) else {
+            return false;
+        };
+        let occupancy = &pool.occupancy[index_in_pool];
+        // Note: the live range is padded if the allocation has guard bands, so the allocation is
+        // found as the last live range that starts no later than it does.
+        occupancy.ranges.range(..=range.start).next_back().is_some_and(|(start, &end)| {
+            range.end <= end && occupancy.generations[start] == allocation.generation
+        })
+    }
+
+    /// Panics in debug builds if `allocation` is not live, as determined by [`Self::is_live`].
+    fn debug_assert_live(&self, allocation: &Allocation) {
+        debug_assert!(
+            self.is_live(allocation),
+            "{:?} was freed, or its memory was given to another allocation",
+            allocation,
+        );
+    }
+
+    /// Describes `allocation` and the heap that holds it, or returns `None` if there is no such
+    /// heap, such as if `allocation` was made by another arena.
+    pub fn allocation_info(&self, allocation: &Allocation) -> Option<AllocationInfo> {
+        let (heap, _) = self.get(allocation.arena_key)?;
+
+        Some(AllocationInfo {
+            heap_size: heap.size(),
+            size_class: allocation.arena_key.size_class,
+            heap_index: allocation.arena_key.index_in_pool,
+            offset: allocation.offset(),
+            size: allocation.size(),
+        })
+    }
+
+    /// Records a copy of the contents of `source` into `destination`, such as to move data between
+    /// heaps during compaction or streaming.
+    ///
+    /// `destination` may be larger than `source`, in which case the bytes past the size of
+    /// `source` are left alone. For arenas of [`Heap`]s, see [`Heap::copy_to`] for how unflushed
+    /// writes are treated.
+    ///
+    /// # Errors
+    ///
+    /// This fails, recording nothing, if `destination` is smaller than `source`, or if
+    /// [`GpuBacking::copy`] fails, such as because both are in the same heap.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if either allocation wasn't made by this arena.
+    pub fn copy_allocation(
+        &self,
+        encoder: &mut B::Encoder,
+        source: &Allocation,
+        destination: &Allocation,
+    ) -> Result<(), CopyError> {
+        if destination.size() < source.size() {
+            return Err(CopyError::SizeMismatch {
+                source_size: source.size(),
+                destination_size: destination.size(),
+            });
+        }
+
+        B::copy(
+            encoder,
+            self.heap_for(source),
+            source.range_in_heap.clone(),
+            self.heap_for(destination),
+            destination.offset(),
+        )
+    }
+
+    /// Takes a snapshot of the cumulative counters of every pool in this arena.
+    ///
+    /// See [`Metrics::diff`] for finding out what changed between two snapshots.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            tiny_pool: self.tiny_pool.metrics.get(),
+            size_pools: self.size_pools.iter().map(|pool| pool.metrics.get()).collect(),
         }
     }
 }
 
+/// # Panics
+///
+/// Indexing panics if there is no heap at the key; see [`HeapArena::get`] for a fallible lookup.
+impl<A, B: GpuBacking> Index<ArenaKey> for HeapArena<A, B> {
+    type Output = (B::Heap, A);
+
+    fn index(&self, key: ArenaKey) -> &Self::Output {
+        self.get(key).expect("no heap for arena key")
+    }
+}
+
+impl<A, B: GpuBacking> IndexMut<ArenaKey> for HeapArena<A, B> {
+    fn index_mut(&mut self, key: ArenaKey) -> &mut Self::Output {
+        self.get_mut(key).expect("no heap for arena key")
+    }
+}
+
 macro_rules! impl_heap_api {
     (
         fn $fn:ident(
@@ -276,7 +3264,9 @@ macro_rules! impl_heap_api {
             allocation: &Allocation,
             $($($post_arg_name: $post_arg_ty),*)?
         ) $(-> $ret_ty)? {
-            self[allocation.arena_key.clone()]
+            self.debug_assert_live(allocation);
+            self.record_bound(allocation);
+            self[allocation.arena_key]
                 .0
                 .$fn(
                     $($($pre_arg_name),* ,)?
@@ -288,15 +3278,270 @@ macro_rules! impl_heap_api {
 }
 
 impl<A> HeapArena<A> {
-    impl_heap_api!(
-        fn write_and_flush(
-            encoder: &mut wgpu::CommandEncoder,
-            @,
-            contents: &[u8],
-        )
-    );
-    impl_heap_api!(fn write(@, contents: &[u8]));
     impl_heap_api!(fn slice(@) -> wgpu::BufferSlice<'a>);
     impl_heap_api!(fn binding(@) -> wgpu::BufferBinding<'a>);
-    impl_heap_api!(fn flush_range(encoder: &mut wgpu::CommandEncoder, @));
+    impl_heap_api!(fn checked_binding(@) -> Result<wgpu::BufferBinding<'a>, BindingError>);
+    impl_heap_api!(fn checked_binding_as(
+        @,
+        binding_type: wgpu::BufferBindingType,
+    ) -> Result<wgpu::BufferBinding<'a>, BindingError>);
+    impl_heap_api!(fn checked_slice(
+        @,
+        usage: HeapUsages,
+    ) -> Result<wgpu::BufferSlice<'a>, BindingError>);
+
+    pub fn write_and_flush(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        allocation: &Allocation,
+        contents: &[u8],
+    ) {
+        self.write(allocation, contents);
+        self.flush_range(encoder, allocation);
+    }
+
+    /// A binding of the start of the heap of `allocation`, as large as `allocation`, to be bound
+    /// at the offset returned by [`Self::dynamic_offset`].
+    ///
+    /// Every allocation of the same size in the same heap has the same such binding, so they can
+    /// all share one bind group with a dynamic offset.
+    pub fn dynamic_binding<'a>(&'a self, allocation: &Allocation) -> wgpu::BufferBinding<'a> {
+        self.debug_assert_live(allocation);
+        self.record_bound(allocation);
+        let (heap, _) = &self[allocation.arena_key];
+        // Note: the binding is validated at the offset it will be bound at, not at the start of
+        // the heap, where other allocations may have been written but not flushed.
+        #[cfg(feature = "validate-bindings")]
+        if let Err(error) = heap.validate_binding(allocation.range_in_heap.clone()) {
+            panic!("{}", error);
+        }
+
+        crate::create_binding(&heap.gpu_buffer, 0..allocation.size())
+    }
+
+    /// The dynamic offset at which to bind `allocation` with [`Self::dynamic_binding`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the offset of `allocation` doesn't fit in a `u32`.
+    pub fn dynamic_offset(&self, allocation: &Allocation) -> wgpu::DynamicOffset {
+        allocation.offset().try_into().expect("allocation offset does not fit in a dynamic offset")
+    }
+
+    pub fn write(&self, allocation: &Allocation, contents: &[u8]) {
+        self.debug_assert_live(allocation);
+        self[allocation.arena_key].0.write(allocation.range_in_heap.clone(), contents);
+        self.record_frame(|counters| counters.bytes_written += contents.len() as u64);
+        self.record_written(allocation);
+    }
+
+    /// Writes `contents` into `allocation`, laid out as [`ArrayLayout::of`] describes for the
+    /// usage of this arena.
+    ///
+    /// `allocation` must be exactly as large as that layout, which is the case for allocations
+    /// made by [`Self::alloc_for`] with the same element type and count.
+    pub fn write_slice<T: bytemuck::Pod>(&self, allocation: &Allocation, contents: &[T]) {
+        let count = NonZeroBufferAddress::new(contents.len() as BufferAddress)
+            .expect("cannot write an empty slice");
+        let layout = ArrayLayout::of::<T>(self.usage, count);
+        self.write(allocation, &layout.bytes_of(contents));
+    }
+
+    /// Uploads `contents` into the GPU memory of `allocation` by whichever path the current
+    /// [`UploadPolicy`] chooses, returning that path.
+    ///
+    /// If the upload would exceed [`UploadPolicy::per_frame_budget`], nothing is uploaded and an
+    /// error is returned instead; the upload can then be retried in a later frame.
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        allocation: &Allocation,
+        contents: &[u8],
+    ) -> Result<UploadPath, BudgetExceeded> {
+        let size = contents.len() as BufferAddress;
+        if let Some(budget) = self.upload_policy.per_frame_budget {
+            let remaining = budget.saturating_sub(self.frame_counters.get().bytes_uploaded);
+            if size > remaining {
+                return Err(BudgetExceeded { requested: size, remaining });
+            }
+        }
+
+        let path = self.upload_policy.choose(size);
+        match path {
+            UploadPath::Staging => self.write_and_flush(encoder, allocation, contents),
+            _ => {
+                let key = allocation.arena_key;
+                self[key].0.upload(
+                    device,
+                    queue,
+                    encoder,
+                    allocation.range_in_heap.clone(),
+                    contents,
+                    path,
+                );
+                if path == UploadPath::DedicatedStaging {
+                    self.pool(key.size_class).record(|metrics| metrics.copies_recorded += 1);
+                    self.record_frame(|counters| {
+                        counters.bytes_flushed += size;
+                        counters.flush_commands += 1;
+                    });
+                }
+            }
+        }
+        self.record_frame(|counters| counters.bytes_uploaded += size);
+        self.record_written(allocation);
+
+        Ok(path)
+    }
+
+    /// Queues `contents` to be uploaded into the GPU memory of `allocation` by a later call to
+    /// [`Self::upload_pending`].
+    ///
+    /// Pending uploads are performed from highest to lowest `priority`, and in the order in which
+    /// they were deferred among uploads of equal priority. This lets data that is needed this
+    /// frame, such as the visible level of detail of a streamed mesh, overtake data that is merely
+    /// being prefetched.
+    pub fn defer_upload(&mut self, allocation: &Allocation, contents: Vec<u8>, priority: u32) {
+        let index = self.pending_uploads.partition_point(|pending| pending.priority >= priority);
+        self.pending_uploads.insert(
+            index,
+            PendingUpload {
+                allocation: allocation.clone(),
+                contents,
+                priority,
+            },
+        );
+    }
+
+    /// Performs as many pending uploads as [`UploadPolicy::per_frame_budget`] allows, in priority
+    /// order, and returns how many were performed.
+    ///
+    /// This stops at the first upload that would exceed the budget rather than skipping ahead to
+    /// smaller ones, so that lower-priority data never overtakes higher-priority data. The
+    /// remaining uploads stay queued for the next call.
+    pub fn upload_pending(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> usize {
+        let mut count = 0;
+        while let Some(pending) = self.pending_uploads.get(count) {
+            let PendingUpload { allocation, contents, .. } = pending;
+            if self.upload(device, queue, encoder, allocation, contents).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        self.pending_uploads.drain(..count);
+
+        count
+    }
+
+    /// The number of uploads deferred by [`Self::defer_upload`] that have yet to be performed.
+    pub fn pending_upload_count(&self) -> usize {
+        self.pending_uploads.len()
+    }
+
+    /// The total size, in bytes, of the uploads deferred by [`Self::defer_upload`] that have yet to
+    /// be performed.
+    pub fn pending_upload_bytes(&self) -> BufferAddress {
+        self.pending_uploads.iter().map(|pending| pending.contents.len() as BufferAddress).sum()
+    }
+
+    /// Writes `allocation` directly from a memory-mapped file, starting at byte `src_offset` of
+    /// the file.
+    ///
+    /// See [`Heap::write_from_mmap`].
+    #[cfg(feature = "memmap2")]
+    pub fn write_from_mmap(
+        &self,
+        allocation: &Allocation,
+        mmap: &memmap2::Mmap,
+        src_offset: usize,
+    ) {
+        self.debug_assert_live(allocation);
+        let range = allocation.range_in_heap.clone();
+        self[allocation.arena_key].0.write_from_mmap(range.clone(), mmap, src_offset);
+        self.record_frame(|counters| counters.bytes_written += range.end - range.start);
+        self.record_written(allocation);
+    }
+
+    /// Flushes every region of every heap written since it was last flushed with this method, as
+    /// with [`Heap::flush_dirty`], returning the number of copies recorded.
+    pub fn flush_dirty(&self, encoder: &mut wgpu::CommandEncoder) -> usize {
+        let mut copies = 0;
+        for (size_class, pool) in self.pools() {
+            for (index_in_pool, (heap, _)) in pool.heaps.iter().enumerate() {
+                let ranges = heap.flush_dirty(encoder);
+                let arena_key = pool.key(size_class, index_in_pool);
+                for range in ranges.iter() {
+                    self.observe(|observer| observer.flushed(arena_key, range.clone()));
+                    self.record_use(arena_key, range.clone());
+                }
+                let bytes: BufferAddress = ranges.iter().map(|range| range.end - range.start).sum();
+                pool.record(|metrics| metrics.copies_recorded += ranges.len() as u64);
+                self.record_frame(|counters| {
+                    counters.bytes_flushed += bytes;
+                    counters.flush_commands += ranges.len() as u64;
+                });
+                copies += ranges.len();
+            }
+        }
+
+        copies
+    }
+
+    /// Flushes every region of every heap written since it was last flushed into the encoder of
+    /// `transfer`, as with [`Self::flush_dirty`], returning the number of copies recorded.
+    pub fn flush_with(&self, transfer: &TransferContext) -> usize {
+        transfer.record(|encoder| self.flush_dirty(encoder))
+    }
+
+    /// Flushes the whole of every heap, as with [`Heap::flush`].
+    ///
+    /// This records one copy per heap with staging memory, however little of it was written, so
+    /// [`Self::flush_dirty`] is usually cheaper.
+    pub fn flush_all(&self, encoder: &mut wgpu::CommandEncoder) {
+        for (size_class, pool) in self.pools() {
+            for (index_in_pool, (heap, _)) in pool.heaps.iter().enumerate() {
+                if heap.upload_strategy() == UploadStrategy::QueueWrite {
+                    continue;
+                }
+                heap.flush(encoder);
+                let arena_key = pool.key(size_class, index_in_pool);
+                self.observe(|observer| observer.flushed(arena_key, 0..heap.size().get()));
+                self.record_use(arena_key, 0..heap.size().get());
+                pool.record(|metrics| metrics.copies_recorded += 1);
+                self.record_frame(|counters| {
+                    counters.bytes_flushed += heap.size().get();
+                    counters.flush_commands += 1;
+                });
+            }
+        }
+    }
+
+    pub fn flush_range(&self, encoder: &mut wgpu::CommandEncoder, allocation: &Allocation) {
+        self.debug_assert_live(allocation);
+        self.flush_heap_range(encoder, allocation.arena_key, allocation.range_in_heap.clone());
+    }
+
+    /// Flushes `range` of the heap at `key`, which may span several allocations.
+    pub(crate) fn flush_heap_range(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        key: ArenaKey,
+        range: Range<BufferAddress>,
+    ) {
+        self[key].0.flush_range(encoder, range.clone());
+        self.observe(|observer| observer.flushed(key, range.clone()));
+        self.record_use(key, range.clone());
+        self.pool(key.size_class).record(|metrics| metrics.copies_recorded += 1);
+        self.record_frame(|counters| {
+            counters.bytes_flushed += range.end - range.start;
+            counters.flush_commands += 1;
+        });
+    }
 }
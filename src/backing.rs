@@ -0,0 +1,170 @@
+//! What an [`Allocator`](crate::Allocator) is created for.
+//!
+//! Allocators only need to know how large the memory they manage is, so they are created with
+//! [`Allocator::with_capacity`](crate::Allocator::with_capacity), or from any [`HeapBacking`] with
+//! [`Allocator::new`](crate::Allocator::new). A [`MockHeap`] is a backing with a size but no GPU
+//! memory, which stands in for a [`Heap`] where one is expected without a [`wgpu::Device`].
+//!
+//! A [`HeapArena`](crate::HeapArena) additionally creates, writes, and copies between the heaps
+//! that it places allocations in, all of which it does through a [`GpuBacking`]. Arenas use the
+//! [`Wgpu`] backing unless told otherwise; implementing the trait for buffers of another API, such
+//! as those of ash or vulkano, lets a renderer built on it reuse the placement logic of arenas.
+
+use wgpu::BufferAddress;
+
+use std::{fmt, ops::Range};
+
+use crate::{
+    mapping::{MapState, NotMapped},
+    Allocator,
+    CopyError,
+    Heap,
+    HeapDescriptor,
+    HeapUsages,
+    NonZeroBufferAddress,
+    RawHeap,
+    VirtualHeap,
+};
+
+/// The memory managed by an [`Allocator`](crate::Allocator).
+pub trait HeapBacking {
+    /// The size, in bytes, of the memory.
+    fn size(&self) -> NonZeroBufferAddress;
+}
+
+impl HeapBacking for Heap {
+    fn size(&self) -> NonZeroBufferAddress {
+        self.size
+    }
+}
+
+impl<A: Allocator> HeapBacking for RawHeap<'_, A> {
+    fn size(&self) -> NonZeroBufferAddress {
+        RawHeap::size(self)
+    }
+}
+
+impl<A: Allocator> HeapBacking for VirtualHeap<A> {
+    fn size(&self) -> NonZeroBufferAddress {
+        VirtualHeap::size(self)
+    }
+}
+
+/// A stand-in for a [`Heap`] that has a size and usage but no buffers behind it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MockHeap {
+    size: NonZeroBufferAddress,
+    usage: HeapUsages,
+}
+
+impl MockHeap {
+    pub fn new(size: NonZeroBufferAddress, usage: HeapUsages) -> Self {
+        Self { size, usage }
+    }
+
+    pub fn usage(&self) -> HeapUsages {
+        self.usage
+    }
+}
+
+impl HeapBacking for MockHeap {
+    fn size(&self) -> NonZeroBufferAddress {
+        self.size
+    }
+}
+
+/// The GPU API that the heaps of a [`HeapArena`](crate::HeapArena) are created with.
+///
+/// Only allocation, deallocation, and the bookkeeping around them are available on arenas of any
+/// backing; binding, flushing, uploading, and the like are specific to [`Wgpu`].
+pub trait GpuBacking {
+    /// What heaps are created with.
+    type Device: ?Sized;
+    /// What copies between heaps are recorded into.
+    type Encoder: ?Sized;
+    /// A buffer of GPU memory, along with any CPU-side memory used to upload to it.
+    type Heap: HeapBacking + fmt::Debug;
+
+    /// The size, in bytes, of the largest heap that can be created with `device`.
+    fn max_heap_size(device: &Self::Device) -> BufferAddress;
+
+    /// Creates a heap as described by `descriptor`.
+    fn create_heap(device: &Self::Device, descriptor: &HeapDescriptor) -> Self::Heap;
+
+    /// Writes `contents` to `range` of the staging memory of `heap`, to be copied to the GPU by a
+    /// later flush.
+    ///
+    /// # Errors
+    ///
+    /// This fails, writing nothing, if `heap` has no staging memory or it isn't mapped.
+    fn write(
+        heap: &Self::Heap,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+    ) -> Result<(), NotMapped>;
+
+    /// Requests that the staging memory of `heap`, if any, be mapped so that it can be written
+    /// again. The mapping may complete later.
+    fn map(heap: &Self::Heap);
+
+    /// Unmaps the staging memory of `heap`, if any, so that it can be copied from.
+    fn unmap(heap: &Self::Heap);
+
+    /// Records a copy of `source_range` of `source` into `destination`, starting at
+    /// `destination_offset`.
+    ///
+    /// # Errors
+    ///
+    /// This fails, recording nothing, if the copy is invalid; see [`Heap::copy_to`].
+    fn copy(
+        encoder: &mut Self::Encoder,
+        source: &Self::Heap,
+        source_range: Range<BufferAddress>,
+        destination: &Self::Heap,
+        destination_offset: BufferAddress,
+    ) -> Result<(), CopyError>;
+}
+
+/// The [`GpuBacking`] of [`Heap`]s, which is used by every arena unless told otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Wgpu;
+
+impl GpuBacking for Wgpu {
+    type Device = wgpu::Device;
+    type Encoder = wgpu::CommandEncoder;
+    type Heap = Heap;
+
+    fn max_heap_size(device: &wgpu::Device) -> BufferAddress {
+        device.limits().max_buffer_size
+    }
+
+    fn create_heap(device: &wgpu::Device, descriptor: &HeapDescriptor) -> Heap {
+        Heap::with_descriptor(device, descriptor)
+    }
+
+    fn write(heap: &Heap, range: Range<BufferAddress>, contents: &[u8]) -> Result<(), NotMapped> {
+        if heap.staging_buffer.is_none() {
+            return Err(NotMapped { state: MapState::Unmapped });
+        }
+
+        heap.checked_write(range, contents)
+    }
+
+    fn map(heap: &Heap) {
+        heap.map_range_async(0..heap.size().get(), wgpu::MapMode::Write);
+    }
+
+    fn unmap(heap: &Heap) {
+        heap.unmap();
+    }
+
+    fn copy(
+        encoder: &mut wgpu::CommandEncoder,
+        source: &Heap,
+        source_range: Range<BufferAddress>,
+        destination: &Heap,
+        destination_offset: BufferAddress,
+    ) -> Result<(), CopyError> {
+        source.copy_to(encoder, source_range, destination, destination_offset)
+    }
+}
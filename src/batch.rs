@@ -0,0 +1,105 @@
+//! Batching of writes to the allocations of an arena into as few flushes as possible.
+//!
+//! Flushing every write as soon as it is made, as [`HeapArena::write_and_flush`] does, records a
+//! copy per write. A [`WriteBatcher`] instead writes into staging memory right away but only
+//! remembers what it wrote, and then flushes all of it at once with [`WriteBatcher::finish`],
+//! typically at the end of a frame. Writes to the same heap are merged wherever they overlap or
+//! are adjacent, so each heap is flushed with as few copies as possible.
+//!
+//! [`HeapArena::write_and_flush`]: crate::HeapArena::write_and_flush
+
+use wgpu::BufferAddress;
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    align_range_for_copy,
+    arena::{Allocation, ArenaKey},
+    coalesce_ranges,
+    HeapArena,
+};
+
+/// A record of the writes to the allocations of a [`HeapArena`] that have yet to be flushed.
+///
+/// See the [module-level documentation](self) for details. A batcher does not borrow the arena,
+/// so that allocations can still be made and freed while writes are collected, but every call
+/// must be given the same arena.
+#[derive(Debug, Default)]
+pub struct WriteBatcher {
+    /// The ranges written in each heap, widened to [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    written: BTreeMap<ArenaKey, Vec<Range<BufferAddress>>>,
+    /// The number of writes since the last call to [`Self::finish`].
+    write_count: usize,
+    /// The number of bytes written since the last call to [`Self::finish`].
+    bytes_written: BufferAddress,
+}
+
+impl WriteBatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `contents` into the staging memory of `allocation`, to be flushed by
+    /// [`Self::finish`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics as [`HeapArena::write`] does.
+    pub fn write<A>(&mut self, arena: &HeapArena<A>, allocation: &Allocation, contents: &[u8]) {
+        arena.write(allocation, contents);
+        self.record(arena, allocation, contents.len());
+    }
+
+    /// Like [`Self::write`], but lays `contents` out as [`HeapArena::write_slice`] does.
+    pub fn write_slice<A, T: bytemuck::Pod>(
+        &mut self,
+        arena: &HeapArena<A>,
+        allocation: &Allocation,
+        contents: &[T],
+    ) {
+        arena.write_slice(allocation, contents);
+        self.record(arena, allocation, std::mem::size_of_val(contents));
+    }
+
+    fn record<A>(&mut self, arena: &HeapArena<A>, allocation: &Allocation, len: usize) {
+        let heap_size = arena.heap_for(allocation).size().get();
+        let range = align_range_for_copy(allocation.range_in_heap.clone(), heap_size);
+        self.written.entry(allocation.arena_key).or_default().push(range);
+        self.write_count += 1;
+        self.bytes_written += len as BufferAddress;
+    }
+
+    /// The number of writes since the last call to [`Self::finish`].
+    pub fn len(&self) -> usize {
+        self.write_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.write_count == 0
+    }
+
+    /// The number of bytes written since the last call to [`Self::finish`].
+    pub fn bytes_written(&self) -> BufferAddress {
+        self.bytes_written
+    }
+
+    /// Flushes everything written since the last call to this method into `encoder`, returning
+    /// the number of copies recorded.
+    ///
+    /// As with any flush, the staging memory of the arena must be unmapped before `encoder` is
+    /// submitted. Afterwards, this batcher is empty and can be reused for the next frame.
+    pub fn finish<A>(&mut self, arena: &HeapArena<A>, encoder: &mut wgpu::CommandEncoder) -> usize {
+        let mut copy_count = 0;
+        for (arena_key, mut ranges) in std::mem::take(&mut self.written) {
+            coalesce_ranges(&mut ranges);
+            for range_in_heap in ranges {
+                arena.flush_heap_range(encoder, arena_key, range_in_heap);
+                copy_count += 1;
+            }
+        }
+        self.write_count = 0;
+        self.bytes_written = 0;
+
+        copy_count
+    }
+}
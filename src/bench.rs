@@ -0,0 +1,190 @@
+//! Deterministic workloads for comparing allocators.
+//!
+//! This module is only available with the `bench` feature enabled. It generates sequences of
+//! allocations and deallocations shaped like common uses of heaps, and replays them against an
+//! [`Allocator`] that manages a virtual heap: a capacity in bytes with no GPU buffer behind it.
+//! Workloads therefore run without a device, and the same seed always produces the same sequence,
+//! so results can be compared across allocators, machines, and revisions.
+//!
+//! The criterion benchmarks in `benches/allocators.rs` replay every workload against every
+//! allocator in this crate. Any other [`Allocator`] can be compared with [`Workload::run`].
+
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{Allocator, NonZeroBufferAddress};
+
+/// A single step of a [`Workload`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// Allocates `size` bytes aligned to `alignment`.
+    Alloc { size: NonZeroBufferAddress, alignment: NonZeroBufferAddress },
+    /// Frees the live allocation at `index` modulo the number of live allocations, counting from
+    /// the oldest. This does nothing if nothing is live.
+    Dealloc { index: usize },
+    /// Frees the most recent live allocation. This does nothing if nothing is live.
+    DeallocNewest,
+}
+
+/// A sequence of [`Op`]s to be replayed against an allocator of a virtual heap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workload {
+    /// A short name for the workload, suitable as a benchmark ID.
+    pub name: &'static str,
+    /// The size, in bytes, of the virtual heap that the workload is meant to run in.
+    pub capacity: NonZeroBufferAddress,
+    pub ops: Vec<Op>,
+}
+
+impl Workload {
+    /// Per-object uniform buffers: each of `frames` frames allocates between 16 and 64 blocks of
+    /// 64 to 256 bytes, each aligned to 256 bytes, then frees them all in reverse order.
+    pub fn uniform_heavy(seed: u64, frames: usize) -> Self {
+        let mut rng = SplitMix64(seed);
+        let mut ops = Vec::new();
+        for _ in 0..frames {
+            let count = rng.range(16..65);
+            for _ in 0..count {
+                let size = nonzero(rng.range(1..5) * 64);
+                ops.push(Op::Alloc { size, alignment: nonzero(256) });
+            }
+            ops.extend(std::iter::repeat_n(Op::DeallocNewest, count as usize));
+        }
+
+        Self { name: "uniform-heavy", capacity: nonzero(64 * 256), ops }
+    }
+
+    /// Streamed vertex data: each of `frames` frames allocates between 1 and 8 buffers of 1 to 16
+    /// KiB, each aligned to 4 bytes, and frees the oldest buffers once more than 24 are live.
+    pub fn vertex_streaming(seed: u64, frames: usize) -> Self {
+        const MAX_LIVE: usize = 24;
+
+        let mut rng = SplitMix64(seed);
+        let mut ops = Vec::new();
+        let mut live = 0;
+        for _ in 0..frames {
+            for _ in 0..rng.range(1..9) {
+                let size = nonzero(rng.range(1..17) * 1024);
+                ops.push(Op::Alloc { size, alignment: nonzero(4) });
+                live += 1;
+            }
+            while live > MAX_LIVE {
+                ops.push(Op::Dealloc { index: 0 });
+                live -= 1;
+            }
+        }
+
+        Self { name: "vertex-streaming", capacity: nonzero((MAX_LIVE as u64 + 8) * 16384), ops }
+    }
+
+    /// Random churn: `count` operations, each equally likely to allocate between 1 and 4,096 bytes
+    /// aligned to a power of two up to 256 bytes, or to free a random live allocation.
+    pub fn random_churn(seed: u64, count: usize) -> Self {
+        let mut rng = SplitMix64(seed);
+        let ops = (0..count)
+            .map(|_| match rng.range(0..2) {
+                0 => Op::Alloc {
+                    size: nonzero(rng.range(1..4097)),
+                    alignment: nonzero(1 << rng.range(0..9)),
+                },
+                _ => Op::Dealloc { index: rng.next() as usize },
+            })
+            .collect();
+
+        Self { name: "random-churn", capacity: nonzero(1 << 20), ops }
+    }
+
+    /// Every built-in workload, generated from `seed` at a size that replays quickly.
+    pub fn all(seed: u64) -> Vec<Self> {
+        vec![
+            Self::uniform_heavy(seed, 64),
+            Self::vertex_streaming(seed, 256),
+            Self::random_churn(seed, 4096),
+        ]
+    }
+
+    /// Replays this workload against a new allocator of a virtual heap of [`Self::capacity`]
+    /// bytes.
+    pub fn run<A: Allocator>(&self) -> ReplayStats {
+        self.replay(&mut A::with_capacity(self.capacity))
+    }
+
+    /// Replays this workload against `allocator`, which is left holding whatever the workload
+    /// didn't free.
+    pub fn replay(&self, allocator: &mut impl Allocator) -> ReplayStats {
+        let mut stats = ReplayStats::default();
+        let mut live: Vec<Range<BufferAddress>> = Vec::new();
+        let mut live_bytes = 0;
+        for op in self.ops.iter() {
+            let index = match *op {
+                Op::Alloc { size, alignment } => {
+                    match allocator.alloc(size, alignment) {
+                        Ok(range) => {
+                            stats.allocations += 1;
+                            live_bytes += range.end - range.start;
+                            stats.peak_live_bytes = stats.peak_live_bytes.max(live_bytes);
+                            live.push(range);
+                        }
+                        Err(_) => stats.failed_allocations += 1,
+                    }
+                    continue;
+                }
+                Op::Dealloc { index } if !live.is_empty() => index % live.len(),
+                Op::DeallocNewest if !live.is_empty() => live.len() - 1,
+                Op::Dealloc { .. } | Op::DeallocNewest => continue,
+            };
+
+            // SAFETY: `live` only holds ranges allocated by `allocator` and not yet freed.
+            match unsafe { allocator.dealloc(live[index].clone()) } {
+                Ok(()) => {
+                    stats.deallocations += 1;
+                    let range = live.remove(index);
+                    live_bytes -= range.end - range.start;
+                }
+                Err(_) => stats.failed_deallocations += 1,
+            }
+        }
+
+        stats
+    }
+}
+
+/// What happened while replaying a [`Workload`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ReplayStats {
+    pub allocations: usize,
+    /// The number of allocations that failed, such as because the virtual heap was full.
+    pub failed_allocations: usize,
+    pub deallocations: usize,
+    /// The number of deallocations that the allocator refused, such as out-of-order frees from a
+    /// [`Stack`].
+    pub failed_deallocations: usize,
+    /// The most bytes that were allocated at once.
+    pub peak_live_bytes: BufferAddress,
+}
+
+fn nonzero(value: u64) -> NonZeroBufferAddress {
+    NonZeroBufferAddress::new(value).unwrap()
+}
+
+/// A small, fast, deterministic pseudorandom number generator.
+///
+/// See <https://prng.di.unimi.it/splitmix64.c>.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// A number in `range`, which must not be empty.
+    fn range(&mut self, range: Range<u64>) -> u64 {
+        range.start + self.next() % (range.end - range.start)
+    }
+}
@@ -0,0 +1,316 @@
+//! Integration with the render app of [Bevy](https://bevyengine.org) 0.13.
+//!
+//! Arenas are inserted into the render app as resources: a [`HeapArenaResource`] for buffers
+//! such as uniforms, and a [`MeshArenaResource`] for meshes. They are typically created in the
+//! [`Plugin::finish`] of a custom render pipeline, once the [`RenderDevice`] exists, and used from
+//! its systems through `ResMut`.
+//!
+//! [`HeapArenaPlugin`] then drives every arena of an allocator type through the frame:
+//!
+//! - In the [`ExtractSchedule`], it begins a new frame, as with [`HeapArena::begin_frame`], once
+//!   staging memory has been mapped again.
+//! - In [`RenderSet::PrepareResourcesFlush`], it unmaps staging memory and flushes what was
+//!   written, submitting the copies before any render pass reads the data. Writes must therefore
+//!   be made in [`RenderSet::PrepareResources`] or earlier. Right after the submission, it
+//!   requests that staging memory be mapped again.
+//! - In [`RenderSet::Cleanup`], it polls the device without blocking, so that the mappings
+//!   complete as soon as the GPU has finished the copies.
+//!
+//! Heaps become writable as the callbacks of their mappings fire, which usually happens before
+//! the next frame begins. Only if a mapping is still pending by then, because the GPU has fallen
+//! a frame behind, does the next frame block until it completes.
+//!
+//! This feature builds against wgpu 0.19, the version used by Bevy 0.13, and so can't be combined
+//! with the `wgpu-22` feature.
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::{
+    schedule::{common_conditions::resource_exists, IntoSystemConfigs},
+    system::{Res, ResMut, Resource},
+};
+use bevy_render::{
+    renderer::{RenderDevice, RenderQueue},
+    ExtractSchedule,
+    Render,
+    RenderApp,
+    RenderSet,
+};
+use wgpu::BufferAddress;
+
+use std::{
+    marker::PhantomData,
+    num::NonZeroU32,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    arena::Allocation,
+    mesh::{MeshAllocator, MeshSlice},
+    AllocError,
+    Allocator,
+    GrowthPolicy,
+    HeapArena,
+    HeapUsages,
+    MapState,
+    NonZeroBufferAddress,
+};
+
+/// A [`HeapArena`] in the render world, kept in step with each frame by [`HeapArenaPlugin`].
+///
+/// Resources must be [`Sync`], which `HeapArena` is not, so the arena is held behind a [`Mutex`].
+/// Systems with `ResMut` reach it through [`Self::get_mut`] without locking.
+#[derive(Debug, Resource)]
+pub struct HeapArenaResource<A> {
+    arena: Mutex<HeapArena<A>>,
+}
+
+impl<A> HeapArenaResource<A> {
+    pub fn new(arena: HeapArena<A>) -> Self {
+        Self { arena: Mutex::new(arena) }
+    }
+
+    /// Creates the resource of an arena whose allocations can be bound at dynamic offsets on
+    /// `device`, as with [`HeapArena::with_limits`].
+    pub fn for_device(
+        device: &RenderDevice,
+        usage: HeapUsages,
+        growth_policy: impl GrowthPolicy + Send + 'static,
+    ) -> Self {
+        Self::new(HeapArena::with_limits(usage, growth_policy, &device.limits()))
+    }
+
+    pub fn get_mut(&mut self) -> &mut HeapArena<A> {
+        self.arena.get_mut().unwrap()
+    }
+
+    /// Locks the arena for access through a shared reference, such as from a system with `Res`.
+    pub fn lock(&self) -> MutexGuard<'_, HeapArena<A>> {
+        self.arena.lock().unwrap()
+    }
+
+    pub fn into_inner(self) -> HeapArena<A> {
+        self.arena.into_inner().unwrap()
+    }
+}
+
+impl<A: Allocator> HeapArenaResource<A> {
+    /// Allocates room for `value` and writes it, laid out for the usage of the arena, to be
+    /// flushed by [`HeapArenaPlugin`] this frame.
+    ///
+    /// # Errors
+    ///
+    /// See [`HeapArena::alloc`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the staging memory of the heap is not mapped, such as when called
+    /// after [`RenderSet::PrepareResourcesFlush`].
+    pub fn upload_uniform<T: bytemuck::Pod>(
+        &mut self,
+        device: &RenderDevice,
+        value: &T,
+    ) -> Result<Allocation, AllocError> {
+        self.upload_slice(device, std::slice::from_ref(value))
+    }
+
+    /// Like [`Self::upload_uniform`], but for an array of values, as with
+    /// [`HeapArena::alloc_for`] and [`HeapArena::write_slice`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `contents` is empty, or as [`Self::upload_uniform`] does.
+    pub fn upload_slice<T: bytemuck::Pod>(
+        &mut self,
+        device: &RenderDevice,
+        contents: &[T],
+    ) -> Result<Allocation, AllocError> {
+        let count = NonZeroBufferAddress::new(contents.len() as BufferAddress)
+            .expect("cannot upload an empty slice");
+        let arena = self.get_mut();
+        let allocation = arena.alloc_for::<T>(device.wgpu_device(), count)?;
+        arena.write_slice(&allocation, contents);
+
+        Ok(allocation)
+    }
+}
+
+/// A [`MeshAllocator`] in the render world, kept in step with each frame by
+/// [`HeapArenaPlugin`].
+///
+/// As with [`HeapArenaResource`], the allocator is held behind a [`Mutex`].
+#[derive(Debug, Resource)]
+pub struct MeshArenaResource<A> {
+    meshes: Mutex<MeshAllocator<A>>,
+}
+
+impl<A> MeshArenaResource<A> {
+    pub fn new(meshes: MeshAllocator<A>) -> Self {
+        Self { meshes: Mutex::new(meshes) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut MeshAllocator<A> {
+        self.meshes.get_mut().unwrap()
+    }
+
+    /// Locks the allocator for access through a shared reference, such as from a system with
+    /// `Res`.
+    pub fn lock(&self) -> MutexGuard<'_, MeshAllocator<A>> {
+        self.meshes.lock().unwrap()
+    }
+
+    pub fn into_inner(self) -> MeshAllocator<A> {
+        self.meshes.into_inner().unwrap()
+    }
+}
+
+impl<A: Allocator> MeshArenaResource<A> {
+    /// Allocates a mesh and writes its vertices and indices, to be flushed by [`HeapArenaPlugin`]
+    /// this frame.
+    ///
+    /// The vertex count is the size of `vertices` over the vertex stride of the allocator, and
+    /// the index count is the length of `indices`, which must be of its index format.
+    ///
+    /// # Errors
+    ///
+    /// See [`MeshAllocator::alloc`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `vertices` or `indices` is empty, if either doesn't match the layout
+    /// of the allocator, as with [`MeshAllocator::write`], or as
+    /// [`HeapArenaResource::upload_uniform`] does.
+    pub fn upload_mesh<V: bytemuck::Pod, I: bytemuck::Pod>(
+        &mut self,
+        device: &RenderDevice,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshSlice, AllocError> {
+        let meshes = self.get_mut();
+        let vertex_count = std::mem::size_of_val(vertices) as BufferAddress
+            / meshes.vertex_stride().get();
+        let vertex_count = u32::try_from(vertex_count)
+            .ok()
+            .and_then(NonZeroU32::new)
+            .expect("mesh must have between 1 and `u32::MAX` vertices");
+        let index_count = u32::try_from(indices.len())
+            .ok()
+            .and_then(NonZeroU32::new)
+            .expect("mesh must have between 1 and `u32::MAX` indices");
+
+        let mesh = meshes.alloc(device.wgpu_device(), vertex_count, index_count)?;
+        meshes.write(&mesh, vertices, indices);
+
+        Ok(mesh)
+    }
+}
+
+/// The resources in the render world that [`HeapArenaPlugin`] drives.
+trait ArenaResource: Resource {
+    type Allocator: Allocator;
+
+    fn arena_mut(&mut self) -> &mut HeapArena<Self::Allocator>;
+}
+
+impl<A: Allocator + Send + 'static> ArenaResource for HeapArenaResource<A> {
+    type Allocator = A;
+
+    fn arena_mut(&mut self) -> &mut HeapArena<A> {
+        self.get_mut()
+    }
+}
+
+impl<A: Allocator + Send + 'static> ArenaResource for MeshArenaResource<A> {
+    type Allocator = A;
+
+    fn arena_mut(&mut self) -> &mut HeapArena<A> {
+        self.get_mut().arena_mut()
+    }
+}
+
+/// Flushes, unmaps, and remaps the [`HeapArenaResource`] and [`MeshArenaResource`] of allocator
+/// type `A` in the render app, as described in the [module-level documentation](self).
+///
+/// Either resource may be inserted at any time; the systems of this plugin only run while it
+/// exists.
+#[derive(Debug)]
+pub struct HeapArenaPlugin<A> {
+    flush_whole_heaps: bool,
+    _allocator: PhantomData<fn() -> A>,
+}
+
+impl<A> Default for HeapArenaPlugin<A> {
+    fn default() -> Self {
+        Self { flush_whole_heaps: false, _allocator: PhantomData }
+    }
+}
+
+impl<A> HeapArenaPlugin<A> {
+    /// Creates a plugin that flushes only the regions written during each frame, as with
+    /// [`HeapArena::flush_dirty`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the plugin flush the whole of every heap each frame, as with
+    /// [`HeapArena::flush_all`].
+    ///
+    /// This is needed if heaps are written other than through the arena, such as directly through
+    /// a [`Heap`](crate::Heap).
+    pub fn flushing_whole_heaps(mut self) -> Self {
+        self.flush_whole_heaps = true;
+        self
+    }
+}
+
+impl<A: Allocator + Send + 'static> Plugin for HeapArenaPlugin<A> {
+    fn build(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        add_systems::<HeapArenaResource<A>>(render_app, self.flush_whole_heaps);
+        add_systems::<MeshArenaResource<A>>(render_app, self.flush_whole_heaps);
+    }
+}
+
+fn add_systems<R: ArenaResource>(render_app: &mut App, flush_whole_heaps: bool) {
+    let flush =
+        move |mut resource: ResMut<R>, device: Res<RenderDevice>, queue: Res<RenderQueue>| {
+            let arena = resource.arena_mut();
+            arena.unmap();
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("heap-arena-flush"),
+            });
+            if flush_whole_heaps {
+                arena.flush_all(&mut encoder);
+            } else {
+                arena.flush_dirty(&mut encoder);
+            }
+            queue.submit(Some(encoder.finish()));
+            // The mappings complete, and their callbacks fire, once the GPU has finished the copies
+            // and the device is polled.
+            arena.remap();
+        };
+
+    render_app
+        .add_systems(ExtractSchedule, begin_frame::<R>.run_if(resource_exists::<R>))
+        .add_systems(
+            Render,
+            flush.run_if(resource_exists::<R>).in_set(RenderSet::PrepareResourcesFlush),
+        )
+        .add_systems(Render, poll.run_if(resource_exists::<R>).in_set(RenderSet::Cleanup));
+}
+
+fn begin_frame<R: ArenaResource>(mut resource: ResMut<R>, device: Res<RenderDevice>) {
+    let arena = resource.arena_mut();
+    device.poll(wgpu::Maintain::Poll);
+    // Note: staging memory can't be written until it is mapped, so a frame that begins before
+    // the GPU has finished the copies of the last one must wait for them.
+    if arena.heaps().any(|(heap, _)| heap.map_state() == MapState::Pending) {
+        device.poll(wgpu::Maintain::Wait);
+    }
+    arena.begin_frame();
+}
+
+fn poll(device: Res<RenderDevice>) {
+    device.poll(wgpu::Maintain::Poll);
+}
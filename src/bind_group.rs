@@ -0,0 +1,119 @@
+//! Bind groups for ranges of heaps, created on first use and then reused.
+//!
+//! Binding a sub-allocated buffer, such as the uniforms of one object, takes a [`wgpu::BindGroup`]
+//! whose entry is the [`Heap::binding`] of the allocation. Bind groups are expensive to create, so
+//! a [`BindGroupCache`] keeps every bind group it creates, keyed by the heap, the range within it,
+//! and the layout.
+//!
+//! Heaps are identified by their [`HeapId`], which changes whenever a heap's GPU buffer is
+//! replaced, so a bind group of a buffer that no longer belongs to the heap is never returned.
+//! Such bind groups still take up memory until [`BindGroupCache::prune`] or
+//! [`BindGroupCache::invalidate_heap`] is called, which should be done after heaps are destroyed,
+//! grown, or compacted.
+
+use wgpu::BufferAddress;
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::{arena::Allocation, suffixed_label, Heap, HeapArena, HeapId};
+
+/// Identifies a layout added to a [`BindGroupCache`] with [`BindGroupCache::add_layout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayoutId(usize);
+
+/// A cache of bind groups that each bind a range of a heap as their only entry.
+#[derive(Debug, Default)]
+pub struct BindGroupCache {
+    layouts: Vec<wgpu::BindGroupLayout>,
+    bind_groups: HashMap<(HeapId, Range<BufferAddress>, LayoutId), wgpu::BindGroup>,
+}
+
+impl BindGroupCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layout for bind groups to be created with, returning the ID by which to refer to
+    /// it.
+    ///
+    /// The layout must have exactly one entry: a buffer at binding 0.
+    pub fn add_layout(&mut self, layout: wgpu::BindGroupLayout) -> LayoutId {
+        self.layouts.push(layout);
+
+        LayoutId(self.layouts.len() - 1)
+    }
+
+    /// The layout added as `id`.
+    pub fn layout(&self, id: LayoutId) -> &wgpu::BindGroupLayout {
+        &self.layouts[id.0]
+    }
+
+    /// Returns the bind group with layout `layout` that binds `range` of `heap`, creating it if it
+    /// isn't cached yet.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `range` is empty or `layout` was not added to this cache.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        layout: LayoutId,
+        heap: &Heap,
+        range: Range<BufferAddress>,
+    ) -> &wgpu::BindGroup {
+        let layouts = &self.layouts;
+
+        self.bind_groups.entry((heap.id(), range.clone(), layout)).or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: suffixed_label(heap.label(), "bind-group").as_deref(),
+                layout: &layouts[layout.0],
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(heap.binding(range)),
+                }],
+            })
+        })
+    }
+
+    /// Like [`Self::get`], but for `allocation` in `arena`.
+    pub fn get_for<A>(
+        &mut self,
+        device: &wgpu::Device,
+        layout: LayoutId,
+        arena: &HeapArena<A>,
+        allocation: &Allocation,
+    ) -> &wgpu::BindGroup {
+        let (heap, _) = &arena[allocation.arena_key];
+
+        self.get(device, layout, heap, allocation.range_in_heap.clone())
+    }
+
+    /// Drops every bind group of the heap with ID `heap`.
+    pub fn invalidate_heap(&mut self, heap: HeapId) {
+        self.bind_groups.retain(|(id, _, _), _| *id != heap);
+    }
+
+    /// Drops every bind group of a heap that is no longer part of `arena`, such as because it was
+    /// destroyed, grown, or evacuated by [`HeapArena::compact`].
+    ///
+    /// Bind groups of heaps that aren't part of `arena` at all are dropped, too, so a cache that
+    /// is pruned this way should only be used with one arena.
+    pub fn prune<A>(&mut self, arena: &HeapArena<A>) {
+        let live: Vec<HeapId> = arena.heaps().map(|(heap, _)| heap.id()).collect();
+        self.bind_groups.retain(|(id, _, _), _| live.contains(id));
+    }
+
+    /// Drops every bind group.
+    pub fn clear(&mut self) {
+        self.bind_groups.clear();
+    }
+
+    /// The number of cached bind groups.
+    pub fn len(&self) -> usize {
+        self.bind_groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bind_groups.is_empty()
+    }
+}
@@ -0,0 +1,254 @@
+//! Caching of recreatable GPU data, such as glyphs or streamed textures, with least-recently-used
+//! eviction.
+//!
+//! A [`CachedArena`] maps keys of the application, such as asset IDs, to allocations in a
+//! [`HeapArena`]. [`CachedArena::get_or_alloc`] reuses the allocation of a key if it has one, and
+//! otherwise allocates and writes one from data produced on demand. When a new allocation would
+//! exceed the budget of the arena (see [`HeapArena::set_budget`]), the least recently used
+//! entries whose memory could be reused for it are evicted until it fits.
+//!
+//! Each use of an entry is tagged with a fence, such as the [`Serial`] of the submission that
+//! will read it, and an entry is only evicted once its fence has been reported complete with
+//! [`CachedArena::retire_completed`], so the GPU never reads memory that has been reused.
+
+use wgpu::BufferAddress;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+use crate::{
+    arena::{Allocation, EmptyHeapPolicy},
+    pad_for_copy,
+    queue::Serial,
+    sizes,
+    AllocError,
+    Allocator,
+    HeapArena,
+    NonZeroBufferAddress,
+};
+
+/// A [`HeapArena`] of cached data, keyed by `K`.
+///
+/// See the [module-level documentation](self) for details. Allocations returned by this cache
+/// belong to it: they must not be freed through the arena, and must not be used after their
+/// entry is evicted or removed, so they are best looked up again each frame.
+#[derive(Debug)]
+pub struct CachedArena<K, A> {
+    arena: HeapArena<A>,
+    entries: HashMap<K, CacheEntry>,
+    /// The key of every entry, by the time it was last used, from least to most recent.
+    recency: BTreeMap<u64, K>,
+    /// The number of uses of entries so far, which orders them by recency.
+    clock: u64,
+    /// The greatest fence reported to [`Self::retire_completed`].
+    completed: Serial,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    allocation: Allocation,
+    /// The value of [`CachedArena::clock`] when this entry was last used.
+    last_used: u64,
+    /// The greatest fence that this entry was used with.
+    fence: Serial,
+}
+
+impl<K, A> CachedArena<K, A> {
+    /// Creates an empty cache of allocations in `arena`.
+    ///
+    /// Without a budget, the arena grows without bound and nothing is evicted except by
+    /// [`Self::evict_lru`]. Memory freed by evictions is only reused by entries of the same size
+    /// class unless the arena releases emptied heaps (see [`HeapArena::set_empty_heap_policy`]).
+    pub fn new(arena: HeapArena<A>) -> Self {
+        Self {
+            arena,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            completed: 0,
+        }
+    }
+
+    pub fn arena(&self) -> &HeapArena<A> {
+        &self.arena
+    }
+
+    /// The arena that entries are allocated from, such as for flushing or configuring it.
+    ///
+    /// Allocations made directly in the arena are not entries, and are never evicted.
+    pub fn arena_mut(&mut self) -> &mut HeapArena<A> {
+        &mut self.arena
+    }
+
+    /// The number of entries in this cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Clone + Eq + Hash, A: Allocator> CachedArena<K, A> {
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// The allocation of `key`, if it is cached, which is marked as used with `fence`.
+    pub fn get(&mut self, key: &K, fence: Serial) -> Option<&Allocation> {
+        let entry = self.entries.get_mut(key)?;
+        touch(&mut self.recency, &mut self.clock, entry, fence);
+
+        Some(&entry.allocation)
+    }
+
+    /// The allocation of `key`, which is marked as used with `fence`, allocating `size` bytes and
+    /// writing the result of `data` into them first if `key` isn't cached.
+    ///
+    /// `data` is only called on a miss. As with [`HeapArena::write`], its contents reach the GPU
+    /// once the heap of the allocation is flushed.
+    ///
+    /// # Errors
+    ///
+    /// See [`HeapArena::alloc`]. [`AllocError::BudgetExceeded`] is only returned once no entry
+    /// whose fence has completed is left to evict, or evicting those that are left wouldn't make
+    /// room for the allocation.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `data` returns more than `size` bytes, or as [`HeapArena::write`]
+    /// does.
+    pub fn get_or_alloc<D: AsRef<[u8]>>(
+        &mut self,
+        device: &wgpu::Device,
+        key: K,
+        size: NonZeroBufferAddress,
+        fence: Serial,
+        data: impl FnOnce() -> D,
+    ) -> Result<&Allocation, AllocError> {
+        if !self.entries.contains_key(&key) {
+            let allocation = self.alloc_evicting(device, size)?;
+            let data = data();
+            let contents = data.as_ref();
+            assert!(contents.len() as BufferAddress <= size.get(), "data exceeds size of entry");
+            if !contents.is_empty() {
+                // Note: data shorter than the entry is written to its start, leaving the rest of
+                // it as it was.
+                let contents = pad_for_copy(contents);
+                let start = allocation.range_in_heap.start;
+                let written = Allocation {
+                    range_in_heap: start..(start + contents.len() as BufferAddress),
+                    ..allocation.clone()
+                };
+                self.arena.write(&written, &contents);
+            }
+            self.recency.insert(self.clock, key.clone());
+            let entry = CacheEntry { allocation, last_used: self.clock, fence };
+            self.entries.insert(key.clone(), entry);
+            self.clock += 1;
+        }
+
+        // Note: the entry was either already cached or just inserted.
+        let entry = self.entries.get_mut(&key).unwrap();
+        touch(&mut self.recency, &mut self.clock, entry, fence);
+
+        Ok(&entry.allocation)
+    }
+
+    /// Allocates `size` bytes, evicting entries as needed to stay within the budget of the
+    /// arena.
+    ///
+    /// Only entries whose memory could be reused for the allocation are evicted: those in the
+    /// pool that it would be made in or, if the arena releases emptied heaps, any entry.
+    fn alloc_evicting(
+        &mut self,
+        device: &wgpu::Device,
+        size: NonZeroBufferAddress,
+    ) -> Result<Allocation, AllocError> {
+        let size = size.get().next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        // Note: rounding a nonzero size up can't make it zero.
+        let size = NonZeroBufferAddress::new(size).unwrap();
+        // Entries are written through staging memory, which can only be mapped at this alignment.
+        let alignment = NonZeroBufferAddress::new(wgpu::MAP_ALIGNMENT).unwrap();
+        let size_class = HeapArena::<A>::size_class(size);
+        let releases_heaps = self.arena.empty_heap_policy() == EmptyHeapPolicy::ReleaseTrailing;
+        let can_make_room = |allocation: &Allocation| {
+            releases_heaps || sizes::share_pool(allocation.arena_key.size_class(), size_class)
+        };
+        loop {
+            match self.arena.alloc(device, size, alignment) {
+                Err(AllocError::BudgetExceeded { .. })
+                    if self.evict_lru_where(can_make_room).is_some() => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Removes the entry of `key`, returning whether there was one.
+    ///
+    /// Its allocation is freed once its fence is reported complete, as with
+    /// [`HeapArena::dealloc_deferred`].
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.recency.remove(&entry.last_used);
+        // SAFETY: The allocation belongs to the removed entry, which can't be used again, and is
+        // only freed once every use of it has completed.
+        unsafe { self.arena.dealloc_deferred(entry.allocation, entry.fence) };
+        self.arena.retire_completed(self.completed);
+
+        true
+    }
+
+    /// Evicts the least recently used entry whose fence has completed, returning its key, or
+    /// returns `None` if there is no such entry.
+    pub fn evict_lru(&mut self) -> Option<K> {
+        self.evict_lru_where(|_| true)
+    }
+
+    /// Like [`Self::evict_lru`], but only considers entries whose allocation satisfies
+    /// `predicate`.
+    fn evict_lru_where(&mut self, predicate: impl Fn(&Allocation) -> bool) -> Option<K> {
+        let completed = self.completed;
+        let key = self
+            .recency
+            .values()
+            .find(|&key| {
+                let entry = &self.entries[key];
+                entry.fence <= completed && predicate(&entry.allocation)
+            })?
+            .clone();
+        self.remove(&key);
+
+        Some(key)
+    }
+
+    /// Reports that every use of entries with a fence no greater than `completed` is done, so
+    /// that those entries may be evicted, and frees the allocations of removed entries that were
+    /// waiting on it.
+    ///
+    /// Returns the number of allocations freed, as with [`HeapArena::retire_completed`].
+    pub fn retire_completed(&mut self, completed: Serial) -> usize {
+        self.completed = self.completed.max(completed);
+        self.arena.retire_completed(self.completed)
+    }
+}
+
+/// Marks `entry` as used with `fence`, as the most recently used entry.
+fn touch<K: Clone>(
+    recency: &mut BTreeMap<u64, K>,
+    clock: &mut u64,
+    entry: &mut CacheEntry,
+    fence: Serial,
+) {
+    // Note: every entry is in `recency` under the time it was last used.
+    let key = recency.remove(&entry.last_used).unwrap();
+    entry.last_used = *clock;
+    entry.fence = entry.fence.max(fence);
+    recency.insert(*clock, key);
+    *clock += 1;
+}
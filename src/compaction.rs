@@ -0,0 +1,166 @@
+//! Budgets for, and reports on, the compaction of a [`HeapArena`].
+//!
+//! [`HeapArena::compact`] evacuates every sparsely used heap it can in one go, which may mean
+//! copying a lot of memory at once. [`HeapArena::compact_incremental`] instead moves no more than
+//! a [`CompactionBudget`] allows per call, and picks up where it left off on the next call, so
+//! that heavy fragmentation can be worked off over many frames. Either way, what was done is
+//! described by a [`CompactionReport`].
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::compact`]: crate::HeapArena::compact
+//! [`HeapArena::compact_incremental`]: crate::HeapArena::compact_incremental
+
+use wgpu::BufferAddress;
+
+use std::time::Duration;
+
+use crate::{arena::Relocation, Stats};
+
+/// The most work that one call to
+/// [`HeapArena::compact_incremental`](crate::HeapArena::compact_incremental) may do.
+///
+/// Heaps are evacuated whole, so a call stops before the first heap whose allocations would take
+/// it over either limit. Heaps whose allocations exceed a limit on their own are never evacuated
+/// incrementally. The default budget has no limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompactionBudget {
+    /// The most bytes that may be moved, or `None` for no limit.
+    pub max_bytes: Option<BufferAddress>,
+    /// The most allocations that may be moved, or `None` for no limit.
+    pub max_allocations: Option<usize>,
+}
+
+impl CompactionBudget {
+    /// A budget of at most `max_bytes` bytes moved per call.
+    pub fn bytes(max_bytes: BufferAddress) -> Self {
+        Self { max_bytes: Some(max_bytes), ..Self::default() }
+    }
+
+    /// A budget of at most `max_allocations` allocations moved per call.
+    pub fn allocations(max_allocations: usize) -> Self {
+        Self { max_allocations: Some(max_allocations), ..Self::default() }
+    }
+}
+
+/// Whether the evacuation of a heap fits in what is left of a [`CompactionBudget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Admitted,
+    /// The evacuation doesn't fit in what is left of the budget, but would in a later call.
+    Deferred,
+    /// The evacuation exceeds the whole budget, so it never fits.
+    Refused,
+}
+
+/// The work done so far against a [`CompactionBudget`].
+#[derive(Debug)]
+pub(crate) struct BudgetTracker {
+    budget: CompactionBudget,
+    bytes: BufferAddress,
+    allocations: usize,
+}
+
+impl BudgetTracker {
+    pub(crate) fn new(budget: CompactionBudget) -> Self {
+        Self { budget, bytes: 0, allocations: 0 }
+    }
+
+    /// Decides whether moving `allocations` allocations of `bytes` bytes in total fits, and if
+    /// so, counts them against the budget.
+    pub(crate) fn admit(&mut self, allocations: usize, bytes: BufferAddress) -> Admission {
+        // Note: each limit is paired with the work done so far and the work to be admitted.
+        let max_allocations = self.budget.max_allocations.map(|max| max as u64);
+        let limits = [
+            (self.budget.max_bytes, self.bytes, bytes),
+            (max_allocations, self.allocations as u64, allocations as u64),
+        ];
+        let exceeds = |from_scratch: bool| {
+            limits.iter().any(|&(limit, used, more)| {
+                let used = if from_scratch { 0 } else { used };
+                limit.is_some_and(|limit| used.saturating_add(more) > limit)
+            })
+        };
+        if exceeds(true) {
+            return Admission::Refused;
+        }
+        if exceeds(false) {
+            return Admission::Deferred;
+        }
+        self.bytes += bytes;
+        self.allocations += allocations;
+
+        Admission::Admitted
+    }
+}
+
+/// The progress of an incremental compaction that has yet to finish.
+#[derive(Debug, Default)]
+pub(crate) struct CompactionProgress {
+    /// The index of the pool to resume from, where 0 is the pool of tiny heaps and `n + 1` is
+    /// the `n`th pool after it.
+    pub(crate) next_pool: usize,
+    /// The number of bytes moved by earlier calls.
+    pub(crate) bytes_moved: BufferAddress,
+}
+
+/// What a compaction of a [`HeapArena`](crate::HeapArena) did, and what it left behind.
+#[derive(Debug, Default)]
+pub struct CompactionReport {
+    /// Where each affected allocation went.
+    ///
+    /// This includes allocations that stayed where they were but whose [`ArenaKey`] changed as
+    /// earlier heaps in their pool were destroyed.
+    ///
+    /// [`ArenaKey`]: crate::arena::ArenaKey
+    pub relocations: Vec<Relocation>,
+    /// The total size, in bytes, of the heaps destroyed.
+    pub bytes_reclaimed: BufferAddress,
+    /// The number of allocations whose contents were moved into another heap.
+    pub allocations_moved: usize,
+    /// The total size, in bytes, of the allocations moved, including any guard bands.
+    pub bytes_moved: BufferAddress,
+    /// The number of buffer-to-buffer copies recorded into the command encoder.
+    pub copies_recorded: usize,
+    /// The number of heaps destroyed.
+    pub heaps_destroyed: usize,
+    /// The time spent planning the moves and recording their copies on the CPU, or `None` on
+    /// platforms without a clock, such as `wasm32-unknown-unknown`.
+    ///
+    /// This does not include the time the GPU takes to execute the copies.
+    pub duration: Option<Duration>,
+    /// The memory usage of the whole arena afterwards, as summed by
+    /// [`ArenaStats::total`](crate::ArenaStats::total).
+    pub remaining: Stats,
+    /// The estimated fraction of the compaction that is done, from 0 to 1.
+    ///
+    /// This is always 1 after [`HeapArena::compact`](crate::HeapArena::compact), and after the
+    /// call to [`HeapArena::compact_incremental`](crate::HeapArena::compact_incremental) that
+    /// finishes a compaction.
+    pub progress: f64,
+}
+
+impl CompactionReport {
+    /// The fragmentation of the arena afterwards, as estimated by [`Stats::fragmentation`].
+    pub fn fragmentation(&self) -> Option<f64> {
+        self.remaining.fragmentation()
+    }
+
+    /// Whether the compaction is done, so that another call to
+    /// [`HeapArena::compact_incremental`](crate::HeapArena::compact_incremental) would start a
+    /// new one.
+    pub fn is_complete(&self) -> bool {
+        self.progress >= 1.0
+    }
+}
+
+/// The current time, if the platform has a clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> Option<std::time::Instant> {
+    Some(std::time::Instant::now())
+}
+
+/// The current time, if the platform has a clock.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> Option<std::time::Instant> {
+    None
+}
@@ -0,0 +1,298 @@
+//! A facade shaped like the API of the [`gpu-allocator`] crate.
+//!
+//! This module is only available with the `compat` feature enabled. It exists to ease the
+//! migration of code written against Vulkan or DX12 allocators to wgpu, and is implemented on top
+//! of one [`HeapArena`] per distinct [`HeapUsages`].
+//!
+//! The main difference from `gpu-allocator` is that host-visible memory is staging memory: writes
+//! through [`Allocator::mapped_slice_mut`] only become visible to the GPU once they have been
+//! flushed with [`Allocator::flush`].
+//!
+//! [`gpu-allocator`]: https://crates.io/crates/gpu-allocator
+
+use wgpu::BufferAddress;
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    arena::{self, ArenaKey, NewHeapSizeContext},
+    AllocError,
+    HeapArena,
+    HeapUsages,
+    NonZeroBufferAddress,
+};
+
+/// Where the memory of an allocation should live.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryLocation {
+    /// The memory is only used by the GPU; [`Allocator::mapped_slice_mut`] returns `None`.
+    GpuOnly,
+    /// The memory is written by the CPU and then used by the GPU.
+    CpuToGpu,
+}
+
+/// The size and alignment requirements of an allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRequirements {
+    /// The size, in bytes, of the allocation.
+    pub size: BufferAddress,
+    /// The alignment, in bytes, of the allocation.
+    pub alignment: BufferAddress,
+}
+
+/// Describes an allocation to be made by [`Allocator::allocate`].
+#[derive(Clone, Copy, Debug)]
+pub struct AllocationCreateDesc<'a> {
+    /// The name of the allocation, for debugging.
+    pub name: &'a str,
+    pub requirements: MemoryRequirements,
+    pub location: MemoryLocation,
+    /// The usage of the heap that the allocation is made from.
+    pub usage: HeapUsages,
+}
+
+/// An allocation made by an [`Allocator`].
+#[derive(Debug)]
+pub struct Allocation {
+    name: String,
+    inner: arena::Allocation,
+    usage: HeapUsages,
+    location: MemoryLocation,
+    /// The `id` of the [`Allocator`] that made this allocation.
+    allocator_id: u64,
+}
+
+impl Allocation {
+    /// The name given to this allocation when it was created.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset, in bytes, of this allocation within its heap.
+    pub fn offset(&self) -> BufferAddress {
+        self.inner.range_in_heap.start
+    }
+
+    /// The size, in bytes, of this allocation.
+    pub fn size(&self) -> BufferAddress {
+        self.inner.range_in_heap.end - self.inner.range_in_heap.start
+    }
+
+    /// The usage of the heap that this allocation was made from.
+    pub fn usage(&self) -> HeapUsages {
+        self.usage
+    }
+
+    /// Where the memory of this allocation lives.
+    pub fn location(&self) -> MemoryLocation {
+        self.location
+    }
+
+    /// The underlying [`HeapArena`] allocation.
+    pub fn inner(&self) -> &arena::Allocation {
+        &self.inner
+    }
+}
+
+/// The error returned by [`Allocator`] operations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllocationError {
+    /// The size or alignment in an [`AllocationCreateDesc`] was zero.
+    InvalidAllocationCreateDesc,
+    /// The allocation was not made by this allocator.
+    NotOwned,
+    /// The underlying allocator refused to free the allocation.
+    FreeFailed,
+    /// The underlying [`HeapArena`] could not make the allocation.
+    Arena(AllocError),
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidAllocationCreateDesc => "allocation size and alignment must be nonzero",
+            Self::NotOwned => "allocation was not made by this allocator",
+            Self::FreeFailed => "allocator refused to free the allocation",
+            Self::Arena(error) => return write!(f, "arena allocation failed: {}", error),
+        })
+    }
+}
+
+impl std::error::Error for AllocationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Arena(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<AllocError> for AllocationError {
+    fn from(error: AllocError) -> Self {
+        Self::Arena(error)
+    }
+}
+
+/// A source of unique `Allocator::id`s.
+static NEXT_ALLOCATOR_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An allocator with an API shaped like that of `gpu-allocator`.
+#[derive(Debug)]
+pub struct Allocator<A> {
+    /// A number unique to this allocator, used to reject allocations made by other allocators.
+    id: u64,
+    /// The arenas from which allocations are made, one per distinct usage.
+    arenas: Vec<HeapArena<A>>,
+    /// Passed to every [`HeapArena`] created by this allocator.
+    calc_new_heap_size: fn(NewHeapSizeContext) -> NonZeroBufferAddress,
+    /// Whether freed allocations are zeroed; see [`Self::set_clear_on_free`].
+    clear_on_free: bool,
+    /// The ranges freed since the last flush that have yet to be zeroed, grouped by heap.
+    ///
+    /// This is a [`RefCell`] so that the ranges can be taken by [`Self::flush`], which only
+    /// borrows `self` immutably.
+    pending_clears: RefCell<PendingClears>,
+}
+
+/// Freed ranges awaiting [`Allocator::clear_freed`], grouped by the heap they belong to.
+type PendingClears = BTreeMap<(HeapUsages, ArenaKey), Vec<Range<BufferAddress>>>;
+
+impl<A: crate::Allocator> Allocator<A> {
+    /// Creates a new `Allocator`.
+    ///
+    /// See [`HeapArena::new`] for the meaning of `calc_new_heap_size`.
+    pub fn new(calc_new_heap_size: fn(NewHeapSizeContext) -> NonZeroBufferAddress) -> Self {
+        Self {
+            id: NEXT_ALLOCATOR_ID.fetch_add(1, Ordering::Relaxed),
+            arenas: Vec::new(),
+            calc_new_heap_size,
+            clear_on_free: false,
+            pending_clears: RefCell::default(),
+        }
+    }
+
+    /// Sets whether the GPU memory of freed allocations is zeroed, which is off by default.
+    ///
+    /// This is a debugging aid for catching reads of freed memory. Freed ranges are batched and
+    /// zeroed with as few commands as possible at the next call to [`Self::flush`] or
+    /// [`Self::clear_freed`]; see [`Heap::clear_ranges`](crate::Heap::clear_ranges) for the
+    /// caveats.
+    pub fn set_clear_on_free(&mut self, clear_on_free: bool) {
+        self.clear_on_free = clear_on_free;
+    }
+
+    /// Zeroes the GPU memory of every allocation freed since the last flush, if
+    /// [`Self::set_clear_on_free`] is on.
+    pub fn clear_freed(&self, encoder: &mut wgpu::CommandEncoder) {
+        for ((usage, key), mut ranges) in self.pending_clears.take() {
+            // Note: the heap may have been destroyed since, in which case there is nothing to
+            // clear.
+            if let Some((heap, _)) = self.arena(usage).and_then(|arena| arena.get(key)) {
+                heap.clear_ranges(encoder, &mut ranges);
+            }
+        }
+    }
+
+    /// Makes a new allocation as described by `desc`.
+    pub fn allocate(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &AllocationCreateDesc,
+    ) -> Result<Allocation, AllocationError> {
+        let size = NonZeroBufferAddress::new(desc.requirements.size)
+            .ok_or(AllocationError::InvalidAllocationCreateDesc)?;
+        let alignment = NonZeroBufferAddress::new(desc.requirements.alignment)
+            .ok_or(AllocationError::InvalidAllocationCreateDesc)?;
+
+        let inner = self.arena_mut(desc.usage).alloc(device, size, alignment)?;
+
+        Ok(Allocation {
+            name: desc.name.to_owned(),
+            inner,
+            usage: desc.usage,
+            location: desc.location,
+            allocator_id: self.id,
+        })
+    }
+
+    /// Frees `allocation`.
+    pub fn free(&mut self, allocation: Allocation) -> Result<(), AllocationError> {
+        if allocation.allocator_id != self.id {
+            return Err(AllocationError::NotOwned);
+        }
+
+        let arena = self.arena_mut(allocation.usage);
+        // SAFETY: `allocation` was made by this allocator, from this arena, and as it is consumed
+        // here, it cannot be freed twice.
+        unsafe { arena.dealloc(allocation.inner.clone()) }.map_err(|error| match error {
+            AllocError::NotOwnedByAllocator | AllocError::StaleKey => AllocationError::NotOwned,
+            _ => AllocationError::FreeFailed,
+        })?;
+
+        if self.clear_on_free {
+            self.pending_clears
+                .get_mut()
+                .entry((allocation.usage, allocation.inner.arena_key))
+                .or_default()
+                .push(allocation.inner.range_in_heap);
+        }
+
+        Ok(())
+    }
+
+    /// Gets a mutable view of the staging memory of `allocation`, or `None` if it lives in
+    /// [`MemoryLocation::GpuOnly`] memory.
+    ///
+    /// The staging memory must be mapped. Writes become visible to the GPU once they have been
+    /// flushed with [`Self::flush`].
+    pub fn mapped_slice_mut<'a>(
+        &'a self,
+        allocation: &Allocation,
+    ) -> Option<wgpu::BufferViewMut<'a>> {
+        if allocation.location == MemoryLocation::GpuOnly {
+            return None;
+        }
+
+        let (heap, _) = &self.arena(allocation.usage)?[allocation.inner.arena_key];
+
+        Some(
+            heap
+                .staging_buffer()
+                .slice(allocation.inner.range_in_heap.clone())
+                .get_mapped_range_mut(),
+        )
+    }
+
+    /// Copies the staging memory of `allocation` into GPU memory.
+    ///
+    /// Any ranges pending [`Self::clear_freed`] are zeroed first, so that they cannot clobber data
+    /// that has since been reallocated and flushed.
+    pub fn flush(&self, encoder: &mut wgpu::CommandEncoder, allocation: &Allocation) {
+        self.clear_freed(encoder);
+        if let Some(arena) = self.arena(allocation.usage) {
+            arena.flush_range(encoder, &allocation.inner);
+        }
+    }
+
+    /// The arena for heaps of the given usage, if any allocations have been made with it.
+    pub fn arena(&self, usage: HeapUsages) -> Option<&HeapArena<A>> {
+        self.arenas.iter().find(|arena| arena.usage() == usage)
+    }
+
+    fn arena_mut(&mut self, usage: HeapUsages) -> &mut HeapArena<A> {
+        match self.arenas.iter().position(|arena| arena.usage() == usage) {
+            Some(index) => &mut self.arenas[index],
+            None => {
+                self.arenas.push(HeapArena::new(usage, self.calc_new_heap_size));
+                // SAFETY: We just pushed a new arena.
+                unsafe { self.arenas.last_mut().unwrap_unchecked() }
+            }
+        }
+    }
+}
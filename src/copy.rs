@@ -0,0 +1,218 @@
+//! Planning of large numbers of buffer-to-buffer copies.
+//!
+//! Operations such as compaction and segmented uploads can produce thousands of small copies, and
+//! recording each one as its own command stalls submission. A [`CopyPlanner`] collects copies,
+//! merges those that are contiguous in both their source and destination, and records the result
+//! as the shortest sequence of commands that it can find, optionally spread across several
+//! command encoders.
+//!
+//! Copies are only reordered where doing so cannot change the result: a copy that reads or writes
+//! memory written by an earlier copy, or that writes memory read by an earlier copy, is never moved
+//! before it.
+
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::Heap;
+
+/// A single buffer-to-buffer copy.
+///
+/// As with [`wgpu::CommandEncoder::copy_buffer_to_buffer`], the source and destination must be
+/// different buffers.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferCopy<'a> {
+    pub source: &'a wgpu::Buffer,
+    pub source_offset: BufferAddress,
+    pub destination: &'a wgpu::Buffer,
+    pub destination_offset: BufferAddress,
+    pub size: BufferAddress,
+}
+
+impl<'a> BufferCopy<'a> {
+    fn source_range(&self) -> Range<BufferAddress> {
+        self.source_offset..(self.source_offset + self.size)
+    }
+
+    fn destination_range(&self) -> Range<BufferAddress> {
+        self.destination_offset..(self.destination_offset + self.size)
+    }
+
+    /// Records this copy into `encoder`.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            self.source,
+            self.source_offset,
+            self.destination,
+            self.destination_offset,
+            self.size,
+        );
+    }
+}
+
+/// A collector of copies that merges and orders them before recording.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct CopyPlanner<'a> {
+    copies: Vec<BufferCopy<'a>>,
+}
+
+impl<'a> CopyPlanner<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a copy to the plan. Copies of zero bytes are ignored.
+    pub fn push(&mut self, copy: BufferCopy<'a>) {
+        if copy.size > 0 {
+            self.copies.push(copy);
+        }
+    }
+
+    /// Adds a copy of `source_range` of the GPU buffer of `source` into the GPU buffer of
+    /// `destination`, starting at `destination_offset`.
+    ///
+    /// The heaps must have been created with usages that allow their GPU buffers to be copied
+    /// from and to, respectively.
+    pub fn push_heap_copy(
+        &mut self,
+        source: &'a Heap,
+        source_range: Range<BufferAddress>,
+        destination: &'a Heap,
+        destination_offset: BufferAddress,
+    ) {
+        self.push(BufferCopy {
+            source: &source.gpu_buffer,
+            source_offset: source_range.start,
+            destination: &destination.gpu_buffer,
+            destination_offset,
+            size: source_range.end - source_range.start,
+        });
+    }
+
+    /// The number of copies added so far.
+    pub fn len(&self) -> usize {
+        self.copies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.copies.is_empty()
+    }
+
+    /// Merges and orders the copies added so far, returning the copies to record in order.
+    pub fn plan(&self) -> Vec<BufferCopy<'a>> {
+        // Buffers are ordered by when they were first seen rather than by address, so that the
+        // plan does not depend on where buffers happen to land in memory.
+        let mut buffers: Vec<&wgpu::Buffer> = Vec::new();
+        let mut buffer_index = |buffer: &'a wgpu::Buffer| {
+            match buffers.iter().position(|known| std::ptr::eq(*known, buffer)) {
+                Some(index) => index,
+                None => {
+                    buffers.push(buffer);
+                    buffers.len() - 1
+                }
+            }
+        };
+
+        let mut plan = Vec::with_capacity(self.copies.len());
+        for epoch in self.epochs() {
+            let mut epoch: Vec<_> = epoch
+                .iter()
+                .map(|copy| ((buffer_index(copy.source), buffer_index(copy.destination)), *copy))
+                .collect();
+            epoch.sort_by_key(|&(buffers, copy)| (buffers, copy.source_offset));
+
+            let mut merged: Vec<((usize, usize), BufferCopy)> = Vec::with_capacity(epoch.len());
+            for (buffers, copy) in epoch {
+                match merged.last_mut() {
+                    Some((last_buffers, last))
+                        if *last_buffers == buffers
+                            && last.source_range().end == copy.source_offset
+                            && last.destination_range().end == copy.destination_offset =>
+                    {
+                        last.size += copy.size;
+                    }
+                    _ => merged.push((buffers, copy)),
+                }
+            }
+            plan.extend(merged.into_iter().map(|(_, copy)| copy));
+        }
+
+        plan
+    }
+
+    /// Splits the copies into maximal runs, in the order they were added, within which copies may
+    /// be freely reordered.
+    fn epochs(&self) -> Vec<&[BufferCopy<'a>]> {
+        let mut epochs = Vec::new();
+        let mut start = 0;
+        for (index, copy) in self.copies.iter().enumerate() {
+            if self.copies[start..index].iter().any(|earlier| conflicts(earlier, copy)) {
+                epochs.push(&self.copies[start..index]);
+                start = index;
+            }
+        }
+        if start < self.copies.len() {
+            epochs.push(&self.copies[start..]);
+        }
+
+        epochs
+    }
+
+    /// Records the planned copies into `encoder`, returning how many commands were recorded.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder) -> usize {
+        let plan = self.plan();
+        for copy in &plan {
+            copy.record(encoder);
+        }
+
+        plan.len()
+    }
+
+    /// Records the planned copies into as many command buffers as are needed to record no more
+    /// than `max_copies_per_encoder` copies into each.
+    ///
+    /// The command buffers must be submitted in the order they are returned.
+    pub fn record_in_batches(
+        &self,
+        device: &wgpu::Device,
+        max_copies_per_encoder: usize,
+    ) -> Vec<wgpu::CommandBuffer> {
+        assert!(max_copies_per_encoder > 0, "copy count budget must be nonzero");
+
+        self.plan()
+            .chunks(max_copies_per_encoder)
+            .map(|batch| {
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("wgpu-allocators copy batch"),
+                });
+                for copy in batch {
+                    copy.record(&mut encoder);
+                }
+
+                encoder.finish()
+            })
+            .collect()
+    }
+}
+
+/// Determines if `later` must not be reordered before `earlier`.
+fn conflicts(earlier: &BufferCopy, later: &BufferCopy) -> bool {
+    let (earlier_read, earlier_write) = (earlier.source_range(), earlier.destination_range());
+    let (later_read, later_write) = (later.source_range(), later.destination_range());
+
+    // Read after write, write after write, and write after read, respectively.
+    overlaps(earlier.destination, earlier_write.clone(), later.source, later_read)
+        || overlaps(earlier.destination, earlier_write, later.destination, later_write.clone())
+        || overlaps(earlier.source, earlier_read, later.destination, later_write)
+}
+
+fn overlaps(
+    a: &wgpu::Buffer,
+    a_range: Range<BufferAddress>,
+    b: &wgpu::Buffer,
+    b_range: Range<BufferAddress>,
+) -> bool {
+    std::ptr::eq(a, b) && a_range.start < b_range.end && b_range.start < a_range.end
+}
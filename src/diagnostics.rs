@@ -0,0 +1,114 @@
+//! Diagnostics that explain why an allocation cannot be satisfied by existing heaps.
+//!
+//! [`HeapArena::diagnose`](crate::HeapArena::diagnose) gathers everything that bears on where an
+//! allocation would go&mdash;the pool it maps to, how much room each heap in that pool has left,
+//! and how close the process is to its buffer ceiling&mdash;into an [`AllocDiagnostics`], along
+//! with a [`Suggestion`] for what to do about it. Its [`Display`](fmt::Display) implementation is
+//! meant to be logged as-is.
+
+use wgpu::BufferAddress;
+
+use std::fmt;
+
+use crate::NonZeroBufferAddress;
+
+/// The state of a single heap, as seen by [`AllocDiagnostics`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapDiagnostics {
+    /// The size, in bytes, of the heap.
+    pub size: NonZeroBufferAddress,
+    /// The size, in bytes, of the largest free region in the heap, if its allocator reports it.
+    pub largest_free_block: Option<BufferAddress>,
+}
+
+/// What could be done to make room for an allocation that existing heaps cannot hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Suggestion {
+    /// Existing heaps can already hold the allocation; nothing needs to be done.
+    None,
+    /// Create a new heap; nothing stands in the way of doing so.
+    Grow,
+    /// Free memory or destroy heaps, as the buffer ceiling is close or already reached.
+    Trim,
+    /// Compact the pool, as its heaps have enough free memory in total but not in one place.
+    Compact,
+}
+
+/// A report on whether, and why not, an allocation fits in the heaps of an arena.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllocDiagnostics {
+    pub size: NonZeroBufferAddress,
+    pub alignment: NonZeroBufferAddress,
+    /// The size class of the allocation, which determines the pool that was examined.
+    pub size_class: usize,
+    /// The heaps in the examined pool, in order of [`ArenaKey::index_in_pool`].
+    ///
+    /// [`ArenaKey::index_in_pool`]: crate::arena::ArenaKey::index_in_pool
+    pub heaps: Vec<HeapDiagnostics>,
+    /// The total size, in bytes, of every heap in the arena.
+    pub reserved_bytes: BufferAddress,
+    /// The number of buffers owned by heaps across the process.
+    pub live_buffers: usize,
+    /// The maximum number of buffers that heaps may own, if any.
+    pub buffer_ceiling: Option<usize>,
+    pub suggestion: Suggestion,
+}
+
+impl AllocDiagnostics {
+    /// Chooses a suggestion from the other fields.
+    pub(crate) fn suggest(&mut self) {
+        let fits = |block: BufferAddress| block >= self.size.get() + (self.alignment.get() - 1);
+        let largest_free_blocks = || self.heaps.iter().filter_map(|heap| heap.largest_free_block);
+
+        self.suggestion = if largest_free_blocks().any(fits) {
+            Suggestion::None
+        // A new heap needs at least two buffers: one for staging and one for the GPU.
+        } else if self.buffer_ceiling.is_some_and(|ceiling| self.live_buffers + 2 > ceiling) {
+            Suggestion::Trim
+        } else if largest_free_blocks().sum::<BufferAddress>() >= self.size.get() {
+            Suggestion::Compact
+        } else {
+            Suggestion::Grow
+        };
+    }
+}
+
+impl fmt::Display for AllocDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "allocation of {} bytes aligned to {} (size class {}):",
+            self.size,
+            self.alignment,
+            self.size_class,
+        )?;
+        if self.heaps.is_empty() {
+            writeln!(f, "  pool has no heaps")?;
+        }
+        for (index, heap) in self.heaps.iter().enumerate() {
+            write!(f, "  heap {}: {} bytes, largest free block ", index, heap.size)?;
+            match heap.largest_free_block {
+                Some(block) => writeln!(f, "{} bytes", block)?,
+                None => writeln!(f, "unknown")?,
+            }
+        }
+        write!(
+            f,
+            "  {} bytes reserved by arena; {} buffers live",
+            self.reserved_bytes,
+            self.live_buffers,
+        )?;
+        match self.buffer_ceiling {
+            Some(ceiling) => writeln!(f, " of at most {}", ceiling)?,
+            None => writeln!(f)?,
+        }
+
+        write!(f, "  suggestion: ")?;
+        f.write_str(match self.suggestion {
+            Suggestion::None => "none; an existing heap can hold the allocation",
+            Suggestion::Grow => "grow the arena with a new heap",
+            Suggestion::Trim => "free memory or destroy heaps; the buffer ceiling is near",
+            Suggestion::Compact => "compact the pool; enough memory is free but fragmented",
+        })
+    }
+}
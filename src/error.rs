@@ -0,0 +1,224 @@
+//! The errors returned when memory can't be allocated, freed, bound, written, or copied.
+
+use wgpu::BufferAddress;
+
+use std::{fmt, ops::Range};
+
+use crate::{queue::Serial, HeapUsages, NonZeroBufferAddress};
+
+/// The reason an allocation or deallocation failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AllocError {
+    /// There is no free region large enough to hold the allocation.
+    OutOfMemory,
+    /// The allocator can never satisfy the requested alignment, such as a [`Pool`] whose slots
+    /// aren't aligned to it.
+    ///
+    /// [`Pool`]: crate::Pool
+    AlignmentNotSupported { alignment: NonZeroBufferAddress },
+    /// The allocation doesn't fit in the heap that a [`HeapArena`] would create for it, either
+    /// because its growth policy returned too small a size or because that size exceeds
+    /// [`wgpu::Limits::max_buffer_size`].
+    ///
+    /// [`HeapArena`]: crate::HeapArena
+    SizeTooLargeForArena { size: NonZeroBufferAddress, heap_size: NonZeroBufferAddress },
+    /// A new heap was needed, but creating it would have taken the number of buffers past the
+    /// ceiling set with [`governor::set_buffer_ceiling`](crate::governor::set_buffer_ceiling).
+    HeapCreationFailed { heap_size: NonZeroBufferAddress },
+    /// A new heap was needed, but creating it would have taken the memory reserved by a
+    /// [`HeapArena`] past the budget set with [`HeapArena::set_budget`].
+    ///
+    /// [`HeapArena`]: crate::HeapArena
+    /// [`HeapArena::set_budget`]: crate::HeapArena::set_budget
+    BudgetExceeded { heap_size: NonZeroBufferAddress, budget: BufferAddress },
+    /// The range being freed was not allocated by this allocator or has already been freed.
+    NotOwnedByAllocator,
+    /// The allocation being freed names a heap of a [`HeapArena`] that has since been destroyed or
+    /// moved, as described by [`ArenaKey`].
+    ///
+    /// [`HeapArena`]: crate::HeapArena
+    /// [`ArenaKey`]: crate::arena::ArenaKey
+    StaleKey,
+    /// The range being freed was allocated by this allocator, but can't be freed yet, such as a
+    /// [`Stack`] allocation that isn't the most recent one.
+    ///
+    /// [`Stack`]: crate::Stack
+    OutOfOrder,
+    /// The range being freed is still used by the submission with the given serial, which the GPU
+    /// has yet to finish, as found by
+    /// [`HeapArena::enable_hazard_tracking`](crate::HeapArena::enable_hazard_tracking).
+    InUse { serial: Serial },
+    /// The allocator does not free individual allocations, like a [`Ring`].
+    ///
+    /// [`Ring`]: crate::Ring
+    Unsupported,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::AlignmentNotSupported { alignment } => {
+                write!(f, "alignment of {} bytes is not supported", alignment)
+            }
+            Self::SizeTooLargeForArena { size, heap_size } => write!(
+                f,
+                "allocation of {} bytes does not fit in a new heap of {} bytes",
+                size, heap_size,
+            ),
+            Self::HeapCreationFailed { heap_size } => write!(
+                f,
+                "cannot create a heap of {} bytes without exceeding the buffer ceiling",
+                heap_size,
+            ),
+            Self::BudgetExceeded { heap_size, budget } => write!(
+                f,
+                "cannot create a heap of {} bytes without exceeding the budget of {} bytes",
+                heap_size, budget,
+            ),
+            Self::NotOwnedByAllocator => write!(f, "range was not allocated by this allocator"),
+            Self::StaleKey => write!(f, "heap of the allocation was destroyed or moved"),
+            Self::OutOfOrder => write!(f, "range cannot be freed before other allocations"),
+            Self::InUse { serial } => {
+                write!(f, "range is still used by unfinished submission {}", serial)
+            }
+            Self::Unsupported => write!(f, "allocator does not free individual allocations"),
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+/// The reason [`HeapArena::alloc_and_write_async`] failed.
+///
+/// [`HeapArena::alloc_and_write_async`]: crate::HeapArena::alloc_and_write_async
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsyncWriteError {
+    /// The allocation failed.
+    Alloc(AllocError),
+    /// The staging memory of the heap of the allocation could not be mapped.
+    Map(wgpu::BufferAsyncError),
+}
+
+impl From<AllocError> for AsyncWriteError {
+    fn from(error: AllocError) -> Self {
+        Self::Alloc(error)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for AsyncWriteError {
+    fn from(error: wgpu::BufferAsyncError) -> Self {
+        Self::Map(error)
+    }
+}
+
+impl fmt::Display for AsyncWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Alloc(error) => write!(f, "{}", error),
+            Self::Map(error) => write!(f, "staging memory could not be mapped: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AsyncWriteError {}
+
+/// The reason a range of a heap can't be bound or sliced.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BindingError {
+    /// The range is larger than the device allows any binding of the heap to be.
+    TooLarge { range: Range<BufferAddress>, max_size: BufferAddress },
+    /// The range does not begin on a multiple of the offset alignment that the device requires of
+    /// any binding of the heap.
+    Misaligned { range: Range<BufferAddress>, alignment: BufferAddress },
+    /// The range overlaps `unflushed`, a range of the heap that was written but not yet flushed,
+    /// so the GPU would read stale data.
+    Unflushed { range: Range<BufferAddress>, unflushed: Range<BufferAddress> },
+    /// The heap was created with `usage`, which includes none of the usages in `required` that
+    /// the range is to be used as.
+    MissingUsage { required: HeapUsages, usage: HeapUsages },
+    /// The range extends past the end of the heap, of `heap_size` bytes.
+    OutOfBounds { range: Range<BufferAddress>, heap_size: NonZeroBufferAddress },
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooLarge { range, max_size } => write!(
+                f,
+                "binding of {:?} is {} bytes, more than the maximum of {} bytes",
+                range, range.end - range.start, max_size,
+            ),
+            Self::Misaligned { range, alignment } => write!(
+                f,
+                "binding of {:?} does not begin on a multiple of {} bytes",
+                range, alignment,
+            ),
+            Self::Unflushed { range, unflushed } => write!(
+                f,
+                "binding of {:?} overlaps {:?}, which was written but not flushed",
+                range, unflushed,
+            ),
+            Self::MissingUsage { required, usage } => write!(
+                f,
+                "heap with usage {:?} cannot be used as {:?}; create it with that usage",
+                usage, required,
+            ),
+            Self::OutOfBounds { range, heap_size } => write!(
+                f,
+                "binding of {:?} extends past the end of a heap of {} bytes",
+                range, heap_size,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BindingError {}
+
+/// The reason a copy between heaps can't be recorded.
+///
+/// See [`Heap::copy_to`](crate::Heap::copy_to).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CopyError {
+    /// The source and destination are the same heap, which wgpu doesn't allow to be copied
+    /// within.
+    SameHeap,
+    /// The GPU buffer of the source heap can't be copied from, as with heaps that have
+    /// [`HeapUsages::MAP_READ`](crate::HeapUsages::MAP_READ) but no readback.
+    NotCopyable,
+    /// The destination is too small to hold the `source_size` bytes being copied.
+    SizeMismatch { source_size: BufferAddress, destination_size: BufferAddress },
+    /// The copied range extends past the end of the heap it is in, of `heap_size` bytes.
+    OutOfBounds { range: Range<BufferAddress>, heap_size: NonZeroBufferAddress },
+    /// The copied range does not begin and end on multiples of [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    Misaligned { range: Range<BufferAddress> },
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SameHeap => write!(f, "cannot copy between ranges of the same heap"),
+            Self::NotCopyable => write!(f, "the source heap cannot be copied from"),
+            Self::SizeMismatch { source_size, destination_size } => write!(
+                f,
+                "cannot copy {} bytes into a destination of {} bytes",
+                source_size, destination_size,
+            ),
+            Self::OutOfBounds { range, heap_size } => write!(
+                f,
+                "copied range {:?} extends past the end of a heap of {} bytes",
+                range, heap_size,
+            ),
+            Self::Misaligned { range } => write!(
+                f,
+                "copied range {:?} does not begin and end on multiples of {} bytes",
+                range, wgpu::COPY_BUFFER_ALIGNMENT,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {}
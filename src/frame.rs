@@ -0,0 +1,98 @@
+//! Heaps duplicated once per frame in flight.
+//!
+//! While the GPU is still reading the data of one frame, the CPU is usually already writing that
+//! of the next. Writing both into the same heap would overwrite data that is in use, so a
+//! [`FrameHeap`] keeps `N` identical heaps and rotates between them: the heap of frame `i` is only
+//! written again in frame `i + N`, by which time the GPU is expected to be done with it.
+//!
+//! Every heap has the same size, so an allocator can manage the ranges of all of them at once;
+//! an allocation then refers to the same range in each heap.
+
+use std::ops::Deref;
+
+use crate::{suffixed_label, Heap, HeapDescriptor, HeapUsages, MapState, NonZeroBufferAddress};
+
+/// `N` copies of a [`Heap`], one of which is *current* and used for the frame being recorded.
+///
+/// A `FrameHeap` dereferences to its current heap, so it is written, flushed, and bound like any
+/// other heap. A frame proceeds as follows:
+///
+/// 1. [`Self::begin_frame`] makes the heap for the new frame current.
+/// 2. Data is written into the current heap, which is then unmapped and flushed.
+/// 3. The commands of the frame are submitted.
+#[derive(Debug)]
+pub struct FrameHeap<const N: usize> {
+    heaps: [Heap; N],
+    /// The index in [`Self::heaps`] of the current heap.
+    current: usize,
+}
+
+impl<const N: usize> FrameHeap<N> {
+    /// See [`Heap::new`].
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `N` is zero.
+    pub fn new(device: &wgpu::Device, size: NonZeroBufferAddress, usage: HeapUsages) -> Self {
+        Self::with_descriptor(device, &HeapDescriptor::new(size, usage))
+    }
+
+    /// Creates `N` heaps as described by `descriptor`, of which the first is current.
+    ///
+    /// The heaps are labeled with a `-frame0`, `-frame1`, etc. suffix.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `N` is zero.
+    pub fn with_descriptor(device: &wgpu::Device, descriptor: &HeapDescriptor) -> Self {
+        assert!(N > 0, "frame heap must have at least one frame");
+
+        let heaps = std::array::from_fn(|index| {
+            let label = suffixed_label(descriptor.label, &format!("frame{index}"));
+            let descriptor = HeapDescriptor { label: label.as_deref(), ..*descriptor };
+
+            Heap::with_descriptor(device, &descriptor)
+        });
+
+        Self { heaps, current: 0 }
+    }
+
+    /// Begins frame `index`, making heap `index % N` current.
+    ///
+    /// `index` is typically a frame counter that increases by one every frame. The heap that was
+    /// current until now is requested to be mapped for writing again, so the commands that copy
+    /// from it must have been submitted already. Once frame `index + N` begins, the mapping has
+    /// normally completed; if it has not, the current heap can't be written yet (see
+    /// [`Heap::map_state`]).
+    pub fn begin_frame(&mut self, index: usize) {
+        let previous = &self.heaps[self.current];
+        if previous.map_state() == MapState::Unmapped {
+            previous.map_range_async(0..previous.size().get(), wgpu::MapMode::Write);
+        }
+
+        self.current = index % N;
+    }
+
+    /// The index of the current heap, from 0 to `N - 1`.
+    pub fn frame_index(&self) -> usize {
+        self.current
+    }
+
+    /// The heap for the frame being recorded.
+    pub fn current(&self) -> &Heap {
+        &self.heaps[self.current]
+    }
+
+    /// Every heap, in frame order.
+    pub fn heaps(&self) -> &[Heap; N] {
+        &self.heaps
+    }
+}
+
+impl<const N: usize> Deref for FrameHeap<N> {
+    type Target = Heap;
+
+    fn deref(&self) -> &Heap {
+        self.current()
+    }
+}
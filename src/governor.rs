@@ -0,0 +1,126 @@
+//! A process-wide governor on the number of [`wgpu::Buffer`]s owned by heaps.
+//!
+//! Some backends degrade badly once thousands of buffers exist, yet every [`HeapArena`] grows
+//! independently of the others. This module keeps a single count of the buffers owned by every
+//! [`Heap`] in the process, and lets the application set a ceiling on it.
+//!
+//! As the count approaches the ceiling, arenas come under *consolidation pressure*: each new heap
+//! they create is made larger than it otherwise would be (see [`consolidation_factor`]) so that
+//! fewer heaps&mdash;and so fewer buffers&mdash;are needed for the same workload. Arenas made
+//! deterministic with [`HeapArena::set_deterministic`] are exempt. Creating a heap that would take
+//! the count past the ceiling panics, except in [`HeapArena::alloc`], which instead fails with
+//! [`AllocError::HeapCreationFailed`].
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::set_deterministic`]: crate::HeapArena::set_deterministic
+//! [`HeapArena::alloc`]: crate::HeapArena::alloc
+//! [`AllocError::HeapCreationFailed`]: crate::AllocError::HeapCreationFailed
+//! [`Heap`]: crate::Heap
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of buffers currently owned by heaps.
+static LIVE_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// The maximum value of [`LIVE_BUFFERS`], or `usize::MAX` if there is none.
+static CEILING: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The number of [`wgpu::Buffer`]s currently owned by heaps, across all arenas.
+pub fn live_buffer_count() -> usize {
+    LIVE_BUFFERS.load(Ordering::Acquire)
+}
+
+/// The maximum number of [`wgpu::Buffer`]s that heaps may own at once, or `None` if there is no
+/// limit.
+pub fn buffer_ceiling() -> Option<usize> {
+    match CEILING.load(Ordering::Acquire) {
+        usize::MAX => None,
+        ceiling => Some(ceiling),
+    }
+}
+
+/// Sets the maximum number of [`wgpu::Buffer`]s that heaps may own at once, or removes the limit
+/// if `ceiling` is `None`.
+///
+/// Lowering the ceiling below [`live_buffer_count`] does not destroy any buffers; it only
+/// prevents new heaps from being created.
+pub fn set_buffer_ceiling(ceiling: Option<usize>) {
+    CEILING.store(ceiling.unwrap_or(usize::MAX), Ordering::Release);
+}
+
+/// The factor by which arenas currently scale up the size of new heaps.
+///
+/// This is 1 while fewer than half of the buffers permitted by the ceiling exist, and doubles
+/// every time the remaining headroom halves: 2 from one half of the ceiling, 4 from three
+/// quarters, 8 from seven eighths, and so on.
+pub fn consolidation_factor() -> u64 {
+    let Some(ceiling) = buffer_ceiling() else { return 1 };
+    let headroom = ceiling.saturating_sub(live_buffer_count());
+
+    let mut factor = 1;
+    let mut threshold = ceiling / 2;
+    while threshold > 0 && headroom <= threshold {
+        factor *= 2;
+        threshold /= 2;
+    }
+
+    factor
+}
+
+/// Whether `count` more buffers can be created without exceeding the ceiling.
+pub(crate) fn has_headroom(count: usize) -> bool {
+    live_buffer_count()
+        .checked_add(count)
+        .is_some_and(|live| live <= CEILING.load(Ordering::Acquire))
+}
+
+/// Records the creation of `count` buffers, which must be done before they are created.
+///
+/// The buffers are released again if the returned [`Reservation`] is dropped rather than kept,
+/// such as when creating them panics.
+///
+/// # Panics
+///
+/// This function panics if doing so would exceed the ceiling, in which case nothing is recorded.
+pub(crate) fn acquire(count: usize) -> Reservation {
+    let result = LIVE_BUFFERS.fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+        live.checked_add(count).filter(|&live| live <= CEILING.load(Ordering::Acquire))
+    });
+    if let Err(live) = result {
+        panic!(
+            "buffer ceiling of {} reached; cannot create {} more buffers on top of {}",
+            CEILING.load(Ordering::Acquire),
+            count,
+            live,
+        );
+    }
+
+    Reservation { count }
+}
+
+/// Buffers recorded by [`acquire`] that have yet to be created.
+#[must_use]
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    count: usize,
+}
+
+impl Reservation {
+    /// Keeps the buffers recorded, now that they have been created.
+    ///
+    /// They are then released by whoever owns them.
+    pub(crate) fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        release(self.count);
+    }
+}
+
+/// Records the destruction of `count` buffers.
+pub(crate) fn release(count: usize) {
+    LIVE_BUFFERS.fetch_sub(count, Ordering::AcqRel);
+}
@@ -0,0 +1,100 @@
+//! Policies that decide how large the heaps created by a [`HeapArena`] are.
+//!
+//! Whenever an allocation doesn't fit in any existing heap of its size class, the arena asks its
+//! [`GrowthPolicy`] for the size of a new one. Larger heaps mean fewer buffers, and so fewer heap
+//! creations and better batching, at the cost of memory that may go unused; smaller heaps are the
+//! opposite.
+//!
+//! Besides the policies in this module, any `Fn(NewHeapSizeContext) -> NonZeroBufferAddress` is a
+//! policy, so a closure can be passed wherever one is expected.
+//!
+//! [`HeapArena`]: crate::HeapArena
+
+use crate::{arena::NewHeapSizeContext, NonZeroBufferAddress};
+
+/// Decides the size, in bytes, of each new heap of a [`HeapArena`](crate::HeapArena).
+pub trait GrowthPolicy {
+    /// The size, in bytes, of a new heap for the allocation and pool described by `context`.
+    ///
+    /// This must be at least [`NewHeapSizeContext::first_alloc_size`], or else the allocation
+    /// fails with [`AllocError::SizeTooLargeForArena`](crate::AllocError::SizeTooLargeForArena).
+    fn new_heap_size(&self, context: NewHeapSizeContext) -> NonZeroBufferAddress;
+}
+
+impl<F: Fn(NewHeapSizeContext) -> NonZeroBufferAddress> GrowthPolicy for F {
+    fn new_heap_size(&self, context: NewHeapSizeContext) -> NonZeroBufferAddress {
+        self(context)
+    }
+}
+
+/// Makes the first heap of each pool `initial` bytes, and each later one twice as large as the one
+/// before it.
+///
+/// A heap is never made smaller than the allocation it is created for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Doubling {
+    /// The size, in bytes, of the first heap of each pool.
+    pub initial: NonZeroBufferAddress,
+}
+
+impl GrowthPolicy for Doubling {
+    fn new_heap_size(&self, context: NewHeapSizeContext) -> NonZeroBufferAddress {
+        let size = match context.last_heap_size {
+            Some(last_heap_size) => {
+                last_heap_size.saturating_mul(NonZeroBufferAddress::new(2).unwrap())
+            }
+            None => self.initial,
+        };
+
+        size.max(context.first_alloc_size)
+    }
+}
+
+/// Makes every heap the same number of bytes.
+///
+/// Allocations larger than that fail with
+/// [`AllocError::SizeTooLargeForArena`](crate::AllocError::SizeTooLargeForArena).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed(pub NonZeroBufferAddress);
+
+impl GrowthPolicy for Fixed {
+    fn new_heap_size(&self, _: NewHeapSizeContext) -> NonZeroBufferAddress {
+        self.0
+    }
+}
+
+/// Makes each heap as large as the allocation it is created for, rounded up to a power of two.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NextPowerOfTwo;
+
+impl GrowthPolicy for NextPowerOfTwo {
+    fn new_heap_size(&self, context: NewHeapSizeContext) -> NonZeroBufferAddress {
+        context.first_alloc_size.checked_next_power_of_two().unwrap_or(context.first_alloc_size)
+    }
+}
+
+/// Makes the first heap of each pool as large as [`NextPowerOfTwo`] would, and each later one
+/// `base` times as large as the one before it, up to `cap` bytes.
+///
+/// A heap is never made smaller than the allocation it is created for, even if that exceeds
+/// `cap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exponential {
+    /// The factor by which each heap is larger than the one before it. A base of 0 is treated as
+    /// 1.
+    pub base: u64,
+    /// The size, in bytes, beyond which heaps stop growing.
+    pub cap: NonZeroBufferAddress,
+}
+
+impl GrowthPolicy for Exponential {
+    fn new_heap_size(&self, context: NewHeapSizeContext) -> NonZeroBufferAddress {
+        let base = NonZeroBufferAddress::new(self.base).unwrap_or(NonZeroBufferAddress::MIN);
+        let size = match context.last_heap_size {
+            Some(last_heap_size) => last_heap_size.saturating_mul(base),
+            None => NextPowerOfTwo.new_heap_size(context),
+        };
+
+        size.min(self.cap).max(context.first_alloc_size)
+    }
+}
@@ -0,0 +1,196 @@
+//! Guard bands around allocations, for catching writes past their bounds.
+//!
+//! With [`HeapArena::set_guard_size`], every allocation made by the arena is padded on both sides
+//! with a band of guard bytes, which are filled with [`CANARY`] through the staging buffer of the
+//! heap and so reach the GPU with the next flush. Nothing but a write past the end (or before the
+//! start) of an allocation ever changes them. Once the canaries have been flushed,
+//! [`HeapArena::verify_canaries`] reads the heaps back and reports every guard band that was
+//! overwritten, whether by a shader or by the CPU.
+//!
+//! Guard bands are meant for debugging: they waste memory, and verification stalls until the GPU
+//! is idle. Canaries are only written into heaps with staging memory (see
+//! [`UploadStrategy::Staging`](crate::UploadStrategy::Staging)) whose staging buffer is mapped
+//! at the time of the allocation, and that lack [`HeapUsages::MAP_READ`], as those can't be read
+//! back; the guard bands of other allocations are never verified.
+//!
+//! [`HeapArena::set_guard_size`]: crate::HeapArena::set_guard_size
+//! [`HeapUsages::MAP_READ`]: crate::HeapUsages::MAP_READ
+//! [`HeapArena::verify_canaries`]: crate::HeapArena::verify_canaries
+
+use wgpu::BufferAddress;
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::arena::{Allocation, ArenaKey, Relocation};
+
+/// The value of every guard byte.
+pub const CANARY: u8 = 0xfd;
+
+/// Which side of an allocation a guard band is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GuardSide {
+    /// The band before the start of the allocation, which catches underruns.
+    Front,
+    /// The band after the end of the allocation, which catches overruns.
+    Back,
+}
+
+/// A guard band that was found overwritten by [`HeapArena::verify_canaries`].
+///
+/// [`HeapArena::verify_canaries`]: crate::HeapArena::verify_canaries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanaryViolation {
+    /// The allocation that the guard band belongs to.
+    pub allocation: Allocation,
+    pub side: GuardSide,
+    /// The offset, within the heap, of the first overwritten guard byte.
+    pub first_corrupted: BufferAddress,
+    /// The number of overwritten guard bytes in the band.
+    pub corrupted_bytes: u64,
+}
+
+/// A live allocation with guard bands.
+#[derive(Clone, Debug)]
+pub(crate) struct Guarded {
+    /// The end of the range given by the allocator, including both guard bands.
+    pub(crate) padded_end: BufferAddress,
+    /// The range handed out to the caller.
+    pub(crate) range_in_heap: Range<BufferAddress>,
+    /// Whether canaries were written into the guard bands.
+    pub(crate) armed: bool,
+}
+
+impl Guarded {
+    /// The guard bands of an allocation that begins at `padded_start`.
+    pub(crate) fn bands(
+        &self,
+        padded_start: BufferAddress,
+    ) -> [(GuardSide, Range<BufferAddress>); 2] {
+        [
+            (GuardSide::Front, padded_start..self.range_in_heap.start),
+            (GuardSide::Back, self.range_in_heap.end..self.padded_end),
+        ]
+    }
+}
+
+/// The allocations of an arena that have guard bands, keyed by heap and then by the start of the
+/// range given by the allocator.
+#[derive(Debug, Default)]
+pub(crate) struct GuardBands {
+    guarded: BTreeMap<(ArenaKey, BufferAddress), Guarded>,
+}
+
+impl GuardBands {
+    pub(crate) fn insert(
+        &mut self,
+        arena_key: ArenaKey,
+        padded_start: BufferAddress,
+        guarded: Guarded,
+    ) {
+        self.guarded.insert((arena_key, padded_start), guarded);
+    }
+
+    /// Forgets the guard bands of the allocation whose padded range begins at `padded_start`.
+    pub(crate) fn remove(&mut self, arena_key: ArenaKey, padded_start: BufferAddress) {
+        self.guarded.remove(&(arena_key, padded_start));
+    }
+
+    /// The range given by the allocator for `range_in_heap`, or `None` if it has no guard bands.
+    pub(crate) fn padded_range(
+        &self,
+        arena_key: ArenaKey,
+        range_in_heap: &Range<BufferAddress>,
+    ) -> Option<Range<BufferAddress>> {
+        let padded_start = self.padded_start(arena_key, range_in_heap)?;
+
+        Some(padded_start..self.guarded[&(arena_key, padded_start)].padded_end)
+    }
+
+    fn padded_start(
+        &self,
+        arena_key: ArenaKey,
+        range_in_heap: &Range<BufferAddress>,
+    ) -> Option<BufferAddress> {
+        // Note: guarded ranges never overlap, so the only candidate is the last one that begins at
+        // or before `range_in_heap`.
+        let (&(_, padded_start), guarded) =
+            self.guarded.range((arena_key, 0)..=(arena_key, range_in_heap.start)).next_back()?;
+
+        (guarded.range_in_heap == *range_in_heap).then_some(padded_start)
+    }
+
+    /// Forgets every allocation in a heap whose key is not kept by `keep`.
+    pub(crate) fn retain_heaps(&mut self, keep: impl Fn(ArenaKey) -> bool) {
+        self.guarded.retain(|(key, _), _| keep(*key));
+    }
+
+    /// Moves the guard bands of each padded range in `relocations`, returning the relocations of
+    /// the ranges handed out to the caller instead.
+    ///
+    /// Relocations of ranges without guard bands are returned unchanged.
+    pub(crate) fn relocate(&mut self, relocations: Vec<Relocation>) -> Vec<Relocation> {
+        // Every allocation is taken out before any is put back, as the new key and range of one
+        // allocation may be the old ones of another.
+        let moved: Vec<_> = relocations
+            .into_iter()
+            .map(|Relocation { from, to }| {
+                let key = (from.arena_key, from.range_in_heap.start);
+                let Some(mut guarded) = self.guarded.remove(&key) else {
+                    return (Relocation { from, to }, None);
+                };
+                let shift = |offset: BufferAddress| {
+                    offset - from.range_in_heap.start + to.range_in_heap.start
+                };
+                let old_range = guarded.range_in_heap.clone();
+                guarded.range_in_heap = shift(old_range.start)..shift(old_range.end);
+                guarded.padded_end = to.range_in_heap.end;
+                let relocation = Relocation {
+                    from: Allocation { range_in_heap: old_range, ..from },
+                    to: Allocation { range_in_heap: guarded.range_in_heap.clone(), ..to },
+                };
+
+                (relocation, Some((to.arena_key, to.range_in_heap.start, guarded)))
+            })
+            .collect();
+
+        moved
+            .into_iter()
+            .map(|(relocation, guarded)| {
+                if let Some((arena_key, padded_start, guarded)) = guarded {
+                    self.insert(arena_key, padded_start, guarded);
+                }
+
+                relocation
+            })
+            .collect()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.guarded.clear();
+    }
+
+    /// Every allocation with armed guard bands, along with the start of its padded range.
+    pub(crate) fn armed(&self) -> impl Iterator<Item = (ArenaKey, BufferAddress, &Guarded)> {
+        self.guarded
+            .iter()
+            .filter(|(_, guarded)| guarded.armed)
+            .map(|(&(arena_key, padded_start), guarded)| (arena_key, padded_start, guarded))
+    }
+}
+
+/// The padding around an allocation of `size` bytes aligned to `alignment`, which must be a
+/// multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`], with guard bands of at least `guard_size` bytes.
+///
+/// This returns the sizes of the front and back bands. The front band keeps the allocation
+/// aligned, and the back band ends on a multiple of `COPY_BUFFER_ALIGNMENT`, so that flushing the
+/// allocation never copies the bytes of its neighbors.
+pub(crate) fn padding(
+    size: BufferAddress,
+    alignment: BufferAddress,
+    guard_size: BufferAddress,
+) -> (BufferAddress, BufferAddress) {
+    let front = guard_size.next_multiple_of(alignment);
+    let back = (size + guard_size).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) - size;
+
+    (front, back)
+}
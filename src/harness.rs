@@ -0,0 +1,106 @@
+//! Helpers for testing heap-dependent code against a real, headless wgpu device.
+//!
+//! This module is only available with the `test-harness` feature enabled. It is used for this
+//! crate's own end-to-end tests, but is equally suited to testing downstream code that writes
+//! through [`Heap`]s and [`HeapArena`](crate::HeapArena)s.
+//!
+//! Test machines do not always have a GPU (or even a software adapter), so every entry point into
+//! the harness is fallible: [`TestContext::new`] returns `None` if no adapter is available, and
+//! [`with_context`] skips the given closure entirely in that case.
+
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{version, Heap};
+
+/// A headless wgpu device and its queue.
+#[derive(Debug)]
+pub struct TestContext {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+/// Runs `f` with a newly-created [`TestContext`], or does nothing if no adapter is available.
+pub fn with_context(f: impl FnOnce(&TestContext)) {
+    if let Some(context) = TestContext::new() {
+        f(&context);
+    }
+}
+
+impl TestContext {
+    /// Creates a new `TestContext` on the first adapter that wgpu can find, or returns `None` if
+    /// there is none.
+    pub fn new() -> Option<Self> {
+        let instance = version::create_instance();
+        let adapter = pollster::block_on(instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            },
+        ))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &version::device_descriptor("wgpu-allocators test harness"),
+            None,
+        ))
+        .ok()?;
+
+        Some(Self { device, queue })
+    }
+
+    /// Records commands with `f`, submits them, and blocks until the GPU has finished executing
+    /// them.
+    pub fn submit(&self, f: impl FnOnce(&mut wgpu::CommandEncoder)) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None,
+        });
+        f(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+
+    /// Reads back the contents of the GPU buffer of `heap` within `range`.
+    ///
+    /// Only flushed data is visible here; writes that are still in staging memory are not.
+    ///
+    /// Heaps with [`HeapUsages::MAP_READ`](crate::HeapUsages::MAP_READ) can only be read back
+    /// this way with wgpu 0.13; with later versions, map them instead.
+    pub fn read_heap(&self, heap: &Heap, range: Range<BufferAddress>) -> Vec<u8> {
+        self.read_buffer(&heap.gpu_buffer, range)
+    }
+
+    /// Reads back the contents of `buffer` within `range`.
+    ///
+    /// `buffer` must have been created with [`wgpu::BufferUsages::COPY_SRC`].
+    pub fn read_buffer(&self, buffer: &wgpu::Buffer, range: Range<BufferAddress>) -> Vec<u8> {
+        // Buffer copies must begin and end on a multiple of `COPY_BUFFER_ALIGNMENT`, so we copy a
+        // slightly larger range and trim it afterwards.
+        let Range { start, end } = crate::align_range_for_copy(range.clone(), BufferAddress::MAX);
+
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("wgpu-allocators test harness readback"),
+            size: end - start,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        self.submit(|encoder| {
+            encoder.copy_buffer_to_buffer(buffer, start, &readback_buffer, 0, end - start);
+        });
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("failed to map readback buffer");
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let contents = {
+            let mapped = slice.get_mapped_range();
+            // Note: these casts can't truncate as the readback buffer was successfully mapped.
+            mapped[((range.start - start) as usize)..((range.end - start) as usize)].to_vec()
+        };
+        readback_buffer.unmap();
+
+        contents
+    }
+}
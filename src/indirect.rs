@@ -0,0 +1,197 @@
+//! Buffers of arguments for indirect draws and dispatches.
+//!
+//! In GPU-driven rendering, the arguments of draw calls are themselves stored in a buffer, where
+//! they can be written by the CPU or by compute shaders. An [`IndirectArgBuffer`] is such a buffer,
+//! divided into *slots* of one argument struct each. Slots are packed with no padding in between,
+//! as [`wgpu::RenderPass::multi_draw_indirect`] expects, and so are 4-byte aligned rather than
+//! aligned to their size.
+
+use wgpu::BufferAddress;
+
+use std::{marker::PhantomData, num::NonZeroU32, ops::Range};
+
+#[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+pub use wgpu::util::{DispatchIndirect, DrawIndexedIndirect, DrawIndirect};
+// Note: newer versions of wgpu renamed these, and the fields of `DrawIndexedIndirect` with them.
+#[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+pub use wgpu::util::{
+    DispatchIndirectArgs as DispatchIndirect, DrawIndexedIndirectArgs as DrawIndexedIndirect,
+    DrawIndirectArgs as DrawIndirect,
+};
+
+use crate::{AllocError, Allocator, FreeList, Heap, HeapUsages, NonZeroBufferAddress};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// The arguments of an indirect draw or dispatch.
+pub trait IndirectArgs: Copy + sealed::Sealed {
+    /// The bytes of the arguments, as the GPU reads them.
+    fn as_bytes(&self) -> &[u8];
+}
+
+macro_rules! indirect_args {
+    ($($ty:ty),*) => {
+        $(
+            impl sealed::Sealed for $ty {}
+
+            impl IndirectArgs for $ty {
+                fn as_bytes(&self) -> &[u8] {
+                    <$ty>::as_bytes(self)
+                }
+            }
+        )*
+    };
+}
+
+indirect_args!(DrawIndirect, DrawIndexedIndirect, DispatchIndirect);
+
+/// A heap of indirect arguments of type `T`, allocated in runs of consecutive slots.
+#[derive(Debug)]
+pub struct IndirectArgBuffer<T> {
+    heap: Heap,
+    allocator: FreeList,
+    capacity: u32,
+    _args: PhantomData<T>,
+}
+
+impl<T: IndirectArgs> IndirectArgBuffer<T> {
+    /// The size, in bytes, of each slot.
+    pub const SLOT_SIZE: BufferAddress = std::mem::size_of::<T>() as BufferAddress;
+
+    /// Creates a new `IndirectArgBuffer` of `capacity` slots.
+    pub fn new(device: &wgpu::Device, capacity: NonZeroU32) -> Self {
+        let size = BufferAddress::from(capacity.get()) * Self::SLOT_SIZE;
+        // Note: every argument struct is nonempty, so `size` is nonzero.
+        let size = NonZeroBufferAddress::new(size).unwrap();
+
+        Self {
+            heap: Heap::new(device, size, HeapUsages::INDIRECT),
+            allocator: FreeList::with_capacity(size),
+            capacity: capacity.get(),
+            _args: PhantomData,
+        }
+    }
+
+    /// The heap holding the slots, such as for unmapping and flushing it.
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// The total number of slots.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Allocates `count` consecutive slots, which can be drawn from with a single multi-draw.
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::OutOfMemory`] if there is no free run of `count` slots.
+    pub fn alloc_slots(&mut self, count: NonZeroU32) -> Result<Range<u32>, AllocError> {
+        let size = BufferAddress::from(count.get()) * Self::SLOT_SIZE;
+        // Note: slots are aligned to their size so that each allocation starts on a slot.
+        let range = self.allocator.alloc(
+            NonZeroBufferAddress::new(size).unwrap(),
+            NonZeroBufferAddress::new(Self::SLOT_SIZE).unwrap(),
+        )?;
+        let first_slot = (range.start / Self::SLOT_SIZE) as u32;
+
+        Ok(first_slot..(first_slot + count.get()))
+    }
+
+    /// Allocates a single slot.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::alloc_slots`].
+    pub fn alloc_slot(&mut self) -> Result<u32, AllocError> {
+        Ok(self.alloc_slots(NonZeroU32::MIN)?.start)
+    }
+
+    /// Frees `slots`.
+    ///
+    /// # Errors
+    ///
+    /// This fails if `slots` was not allocated by [`Self::alloc_slots`] on this buffer, in which
+    /// case nothing is changed.
+    ///
+    /// # Safety
+    ///
+    /// `slots` must not have been freed already, and must no longer be in use by the GPU.
+    pub unsafe fn dealloc_slots(&mut self, slots: Range<u32>) -> Result<(), AllocError> {
+        let range = self.slot_offset(slots.start)..self.slot_offset(slots.end);
+
+        // SAFETY: The caller guarantees that `slots` is live.
+        unsafe { self.allocator.dealloc(range) }
+    }
+
+    /// The offset, in bytes, of `slot` in the heap, as passed to the indirect draw and dispatch
+    /// methods of wgpu.
+    pub fn slot_offset(&self, slot: u32) -> BufferAddress {
+        BufferAddress::from(slot) * Self::SLOT_SIZE
+    }
+
+    /// Writes `args` into `slot`.
+    ///
+    /// As with [`Heap::write`], the arguments reach the GPU once the heap is flushed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot` is past the last slot, or if the heap is not mapped.
+    pub fn write_args(&self, slot: u32, args: &T) {
+        self.write_all_args(slot, std::slice::from_ref(args));
+    }
+
+    /// Writes each of `args` into consecutive slots, starting at `first_slot`.
+    ///
+    /// # Panics
+    ///
+    /// See [`Self::write_args`].
+    pub fn write_all_args(&self, first_slot: u32, args: &[T]) {
+        let end = first_slot as usize + args.len();
+        assert!(end <= self.capacity as usize, "slots {first_slot}..{end} are out of bounds");
+
+        let contents: Vec<u8> = args.iter().flat_map(|args| args.as_bytes()).copied().collect();
+        let start = self.slot_offset(first_slot);
+        self.heap.write(start..(start + contents.len() as BufferAddress), &contents);
+    }
+}
+
+impl IndirectArgBuffer<DrawIndirect> {
+    /// Draws with the arguments in `slot`.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, slot: u32) {
+        pass.draw_indirect(&self.heap.gpu_buffer, self.slot_offset(slot));
+    }
+
+    /// Draws once with the arguments in each of `slots`.
+    ///
+    /// This requires [`wgpu::Features::MULTI_DRAW_INDIRECT`].
+    pub fn multi_draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, slots: Range<u32>) {
+        let offset = self.slot_offset(slots.start);
+        pass.multi_draw_indirect(&self.heap.gpu_buffer, offset, slots.len() as u32);
+    }
+}
+
+impl IndirectArgBuffer<DrawIndexedIndirect> {
+    /// Draws indexed vertices with the arguments in `slot`.
+    pub fn draw_indexed<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, slot: u32) {
+        pass.draw_indexed_indirect(&self.heap.gpu_buffer, self.slot_offset(slot));
+    }
+
+    /// Draws indexed vertices once with the arguments in each of `slots`.
+    ///
+    /// This requires [`wgpu::Features::MULTI_DRAW_INDIRECT`].
+    pub fn multi_draw_indexed<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, slots: Range<u32>) {
+        let offset = self.slot_offset(slots.start);
+        pass.multi_draw_indexed_indirect(&self.heap.gpu_buffer, offset, slots.len() as u32);
+    }
+}
+
+impl IndirectArgBuffer<DispatchIndirect> {
+    /// Dispatches workgroups with the arguments in `slot`.
+    pub fn dispatch<'a>(&'a self, pass: &mut wgpu::ComputePass<'a>, slot: u32) {
+        pass.dispatch_workgroups_indirect(&self.heap.gpu_buffer, self.slot_offset(slot));
+    }
+}
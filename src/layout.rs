@@ -0,0 +1,267 @@
+//! Memory layouts of WGSL structs, computed on the CPU.
+//!
+//! WGSL lays out the members of a struct at offsets that depend on their alignment, which often
+//! differs from that of the equivalent Rust type: a `vec3<f32>` is aligned to 16 bytes, for
+//! example, while a `[f32; 3]` is aligned to 4. Rather than requiring Rust structs to be padded by
+//! hand to match, this module computes WGSL offsets for a list of member types and writes values
+//! at those offsets, zeroing the padding in between.
+//!
+//! A struct describes its members with [`StructLayout`] and [`StructWriter`], or more simply is
+//! declared with the [`wgsl_struct!`](crate::wgsl_struct) macro, which implements [`WgslType`] for
+//! it. It can then be written into a heap with [`Heap::write_struct`](crate::Heap::write_struct):
+//!
+//! ```
+//! wgpu_allocators::wgsl_struct! {
+//!     pub struct Light {
+//!         pub position: [f32; 3],
+//!         pub intensity: f32,
+//!         pub color: [f32; 3],
+//!     }
+//! }
+//! ```
+//!
+//! Scalars (`f32`, `i32`, and `u32`), vectors of them (`[f32; 2]` through `[u32; 4]`), `f32`
+//! matrices (`[[f32; 2]; 2]` through `[[f32; 4]; 4]`, in column-major order), and other such
+//! structs can be members. Arrays are not supported; see [`ArrayLayout`](crate::typed::ArrayLayout)
+//! for those.
+
+use wgpu::BufferAddress;
+
+use crate::{typed::ArrayLayout, HeapUsages, NonZeroBufferAddress};
+
+/// The rules by which struct members are laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LayoutRules {
+    /// The rules of the WGSL uniform address space, which are much like std140 in GLSL: structs
+    /// are aligned to at least [`ArrayLayout::UNIFORM_ALIGNMENT`] bytes.
+    Std140,
+    /// The rules of the WGSL storage address space, which are much like std430 in GLSL.
+    Std430,
+}
+
+impl LayoutRules {
+    /// [`Self::Std140`] if `usage` contains [`HeapUsages::UNIFORM`], as the buffer may be bound
+    /// as a uniform buffer, or [`Self::Std430`] otherwise.
+    pub fn for_usage(usage: HeapUsages) -> Self {
+        if usage.contains(HeapUsages::UNIFORM) {
+            Self::Std140
+        } else {
+            Self::Std430
+        }
+    }
+}
+
+/// The size and alignment of a WGSL type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TypeLayout {
+    /// The size, in bytes, of the type, including any padding at its end.
+    pub size: BufferAddress,
+    /// The alignment, in bytes, of the type.
+    pub alignment: NonZeroBufferAddress,
+}
+
+impl TypeLayout {
+    fn new(size: BufferAddress, alignment: BufferAddress) -> Self {
+        Self { size, alignment: NonZeroBufferAddress::new(alignment).unwrap() }
+    }
+}
+
+/// A Rust type with an equivalent WGSL type.
+pub trait WgslType {
+    /// The layout of the WGSL type under `rules`.
+    fn layout(rules: LayoutRules) -> TypeLayout;
+
+    /// Writes `self` as laid out under `rules` into `bytes`, which is exactly
+    /// [`TypeLayout::size`] bytes long and zeroed.
+    fn write_bytes(&self, rules: LayoutRules, bytes: &mut [u8]);
+
+    /// The bytes of `self` as laid out under `rules`, with any padding zeroed.
+    fn to_bytes(&self, rules: LayoutRules) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        let mut bytes = vec![0; Self::layout(rules).size as usize];
+        self.write_bytes(rules, &mut bytes);
+
+        bytes
+    }
+}
+
+macro_rules! scalars {
+    ($($ty:ty),*) => {
+        $(
+            impl WgslType for $ty {
+                fn layout(_: LayoutRules) -> TypeLayout {
+                    TypeLayout::new(4, 4)
+                }
+
+                fn write_bytes(&self, _: LayoutRules, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(bytemuck::bytes_of(self));
+                }
+            }
+
+            vectors!($ty: 2 => 8, 3 => 16, 4 => 16);
+        )*
+    };
+}
+
+macro_rules! vectors {
+    ($ty:ty: $($len:literal => $alignment:literal),*) => {
+        $(
+            impl WgslType for [$ty; $len] {
+                fn layout(_: LayoutRules) -> TypeLayout {
+                    TypeLayout::new(4 * $len, $alignment)
+                }
+
+                fn write_bytes(&self, _: LayoutRules, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(bytemuck::cast_slice(self));
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! matrices {
+    ($($columns:literal x $rows:literal),*) => {
+        $(
+            impl WgslType for [[f32; $rows]; $columns] {
+                fn layout(rules: LayoutRules) -> TypeLayout {
+                    // Each column is a vector, padded to its alignment.
+                    let column = <[f32; $rows]>::layout(rules);
+
+                    TypeLayout {
+                        size: $columns * column.alignment.get(),
+                        alignment: column.alignment,
+                    }
+                }
+
+                fn write_bytes(&self, rules: LayoutRules, bytes: &mut [u8]) {
+                    let stride = <[f32; $rows]>::layout(rules).alignment.get() as usize;
+                    for (column, chunk) in self.iter().zip(bytes.chunks_mut(stride)) {
+                        chunk[..4 * $rows].copy_from_slice(bytemuck::cast_slice(column));
+                    }
+                }
+            }
+        )*
+    };
+}
+
+scalars!(f32, i32, u32);
+matrices!(2 x 2, 2 x 3, 2 x 4, 3 x 2, 3 x 3, 3 x 4, 4 x 2, 4 x 3, 4 x 4);
+
+/// The offsets of the members of a WGSL struct, built up one member at a time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructLayout {
+    rules: LayoutRules,
+    offsets: Vec<BufferAddress>,
+    /// The offset just past the last member.
+    end: BufferAddress,
+    /// The largest alignment of any member.
+    alignment: NonZeroBufferAddress,
+}
+
+impl StructLayout {
+    /// Creates the layout of a struct with no members, to be laid out under `rules`.
+    pub fn new(rules: LayoutRules) -> Self {
+        Self { rules, offsets: Vec::new(), end: 0, alignment: NonZeroBufferAddress::MIN }
+    }
+
+    /// Appends a member of type `T`.
+    pub fn field<T: WgslType>(self) -> Self {
+        let layout = T::layout(self.rules);
+
+        self.field_with_layout(layout)
+    }
+
+    /// Appends a member with layout `layout`.
+    pub fn field_with_layout(mut self, layout: TypeLayout) -> Self {
+        let offset = self.end.next_multiple_of(layout.alignment.get());
+        self.offsets.push(offset);
+        self.end = offset + layout.size;
+        self.alignment = self.alignment.max(layout.alignment);
+
+        self
+    }
+
+    pub fn rules(&self) -> LayoutRules {
+        self.rules
+    }
+
+    /// The offset, in bytes, of each member, in the order they were appended.
+    pub fn offsets(&self) -> &[BufferAddress] {
+        &self.offsets
+    }
+
+    /// The size and alignment of the struct.
+    pub fn type_layout(&self) -> TypeLayout {
+        let mut alignment = self.alignment;
+        if self.rules == LayoutRules::Std140 {
+            alignment = alignment.max(
+                NonZeroBufferAddress::new(ArrayLayout::UNIFORM_ALIGNMENT).unwrap(),
+            );
+        }
+
+        TypeLayout { size: self.end.next_multiple_of(alignment.get()), alignment }
+    }
+}
+
+/// Writes the members of a WGSL struct at the offsets computed by a [`StructLayout`].
+#[derive(Debug)]
+pub struct StructWriter<'a> {
+    layout: StructLayout,
+    bytes: &'a mut [u8],
+}
+
+impl<'a> StructWriter<'a> {
+    /// Creates a writer of the members of a struct laid out under `rules` into `bytes`.
+    ///
+    /// `bytes` should be zeroed, as padding is skipped rather than written.
+    pub fn new(rules: LayoutRules, bytes: &'a mut [u8]) -> Self {
+        Self { layout: StructLayout::new(rules), bytes }
+    }
+
+    /// Writes `value` as the next member.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the member doesn't fit in the bytes given to [`Self::new`].
+    pub fn field<T: WgslType>(mut self, value: &T) -> Self {
+        let layout = T::layout(self.layout.rules);
+        self.layout = self.layout.field_with_layout(layout);
+        // Note: a member was just appended, so there is a last offset.
+        let start = *self.layout.offsets.last().unwrap() as usize;
+        let end = start + layout.size as usize;
+        value.write_bytes(self.layout.rules, &mut self.bytes[start..end]);
+
+        self
+    }
+}
+
+/// Declares a struct and implements [`WgslType`](crate::layout::WgslType) for it, with its
+/// members laid out in declaration order.
+///
+/// See the [`layout`](crate::layout) module for which member types are supported.
+#[macro_export]
+macro_rules! wgsl_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty,)*
+        }
+
+        impl $crate::layout::WgslType for $name {
+            fn layout(rules: $crate::layout::LayoutRules) -> $crate::layout::TypeLayout {
+                $crate::layout::StructLayout::new(rules)$(.field::<$ty>())*.type_layout()
+            }
+
+            fn write_bytes(&self, rules: $crate::layout::LayoutRules, bytes: &mut [u8]) {
+                $crate::layout::StructWriter::new(rules, bytes)$(.field(&self.$field))*;
+            }
+        }
+    };
+}
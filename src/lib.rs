@@ -1,32 +1,180 @@
 //! High-level allocators for WGPU.
+//!
+//! The version of wgpu to build against is chosen with one of the `wgpu-0_13` (the default),
+//! `wgpu-0_19`, or `wgpu-22` features, and is re-exported as [`wgpu`] so that dependents use the
+//! same one.
 
-#![feature(unchecked_math)]
+// Note: if several versions are enabled, such as by `--all-features`, the newest wins.
+#[cfg(feature = "wgpu-22")]
+pub extern crate wgpu_22 as wgpu;
+#[cfg(all(feature = "wgpu-0_19", not(feature = "wgpu-22")))]
+pub extern crate wgpu_0_19 as wgpu;
+#[cfg(all(feature = "wgpu-0_13", not(any(feature = "wgpu-0_19", feature = "wgpu-22"))))]
+pub extern crate wgpu_0_13 as wgpu;
 
+#[cfg(not(any(feature = "wgpu-0_13", feature = "wgpu-0_19", feature = "wgpu-22")))]
+compile_error!("one of the `wgpu-0_13`, `wgpu-0_19`, or `wgpu-22` features must be enabled");
+#[cfg(all(feature = "bevy", feature = "wgpu-22"))]
+compile_error!("the `bevy` feature builds against wgpu 0.19 and can't be used with `wgpu-22`");
+
+pub mod aging;
 mod allocators;
 pub mod arena;
+pub mod backing;
+pub mod batch;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod bind_group;
+pub mod cache;
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod compaction;
+pub mod copy;
+pub mod diagnostics;
+pub mod error;
+pub mod frame;
+pub mod governor;
+pub mod growth;
+pub mod guard;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod indirect;
+pub mod layout;
+pub mod mapping;
+pub mod mesh;
+pub mod metrics;
+pub mod observer;
+pub mod queue;
+mod raw;
+#[cfg(feature = "naga")]
+pub mod reflect;
+pub mod selftest;
+pub mod segmented;
+pub mod shared;
+pub mod sizes;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+mod staging;
+pub mod stats;
+pub mod texture;
+#[cfg(feature = "track-allocs")]
+pub mod tracking;
+pub mod typed;
+pub mod upload;
+mod version;
+mod virtual_heap;
+pub mod watermark;
+mod window;
 
 use wgpu::{BufferAddress, BufferUsages};
 
-use std::ops::Range;
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    future::Future,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use layout::{LayoutRules, WgslType};
+use mapping::{MapFuture, MapTracker, NotMapped, WriteView};
+use queue::{InFlightRanges, Serial};
+use window::StagingWindow;
 
 pub use allocators::*;
-pub use arena::HeapArena;
+pub use arena::{HeapArena, HeapEvent, HeapEventKind, Priority};
+pub use backing::{GpuBacking, HeapBacking, MockHeap, Wgpu};
+pub use batch::WriteBatcher;
+pub use bind_group::BindGroupCache;
+pub use cache::CachedArena;
+pub use compaction::{CompactionBudget, CompactionReport};
+pub use error::{AllocError, AsyncWriteError, BindingError, CopyError};
+pub use frame::FrameHeap;
+pub use growth::GrowthPolicy;
+pub use indirect::IndirectArgBuffer;
+pub use mapping::MapState;
+pub use mesh::MeshAllocator;
+pub use metrics::{FrameCounters, Metrics};
+pub use observer::AllocationObserver;
+pub use queue::{Completion, InFlight, ManagedQueue, TransferContext, TransferToken};
+pub use raw::RawHeap;
+#[cfg(feature = "naga")]
+pub use naga;
+pub use segmented::SegmentedAllocation;
+pub use shared::SharedHeapArena;
+pub use staging::StagingHeap;
+pub use stats::{ArenaStats, Stats, TagStats};
+pub use texture::TextureHeap;
+pub use typed::TypedHeap;
+pub use upload::{UploadPath, UploadPolicy, UploadStrategy};
+pub use virtual_heap::VirtualHeap;
 
 pub type NonZeroBufferAddress = std::num::NonZeroU64;
 
 pub trait Allocator {
-    fn new(heap: &Heap) -> Self where Self: Sized;
+    /// Creates an allocator that manages `size` bytes, independently of any [`Heap`].
+    ///
+    /// This lets allocators run over memory other than a `Heap`, such as with a [`RawHeap`] or a
+    /// [`VirtualHeap`], or in tests on their own.
+    fn with_capacity(size: NonZeroBufferAddress) -> Self where Self: Sized;
+
+    /// Creates an allocator that manages all of `heap`.
+    ///
+    /// `heap` is usually a [`Heap`], but may be a [`MockHeap`] when no device is available. This
+    /// is equivalent to [`Self::with_capacity`] with the size of `heap`.
+    fn new(heap: &dyn HeapBacking) -> Self where Self: Sized {
+        Self::with_capacity(heap.size())
+    }
 
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::OutOfMemory`] if there is no room for the allocation, or
+    /// [`AllocError::AlignmentNotSupported`] if the allocator can never honor `alignment`.
     fn alloc(
         &mut self,
         size: NonZeroBufferAddress,
         alignment: NonZeroBufferAddress,
-    ) -> Option<Range<BufferAddress>>;
+    ) -> Result<Range<BufferAddress>, AllocError>;
 
+    /// # Errors
+    ///
+    /// This fails if the allocator can't free `range`, in which case nothing is changed.
+    ///
     /// # Safety
     ///
     /// `range` must be a valid allocation previously returned by this allocator.
-    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), ()>;
+    unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError>;
+
+    /// The size, in bytes, of the largest contiguous free region, if the allocator keeps track of
+    /// it.
+    ///
+    /// A [`HeapArena`] uses this to pick which of its heaps to try first, and to skip those that
+    /// can't hold an allocation; it must therefore never be less than the actual size. It is also
+    /// used for diagnostics.
+    fn largest_free_block(&self) -> Option<BufferAddress> {
+        None
+    }
+
+    /// Frees every allocation at once, returning whether it could.
+    ///
+    /// This is much cheaper than freeing allocations one by one, and keeps any configuration that
+    /// the allocator was created with. Allocators that can't reset themselves return `false`,
+    /// which is the default; a [`HeapArena`] replaces those with fresh ones from [`Self::new`].
+    fn reset(&mut self) -> bool {
+        false
+    }
+
+    /// Extends this allocator to manage `new_size` bytes after its heap has grown, returning
+    /// whether it could.
+    ///
+    /// The new memory is appended to the end of the heap and existing allocations are unaffected.
+    /// Allocators that can't make use of such memory return `false`, which is the default.
+    fn grow(&mut self, new_size: NonZeroBufferAddress) -> bool {
+        let _ = new_size;
+        false
+    }
 }
 
 bitflags::bitflags! {
@@ -41,13 +189,109 @@ bitflags::bitflags! {
         const STORAGE = BufferUsages::STORAGE.bits();
         /// Allows a heap buffer to be the indirect buffer in an indirect draw call.
         const INDIRECT = BufferUsages::INDIRECT.bits();
+        /// Allows a heap buffer to be the source of copies recorded outside of this crate.
+        ///
+        /// The GPU buffer of every heap without [`Self::MAP_READ`] can be copied from regardless;
+        /// combined with `MAP_READ`, this requires the device to have
+        /// [`wgpu::Features::MAPPABLE_PRIMARY_BUFFERS`] enabled.
+        const COPY_SRC = BufferUsages::COPY_SRC.bits();
+        /// Allows a heap buffer to be the destination of copies recorded outside of this crate.
+        ///
+        /// The GPU buffer of every heap can be copied into regardless, as that is how data is
+        /// uploaded to it, so this only serves to document intent.
+        const COPY_DST = BufferUsages::COPY_DST.bits();
+        /// Allows the GPU buffer of a heap to be mapped for reading with [`Heap::map_read_async`].
+        ///
+        /// Heaps with this usage are read directly, so they have no staging buffer and upload data
+        /// as with [`UploadStrategy::QueueWrite`], whatever strategy they are created with.
+        /// Unless the device has [`wgpu::Features::MAPPABLE_PRIMARY_BUFFERS`] enabled, this cannot
+        /// be combined with any usage other than [`Self::COPY_DST`].
+        const MAP_READ = BufferUsages::MAP_READ.bits();
+        /// Allows the GPU buffer of a heap to be mapped for writing with
+        /// [`Heap::map_write_async`].
+        ///
+        /// This requires the device to have [`wgpu::Features::MAPPABLE_PRIMARY_BUFFERS`] enabled.
+        const MAP_WRITE = BufferUsages::MAP_WRITE.bits();
+        /// Allows a heap buffer to be the destination of
+        /// [`wgpu::CommandEncoder::resolve_query_set`].
+        ///
+        /// The destination offset must be a multiple of [`wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT`],
+        /// so allocations for resolved queries should be made with that alignment. wgpu 0.13 has
+        /// no such usage, as any buffer with [`Self::COPY_DST`] can be resolved into.
+        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+        const QUERY_RESOLVE = BufferUsages::QUERY_RESOLVE.bits();
     }
 }
 
 impl HeapUsages {
+    /// The heap usages equivalent to `usages`, or `None` if any of `usages` has no heap
+    /// equivalent.
+    pub fn from_buffer_usages(usages: BufferUsages) -> Option<Self> {
+        Self::from_bits(usages.bits())
+    }
+
     fn as_buffer_usages(self) -> BufferUsages {
-        // SAFETY: TODO
-        unsafe { BufferUsages::from_bits_unchecked(self.bits()) }
+        // Note: every heap usage is defined as the bits of the buffer usage of the same name, and
+        // every buffer usage of each wgpu version has a heap usage, including `QUERY_RESOLVE`.
+        BufferUsages::from_bits(self.bits()).expect("heap usage has no buffer equivalent")
+    }
+}
+
+impl From<HeapUsages> for BufferUsages {
+    fn from(usage: HeapUsages) -> Self {
+        usage.as_buffer_usages()
+    }
+}
+
+/// Describes a [`Heap`] to be created with [`Heap::with_descriptor`].
+#[derive(Clone, Copy, Debug)]
+pub struct HeapDescriptor<'a> {
+    /// The debug label of the heap, which shows up in graphics debuggers such as RenderDoc and
+    /// PIX.
+    ///
+    /// The GPU buffer is given this label as is, and the staging and readback buffers are given it
+    /// with a `-staging` or `-readback` suffix, respectively.
+    pub label: Option<&'a str>,
+    pub size: NonZeroBufferAddress,
+    pub usage: HeapUsages,
+    pub upload_strategy: UploadStrategy,
+    /// Whether the heap has a CPU shadow of its GPU buffer, as with [`Heap::with_readback`].
+    pub readback: bool,
+}
+
+impl<'a> HeapDescriptor<'a> {
+    /// Describes an unlabeled heap that is otherwise like one created with [`Heap::new`].
+    pub fn new(size: NonZeroBufferAddress, usage: HeapUsages) -> Self {
+        Self {
+            label: None,
+            size,
+            usage,
+            upload_strategy: UploadStrategy::default(),
+            readback: false,
+        }
+    }
+
+    /// The number of buffers that a heap created from this descriptor owns, as counted by the
+    /// [`governor`].
+    pub(crate) fn buffer_count(&self) -> usize {
+        1 + usize::from(self.has_staging()) + usize::from(self.readback)
+    }
+
+    /// Whether a heap created from this descriptor has a staging buffer.
+    fn has_staging(&self) -> bool {
+        let is_mapped_directly = self.usage.contains(HeapUsages::MAP_READ);
+
+        self.upload_strategy != UploadStrategy::QueueWrite && !is_mapped_directly
+    }
+
+    /// The size of the staging buffer of a heap created from this descriptor, if it has one.
+    fn staging_size(&self) -> BufferAddress {
+        match self.upload_strategy {
+            UploadStrategy::Windowed { window_size } => {
+                StagingWindow::buffer_size(window_size.get())
+            }
+            _ => self.size.get(),
+        }
     }
 }
 
@@ -57,56 +301,370 @@ impl Heap {
         size: NonZeroBufferAddress,
         usage: HeapUsages,
     ) -> Self {
-        Heap {
-            staging_buffer: create_buffer(
-                device,
-                size.get(),
-                BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
-                true,
-            ),
-            gpu_buffer: create_buffer(
-                device,
-                size.get(),
-                BufferUsages::COPY_DST | usage.as_buffer_usages(),
-                false,
-            ),
+        Self::with_descriptor(device, &HeapDescriptor::new(size, usage))
+    }
+
+    /// Creates a new `Heap` with a CPU shadow of its GPU buffer.
+    ///
+    /// Data written to the GPU buffer by the GPU itself can be copied back into the shadow with
+    /// [`Self::sync_back_dirty`] and then read on the CPU.
+    pub fn with_readback(
+        device: &wgpu::Device,
+        size: NonZeroBufferAddress,
+        usage: HeapUsages,
+    ) -> Self {
+        Self::with_descriptor(device, &HeapDescriptor {
+            readback: true,
+            ..HeapDescriptor::new(size, usage)
+        })
+    }
+
+    /// Creates a new `Heap` that uploads data by way of `strategy`.
+    ///
+    /// With [`UploadStrategy::Staging`], this is equivalent to [`Self::new`].
+    pub fn with_upload_strategy(
+        device: &wgpu::Device,
+        size: NonZeroBufferAddress,
+        usage: HeapUsages,
+        strategy: UploadStrategy,
+    ) -> Self {
+        Self::with_descriptor(device, &HeapDescriptor {
+            upload_strategy: strategy,
+            ..HeapDescriptor::new(size, usage)
+        })
+    }
+
+    /// Creates a new `Heap` holding `contents`, much like
+    /// [`wgpu::util::DeviceExt::create_buffer_init`].
+    ///
+    /// The heap is exactly large enough for `contents`, padded with zeros to
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`], and uploads by way of [`UploadStrategy::QueueWrite`], so
+    /// `contents` reach the GPU buffer at the start of the next submission to `queue`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `contents` is empty.
+    pub fn with_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        usage: HeapUsages,
+        contents: &[u8],
+    ) -> Self {
+        let contents = pad_for_copy(contents);
+        let size = NonZeroBufferAddress::new(contents.len() as BufferAddress)
+            .expect("cannot create a heap from empty contents");
+        let heap = Self::with_upload_strategy(device, size, usage, UploadStrategy::QueueWrite);
+        heap.write_via(queue, 0..size.get(), &contents);
+
+        heap
+    }
+
+    /// Creates a new `Heap` as described by `descriptor`.
+    pub fn with_descriptor(device: &wgpu::Device, descriptor: &HeapDescriptor) -> Self {
+        let HeapDescriptor { label, size, usage, readback: has_readback, .. } = *descriptor;
+        let gpu_usage = gpu_buffer_usages(usage, has_readback);
+        validate_gpu_mappability(device, gpu_usage);
+        let has_staging = descriptor.has_staging();
+        // Note: the buffers are counted before they are created so that a heap is never dropped
+        // with buffers that were not counted, even if creating one of them panics.
+        let reservation = governor::acquire(descriptor.buffer_count());
+
+        let heap = Heap {
+            staging_buffer: has_staging.then(|| {
+                create_buffer(
+                    device,
+                    suffixed_label(label, "staging").as_deref(),
+                    descriptor.staging_size(),
+                    BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
+                    true,
+                )
+            }),
+            window: match descriptor.upload_strategy {
+                UploadStrategy::Windowed { .. } if has_staging => {
+                    Some(RefCell::new(StagingWindow::new(descriptor.staging_size())))
+                }
+                _ => None,
+            },
+            gpu_buffer: create_buffer(device, label, size.get(), gpu_usage, false),
+            readback_buffer: has_readback.then(|| {
+                create_buffer(
+                    device,
+                    suffixed_label(label, "readback").as_deref(),
+                    size.get(),
+                    BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    false,
+                )
+            }),
+            id: HeapId::next(),
+            label: label.map(str::to_owned),
+            gpu_dirty_ranges: RefCell::default(),
+            staging_dirty_ranges: RefCell::default(),
+            staging_in_flight: RefCell::default(),
+            staging_map_state: MapTracker::new(match has_staging {
+                true => MapState::Mapped,
+                false => MapState::Unmapped,
+            }),
+            binding_limits: BindingLimits::new(&device.limits()),
             size,
+            usage,
+        };
+        debug_assert_eq!(heap.buffer_count(), descriptor.buffer_count());
+        reservation.keep();
+
+        heap
+    }
+
+    /// The number of buffers owned by this heap, as counted by the [`governor`].
+    fn buffer_count(&self) -> usize {
+        1 + usize::from(self.staging_buffer.is_some()) + usize::from(self.readback_buffer.is_some())
+    }
+}
+
+/// The usages of the GPU buffer of a heap with usage `usage`.
+fn gpu_buffer_usages(usage: HeapUsages, has_readback: bool) -> BufferUsages {
+    let mut gpu_usage = BufferUsages::COPY_DST | usage.as_buffer_usages();
+    // The GPU buffer is copied from by `Heap::sync_back_dirty` and `Heap::grow`, but MAP_READ
+    // buffers may not be copied from without `Features::MAPPABLE_PRIMARY_BUFFERS`.
+    if has_readback || !usage.contains(HeapUsages::MAP_READ) {
+        gpu_usage |= BufferUsages::COPY_SRC;
+    } else if version::MAP_READ_ALLOWS_COPY_SRC {
+        gpu_usage |= HARNESS_GPU_USAGES;
+    }
+
+    gpu_usage
+}
+
+/// Panics if a GPU buffer with usages `gpu_usage` would need
+/// [`wgpu::Features::MAPPABLE_PRIMARY_BUFFERS`] but `device` doesn't have it enabled.
+///
+/// wgpu would otherwise raise a much less descriptive validation error.
+fn validate_gpu_mappability(device: &wgpu::Device, gpu_usage: BufferUsages) {
+    if device.features().contains(wgpu::Features::MAPPABLE_PRIMARY_BUFFERS) {
+        return;
+    }
+
+    if gpu_usage.contains(BufferUsages::MAP_READ) {
+        let allowed = BufferUsages::MAP_READ | BufferUsages::COPY_DST | HARNESS_GPU_USAGES;
+        if !allowed.contains(gpu_usage) {
+            panic!(
+                "heap usage MAP_READ cannot be combined with other usages unless the device has \
+                 `Features::MAPPABLE_PRIMARY_BUFFERS` enabled",
+            );
         }
     }
+    if gpu_usage.contains(BufferUsages::MAP_WRITE) {
+        panic!(
+            "heap usage MAP_WRITE requires the device to have `Features::MAPPABLE_PRIMARY_BUFFERS` \
+             enabled",
+        );
+    }
+}
+
+/// Additional usages for the GPU buffer of every heap.
+///
+/// With the `test-harness` feature enabled, GPU buffers must be copyable so that the harness can
+/// read them back. Newer versions of wgpu reject this for MAP_READ buffers, which are instead read
+/// by mapping them.
+#[cfg(feature = "test-harness")]
+const HARNESS_GPU_USAGES: BufferUsages = BufferUsages::COPY_SRC;
+#[cfg(not(feature = "test-harness"))]
+const HARNESS_GPU_USAGES: BufferUsages = BufferUsages::empty();
+
+/// The label of a buffer that belongs to a heap labeled `label`, such as its staging buffer.
+fn suffixed_label(label: Option<&str>, suffix: &str) -> Option<String> {
+    label.map(|label| format!("{label}-{suffix}"))
 }
 
 fn create_buffer(
     device: &wgpu::Device,
+    label: Option<&str>,
     size: u64,
     usage: BufferUsages,
     is_mapped_at_creation: bool,
 ) -> wgpu::Buffer {
     device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
+        label,
         size,
         usage,
         mapped_at_creation: is_mapped_at_creation,
     })
 }
 
+/// Identifies the GPU buffer of a [`Heap`], which is unique for as long as the process runs.
+///
+/// A heap is given a new ID whenever its GPU buffer is replaced, as by [`Heap::grow`], so anything
+/// derived from the buffer, such as a bind group, can be keyed by this ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HeapId(u64);
+
+impl HeapId {
+    fn next() -> Self {
+        /// A source of unique `HeapId`s.
+        static NEXT_HEAP_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self(NEXT_HEAP_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Debug)]
 pub struct Heap {
-    staging_buffer: wgpu::Buffer,
+    id: HeapId,
+    /// The staging buffer, unless this heap was created with [`UploadStrategy::QueueWrite`].
+    staging_buffer: Option<wgpu::Buffer>,
+    /// What is staged in [`Self::staging_buffer`], if it is a window smaller than this heap (see
+    /// [`UploadStrategy::Windowed`]) rather than a mirror of the GPU buffer.
+    window: Option<RefCell<StagingWindow>>,
     gpu_buffer: wgpu::Buffer,
+    /// The CPU shadow of [`Self::gpu_buffer`], if this heap was created with
+    /// [`Heap::with_readback`].
+    readback_buffer: Option<wgpu::Buffer>,
+    /// Regions of [`Self::gpu_buffer`] that were marked as modified by the GPU and have not yet
+    /// been copied into [`Self::readback_buffer`].
+    gpu_dirty_ranges: RefCell<Vec<Range<BufferAddress>>>,
+    /// Regions of [`Self::staging_buffer`] that were written and have not yet been flushed.
+    staging_dirty_ranges: RefCell<Vec<Range<BufferAddress>>>,
+    /// Regions of [`Self::staging_buffer`] that are copied from by submissions that may still be
+    /// executing.
+    staging_in_flight: RefCell<InFlightRanges>,
+    /// Whether [`Self::staging_buffer`] is mapped.
+    staging_map_state: MapTracker,
+    /// The limits on bindings of the device that created this heap.
+    binding_limits: BindingLimits,
+    /// The debug label of [`Self::gpu_buffer`], from which those of the other buffers are derived.
+    label: Option<String>,
     size: NonZeroBufferAddress,
+    usage: HeapUsages,
 }
 
 impl Heap {
+    /// The ID of the GPU buffer of this heap.
+    pub fn id(&self) -> HeapId {
+        self.id
+    }
+
     /// The size, in bytes, of this heap.
     pub fn size(&self) -> NonZeroBufferAddress {
         self.size
     }
 
+    /// The usage of this heap.
+    pub fn usage(&self) -> HeapUsages {
+        self.usage
+    }
+
+    /// The debug label of this heap, if it was given one with [`HeapDescriptor::label`].
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The strategy by which this heap uploads data.
+    pub fn upload_strategy(&self) -> UploadStrategy {
+        match (&self.staging_buffer, &self.window) {
+            (Some(_), Some(window)) => UploadStrategy::Windowed {
+                // Note: windows are never empty.
+                window_size: NonZeroBufferAddress::new(window.borrow().size()).unwrap(),
+            },
+            (Some(_), None) => UploadStrategy::Staging,
+            (None, _) => UploadStrategy::QueueWrite,
+        }
+    }
+
+    /// The staging buffer, if it mirrors the whole GPU buffer rather than being a window.
+    fn staging_mirror(&self) -> Option<&wgpu::Buffer> {
+        self.staging_buffer.as_ref().filter(|_| self.window.is_none())
+    }
+
+    /// The range of the staging buffer that a mapping of `range` covers, noting the mapping if
+    /// the staging buffer is a window, which is always mapped whole.
+    fn staging_map_range(&self, range: Range<BufferAddress>) -> Range<BufferAddress> {
+        let Some(window) = self.window.as_ref() else {
+            return range;
+        };
+        let mut window = window.borrow_mut();
+        window.note_map_request();
+
+        0..window.size()
+    }
+
+    pub(crate) fn staging_buffer(&self) -> &wgpu::Buffer {
+        self.staging_buffer.as_ref().expect(
+            "heap has no staging buffer; must be written with `Heap::write_via` or `Heap::upload` \
+             as it was created with `UploadStrategy::QueueWrite`",
+        )
+    }
+
+    /// Requests that `range` of the staging buffer be mapped in `mode`.
+    ///
+    /// This does nothing if this heap has no staging buffer. A staging window (see
+    /// [`UploadStrategy::Windowed`]) is mapped whole, whatever `range` is.
     pub fn map_range_async(&self, range: Range<BufferAddress>, mode: wgpu::MapMode) {
-        self
-            .staging_buffer
-            .slice(range)
-            .map_async(mode, |_| {});
+        if let Some(staging_buffer) = self.staging_buffer.as_ref() {
+            let range = self.staging_map_range(range);
+            self.staging_map_state.map_async(staging_buffer.slice(range), mode);
+        }
+    }
+
+    /// Requests that `range` of the staging buffer be mapped for writing, returning a future that
+    /// resolves once the mapping completes.
+    ///
+    /// As with [`Self::map_range_async`], a staging window is mapped whole. See [`MapFuture`] for
+    /// how the mapping makes progress.
+    pub fn map_async(&self, range: Range<BufferAddress>) -> MapFuture {
+        let staging_buffer = self.staging_buffer();
+        let range = self.staging_map_range(range);
+
+        self.staging_map_state.map_async(staging_buffer.slice(range), wgpu::MapMode::Write)
+    }
+
+    /// Requests that the whole staging buffer be mapped for writing again after [`Self::unmap`].
+    ///
+    /// This is equivalent to [`Self::map_async`] over the whole heap.
+    pub fn remap(&self) -> MapFuture {
+        self.map_async(0..self.size.get())
+    }
+
+    /// Whether the staging buffer is mapped, and so whether it can be written.
+    pub fn map_state(&self) -> MapState {
+        self.staging_map_state.get()
+    }
+
+    /// Resolves once the staging buffer is mapped, remapping it first if it is unmapped.
+    ///
+    /// Unlike [`Self::remap`], `device` is polled for as long as the mapping is pending, so the
+    /// returned future completes without the caller polling the device. Like any mapping, it can
+    /// only complete once every submission that copies from the staging buffer has executed, so
+    /// it must not be awaited between [`Self::unmap`] and submitting those copies.
+    pub async fn ensure_mapped(&self, device: &wgpu::Device) -> Result<(), wgpu::BufferAsyncError> {
+        match self.map_state() {
+            MapState::Mapped => Ok(()),
+            MapState::Pending => mapping::poll_device(device, self.staging_map_state.settled()).await,
+            MapState::Unmapped => mapping::poll_device(device, self.remap()).await,
+        }
+    }
+
+    /// Like [`Self::write`], but waits for the staging buffer to be mapped as with
+    /// [`Self::ensure_mapped`] rather than panicking if it isn't.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the staging buffer can't be mapped, in which case nothing is written.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap has no staging buffer (see
+    /// [`UploadStrategy::QueueWrite`]).
+    pub async fn write_async(
+        &self,
+        device: &wgpu::Device,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+    ) -> Result<(), wgpu::BufferAsyncError> {
+        // Note: this panics before waiting, rather than after, if there is no staging buffer.
+        self.staging_buffer();
+        self.ensure_mapped(device).await?;
+        self.write(range, contents);
+
+        Ok(())
     }
 
     pub fn write_and_flush(
@@ -119,61 +677,1160 @@ impl Heap {
         self.flush_range(encoder, range);
     }
 
+    /// Uploads `contents` into `range` of the GPU buffer by way of `path`.
+    ///
+    /// For [`UploadPath::Staging`], this is equivalent to [`Self::write_and_flush`], and so the
+    /// staging buffer must be mapped. The other paths do not touch the staging buffer. Heaps
+    /// created with [`UploadStrategy::QueueWrite`] take [`UploadPath::QueueWrite`] regardless of
+    /// `path`.
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+        path: UploadPath,
+    ) {
+        let path = match self.upload_strategy() {
+            UploadStrategy::Staging | UploadStrategy::Windowed { .. } => path,
+            UploadStrategy::QueueWrite => UploadPath::QueueWrite,
+        };
+
+        match path {
+            UploadPath::QueueWrite => {
+                queue.write_buffer(&self.gpu_buffer, range.start, contents);
+            }
+            UploadPath::Staging => {
+                self.write_and_flush(encoder, range, contents);
+            }
+            UploadPath::DedicatedStaging => {
+                let size = get_range_size(&range);
+                let staging_buffer = create_buffer(
+                    device,
+                    suffixed_label(self.label(), "dedicated-staging").as_deref(),
+                    align_range_for_copy(0..size, BufferAddress::MAX).end,
+                    BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
+                    true,
+                );
+                staging_buffer
+                    .slice(0..size)
+                    .get_mapped_range_mut()
+                    .copy_from_slice(contents);
+                staging_buffer.unmap();
+
+                // wgpu keeps `staging_buffer` alive until the copy has executed, so it's fine to
+                // drop it here.
+                encoder.copy_buffer_to_buffer(
+                    &staging_buffer,
+                    0,
+                    &self.gpu_buffer,
+                    range.start,
+                    size,
+                );
+            }
+        }
+    }
+
+    /// Writes `contents` into `range` of the staging buffer.
+    ///
+    /// `range` may begin at any offset, but must begin and end on multiples of
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`] to be flushed. With a staging window (see
+    /// [`UploadStrategy::Windowed`]), what doesn't fit into the window is staged later.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the staging buffer is not mapped; see [`Self::map_state`]. With a
+    /// staging window, it instead panics if `range` does not begin and end on multiples of
+    /// `COPY_BUFFER_ALIGNMENT`.
     pub fn write(
         &self,
         range: Range<BufferAddress>,
         contents: &[u8],
     ) {
-        let slice = self.staging_buffer.slice(range.clone());
-        slice.get_mapped_range_mut().copy_from_slice(contents);
+        if let Err(error) = self.checked_write(range, contents) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`Self::write`], but fails instead of panicking if the staging buffer is not mapped.
+    pub fn checked_write(
+        &self,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+    ) -> Result<(), NotMapped> {
+        if let Some(window) = self.window.as_ref() {
+            self.write_through_window(window, range, contents);
+            return Ok(());
+        }
+        self.checked_get_write_view(range)?.copy_from_slice(contents);
+
+        Ok(())
+    }
+
+    fn write_through_window(
+        &self,
+        window: &RefCell<StagingWindow>,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+    ) {
+        assert!(
+            shrink_range_for_copy(range.clone()) == range,
+            "range {:?} written through a staging window must begin and end on multiples of \
+             `COPY_BUFFER_ALIGNMENT`",
+            range,
+        );
+        assert_eq!(
+            get_range_size(&range),
+            contents.len() as BufferAddress,
+            "contents must be exactly as long as the written range",
+        );
+        if range.end > self.size.get() {
+            panic!("range {:?} extends past the end of the heap", range);
+        }
+
+        let is_mapped = self.map_state() == MapState::Mapped;
+        let mut window = window.borrow_mut();
+        window.write(is_mapped.then(|| self.staging_buffer()), range.start, contents);
+        *self.staging_dirty_ranges.borrow_mut() = window.unflushed_ranges();
+    }
+
+    /// Gets a mutable view of `range` of the staging buffer, so that data can be written into it
+    /// directly rather than copied from elsewhere.
+    ///
+    /// `range` is marked as written, and so is flushed by [`Self::flush_dirty`], even if the view
+    /// is left untouched. The view must be dropped before this heap is unmapped.
+    ///
+    /// # Panics
+    ///
+    /// This method panics as [`Self::write`] does, or if the staging buffer is a window (see
+    /// [`UploadStrategy::Windowed`]).
+    pub fn get_write_view(&self, range: Range<BufferAddress>) -> WriteView<'_> {
+        self.checked_get_write_view(range).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`Self::get_write_view`], but the view is of the `T`s in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Besides the panics of [`Self::get_write_view`], this method panics if the length of `range`
+    /// is not a multiple of the size of `T`, or if its start is not aligned for `T`.
+    pub fn get_write_view_as<T: bytemuck::Pod>(
+        &self,
+        range: Range<BufferAddress>,
+    ) -> WriteView<'_, T> {
+        self.get_write_view(range).cast()
+    }
+
+    /// Like [`Self::get_write_view`], but fails instead of panicking if the staging buffer is not
+    /// mapped.
+    pub fn checked_get_write_view(
+        &self,
+        range: Range<BufferAddress>,
+    ) -> Result<WriteView<'_>, NotMapped> {
+        let staging_buffer = self.staging_buffer();
+        if self.window.is_some() {
+            panic!(
+                "heap stages writes through a window, which has no views; must be written with \
+                 `Heap::write`",
+            );
+        }
+        self.staging_map_state.check()?;
+        // Views of mapped memory must begin on a multiple of `MAP_ALIGNMENT`, so the view of
+        // `range` may be part of a wider one.
+        let view_range = align_range_for_map(range.clone(), self.size.get());
+        let view = staging_buffer.slice(view_range.clone()).get_mapped_range_mut();
+        let start = (range.start - view_range.start) as usize;
+        let end = start + get_range_size(&range) as usize;
+        self.staging_dirty_ranges.borrow_mut().push(range);
+
+        Ok(WriteView::new(view, start..end))
+    }
+
+    /// Writes `contents` into `range` of the GPU buffer by way of this heap's
+    /// [`UploadStrategy`].
+    ///
+    /// With [`UploadStrategy::Staging`] or [`UploadStrategy::Windowed`], this is equivalent to
+    /// [`Self::write`], so the data reaches the GPU buffer only once it is flushed. With
+    /// [`UploadStrategy::QueueWrite`], the data is handed to `queue` and reaches the GPU buffer at
+    /// the start of its next submission.
+    pub fn write_via(&self, queue: &wgpu::Queue, range: Range<BufferAddress>, contents: &[u8]) {
+        match self.upload_strategy() {
+            UploadStrategy::Staging | UploadStrategy::Windowed { .. } => {
+                self.write(range, contents);
+            }
+            UploadStrategy::QueueWrite => {
+                queue.write_buffer(&self.gpu_buffer, range.start, contents);
+            }
+        }
+    }
+
+    /// Like [`Self::write`], but writes the bytes of `contents`, which must be exactly as long as
+    /// `range`.
+    pub fn write_slice<T: bytemuck::Pod>(&self, range: Range<BufferAddress>, contents: &[T]) {
+        self.write(range, bytemuck::cast_slice(contents));
+    }
+
+    /// Writes `value` into `range`, laid out as WGSL lays out its type in a buffer with the usage
+    /// of this heap (see [`LayoutRules::for_usage`]).
+    ///
+    /// # Panics
+    ///
+    /// This method panics as [`Self::write`] does, including if the length of `range` differs from
+    /// the size of the laid-out type.
+    pub fn write_struct<T: WgslType>(&self, range: Range<BufferAddress>, value: &T) {
+        self.write(range, &value.to_bytes(LayoutRules::for_usage(self.usage)));
+    }
+
+    /// Like [`Self::write`], but fails instead of writing if a submission that copies from
+    /// `range` has not completed as of `last_completed`.
+    ///
+    /// Copies are attributed to submissions by [`ManagedQueue::submit`], so `last_completed` is
+    /// typically [`ManagedQueue::last_completed`].
+    pub fn try_write(
+        &self,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+        last_completed: Serial,
+    ) -> Result<(), InFlight> {
+        if let Some(serial) = self.staging_busy_until(range.clone(), last_completed) {
+            return Err(InFlight { serial });
+        }
+        self.write(range, contents);
+
+        Ok(())
+    }
+
+    /// The serial of the latest submission that copies from `range` of the staging buffer and has
+    /// not completed as of `last_completed`, if any.
+    pub fn staging_busy_until(
+        &self,
+        range: Range<BufferAddress>,
+        last_completed: Serial,
+    ) -> Option<Serial> {
+        let mut in_flight = self.staging_in_flight.borrow_mut();
+        in_flight.retire(last_completed);
+
+        in_flight.busy_until(range)
+    }
+
+    /// Writes `range` of the staging buffer directly from a memory-mapped file, starting at byte
+    /// `src_offset` of the file.
+    ///
+    /// The copy is performed in chunks of [`MMAP_CHUNK_SIZE`] bytes so that pages of the file are
+    /// faulted in incrementally, and without first reading the file into an intermediate buffer.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the file is too short to fill `range` from `src_offset`, or if the
+    /// staging buffer is not mapped.
+    #[cfg(feature = "memmap2")]
+    pub fn write_from_mmap(
+        &self,
+        range: Range<BufferAddress>,
+        mmap: &memmap2::Mmap,
+        src_offset: usize,
+    ) {
+        let len = get_range_size(&range) as usize;
+        let src = src_offset
+            .checked_add(len)
+            .and_then(|src_end| mmap.get(src_offset..src_end))
+            .unwrap_or_else(|| {
+                panic!(
+                    "memory-mapped file is too short; must contain {} bytes from offset {}",
+                    len,
+                    src_offset,
+                )
+            });
+
+        if self.window.is_some() {
+            self.write(range, src);
+            return;
+        }
+        if let Err(error) = self.staging_map_state.check() {
+            panic!("{}", error);
+        }
+        let mut dst = self.staging_buffer().slice(range.clone()).get_mapped_range_mut();
+        for (dst, src) in dst.chunks_mut(MMAP_CHUNK_SIZE).zip(src.chunks(MMAP_CHUNK_SIZE)) {
+            dst.copy_from_slice(src);
+        }
+        self.staging_dirty_ranges.borrow_mut().push(range);
     }
 
     pub fn slice<'a>(&'a self, range: Range<BufferAddress>) -> wgpu::BufferSlice<'a> {
         self.gpu_buffer.slice(range)
     }
 
+    /// Like [`Self::slice`], but fails instead of returning a slice that can't be used as any of
+    /// `usage`, such as [`HeapUsages::VERTEX`] for a vertex buffer, or that would read stale data.
+    ///
+    /// # Errors
+    ///
+    /// This fails if this heap has none of `usage`, if `range` extends past the end of this heap,
+    /// or if data written into `range` has not been flushed yet.
+    pub fn checked_slice<'a>(
+        &'a self,
+        range: Range<BufferAddress>,
+        usage: HeapUsages,
+    ) -> Result<wgpu::BufferSlice<'a>, BindingError> {
+        self.validate_usage(usage)?;
+        self.validate_range(range.clone())?;
+
+        Ok(self.gpu_buffer.slice(range))
+    }
+
+    /// A binding of `range` of the GPU buffer.
+    ///
+    /// With the `validate-bindings` feature, this checks the binding as [`Self::checked_binding`]
+    /// does, and panics if it is invalid.
     pub fn binding<'a>(&'a self, range: Range<BufferAddress>) -> wgpu::BufferBinding<'a> {
-        wgpu::BufferBinding {
-            buffer: &self.gpu_buffer,
-            offset: range.start,
-            size: Some(
-                NonZeroBufferAddress::new(get_range_size(&range))
-                    .expect("buffer binding size is zero; must be nonzero")
-            ),
+        #[cfg(feature = "validate-bindings")]
+        if let Err(error) = self.validate_binding(range.clone()) {
+            panic!("{}", error);
+        }
+
+        create_binding(&self.gpu_buffer, range)
+    }
+
+    /// Like [`Self::binding`], but fails instead of returning a binding that wgpu would reject
+    /// or that would read stale data.
+    ///
+    /// # Errors
+    ///
+    /// This fails if this heap can be bound as neither a uniform nor a storage buffer, or if
+    /// `range` is larger, or begins on a less aligned offset, than such a binding allows on the
+    /// device that created this heap, whichever this heap can be bound as. It also fails if
+    /// `range` extends past the end of this heap, or if data written into it has not been flushed
+    /// yet.
+    pub fn checked_binding<'a>(
+        &'a self,
+        range: Range<BufferAddress>,
+    ) -> Result<wgpu::BufferBinding<'a>, BindingError> {
+        self.validate_binding(range.clone())?;
+
+        Ok(create_binding(&self.gpu_buffer, range))
+    }
+
+    /// Like [`Self::checked_binding`], but checks the binding as one of `binding_type` only.
+    ///
+    /// # Errors
+    ///
+    /// This fails as [`Self::checked_binding`] does, but against the usage and limits of
+    /// `binding_type`, so that, for example, a uniform binding of a heap without
+    /// [`HeapUsages::UNIFORM`] is caught here rather than when a bind group is created from it.
+    pub fn checked_binding_as<'a>(
+        &'a self,
+        range: Range<BufferAddress>,
+        binding_type: wgpu::BufferBindingType,
+    ) -> Result<wgpu::BufferBinding<'a>, BindingError> {
+        let limits = self.binding_limits;
+        let (usage, max_size, alignment) = match binding_type {
+            wgpu::BufferBindingType::Uniform => {
+                (HeapUsages::UNIFORM, limits.max_uniform_size, limits.uniform_alignment)
+            }
+            wgpu::BufferBindingType::Storage { .. } => {
+                (HeapUsages::STORAGE, limits.max_storage_size, limits.storage_alignment)
+            }
+        };
+        self.validate_usage(usage)?;
+        self.validate_binding_limits(range.clone(), &[(max_size, alignment)])?;
+
+        Ok(create_binding(&self.gpu_buffer, range))
+    }
+
+    fn validate_binding(&self, range: Range<BufferAddress>) -> Result<(), BindingError> {
+        let limits = self.binding_limits;
+        let mut allowed = Vec::with_capacity(2);
+        if self.usage.contains(HeapUsages::UNIFORM) {
+            allowed.push((limits.max_uniform_size, limits.uniform_alignment));
+        }
+        if self.usage.contains(HeapUsages::STORAGE) {
+            allowed.push((limits.max_storage_size, limits.storage_alignment));
+        }
+        self.validate_usage(HeapUsages::UNIFORM | HeapUsages::STORAGE)?;
+
+        self.validate_binding_limits(range, &allowed)
+    }
+
+    /// Checks `range` against `allowed`, the maximum size and offset alignment of each kind of
+    /// binding that it may be used as, and then as [`Self::validate_range`] does.
+    fn validate_binding_limits(
+        &self,
+        range: Range<BufferAddress>,
+        allowed: &[(BufferAddress, BufferAddress)],
+    ) -> Result<(), BindingError> {
+        // Note: a binding is only invalid if it is invalid as every kind of binding allowed.
+        let size = get_range_size(&range);
+        if let Some(max_size) = allowed.iter().map(|&(max_size, _)| max_size).max() {
+            if size > max_size {
+                return Err(BindingError::TooLarge { range, max_size });
+            }
+        }
+        if let Some(alignment) = allowed.iter().map(|&(_, alignment)| alignment).min() {
+            if !range.start.is_multiple_of(alignment) {
+                return Err(BindingError::Misaligned { range, alignment });
+            }
+        }
+
+        self.validate_range(range)
+    }
+
+    /// Fails unless this heap has any of `required`.
+    fn validate_usage(&self, required: HeapUsages) -> Result<(), BindingError> {
+        if !self.usage.intersects(required) {
+            return Err(BindingError::MissingUsage { required, usage: self.usage });
+        }
+
+        Ok(())
+    }
+
+    /// Fails if `range` extends past the end of this heap or overlaps unflushed data.
+    fn validate_range(&self, range: Range<BufferAddress>) -> Result<(), BindingError> {
+        if range.end > self.size.get() {
+            return Err(BindingError::OutOfBounds { range, heap_size: self.size });
+        }
+        let unflushed = self
+            .staging_dirty_ranges
+            .borrow()
+            .iter()
+            .find(|dirty| dirty.start < range.end && range.start < dirty.end)
+            .cloned();
+        if let Some(unflushed) = unflushed {
+            return Err(BindingError::Unflushed { range, unflushed });
         }
+
+        Ok(())
     }
 
     pub fn flush(&self, encoder: &mut wgpu::CommandEncoder) {
         self.flush_range(encoder, 0..self.size.get());
+        if self.window.is_none() {
+            self.staging_dirty_ranges.borrow_mut().clear();
+        }
     }
 
+    /// Copies every region written since the last call to this method or to [`Self::flush`]
+    /// from the staging buffer into the GPU buffer, returning the (coalesced) ranges that were
+    /// copied.
+    ///
+    /// Overlapping and adjacent regions are merged after being widened to
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`], so that as few copies as possible are recorded. This is
+    /// much cheaper than calling [`Self::flush_range`] after every small write.
+    ///
+    /// With a staging window (see [`UploadStrategy::Windowed`]), this copies what has been staged
+    /// into the window, and the writes that have yet to be staged stay dirty.
+    pub fn flush_dirty(&self, encoder: &mut wgpu::CommandEncoder) -> Vec<Range<BufferAddress>> {
+        if let Some(window) = self.window.as_ref() {
+            let mut ranges = self.flush_window(window, encoder, 0..self.size.get());
+            coalesce_ranges(&mut ranges);
+            return ranges;
+        }
+        let mut ranges = std::mem::take(&mut *self.staging_dirty_ranges.borrow_mut());
+        for range in ranges.iter_mut() {
+            *range = align_range_for_copy(range.clone(), self.size.get());
+        }
+        coalesce_ranges(&mut ranges);
+
+        for range in ranges.iter() {
+            self.flush_range(encoder, range.clone());
+        }
+
+        ranges
+    }
+
+    /// Flushes every region written since it was last flushed into the encoder of `transfer`,
+    /// as with [`Self::flush_dirty`].
+    pub fn flush_with(&self, transfer: &TransferContext) -> Vec<Range<BufferAddress>> {
+        transfer.record(|encoder| self.flush_dirty(encoder))
+    }
+
+    /// Flushes `range` into the encoder of `transfer`, as with [`Self::flush_range`].
+    pub fn flush_range_with(&self, transfer: &TransferContext, range: Range<BufferAddress>) {
+        transfer.record(|encoder| self.flush_range(encoder, range));
+    }
+
+    /// Copies `range` from the staging buffer into the GPU buffer.
+    ///
+    /// With a staging window (see [`UploadStrategy::Windowed`]), this instead copies what has been
+    /// staged into the window for `range`, along with everything staged before it.
     pub fn flush_range(
         &self,
         encoder: &mut wgpu::CommandEncoder,
         range: Range<BufferAddress>,
     ) {
+        if let Some(window) = self.window.as_ref() {
+            self.flush_window(window, encoder, range);
+            return;
+        }
+        // Data written to a heap without staging memory is already on its way to the GPU buffer.
+        let Some(staging_buffer) = self.staging_buffer.as_ref() else {
+            return;
+        };
         encoder.copy_buffer_to_buffer(
-            &self.staging_buffer,
+            staging_buffer,
             range.start,
             &self.gpu_buffer,
             range.start,
             get_range_size(&range),
         );
+        subtract_range(&mut self.staging_dirty_ranges.borrow_mut(), &range);
+        self.staging_in_flight.borrow_mut().record_copy(range);
     }
 
+    /// Records copies of what has been staged into `window` for `range`, as with
+    /// [`StagingWindow::flush`], returning the ranges of the heap copied.
+    fn flush_window(
+        &self,
+        window: &RefCell<StagingWindow>,
+        encoder: &mut wgpu::CommandEncoder,
+        range: Range<BufferAddress>,
+    ) -> Vec<Range<BufferAddress>> {
+        let mut window = window.borrow_mut();
+        let copied = window.flush(encoder, self.staging_buffer(), &self.gpu_buffer, range);
+        *self.staging_dirty_ranges.borrow_mut() = window.unflushed_ranges();
+
+        copied
+    }
+
+    /// Unmaps the staging buffer, so that commands that copy from it can be submitted.
+    ///
+    /// A staging window (see [`UploadStrategy::Windowed`]) that is mapped is first filled with as
+    /// many of the writes that have yet to be staged as fit, to be copied by the next flush.
     pub fn unmap(&self) {
-        self.staging_buffer.unmap();
+        if let Some(staging_buffer) = self.staging_buffer.as_ref() {
+            if let Some(window) = self.window.as_ref() {
+                if self.map_state() == MapState::Mapped {
+                    window.borrow_mut().stage_pending(staging_buffer);
+                }
+            }
+            staging_buffer.unmap();
+            self.staging_map_state.set(MapState::Unmapped);
+        }
+    }
+
+    /// Zeroes `range` of the GPU buffer with a clear command, without uploading any data.
+    ///
+    /// This is the cheap way to reset counters, histogram bins, and the like. Writes to `range`
+    /// that have not been flushed are discarded, as the clear supersedes them, but the staging
+    /// memory keeps its contents and so would be copied back by a [`Self::flush`] of the whole
+    /// heap; see [`Self::clear_staging`] to zero it as well.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `range` does not begin and end on multiples of
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder, range: Range<BufferAddress>) {
+        assert!(
+            shrink_range_for_copy(range.clone()) == range,
+            "cleared range {:?} must begin and end on multiples of `COPY_BUFFER_ALIGNMENT`",
+            range,
+        );
+        if range.is_empty() {
+            return;
+        }
+
+        encoder.clear_buffer(
+            &self.gpu_buffer,
+            range.start,
+            version::clear_size(get_range_size(&range)),
+        );
+        self.discard_unflushed(&range);
+    }
+
+    /// Drops writes to `range` that have not been flushed, as something else has superseded them.
+    fn discard_unflushed(&self, range: &Range<BufferAddress>) {
+        let mut dirty_ranges = self.staging_dirty_ranges.borrow_mut();
+        match self.window.as_ref() {
+            Some(window) => {
+                let mut window = window.borrow_mut();
+                window.discard(range);
+                *dirty_ranges = window.unflushed_ranges();
+            }
+            None => subtract_range(&mut dirty_ranges, range),
+        }
+    }
+
+    /// Zeroes `range` of the staging buffer, without marking it as written.
+    ///
+    /// Together with [`Self::clear`], this leaves both copies of `range` zeroed, so that no later
+    /// flush can bring back what was there before. Heaps without staging memory, or with only a
+    /// staging window (see [`UploadStrategy::Windowed`]), have nothing to zero.
+    ///
+    /// # Errors
+    ///
+    /// This fails if the staging buffer is not mapped, in which case nothing is zeroed.
+    pub fn clear_staging(&self, range: Range<BufferAddress>) -> Result<(), NotMapped> {
+        if self.staging_mirror().is_none() {
+            return Ok(());
+        }
+        let written = self.staging_dirty_ranges.borrow().len();
+        self.checked_get_write_view(range)?.fill(0);
+        // Note: the view marked `range` as written, which isn't wanted here.
+        self.staging_dirty_ranges.borrow_mut().truncate(written);
+
+        Ok(())
+    }
+
+    /// Zeroes `ranges` of the GPU buffer with as few clear commands as possible.
+    ///
+    /// Overlapping and adjacent ranges are merged first, and each merged range is then shrunk to
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`], so up to three bytes at either end of a range may be left
+    /// uncleared. `ranges` is left holding the ranges that were cleared.
+    pub fn clear_ranges(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        ranges: &mut Vec<Range<BufferAddress>>,
+    ) {
+        coalesce_ranges(ranges);
+        ranges.retain_mut(|range| {
+            *range = shrink_range_for_copy(range.clone());
+            range.start < range.end
+        });
+
+        for range in ranges.iter() {
+            encoder.clear_buffer(
+                &self.gpu_buffer,
+                range.start,
+                version::clear_size(get_range_size(range)),
+            );
+        }
+    }
+
+    /// Records a copy of `source_range` of the GPU buffer of this heap into the GPU buffer of
+    /// `destination`, starting at `destination_offset`.
+    ///
+    /// Writes to this heap that have not been flushed are not copied, so flush them into the same
+    /// encoder first. Unflushed writes to the copied-to range of `destination` are discarded, as
+    /// the copy supersedes them, but its staging memory keeps its contents, as with
+    /// [`Self::clear`].
+    ///
+    /// # Errors
+    ///
+    /// This fails, recording nothing, if `destination` is this heap, if this heap can't be copied
+    /// from, if either range extends past the end of its heap, or if either range does not begin
+    /// and end on multiples of [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub fn copy_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        source_range: Range<BufferAddress>,
+        destination: &Heap,
+        destination_offset: BufferAddress,
+    ) -> Result<(), CopyError> {
+        if self.id == destination.id {
+            return Err(CopyError::SameHeap);
+        }
+        let has_readback = self.readback_buffer.is_some();
+        if !gpu_buffer_usages(self.usage, has_readback).contains(BufferUsages::COPY_SRC) {
+            return Err(CopyError::NotCopyable);
+        }
+
+        let size = get_range_size(&source_range);
+        let destination_range = destination_offset..(destination_offset + size);
+        for (range, heap) in [(&source_range, self), (&destination_range, destination)] {
+            if range.end > heap.size.get() {
+                return Err(CopyError::OutOfBounds { range: range.clone(), heap_size: heap.size });
+            }
+            if shrink_range_for_copy(range.clone()) != *range {
+                return Err(CopyError::Misaligned { range: range.clone() });
+            }
+        }
+        if size == 0 {
+            return Ok(());
+        }
+
+        encoder.copy_buffer_to_buffer(
+            &self.gpu_buffer,
+            source_range.start,
+            &destination.gpu_buffer,
+            destination_offset,
+            size,
+        );
+        destination.discard_unflushed(&destination_range);
+
+        Ok(())
+    }
+
+    /// Marks `range` of the GPU buffer as having been modified by the GPU.
+    ///
+    /// The next call to [`Self::sync_back_dirty`] will copy this range into the CPU shadow.
+    pub fn mark_gpu_modified(&self, range: Range<BufferAddress>) {
+        self.gpu_dirty_ranges.borrow_mut().push(range);
+    }
+
+    /// Copies every region marked with [`Self::mark_gpu_modified`] from the GPU buffer into the
+    /// CPU shadow, returning the (coalesced) ranges that were copied.
+    ///
+    /// Overlapping and adjacent regions are merged so that as few copies as possible are
+    /// recorded. Once `encoder` has been submitted and executed, the returned ranges can be mapped
+    /// with [`Self::map_readback_async`] and read with [`Self::read_mapped`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn sync_back_dirty(&self, encoder: &mut wgpu::CommandEncoder) -> Vec<Range<BufferAddress>> {
+        let readback_buffer = self.readback_buffer();
+        let mut ranges = std::mem::take(&mut *self.gpu_dirty_ranges.borrow_mut());
+        for range in ranges.iter_mut() {
+            *range = align_range_for_copy(range.clone(), self.size.get());
+        }
+        coalesce_ranges(&mut ranges);
+
+        for range in ranges.iter() {
+            encoder.copy_buffer_to_buffer(
+                &self.gpu_buffer,
+                range.start,
+                readback_buffer,
+                range.start,
+                get_range_size(range),
+            );
+        }
+
+        ranges
+    }
+
+    /// Records a copy of `range` from the GPU buffer into the CPU shadow, so that it can then be
+    /// read with [`Self::fetch`].
+    ///
+    /// Unlike [`Self::sync_back_dirty`], this copies `range` immediately rather than waiting for
+    /// it to be marked as modified. `range` is widened to [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn read_range(&self, encoder: &mut wgpu::CommandEncoder, range: Range<BufferAddress>) {
+        let range = align_range_for_copy(range, self.size.get());
+        encoder.copy_buffer_to_buffer(
+            &self.gpu_buffer,
+            range.start,
+            self.readback_buffer(),
+            range.start,
+            get_range_size(&range),
+        );
+    }
+
+    /// Reads `range` of the CPU shadow once it can be mapped, unmapping it again afterwards.
+    ///
+    /// The mapping is requested immediately, so it waits for every submission made so far,
+    /// including the one containing a preceding [`Self::read_range`]. As with [`MapFuture`], the
+    /// device must be polled for the returned future to complete.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn fetch(
+        &self,
+        range: Range<BufferAddress>,
+    ) -> impl Future<Output = Result<Vec<u8>, wgpu::BufferAsyncError>> + '_ {
+        // Mappings must be a multiple of `COPY_BUFFER_ALIGNMENT` in size, so we map a slightly
+        // larger range and trim it afterwards. The mapping always begins at 0, as wgpu 0.13
+        // returns views at the wrong offset for mappings that begin elsewhere.
+        let end = align_range_for_copy(range.clone(), self.size.get()).end;
+        let readback_buffer = self.readback_buffer();
+        let mapping = mapping::map_async(readback_buffer.slice(0..end), wgpu::MapMode::Read, None);
+
+        async move {
+            mapping.await?;
+            let contents = {
+                let view = readback_buffer.slice(0..end).get_mapped_range();
+                view[(range.start as usize)..(range.end as usize)].to_vec()
+            };
+            readback_buffer.unmap();
+
+            Ok(contents)
+        }
+    }
+
+    /// Maps `range` of the CPU shadow for reading.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn map_readback_async(&self, range: Range<BufferAddress>) {
+        self
+            .readback_buffer()
+            .slice(range)
+            .map_async(wgpu::MapMode::Read, |_| {});
+    }
+
+    /// Reads `range` from the CPU shadow, which must be mapped.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn read_mapped(&self, range: Range<BufferAddress>) -> Vec<u8> {
+        self.readback_buffer().slice(range).get_mapped_range().to_vec()
+    }
+
+    /// Unmaps the CPU shadow.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`Heap::with_readback`].
+    pub fn unmap_readback(&self) {
+        self.readback_buffer().unmap();
+    }
+
+    /// Maps `range` of the GPU buffer itself for reading, bypassing the staging buffer.
+    ///
+    /// `callback` is called once the mapping completes (or fails). The mapped memory can then be
+    /// accessed with [`Self::gpu_mapped_range`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`HeapUsages::MAP_READ`].
+    pub fn map_read_async(
+        &self,
+        range: Range<BufferAddress>,
+        callback: impl FnOnce(Result<(), wgpu::BufferAsyncError>) + Send + 'static,
+    ) {
+        self.assert_usage(HeapUsages::MAP_READ);
+        self.gpu_buffer.slice(range).map_async(wgpu::MapMode::Read, callback);
+    }
+
+    /// Maps `range` of the GPU buffer itself for writing, bypassing the staging buffer.
+    ///
+    /// `callback` is called once the mapping completes (or fails). The mapped memory can then be
+    /// accessed with [`Self::gpu_mapped_range_mut`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this heap was not created with [`HeapUsages::MAP_WRITE`].
+    pub fn map_write_async(
+        &self,
+        range: Range<BufferAddress>,
+        callback: impl FnOnce(Result<(), wgpu::BufferAsyncError>) + Send + 'static,
+    ) {
+        self.assert_usage(HeapUsages::MAP_WRITE);
+        self.gpu_buffer.slice(range).map_async(wgpu::MapMode::Write, callback);
+    }
+
+    /// Gets a view of `range` of the GPU buffer, which must have been mapped with
+    /// [`Self::map_read_async`] or [`Self::map_write_async`].
+    pub fn gpu_mapped_range<'a>(&'a self, range: Range<BufferAddress>) -> wgpu::BufferView<'a> {
+        self.gpu_buffer.slice(range).get_mapped_range()
+    }
+
+    /// Gets a mutable view of `range` of the GPU buffer, which must have been mapped with
+    /// [`Self::map_write_async`].
+    pub fn gpu_mapped_range_mut<'a>(
+        &'a self,
+        range: Range<BufferAddress>,
+    ) -> wgpu::BufferViewMut<'a> {
+        self.gpu_buffer.slice(range).get_mapped_range_mut()
+    }
+
+    /// Unmaps the GPU buffer after a call to [`Self::map_read_async`] or
+    /// [`Self::map_write_async`].
+    pub fn unmap_gpu(&self) {
+        self.gpu_buffer.unmap();
+    }
+
+    fn assert_usage(&self, usage: HeapUsages) {
+        if !self.usage.contains(usage) {
+            panic!("heap usage {:?} is missing required usage {:?}", self.usage, usage);
+        }
+    }
+
+    fn readback_buffer(&self) -> &wgpu::Buffer {
+        self
+            .readback_buffer
+            .as_ref()
+            .expect("heap has no CPU shadow; must be created with `Heap::with_readback`")
+    }
+
+    /// Whether this heap can currently be grown with [`Self::grow`].
+    ///
+    /// Heaps with usage [`HeapUsages::MAP_READ`] can never grow, and heaps with staging memory can
+    /// only grow while it is mapped, so that its contents can be carried over. A staging window
+    /// (see [`UploadStrategy::Windowed`]) is kept as is, so needn't be mapped.
+    pub fn can_grow(&self) -> bool {
+        match self.window {
+            Some(_) => !self.usage.contains(HeapUsages::MAP_READ),
+            None => self.can_copy_out(),
+        }
+    }
+
+    /// Whether the contents of this heap can currently be copied elsewhere, both on the GPU and
+    /// in staging memory.
+    ///
+    /// Writes to a heap with a staging window (see [`UploadStrategy::Windowed`]) are not carried
+    /// over, so it can only copy out once they have all been flushed.
+    pub(crate) fn can_copy_out(&self) -> bool {
+        if self.usage.contains(HeapUsages::MAP_READ) {
+            return false;
+        }
+
+        match self.window.as_ref() {
+            Some(window) => window.borrow().is_flushed(),
+            None => self.staging_buffer.is_none() || self.map_state() == MapState::Mapped,
+        }
+    }
+
+    /// Copies `range` of this heap into `destination`, starting at `destination_start`.
+    ///
+    /// The GPU buffer is copied by recording a copy into `encoder`, and staging memory is copied on
+    /// the CPU. The copied range in `destination` is then considered dirty, so that it is flushed
+    /// by [`Self::flush_dirty`]. Both heaps must be able to copy out (see [`Self::can_copy_out`]),
+    /// and `range.start` and `destination_start` must be multiples of
+    /// [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub(crate) fn copy_to_heap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        range: Range<BufferAddress>,
+        destination: &Heap,
+        destination_start: BufferAddress,
+    ) {
+        let size = get_range_size(&range);
+        let copy_range = align_range_for_copy(range.clone(), self.size.get());
+        encoder.copy_buffer_to_buffer(
+            &self.gpu_buffer,
+            copy_range.start,
+            &destination.gpu_buffer,
+            destination_start,
+            get_range_size(&copy_range),
+        );
+
+        let (Some(source_staging), Some(destination_staging)) =
+            (self.staging_mirror(), destination.staging_mirror())
+        else {
+            return;
+        };
+        let destination_range = destination_start..(destination_start + size);
+        let source_view_range = align_range_for_map(range.clone(), self.size.get());
+        let destination_view_range =
+            align_range_for_map(destination_range.clone(), destination.size.get());
+        let source_view = source_staging.slice(source_view_range.clone()).get_mapped_range();
+        let mut destination_view = destination_staging
+            .slice(destination_view_range.clone())
+            .get_mapped_range_mut();
+
+        let source_offset = (range.start - source_view_range.start) as usize;
+        let destination_offset = (destination_start - destination_view_range.start) as usize;
+        destination_view[destination_offset..][..size as usize]
+            .copy_from_slice(&source_view[source_offset..][..size as usize]);
+        destination.staging_dirty_ranges.borrow_mut().push(destination_range);
+    }
+
+    /// Replaces the buffers of this heap with larger ones of `new_size` bytes, recording a copy of
+    /// the contents of the GPU buffer into `encoder`.
+    ///
+    /// The contents of the staging buffer are carried over on the CPU, along with any writes that
+    /// have not yet been flushed, so existing allocations remain valid at the same offsets. A
+    /// staging window is not replaced, and what it has yet to flush is flushed into the new GPU
+    /// buffer. The old
+    /// buffers are freed once `encoder` has been submitted and has finished executing. Data
+    /// previously read back with [`Self::sync_back_dirty`] must be read back again. As the GPU
+    /// buffer is replaced, the heap is given a new [`HeapId`].
+    ///
+    /// This does nothing if `new_size` equals the current size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_size` is smaller than the current size or the heap can't grow (see
+    /// [`Self::can_grow`]).
+    pub fn grow(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        new_size: NonZeroBufferAddress,
+    ) {
+        if new_size < self.size {
+            panic!(
+                "heap cannot shrink; new size {} is less than current size {}",
+                new_size.get(),
+                self.size.get(),
+            );
+        }
+        if !self.can_grow() {
+            panic!(
+                "heap cannot grow; must not have usage MAP_READ and must have its staging memory \
+                 mapped",
+            );
+        }
+        if new_size == self.size {
+            return;
+        }
+        let old_size = self.size.get();
+
+        if let Some(old_staging_buffer) = self.staging_mirror() {
+            let staging_buffer = create_buffer(
+                device,
+                suffixed_label(self.label(), "staging").as_deref(),
+                new_size.get(),
+                BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
+                true,
+            );
+            staging_buffer
+                .slice(0..old_size)
+                .get_mapped_range_mut()
+                .copy_from_slice(&old_staging_buffer.slice(0..old_size).get_mapped_range());
+            self.staging_buffer = Some(staging_buffer);
+        }
+
+        let gpu_usage = gpu_buffer_usages(self.usage, self.readback_buffer.is_some());
+        let gpu_buffer = create_buffer(device, self.label(), new_size.get(), gpu_usage, false);
+        // Note: copies must be a multiple of `COPY_BUFFER_ALIGNMENT` in size, so the last few bytes
+        // of an old buffer whose size is not are not carried over.
+        let copy_size = old_size - old_size % wgpu::COPY_BUFFER_ALIGNMENT;
+        if copy_size > 0 {
+            encoder.copy_buffer_to_buffer(&self.gpu_buffer, 0, &gpu_buffer, 0, copy_size);
+        }
+        self.gpu_buffer = gpu_buffer;
+        self.id = HeapId::next();
+
+        if self.readback_buffer.is_some() {
+            self.readback_buffer = Some(create_buffer(
+                device,
+                suffixed_label(self.label(), "readback").as_deref(),
+                new_size.get(),
+                BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                false,
+            ));
+            self.gpu_dirty_ranges.borrow_mut().push(0..old_size);
+        }
+
+        // Nothing has been copied out of the new staging buffer yet.
+        *self.staging_in_flight.borrow_mut() = InFlightRanges::default();
+        self.size = new_size;
     }
 
     pub fn destroy(&self) {
-        self.staging_buffer.destroy();
+        if let Some(staging_buffer) = self.staging_buffer.as_ref() {
+            staging_buffer.destroy();
+        }
         self.gpu_buffer.destroy();
     }
 }
 
+/// The number of bytes copied at a time by [`Heap::write_from_mmap`].
+#[cfg(feature = "memmap2")]
+pub const MMAP_CHUNK_SIZE: usize = 1 << 20;
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        governor::release(self.buffer_count());
+    }
+}
+
+fn create_binding(buffer: &wgpu::Buffer, range: Range<BufferAddress>) -> wgpu::BufferBinding<'_> {
+    wgpu::BufferBinding {
+        buffer,
+        offset: range.start,
+        size: Some(
+            NonZeroBufferAddress::new(get_range_size(&range))
+                .expect("buffer binding size is zero; must be nonzero")
+        ),
+    }
+}
+
 fn get_range_size(range: &Range<BufferAddress>) -> BufferAddress {
     range
         .end
         .checked_sub(range.start)
         .expect("range is backwards; end should not be less than start")
 }
+
+/// `contents`, padded with zeros to a multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`] bytes, as is
+/// required of the size of buffer copies and writes.
+pub(crate) fn pad_for_copy(contents: &[u8]) -> Cow<'_, [u8]> {
+    let len = contents.len() as BufferAddress;
+    let padded_len = len.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+    if padded_len == len {
+        return Cow::Borrowed(contents);
+    }
+
+    let mut padded = contents.to_vec();
+    padded.resize(padded_len as usize, 0);
+
+    Cow::Owned(padded)
+}
+
+/// Widens `range` so that it begins and ends on a multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`], as
+/// is required of buffer-to-buffer copies, without exceeding `limit`.
+fn align_range_for_copy(range: Range<BufferAddress>, limit: BufferAddress) -> Range<BufferAddress> {
+    let alignment = wgpu::COPY_BUFFER_ALIGNMENT;
+    let start = range.start - (range.start % alignment);
+    let end = match range.end % alignment {
+        0 => range.end,
+        remainder => range.end + (alignment - remainder),
+    };
+
+    start..end.min(limit)
+}
+
+/// Widens `range` so that it begins on a multiple of [`wgpu::MAP_ALIGNMENT`] and ends on a
+/// multiple of [`wgpu::COPY_BUFFER_ALIGNMENT`], as is required of views of mapped memory, without
+/// exceeding `limit`.
+fn align_range_for_map(range: Range<BufferAddress>, limit: BufferAddress) -> Range<BufferAddress> {
+    let end = align_range_for_copy(range.clone(), limit).end;
+
+    (range.start - range.start % wgpu::MAP_ALIGNMENT)..end
+}
+
+/// Shrinks `range` to the largest subrange that is aligned to [`wgpu::COPY_BUFFER_ALIGNMENT`].
+///
+/// The result is empty if there is no such subrange.
+fn shrink_range_for_copy(range: Range<BufferAddress>) -> Range<BufferAddress> {
+    let alignment = wgpu::COPY_BUFFER_ALIGNMENT;
+    let start = match range.start % alignment {
+        0 => range.start,
+        remainder => range.start + (alignment - remainder),
+    };
+    let end = range.end - (range.end % alignment);
+
+    start..end.max(start)
+}
+
+/// Removes the parts of `ranges` that overlap `removed`.
+fn subtract_range(ranges: &mut Vec<Range<BufferAddress>>, removed: &Range<BufferAddress>) {
+    let mut remaining = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        if range.end <= removed.start || removed.end <= range.start {
+            remaining.push(range);
+            continue;
+        }
+        if range.start < removed.start {
+            remaining.push(range.start..removed.start);
+        }
+        if removed.end < range.end {
+            remaining.push(removed.end..range.end);
+        }
+    }
+
+    *ranges = remaining;
+}
+
+/// The limits of a device on buffer bindings, kept by each [`Heap`] for validating its bindings.
+#[derive(Clone, Copy, Debug)]
+struct BindingLimits {
+    max_uniform_size: BufferAddress,
+    max_storage_size: BufferAddress,
+    uniform_alignment: BufferAddress,
+    storage_alignment: BufferAddress,
+}
+
+impl BindingLimits {
+    fn new(limits: &wgpu::Limits) -> Self {
+        Self {
+            max_uniform_size: limits.max_uniform_buffer_binding_size.into(),
+            max_storage_size: limits.max_storage_buffer_binding_size.into(),
+            uniform_alignment: limits.min_uniform_buffer_offset_alignment.into(),
+            storage_alignment: limits.min_storage_buffer_offset_alignment.into(),
+        }
+    }
+}
+
+/// Sorts `ranges` and merges those that overlap or are adjacent.
+fn coalesce_ranges(ranges: &mut Vec<Range<BufferAddress>>) {
+    ranges.sort_unstable_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<BufferAddress>> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    *ranges = merged;
+}
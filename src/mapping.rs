@@ -0,0 +1,230 @@
+//! Tracking of whether the staging memory of a [`Heap`](crate::Heap) is mapped.
+//!
+//! Staging buffers are mapped at creation, must be unmapped before commands that copy from them
+//! are submitted, and can only be written again once they have been remapped. Writing to a
+//! staging buffer that isn't mapped panics deep inside wgpu, so heaps keep track of their
+//! [`MapState`] and check it before writing.
+
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut, Range},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Whether staging memory can currently be written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MapState {
+    /// The memory is mapped and can be written.
+    Mapped,
+    /// A mapping has been requested but has not completed yet.
+    ///
+    /// Mappings only complete once the GPU is done with the memory and the device is polled.
+    Pending,
+    /// The memory is unmapped, either explicitly or because a requested mapping failed.
+    Unmapped,
+}
+
+/// The error returned when writing staging memory that isn't mapped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotMapped {
+    /// The state of the staging memory at the time of the write.
+    pub state: MapState,
+}
+
+impl fmt::Display for NotMapped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state {
+            MapState::Pending => f.write_str(
+                "staging memory is not mapped yet; poll the device until the mapping completes",
+            ),
+            _ => f.write_str("staging memory is not mapped; remap it before writing"),
+        }
+    }
+}
+
+impl std::error::Error for NotMapped {}
+
+/// A mutable view of part of the mapped staging memory of a heap, as a slice of `T`s.
+///
+/// See [`Heap::get_write_view`](crate::Heap::get_write_view).
+#[derive(Debug)]
+pub struct WriteView<'a, T = u8> {
+    /// Keeps the memory mapped.
+    _view: wgpu::BufferViewMut<'a>,
+    /// The bytes of the view that this is a view of.
+    ///
+    /// These are accessed through a pointer because wgpu panics on reading memory mapped for
+    /// writing through a [`wgpu::BufferViewMut`], even when the bytes being read are ones that
+    /// were just written.
+    bytes: *mut [u8],
+    _elements: PhantomData<T>,
+}
+
+impl<'a> WriteView<'a> {
+    pub(crate) fn new(mut view: wgpu::BufferViewMut<'a>, range: Range<usize>) -> Self {
+        let bytes: *mut [u8] = &mut view.as_mut()[range];
+
+        Self { _view: view, bytes, _elements: PhantomData }
+    }
+
+    /// Reinterprets this view as one of `T`s.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the length of this view is not a multiple of the size of `T`, or if
+    /// its start is not aligned for `T`.
+    pub fn cast<T: bytemuck::Pod>(self) -> WriteView<'a, T> {
+        if let Err(error) = bytemuck::try_cast_slice::<u8, T>(&self) {
+            let type_name = std::any::type_name::<T>();
+            panic!("cannot view {} bytes as `{}`: {}", self.len(), type_name, error);
+        }
+
+        WriteView { _view: self._view, bytes: self.bytes, _elements: PhantomData }
+    }
+}
+
+impl<T: bytemuck::Pod> Deref for WriteView<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: `bytes` points into the mapped memory of `_view`, which stays mapped for as
+        // long as `self` is borrowed. wgpu initializes memory before mapping it, so whatever was
+        // there before it was written is merely stale, not uninitialized.
+        bytemuck::cast_slice(unsafe { &*self.bytes })
+    }
+}
+
+impl<T: bytemuck::Pod> DerefMut for WriteView<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: See `Deref::deref`. `self` is borrowed mutably, so this is the only reference.
+        bytemuck::cast_slice_mut(unsafe { &mut *self.bytes })
+    }
+}
+
+/// The shared state of a buffer's staging memory, updated by map callbacks.
+#[derive(Clone, Debug)]
+pub(crate) struct MapTracker(Arc<Mutex<MapState>>);
+
+impl MapTracker {
+    pub(crate) fn new(state: MapState) -> Self {
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    pub(crate) fn get(&self) -> MapState {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn set(&self, state: MapState) {
+        *self.0.lock().unwrap() = state;
+    }
+
+    /// Fails with [`NotMapped`] unless the memory is mapped.
+    pub(crate) fn check(&self) -> Result<(), NotMapped> {
+        match self.get() {
+            MapState::Mapped => Ok(()),
+            state => Err(NotMapped { state }),
+        }
+    }
+
+    /// A future that resolves once a pending mapping completes or fails, or immediately if none
+    /// is pending.
+    ///
+    /// The future does not register a waker, as nothing wakes it but polling the device; it must
+    /// be driven by [`poll_device`].
+    pub(crate) fn settled(&self) -> impl Future<Output = Result<(), wgpu::BufferAsyncError>> + '_ {
+        std::future::poll_fn(move |_| match self.get() {
+            MapState::Mapped => Poll::Ready(Ok(())),
+            MapState::Pending => Poll::Pending,
+            MapState::Unmapped => Poll::Ready(Err(wgpu::BufferAsyncError)),
+        })
+    }
+
+    /// Maps `slice` in `mode`, tracking the outcome and returning a future that resolves with it.
+    pub(crate) fn map_async(&self, slice: wgpu::BufferSlice, mode: wgpu::MapMode) -> MapFuture {
+        self.set(MapState::Pending);
+        map_async(slice, mode, Some(self.clone()))
+    }
+}
+
+/// Maps `slice` in `mode`, returning a future that resolves once the mapping completes.
+///
+/// If a tracker is given, it is updated with the outcome.
+pub(crate) fn map_async(
+    slice: wgpu::BufferSlice,
+    mode: wgpu::MapMode,
+    tracker: Option<MapTracker>,
+) -> MapFuture {
+    let shared = Arc::new(Mutex::new(MapShared::default()));
+    let future = MapFuture(Arc::clone(&shared));
+    slice.map_async(mode, move |result| {
+        if let Some(tracker) = tracker {
+            tracker.set(match result {
+                Ok(()) => MapState::Mapped,
+                Err(_) => MapState::Unmapped,
+            });
+        }
+
+        let mut shared = shared.lock().unwrap();
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+
+    future
+}
+
+/// A future that resolves once a mapping requested with
+/// [`Heap::map_async`](crate::Heap::map_async) or [`Heap::remap`](crate::Heap::remap) completes
+/// or fails.
+///
+/// The device must be polled, such as with [`wgpu::Device::poll`], for the mapping to make
+/// progress. Dropping this future does not cancel the mapping.
+#[derive(Debug)]
+pub struct MapFuture(Arc<Mutex<MapShared>>);
+
+#[derive(Debug, Default)]
+struct MapShared {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+impl Future for MapFuture {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.0.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Drives `future` to completion by polling `device` without blocking each time `future` is
+/// polled, yielding to the executor in between.
+///
+/// This lets mappings make progress in async code without the caller polling the device, at the
+/// cost of keeping the task busy until they complete. On the web, where the browser drives
+/// mappings itself, polling the device does nothing.
+pub(crate) async fn poll_device<F: Future>(device: &wgpu::Device, future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| {
+        device.poll(wgpu::Maintain::Poll);
+        match future.as_mut().poll(cx) {
+            Poll::Ready(output) => Poll::Ready(output),
+            Poll::Pending => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
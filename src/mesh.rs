@@ -0,0 +1,223 @@
+//! Packing of meshes into shared vertex and index heaps.
+//!
+//! Renderers usually draw many meshes with the same vertex layout. Rather than giving each mesh
+//! buffers of its own, a [`MeshAllocator`] packs the vertices and indices of each mesh together
+//! into one allocation of a heap that is both a vertex and an index buffer. Every mesh in a heap
+//! can then be drawn with that heap bound once, the position of each mesh in it being given to
+//! [`wgpu::RenderPass::draw_indexed`] as a base vertex and a first index.
+
+use wgpu::BufferAddress;
+
+use std::{num::NonZeroU32, ops::Range};
+
+use crate::{
+    arena::Allocation,
+    sizes::combine_alignments,
+    AllocError,
+    Allocator,
+    GrowthPolicy,
+    HeapArena,
+    HeapUsages,
+    NonZeroBufferAddress,
+};
+
+/// Allocates the vertices and indices of meshes in pairs, out of a [`HeapArena`] of vertex and
+/// index heaps.
+///
+/// Each mesh is a single allocation: its vertices, followed immediately by its indices. Vertices
+/// are aligned to the vertex stride and indices to the size of the index format, so both can be
+/// addressed from the start of the heap.
+#[derive(Debug)]
+pub struct MeshAllocator<A> {
+    arena: HeapArena<A>,
+    vertex_stride: NonZeroBufferAddress,
+    index_format: wgpu::IndexFormat,
+}
+
+impl<A> MeshAllocator<A> {
+    /// Creates a new `MeshAllocator` of meshes whose vertices are `vertex_stride` bytes apart and
+    /// whose indices are of `index_format`.
+    ///
+    /// See [`HeapArena::new`] for the meaning of `growth_policy`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `vertex_stride` is not a multiple of
+    /// [`wgpu::VERTEX_STRIDE_ALIGNMENT`].
+    pub fn new(
+        vertex_stride: NonZeroBufferAddress,
+        index_format: wgpu::IndexFormat,
+        growth_policy: impl GrowthPolicy + Send + 'static,
+    ) -> Self {
+        assert!(
+            vertex_stride.get().is_multiple_of(wgpu::VERTEX_STRIDE_ALIGNMENT),
+            "vertex stride must be a multiple of `VERTEX_STRIDE_ALIGNMENT`",
+        );
+
+        Self {
+            arena: HeapArena::new(HeapUsages::VERTEX | HeapUsages::INDEX, growth_policy),
+            vertex_stride,
+            index_format,
+        }
+    }
+
+    pub fn vertex_stride(&self) -> NonZeroBufferAddress {
+        self.vertex_stride
+    }
+
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        self.index_format
+    }
+
+    /// The arena that meshes are allocated from, such as for flushing its heaps.
+    pub fn arena(&self) -> &HeapArena<A> {
+        &self.arena
+    }
+
+    /// The arena that meshes are allocated from, such as for configuring it.
+    ///
+    /// Allocations made directly in the arena are not meshes, and should not be freed with
+    /// [`Self::dealloc`].
+    pub fn arena_mut(&mut self) -> &mut HeapArena<A> {
+        &mut self.arena
+    }
+
+    /// The size, in bytes, of each index.
+    fn index_size(&self) -> BufferAddress {
+        match self.index_format {
+            wgpu::IndexFormat::Uint16 => 2,
+            wgpu::IndexFormat::Uint32 => 4,
+        }
+    }
+
+    /// Writes the vertices and indices of `mesh`.
+    ///
+    /// As with [`HeapArena::write`], the data reaches the GPU once the heap of `mesh` is flushed.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `vertices` or `indices` is not exactly as large as the vertex or
+    /// index range of `mesh`.
+    pub fn write<V: bytemuck::Pod, I: bytemuck::Pod>(
+        &self,
+        mesh: &MeshSlice,
+        vertices: &[V],
+        indices: &[I],
+    ) {
+        let vertices: &[u8] = bytemuck::cast_slice(vertices);
+        let indices: &[u8] = bytemuck::cast_slice(indices);
+        assert_eq!(vertices.len() as u64, mesh.vertex.end - mesh.vertex.start, "vertex size");
+        assert_eq!(indices.len() as u64, mesh.index.end - mesh.index.start, "index size");
+
+        // The allocation is written whole, as the indices may not start on a mappable offset.
+        let mut contents = Vec::with_capacity(mesh.allocation.size() as usize);
+        contents.extend_from_slice(vertices);
+        contents.extend_from_slice(indices);
+        contents.resize(mesh.allocation.size() as usize, 0);
+        self.arena.write(&mesh.allocation, &contents);
+    }
+
+    /// Binds the heap of `mesh` as the vertex buffer at `slot` and as the index buffer of `pass`.
+    ///
+    /// The binding covers the whole heap, so it serves every other mesh in the same heap too.
+    pub fn bind<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, slot: u32, mesh: &MeshSlice) {
+        let (heap, _) = &self.arena[mesh.allocation.arena_key];
+        pass.set_vertex_buffer(slot, heap.slice(0..heap.size().get()));
+        pass.set_index_buffer(heap.slice(0..heap.size().get()), self.index_format);
+    }
+}
+
+impl<A: Allocator> MeshAllocator<A> {
+    /// Allocates room for `vertex_count` vertices and `index_count` indices.
+    ///
+    /// # Errors
+    ///
+    /// See [`HeapArena::alloc`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the base vertex of the mesh doesn't fit in an `i32`, or its first
+    /// index doesn't fit in a `u32`.
+    pub fn alloc(
+        &mut self,
+        device: &wgpu::Device,
+        vertex_count: NonZeroU32,
+        index_count: NonZeroU32,
+    ) -> Result<MeshSlice, AllocError> {
+        let vertex_size = BufferAddress::from(vertex_count.get()) * self.vertex_stride.get();
+        let index_size = BufferAddress::from(index_count.get()) * self.index_size();
+        // Note: both sizes are nonzero, so their sum is too.
+        let size = NonZeroBufferAddress::new(
+            (vertex_size + index_size).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+        )
+        .unwrap();
+        // Vertices must start on a multiple of the stride for the base vertex to address them,
+        // and the allocation on a multiple of `MAP_ALIGNMENT` to be written.
+        let alignment = combine_alignments(
+            self.vertex_stride,
+            NonZeroBufferAddress::new(wgpu::MAP_ALIGNMENT).unwrap(),
+        );
+
+        let allocation = self.arena.alloc(device, size, alignment)?;
+        let start = allocation.offset();
+        let vertex = start..(start + vertex_size);
+        // Note: the stride is a multiple of 4, so the indices are aligned to their size.
+        let index = vertex.end..(vertex.end + index_size);
+
+        Ok(MeshSlice {
+            base_vertex: (start / self.vertex_stride.get())
+                .try_into()
+                .expect("base vertex does not fit in an `i32`"),
+            first_index: (index.start / self.index_size())
+                .try_into()
+                .expect("first index does not fit in a `u32`"),
+            index_count: index_count.get(),
+            allocation,
+            vertex,
+            index,
+        })
+    }
+
+    /// Frees `mesh`.
+    ///
+    /// # Errors
+    ///
+    /// See [`HeapArena::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `mesh` must have been returned by [`Self::alloc`] on this allocator, must not have been
+    /// freed already, and must no longer be in use by the GPU.
+    pub unsafe fn dealloc(&mut self, mesh: MeshSlice) -> Result<(), AllocError> {
+        // SAFETY: The caller upholds the same contract for the allocation of `mesh`.
+        unsafe { self.arena.dealloc(mesh.allocation) }
+    }
+}
+
+/// The vertices and indices of a mesh allocated by a [`MeshAllocator`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshSlice {
+    /// The allocation holding both the vertices and the indices.
+    pub allocation: Allocation,
+    /// The range of the vertices in the heap of [`Self::allocation`].
+    pub vertex: Range<BufferAddress>,
+    /// The range of the indices in the heap of [`Self::allocation`].
+    pub index: Range<BufferAddress>,
+    /// The index of the first vertex, counting from the start of the heap.
+    pub base_vertex: i32,
+    /// The index of the first index, counting from the start of the heap.
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+impl MeshSlice {
+    /// The range of indices to draw the whole mesh, relative to the start of the heap.
+    pub fn indices(&self) -> Range<u32> {
+        self.first_index..(self.first_index + self.index_count)
+    }
+
+    /// Draws `instances` of this mesh, whose heap must be bound with [`MeshAllocator::bind`].
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, instances: Range<u32>) {
+        pass.draw_indexed(self.indices(), self.base_vertex, instances);
+    }
+}
@@ -0,0 +1,114 @@
+//! Cumulative telemetry for [`HeapArena`](crate::HeapArena)s.
+
+/// Cumulative counters for a single size pool of a [`HeapArena`](crate::HeapArena).
+///
+/// Every counter only ever increases over the lifetime of an arena; to find out what changed over
+/// some period of time, such as a frame, take a snapshot with
+/// [`HeapArena::metrics`](crate::HeapArena::metrics) at both ends and [`Metrics::diff`] them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// The total number of bytes allocated from heaps in this pool.
+    pub bytes_allocated: u64,
+    /// The total number of bytes returned to heaps in this pool.
+    pub bytes_freed: u64,
+    /// The number of heaps created in this pool.
+    pub heaps_created: u64,
+    /// The number of heaps destroyed in this pool.
+    pub heaps_destroyed: u64,
+    /// The number of times a heap in this pool was grown rather than a new one created.
+    pub heaps_grown: u64,
+    /// The number of buffer-to-buffer copies recorded into command encoders on behalf of
+    /// allocations in this pool.
+    pub copies_recorded: u64,
+}
+
+impl PoolMetrics {
+    /// Returns the difference between these counters and those in `previous`.
+    ///
+    /// Counters that are smaller than their counterparts in `previous` saturate at zero.
+    pub fn diff(&self, previous: &Self) -> Self {
+        Self {
+            bytes_allocated: self.bytes_allocated.saturating_sub(previous.bytes_allocated),
+            bytes_freed: self.bytes_freed.saturating_sub(previous.bytes_freed),
+            heaps_created: self.heaps_created.saturating_sub(previous.heaps_created),
+            heaps_destroyed: self.heaps_destroyed.saturating_sub(previous.heaps_destroyed),
+            heaps_grown: self.heaps_grown.saturating_sub(previous.heaps_grown),
+            copies_recorded: self.copies_recorded.saturating_sub(previous.copies_recorded),
+        }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self {
+            bytes_allocated: self.bytes_allocated + other.bytes_allocated,
+            bytes_freed: self.bytes_freed + other.bytes_freed,
+            heaps_created: self.heaps_created + other.heaps_created,
+            heaps_destroyed: self.heaps_destroyed + other.heaps_destroyed,
+            heaps_grown: self.heaps_grown + other.heaps_grown,
+            copies_recorded: self.copies_recorded + other.copies_recorded,
+        }
+    }
+}
+
+/// A snapshot of the [`PoolMetrics`] of every size pool in a [`HeapArena`](crate::HeapArena).
+///
+/// The layout of this struct mirrors that of the arena itself: [`Self::tiny_pool`] holds the
+/// counters for heaps smaller than 4,096 bytes, and [`Self::size_pools`] holds the counters for
+/// every other size class, beginning at 12.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The counters for the tiny pool, which contains heaps from size classes 0 to 11 (inclusive).
+    pub tiny_pool: PoolMetrics,
+    /// The counters for the remaining pools, ordered from lowest to highest size class, beginning
+    /// at 12.
+    pub size_pools: Vec<PoolMetrics>,
+}
+
+impl Metrics {
+    /// Returns the per-pool difference between this snapshot and `previous`.
+    ///
+    /// This is intended to be called once per frame with the snapshot from the previous frame, so
+    /// that allocation spikes can be attributed to the pools that caused them. Pools that did not
+    /// yet exist when `previous` was taken are treated as having had all-zero counters.
+    pub fn diff(&self, previous: &Self) -> Self {
+        let default = PoolMetrics::default();
+
+        Self {
+            tiny_pool: self.tiny_pool.diff(&previous.tiny_pool),
+            size_pools: self
+                .size_pools
+                .iter()
+                .enumerate()
+                .map(|(index, pool)| {
+                    pool.diff(previous.size_pools.get(index).unwrap_or(&default))
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the sum of the counters of every pool.
+    pub fn total(&self) -> PoolMetrics {
+        self.size_pools.iter().fold(self.tiny_pool, |total, pool| total.add(pool))
+    }
+}
+
+/// Counters for the operations performed on a [`HeapArena`](crate::HeapArena) during the current
+/// frame.
+///
+/// Unlike [`Metrics`], these counters are reset at the start of every frame by
+/// [`HeapArena::begin_frame`](crate::HeapArena::begin_frame).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCounters {
+    /// The number of allocations made.
+    pub allocations: u64,
+    /// The number of allocations returned to the arena.
+    pub deallocations: u64,
+    /// The number of bytes written into staging memory.
+    pub bytes_written: u64,
+    /// The number of bytes uploaded with [`HeapArena::upload`](crate::HeapArena::upload), by any
+    /// path.
+    pub bytes_uploaded: u64,
+    /// The number of bytes copied from staging memory to GPU memory.
+    pub bytes_flushed: u64,
+    /// The number of copy commands recorded to flush staging memory.
+    pub flush_commands: u64,
+}
@@ -0,0 +1,84 @@
+//! Hooks into the lifecycle of the heaps and allocations of a [`HeapArena`].
+//!
+//! An [`AllocationObserver`] installed with [`HeapArena::set_allocation_observer`] is told about
+//! every heap the arena creates, grows, or destroys, and every allocation it makes, frees,
+//! flushes, or moves while compacting. This is meant for piping allocator telemetry into a
+//! profiler such as Tracy or puffin, whose APIs differ too much for this crate to target directly.
+//!
+//! With the `log` feature, every arena also logs the same events through the [`log`] crate: heap
+//! and compaction events at the debug level, and the more frequent allocation and flush events at
+//! the trace level.
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::set_allocation_observer`]: crate::HeapArena::set_allocation_observer
+//! [`log`]: https://docs.rs/log
+
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::arena::{Allocation, ArenaKey, HeapEvent, Relocation};
+
+/// Receives the lifecycle events of a [`HeapArena`](crate::HeapArena).
+///
+/// Every method does nothing by default, so only the events of interest need be handled.
+pub trait AllocationObserver {
+    /// A heap was created, grown, or destroyed.
+    fn heap_event(&mut self, _event: &HeapEvent) {}
+
+    /// `allocation` was made.
+    fn allocated(&mut self, _allocation: &Allocation) {}
+
+    /// `allocation` was freed.
+    fn deallocated(&mut self, _allocation: &Allocation) {}
+
+    /// A copy of `range` of the heap at `arena_key` from staging memory was recorded.
+    ///
+    /// The size class of a heap in the pool of tiny heaps is reported as 0.
+    fn flushed(&mut self, _arena_key: ArenaKey, _range: Range<BufferAddress>) {}
+
+    /// The arena was compacted, moving each allocation in `relocations`.
+    fn compacted(&mut self, _relocations: &[Relocation]) {}
+}
+
+/// Logs every event through the `log` crate.
+#[cfg(feature = "log")]
+pub(crate) struct LogObserver;
+
+#[cfg(feature = "log")]
+impl AllocationObserver for LogObserver {
+    fn heap_event(&mut self, event: &HeapEvent) {
+        log::debug!(
+            "heap {:?}: {} bytes in size class {}, {} bytes reserved in total",
+            event.kind, event.size, event.size_class, event.total_reserved,
+        );
+    }
+
+    fn allocated(&mut self, allocation: &Allocation) {
+        let Allocation { arena_key, range_in_heap, .. } = allocation;
+        log::trace!(
+            "allocated {:?} in heap {} of size class {}",
+            range_in_heap, arena_key.index_in_pool(), arena_key.size_class(),
+        );
+    }
+
+    fn deallocated(&mut self, allocation: &Allocation) {
+        let Allocation { arena_key, range_in_heap, .. } = allocation;
+        log::trace!(
+            "freed {:?} in heap {} of size class {}",
+            range_in_heap, arena_key.index_in_pool(), arena_key.size_class(),
+        );
+    }
+
+    fn flushed(&mut self, arena_key: ArenaKey, range: Range<BufferAddress>) {
+        log::trace!(
+            "flushed {:?} of heap {} of size class {}",
+            range, arena_key.index_in_pool(), arena_key.size_class(),
+        );
+    }
+
+    fn compacted(&mut self, relocations: &[Relocation]) {
+        let bytes: BufferAddress = relocations.iter().map(|relocation| relocation.to.size()).sum();
+        log::debug!("compacted by moving {} allocations of {} bytes", relocations.len(), bytes);
+    }
+}
@@ -0,0 +1,450 @@
+//! A [`wgpu::Queue`] wrapper that manages the staging-buffer lifecycle around submissions.
+//!
+//! Staging buffers must be unmapped before any command buffer that copies from them is submitted,
+//! and may only be written again once they have been remapped&mdash;which, in turn, only completes
+//! after the GPU has finished with them. [`ManagedQueue::submit`] performs these steps in the
+//! correct order and keeps track of which submissions the GPU has completed.
+//!
+//! Heaps also track which ranges of their staging memory were copied from by each submission
+//! (see [`InFlightRanges`]), so that a range is not overwritten while a submission that reads it
+//! is still executing.
+//!
+//! Uploads can also be recorded into a [`TransferContext`], whose encoder is submitted on its own
+//! ahead of the encoders that use the uploaded data. This keeps copies out of the main encoder,
+//! so that recording it need not wait for them, and yields a [`TransferToken`] that reports when
+//! the uploads have completed.
+
+use wgpu::BufferAddress;
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fmt,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    arena::{ArenaKey, Relocation},
+    Allocator,
+    Heap,
+    HeapArena,
+};
+
+/// A monotonically increasing number identifying a submission made through a [`ManagedQueue`].
+///
+/// The first submission is assigned serial 1, so a serial of 0 refers to no submission at all.
+pub type Serial = u64;
+
+/// An owner of staging memory that must be unmapped for submissions and remapped afterwards.
+pub trait Staging {
+    /// Unmaps all staging memory so that it can be used by submitted commands.
+    fn unmap(&self);
+
+    /// Requests that all staging memory be mapped for writing again.
+    ///
+    /// The mapping completes only after the GPU has finished all submitted work that uses the
+    /// staging memory, and only once the device has been polled.
+    fn remap(&self);
+
+    /// Records that every copy from staging memory recorded since the last submission is part of
+    /// the submission with the given serial.
+    ///
+    /// This is called by [`ManagedQueue::submit`] after submitting.
+    fn submitted(&self, _serial: Serial) {}
+
+    /// Like [`Self::submitted`], but also given the [`Completion`] of the queue that made the
+    /// submission, so that it can later be told whether the submission has finished.
+    ///
+    /// This is what [`ManagedQueue::submit`] calls; by default, it calls [`Self::submitted`].
+    fn submitted_to(&self, serial: Serial, _completion: &Completion) {
+        self.submitted(serial);
+    }
+}
+
+impl Staging for Heap {
+    fn unmap(&self) {
+        Heap::unmap(self);
+    }
+
+    fn remap(&self) {
+        self.map_range_async(0..self.size().get(), wgpu::MapMode::Write);
+    }
+
+    fn submitted(&self, serial: Serial) {
+        self.staging_in_flight.borrow_mut().submit(serial);
+    }
+}
+
+impl<A: Allocator> Staging for HeapArena<A> {
+    fn unmap(&self) {
+        HeapArena::unmap(self);
+    }
+
+    fn remap(&self) {
+        HeapArena::remap(self);
+    }
+
+    fn submitted(&self, serial: Serial) {
+        for (heap, _) in self.heaps() {
+            Staging::submitted(heap, serial);
+        }
+    }
+
+    fn submitted_to(&self, serial: Serial, completion: &Completion) {
+        Staging::submitted(self, serial);
+        self.record_submission(serial, completion);
+    }
+}
+
+/// A handle to the serial of the most recent submission through a [`ManagedQueue`] that the GPU
+/// has finished executing.
+///
+/// Handles are cheap to clone, and all of them observe the same serial as their queue.
+#[derive(Clone, Debug, Default)]
+pub struct Completion(Arc<AtomicU64>);
+
+impl Completion {
+    /// The serial of the most recent submission that the GPU has finished executing.
+    ///
+    /// This only advances when the device is polled.
+    pub fn last_completed(&self) -> Serial {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Determines if the GPU has finished executing the submission with the given serial.
+    pub fn is_completed(&self, serial: Serial) -> bool {
+        serial <= self.last_completed()
+    }
+}
+
+/// A [`wgpu::Queue`] whose submissions unmap, and then remap, staging memory automatically.
+#[derive(Debug)]
+pub struct ManagedQueue {
+    queue: wgpu::Queue,
+    /// The serial of the most recent submission.
+    last_submitted: Serial,
+    /// The serial of the most recent submission that the GPU has finished executing.
+    ///
+    /// This is shared with the callbacks registered by [`Self::submit`].
+    last_completed: Completion,
+}
+
+impl ManagedQueue {
+    /// Wraps `queue`.
+    pub fn new(queue: wgpu::Queue) -> Self {
+        Self {
+            queue,
+            last_submitted: 0,
+            last_completed: Completion::default(),
+        }
+    }
+
+    /// The wrapped queue.
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Unwraps the queue.
+    pub fn into_inner(self) -> wgpu::Queue {
+        self.queue
+    }
+
+    /// Submits `command_buffers`, returning the serial of the new submission.
+    ///
+    /// This:
+    ///
+    /// 1. unmaps all of `staging`;
+    /// 2. submits `command_buffers`;
+    /// 3. records the new serial against the staging ranges that were copied from, and against
+    ///    the allocations that were flushed or bound (see [`Staging::submitted_to`]);
+    /// 4. registers a callback that marks the submission as completed once the GPU has finished
+    ///    executing it; and
+    /// 5. requests that all of `staging` be remapped for writing.
+    ///
+    /// The remapping does not complete until the device is polled (e.g., with
+    /// [`wgpu::Device::poll`]) after the GPU has finished the submission, so staging memory must
+    /// not be written before then.
+    pub fn submit<I: IntoIterator<Item = wgpu::CommandBuffer>>(
+        &mut self,
+        staging: &[&dyn Staging],
+        command_buffers: I,
+    ) -> Serial {
+        for staging in staging {
+            staging.unmap();
+        }
+
+        self.queue.submit(command_buffers);
+        self.last_submitted += 1;
+
+        let serial = self.last_submitted;
+        for staging in staging {
+            staging.submitted_to(serial, &self.last_completed);
+        }
+        let last_completed = Arc::clone(&self.last_completed.0);
+        self.queue.on_submitted_work_done(move || {
+            last_completed.fetch_max(serial, Ordering::AcqRel);
+        });
+
+        for staging in staging {
+            staging.remap();
+        }
+
+        serial
+    }
+
+    /// The serial of the most recent submission.
+    pub fn last_submitted(&self) -> Serial {
+        self.last_submitted
+    }
+
+    /// The serial of the most recent submission that the GPU has finished executing.
+    ///
+    /// This only advances when the device is polled.
+    pub fn last_completed(&self) -> Serial {
+        self.last_completed.last_completed()
+    }
+
+    /// Determines if the GPU has finished executing the submission with the given serial.
+    pub fn is_completed(&self, serial: Serial) -> bool {
+        self.last_completed.is_completed(serial)
+    }
+
+    /// A handle to [`Self::last_completed`] that can outlive this queue.
+    pub fn completion(&self) -> Completion {
+        self.last_completed.clone()
+    }
+}
+
+/// A command encoder dedicated to uploads, submitted ahead of the encoders that use them.
+///
+/// Copies are recorded into it through `&self`, with methods such as [`Heap::flush_with`] and
+/// [`HeapArena::flush_with`], so the same context can be handed to every heap being uploaded to.
+/// [`Self::submit`] then submits it on its own, and any encoder submitted afterward observes the
+/// uploads: wgpu orders submissions to a queue and inserts whatever barriers are needed between
+/// them, so no explicit handoff is required.
+///
+/// wgpu 0.13 exposes a single queue per device, so the transfer submission shares the queue of
+/// the render submissions rather than running on a dedicated transfer queue.
+#[derive(Debug)]
+pub struct TransferContext {
+    encoder: RefCell<wgpu::CommandEncoder>,
+}
+
+impl TransferContext {
+    /// Creates a context with a new, empty encoder.
+    pub fn new(device: &wgpu::Device, label: Option<&str>) -> Self {
+        let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label });
+
+        Self { encoder: RefCell::new(encoder) }
+    }
+
+    /// Records commands into the encoder of this context with `f`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called from within `f`.
+    pub fn record<R>(&self, f: impl FnOnce(&mut wgpu::CommandEncoder) -> R) -> R {
+        f(&mut self.encoder.borrow_mut())
+    }
+
+    /// Submits the recorded commands through `queue`, as with [`ManagedQueue::submit`], returning
+    /// a token that reports when they have completed.
+    ///
+    /// This should be called before submitting any encoder that uses the uploaded data.
+    pub fn submit(self, queue: &mut ManagedQueue, staging: &[&dyn Staging]) -> TransferToken {
+        let serial = queue.submit(staging, Some(self.encoder.into_inner().finish()));
+
+        TransferToken { serial }
+    }
+}
+
+/// Identifies a submission made by [`TransferContext::submit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransferToken {
+    serial: Serial,
+}
+
+impl TransferToken {
+    /// The serial of the submission.
+    pub fn serial(&self) -> Serial {
+        self.serial
+    }
+
+    /// Determines if the GPU has finished the uploads.
+    ///
+    /// This only changes when the device is polled.
+    pub fn is_completed(&self, queue: &ManagedQueue) -> bool {
+        queue.is_completed(self.serial)
+    }
+
+    /// Blocks until the GPU has finished the uploads.
+    ///
+    /// This waits for all work submitted to `device` so far, not only the uploads.
+    pub fn wait(&self, device: &wgpu::Device, queue: &ManagedQueue) {
+        while !self.is_completed(queue) {
+            device.poll(wgpu::Maintain::Wait);
+        }
+    }
+}
+
+/// The ranges of a staging buffer that are copied from by submissions that may still be executing.
+///
+/// Copies are first recorded as pending with [`Self::record_copy`], then assigned the serial of the
+/// submission that contains them with [`Self::submit`], and finally forgotten with
+/// [`Self::retire`] once that submission has completed.
+#[derive(Debug, Default)]
+pub struct InFlightRanges {
+    /// Ranges copied from by commands that have not been submitted yet.
+    pending: Vec<Range<BufferAddress>>,
+    /// Ranges copied from by submitted commands, with the serial of their submission.
+    submitted: Vec<(Range<BufferAddress>, Serial)>,
+}
+
+impl InFlightRanges {
+    /// Records a copy from `range` by a command that has yet to be submitted.
+    pub fn record_copy(&mut self, range: Range<BufferAddress>) {
+        self.pending.push(range);
+    }
+
+    /// Assigns `serial` to every pending copy.
+    pub fn submit(&mut self, serial: Serial) {
+        self.submitted.extend(self.pending.drain(..).map(|range| (range, serial)));
+    }
+
+    /// Forgets every copy made by a submission with a serial no greater than `last_completed`.
+    pub fn retire(&mut self, last_completed: Serial) {
+        self.submitted.retain(|(_, serial)| *serial > last_completed);
+    }
+
+    /// The serial of the latest unretired submission that copies from any part of `range`, if any.
+    ///
+    /// Copies that have been recorded but not yet submitted are not considered, as they cannot
+    /// execute before later writes to the same range are flushed along with them.
+    pub fn busy_until(&self, range: Range<BufferAddress>) -> Option<Serial> {
+        self.submitted
+            .iter()
+            .filter(|(copied, _)| copied.start < range.end && range.start < copied.end)
+            .map(|(_, serial)| *serial)
+            .max()
+    }
+}
+
+/// The allocations of an arena that are used by submissions that may still be executing, for
+/// [`HeapArena::enable_hazard_tracking`].
+///
+/// Like [`InFlightRanges`], uses are first recorded as pending, and then assigned the serial of
+/// the submission that contains them, which is the next one that the arena is passed to
+/// [`ManagedQueue::submit`] for. They are forgotten once the [`Completion`] of that queue reports
+/// that the submission has finished.
+#[derive(Debug, Default)]
+pub(crate) struct Hazards {
+    /// Ranges flushed or bound since the last submission, keyed by heap and then by range.
+    ///
+    /// This is a set, as the same allocation is typically bound many times per submission.
+    pending: BTreeSet<(ArenaKey, BufferAddress, BufferAddress)>,
+    /// Ranges used by submitted commands, with the serial of their submission.
+    submitted: Vec<(ArenaKey, Range<BufferAddress>, Serial)>,
+    /// The completion of the queue of the latest submission, if any.
+    completion: Option<Completion>,
+}
+
+impl Hazards {
+    /// Records a use of `range` of the heap of `key` by commands that have yet to be submitted.
+    pub(crate) fn record(&mut self, key: ArenaKey, range: Range<BufferAddress>) {
+        self.pending.insert((key, range.start, range.end));
+    }
+
+    /// Assigns `serial` to every pending use.
+    pub(crate) fn submit(&mut self, serial: Serial, completion: &Completion) {
+        self.retire();
+        let pending = std::mem::take(&mut self.pending).into_iter();
+        self.submitted.extend(pending.map(|(key, start, end)| (key, start..end, serial)));
+        self.completion = Some(completion.clone());
+    }
+
+    /// Forgets every use by a submission that has finished.
+    fn retire(&mut self) {
+        if let Some(completion) = self.completion.as_ref() {
+            let last_completed = completion.last_completed();
+            self.submitted.retain(|(_, _, serial)| *serial > last_completed);
+        }
+    }
+
+    /// The serial of the latest unfinished submission that uses any part of `range` of the heap
+    /// of `key`, if any.
+    pub(crate) fn busy_until(
+        &mut self,
+        key: ArenaKey,
+        range: &Range<BufferAddress>,
+    ) -> Option<Serial> {
+        self.retire();
+        self.submitted
+            .iter()
+            .filter(|(used_key, used, _)| {
+                *used_key == key && used.start < range.end && range.start < used.end
+            })
+            .map(|(_, _, serial)| *serial)
+            .max()
+    }
+
+    /// Forgets the pending uses of `range` of the heap of `key`, which was freed before they
+    /// were submitted.
+    pub(crate) fn remove_pending(&mut self, key: ArenaKey, range: &Range<BufferAddress>) {
+        self.pending.retain(|&(used_key, start, end)| {
+            used_key != key || end <= range.start || range.end <= start
+        });
+    }
+
+    /// Forgets every use of a heap whose key is not kept by `keep`.
+    pub(crate) fn retain_heaps(&mut self, keep: impl Fn(ArenaKey) -> bool) {
+        self.pending.retain(|(key, _, _)| keep(*key));
+        self.submitted.retain(|(key, _, _)| keep(*key));
+    }
+
+    /// Moves the uses of each allocation in `relocations` from its old key and range to its new
+    /// ones.
+    ///
+    /// Uses of ranges other than whole allocations, such as flushes of dirty regions, stay where
+    /// they were.
+    pub(crate) fn relocate(&mut self, relocations: &[Relocation]) {
+        let relocate = |key: &mut ArenaKey, range: &mut Range<BufferAddress>| {
+            let found = relocations.iter().find(|Relocation { from, .. }| {
+                from.arena_key == *key && from.range_in_heap == *range
+            });
+            if let Some(Relocation { to, .. }) = found {
+                *key = to.arena_key;
+                *range = to.range_in_heap.clone();
+            }
+        };
+        self.pending = std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(mut key, start, end)| {
+                let mut range = start..end;
+                relocate(&mut key, &mut range);
+                (key, range.start, range.end)
+            })
+            .collect();
+        for (key, range, _) in self.submitted.iter_mut() {
+            relocate(key, range);
+        }
+    }
+}
+
+/// The error returned when writing staging memory that a submission may still be copying from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InFlight {
+    /// The serial of the latest submission that copies from the range.
+    pub serial: Serial,
+}
+
+impl fmt::Display for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "staging range is still being copied from by submission {}", self.serial)
+    }
+}
+
+impl std::error::Error for InFlight {}
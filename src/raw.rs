@@ -0,0 +1,69 @@
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{AllocError, Allocator, NonZeroBufferAddress, VirtualHeap};
+
+/// An [`Allocator`] running over a single, externally owned [`wgpu::Buffer`].
+///
+/// Unlike a [`Heap`](crate::Heap), a `RawHeap` has no staging buffer and never creates buffers of
+/// its own; it only decides where allocations are placed within the wrapped buffer and hands out
+/// slices and bindings of it. This suits middleware that already has an upload path of its own and
+/// only wants this crate's placement algorithms.
+///
+/// Placement itself is done by a [`VirtualHeap`], which can be used on its own for memory that
+/// isn't a [`wgpu::Buffer`].
+#[derive(Debug)]
+pub struct RawHeap<'a, A> {
+    buffer: &'a wgpu::Buffer,
+    heap: VirtualHeap<A>,
+}
+
+impl<'a, A: Allocator> RawHeap<'a, A> {
+    /// Wraps `buffer`, which must be at least `size` bytes long, with `allocator`, which must
+    /// manage exactly `size` bytes.
+    pub fn new(buffer: &'a wgpu::Buffer, size: NonZeroBufferAddress, allocator: A) -> Self {
+        Self { buffer, heap: VirtualHeap::with_allocator(size, allocator) }
+    }
+
+    /// The wrapped buffer.
+    pub fn buffer(&self) -> &'a wgpu::Buffer {
+        self.buffer
+    }
+
+    /// The size, in bytes, of the memory managed by this heap.
+    pub fn size(&self) -> NonZeroBufferAddress {
+        self.heap.size()
+    }
+
+    /// The allocator that manages the wrapped buffer.
+    pub fn allocator(&self) -> &A {
+        self.heap.allocator()
+    }
+
+    /// See [`Allocator::alloc`].
+    pub fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.heap.alloc(size, alignment)
+    }
+
+    /// See [`Allocator::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `range` must be a valid allocation previously returned by [`Self::alloc`].
+    pub unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        self.heap.dealloc(range)
+    }
+
+    pub fn slice(&self, range: Range<BufferAddress>) -> wgpu::BufferSlice<'a> {
+        self.buffer.slice(range)
+    }
+
+    pub fn binding(&self, range: Range<BufferAddress>) -> wgpu::BufferBinding<'a> {
+        crate::create_binding(self.buffer, range)
+    }
+}
@@ -0,0 +1,180 @@
+//! Allocation sizing driven by shader reflection.
+//!
+//! This module is only available with the `naga` feature enabled. It computes the exact size and
+//! alignment of a uniform or storage binding from a [`naga::Module`] (such as one parsed from WGSL)
+//! so that the layout of a struct doesn't have to be maintained by hand on both sides of the
+//! shader boundary.
+
+use wgpu::BufferAddress;
+
+use std::fmt;
+
+use crate::{arena::Allocation, AllocError, Allocator, HeapArena, NonZeroBufferAddress};
+
+/// The memory layout of a uniform or storage binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BindingLayout {
+    /// The size, in bytes, of the binding, excluding any runtime-sized array at its end.
+    pub fixed_size: BufferAddress,
+    /// The stride, in bytes, of the runtime-sized array at the end of the binding, if any.
+    pub runtime_array_stride: Option<NonZeroBufferAddress>,
+    /// The alignment, in bytes, of the binding's type.
+    pub alignment: NonZeroBufferAddress,
+}
+
+impl BindingLayout {
+    /// Finds the layout of the uniform or storage binding named `name` in `module`.
+    ///
+    /// `name` may either be the name of a global variable in the uniform or storage address
+    /// space, or the name of a type.
+    pub fn reflect(module: &naga::Module, name: &str) -> Result<Self, ReflectError> {
+        let ty = module
+            .global_variables
+            .iter()
+            .find(|(_, var)| {
+                var.name.as_deref() == Some(name) && matches!(
+                    var.space,
+                    naga::AddressSpace::Uniform | naga::AddressSpace::Storage { .. },
+                )
+            })
+            .map(|(_, var)| var.ty)
+            .or_else(|| {
+                module
+                    .types
+                    .iter()
+                    .find(|(_, ty)| ty.name.as_deref() == Some(name))
+                    .map(|(handle, _)| handle)
+            })
+            .ok_or_else(|| ReflectError::NotFound(name.to_owned()))?;
+
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(&module.types, &module.constants).map_err(ReflectError::Layout)?;
+        let layout = layouter[ty];
+
+        let (fixed_size, runtime_array_stride) = match module.types[ty].inner {
+            naga::TypeInner::Struct { ref members, span } => {
+                match members.last().and_then(|member| runtime_array_stride(module, member.ty)) {
+                    Some(stride) => (members.last().unwrap().offset, Some(stride)),
+                    None => (span, None),
+                }
+            }
+            _ => match runtime_array_stride(module, ty) {
+                Some(stride) => (0, Some(stride)),
+                None => (layout.size, None),
+            },
+        };
+
+        Ok(Self {
+            fixed_size: fixed_size.into(),
+            runtime_array_stride: runtime_array_stride
+                .and_then(|stride| NonZeroBufferAddress::new(stride.into())),
+            // Note: `Alignment` has no accessor of its own, but multiplying by one yields its
+            // value.
+            alignment: NonZeroBufferAddress::new((layout.alignment * 1).into()).unwrap(),
+        })
+    }
+
+    /// The size, in bytes, of the binding when its runtime-sized array, if any, has `len`
+    /// elements.
+    ///
+    /// The result is rounded up to the binding's alignment.
+    pub fn size_with_len(&self, len: BufferAddress) -> BufferAddress {
+        let stride = self.runtime_array_stride.map_or(0, NonZeroBufferAddress::get);
+        let size = self.fixed_size + (stride * len);
+        let alignment = self.alignment.get();
+
+        match size % alignment {
+            0 => size,
+            remainder => size + (alignment - remainder),
+        }
+    }
+}
+
+fn runtime_array_stride(module: &naga::Module, ty: naga::Handle<naga::Type>) -> Option<u32> {
+    match module.types[ty].inner {
+        naga::TypeInner::Array { size: naga::ArraySize::Dynamic, stride, .. } => Some(stride),
+        _ => None,
+    }
+}
+
+/// The error returned when a binding layout cannot be reflected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReflectError {
+    /// No global variable or type with the given name exists in the module.
+    NotFound(String),
+    /// The types in the module could not be laid out.
+    Layout(naga::proc::LayoutError),
+    /// The binding ends in a runtime-sized array, so its size cannot be known without an element
+    /// count.
+    RuntimeSized,
+    /// The memory for the binding could not be allocated.
+    Alloc(AllocError),
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "no binding or type named `{}` in module", name),
+            Self::Layout(error) => write!(f, "failed to lay out module types: {}", error),
+            Self::RuntimeSized => f.write_str(
+                "binding is runtime-sized; an element count must be given for its trailing array",
+            ),
+            Self::Alloc(error) => write!(f, "failed to allocate binding: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+impl<A: Allocator> HeapArena<A> {
+    /// Allocates exactly enough memory for the binding named `name` in `module`.
+    ///
+    /// The allocation is aligned to the larger of the binding's type alignment and the device's
+    /// minimum uniform and storage buffer offset alignments, so that it can be bound directly.
+    /// Bindings that end in a runtime-sized array must use [`Self::alloc_for_binding_with_len`].
+    pub fn alloc_for_binding(
+        &mut self,
+        device: &wgpu::Device,
+        module: &naga::Module,
+        name: &str,
+    ) -> Result<Allocation, ReflectError> {
+        let layout = BindingLayout::reflect(module, name)?;
+        if layout.runtime_array_stride.is_some() {
+            return Err(ReflectError::RuntimeSized);
+        }
+
+        self.alloc_for_layout(device, &layout, 0)
+    }
+
+    /// Like [`Self::alloc_for_binding`], but for bindings that may end in a runtime-sized array,
+    /// which is given `len` elements.
+    pub fn alloc_for_binding_with_len(
+        &mut self,
+        device: &wgpu::Device,
+        module: &naga::Module,
+        name: &str,
+        len: BufferAddress,
+    ) -> Result<Allocation, ReflectError> {
+        let layout = BindingLayout::reflect(module, name)?;
+
+        self.alloc_for_layout(device, &layout, len)
+    }
+
+    fn alloc_for_layout(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &BindingLayout,
+        len: BufferAddress,
+    ) -> Result<Allocation, ReflectError> {
+        let limits = device.limits();
+        let offset_alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment);
+        let alignment = NonZeroBufferAddress::new(offset_alignment.into())
+            .map_or(layout.alignment, |offset_alignment| layout.alignment.max(offset_alignment));
+        let size = NonZeroBufferAddress::new(layout.size_with_len(len))
+            .expect("binding size is zero; must be nonzero");
+
+        self.alloc(device, size, alignment).map_err(ReflectError::Alloc)
+    }
+}
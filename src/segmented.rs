@@ -0,0 +1,171 @@
+//! Allocations split into segments across several heaps.
+//!
+//! Some data, such as terrain or large point clouds, is far larger than any heap should be, and
+//! often larger than a device allows a single buffer to be. [`HeapArena::alloc_segmented`] serves
+//! such requests by splitting them into segments of at most a given size, each of which is an
+//! ordinary [`Allocation`] in whichever heap it fits. The data is then written, bound, and drawn
+//! one segment at a time.
+
+use wgpu::BufferAddress;
+
+use crate::{arena::Allocation, AllocError, Allocator, HeapArena, NonZeroBufferAddress};
+
+/// A range of memory made of one or more [`Allocation`]s, possibly in different heaps.
+///
+/// Every segment but the last is exactly [`Self::segment_size`] bytes; the last holds the
+/// remainder. Byte `offset` of the whole is therefore at byte `offset % segment_size` of segment
+/// `offset / segment_size`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SegmentedAllocation {
+    segments: Vec<Allocation>,
+    segment_size: NonZeroBufferAddress,
+    size: NonZeroBufferAddress,
+}
+
+impl SegmentedAllocation {
+    /// The total size, in bytes, of every segment.
+    pub fn size(&self) -> NonZeroBufferAddress {
+        self.size
+    }
+
+    /// The size, in bytes, of every segment but the last.
+    pub fn segment_size(&self) -> NonZeroBufferAddress {
+        self.segment_size
+    }
+
+    /// The segments, in order.
+    pub fn segments(&self) -> &[Allocation] {
+        &self.segments
+    }
+
+    /// Every segment along with its offset, in bytes, from the start of the whole.
+    pub fn iter(&self) -> impl Iterator<Item = (BufferAddress, &Allocation)> {
+        let segment_size = self.segment_size.get();
+
+        (0..).step_by(segment_size as usize).zip(self.segments.iter())
+    }
+
+    /// The index of the segment holding byte `offset` of the whole, along with the offset of that
+    /// byte within the segment, or `None` if `offset` is out of bounds.
+    pub fn locate(&self, offset: BufferAddress) -> Option<(usize, BufferAddress)> {
+        (offset < self.size.get()).then(|| {
+            // Note: `offset` is in bounds, so the index fits in `usize`.
+            let index = (offset / self.segment_size.get()) as usize;
+
+            (index, offset % self.segment_size.get())
+        })
+    }
+
+    /// Consumes this allocation, returning its segments.
+    pub fn into_segments(self) -> Vec<Allocation> {
+        self.segments
+    }
+}
+
+impl<A: Allocator> HeapArena<A> {
+    /// Allocates `size` bytes as segments of at most `max_segment_size` bytes each, every one
+    /// aligned to `alignment`.
+    ///
+    /// Each segment is allocated as if by [`Self::alloc`], so segments are placed in whichever
+    /// heaps fit them, and no heap need be larger than a single segment.
+    ///
+    /// # Errors
+    ///
+    /// This fails as [`Self::alloc`] does if any segment can't be allocated, in which case the
+    /// segments allocated so far are freed again. Segments that the allocator refuses to free,
+    /// such as those of a [`Ring`](crate::Ring), are leaked.
+    pub fn alloc_segmented(
+        &mut self,
+        device: &wgpu::Device,
+        size: NonZeroBufferAddress,
+        max_segment_size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<SegmentedAllocation, AllocError> {
+        let segment_size = max_segment_size.min(size);
+        let mut segments = Vec::new();
+        let mut remaining = size.get();
+        while let Some(left) = NonZeroBufferAddress::new(remaining) {
+            let segment_len = left.min(segment_size);
+            match self.alloc(device, segment_len, alignment) {
+                Ok(segment) => segments.push(segment),
+                Err(error) => {
+                    for segment in segments.into_iter().rev() {
+                        // SAFETY: `segment` was just allocated by this arena and never handed out.
+                        let _ = unsafe { self.dealloc(segment) };
+                    }
+
+                    return Err(error);
+                }
+            }
+            remaining -= segment_len.get();
+        }
+
+        Ok(SegmentedAllocation { segments, segment_size, size })
+    }
+
+    /// Frees every segment of `allocation`, from last to first.
+    ///
+    /// # Errors
+    ///
+    /// This fails with the first error returned by [`Self::dealloc`] for any segment, though
+    /// every other segment is still freed.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::dealloc`], which applies to every segment.
+    pub unsafe fn dealloc_segmented(
+        &mut self,
+        allocation: SegmentedAllocation,
+    ) -> Result<(), AllocError> {
+        let mut result = Ok(());
+        for segment in allocation.segments.into_iter().rev() {
+            // SAFETY: The caller upholds the same contract for every segment.
+            let segment_result = unsafe { self.dealloc(segment) };
+            result = result.and(segment_result);
+        }
+
+        result
+    }
+
+    /// Writes `contents` into `allocation` starting at byte `offset` of the whole, across as many
+    /// segments as it spans.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `contents` doesn't fit in `allocation` from `offset`.
+    pub fn write_segmented(
+        &self,
+        allocation: &SegmentedAllocation,
+        offset: BufferAddress,
+        mut contents: &[u8],
+    ) {
+        assert!(
+            offset + contents.len() as BufferAddress <= allocation.size.get(),
+            "contents don't fit in the segmented allocation",
+        );
+
+        let mut offset = offset;
+        while !contents.is_empty() {
+            // Note: the rest of `contents` fits from `offset`, so `offset` is in bounds.
+            let (index, offset_in_segment) = allocation.locate(offset).unwrap();
+            let segment = &allocation.segments[index];
+            let start = segment.range_in_heap.start + offset_in_segment;
+            let len = (segment.range_in_heap.end - start).min(contents.len() as BufferAddress);
+            let (head, tail) = contents.split_at(len as usize);
+            let part = Allocation { range_in_heap: start..(start + len), ..segment.clone() };
+            self.write(&part, head);
+            contents = tail;
+            offset += len;
+        }
+    }
+}
+
+impl<A> HeapArena<A> {
+    /// A binding of each segment of `allocation`, in order.
+    pub fn segment_bindings<'a>(
+        &'a self,
+        allocation: &'a SegmentedAllocation,
+    ) -> impl Iterator<Item = wgpu::BufferBinding<'a>> + 'a {
+        allocation.segments.iter().map(|segment| self.binding(segment))
+    }
+}
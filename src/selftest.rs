@@ -0,0 +1,159 @@
+//! A diagnostic that checks that data survives a round trip through a heap.
+//!
+//! [`run`] writes known patterns into a [`Heap`], flushes them to GPU memory, copies them back
+//! through the readback path, and compares the result byte for byte. This exercises the whole
+//! write, flush, and readback machinery against the actual device and driver, and so is a quick way
+//! to validate a new platform before debugging corruption at a higher level.
+
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{Heap, HeapUsages, NonZeroBufferAddress};
+
+/// The allocation sizes and alignments exercised by [`run`].
+///
+/// One range is tested for every combination of size and alignment. Every size must be a multiple
+/// of [`wgpu::COPY_BUFFER_ALIGNMENT`], and every alignment a multiple of [`wgpu::MAP_ALIGNMENT`],
+/// as staging memory can only be written at such offsets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestConfig {
+    pub sizes: Vec<NonZeroBufferAddress>,
+    pub alignments: Vec<NonZeroBufferAddress>,
+    /// The usage of the heap that the ranges are written through.
+    pub usage: HeapUsages,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        let nonzero = |value| NonZeroBufferAddress::new(value).unwrap();
+
+        Self {
+            sizes: [4, 12, 256, 4096, 65_540].map(nonzero).to_vec(),
+            alignments: [8, 16, 256].map(nonzero).to_vec(),
+            usage: HeapUsages::STORAGE,
+        }
+    }
+}
+
+/// The outcome of a single range tested by [`run`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestCase {
+    /// The range of the heap that was tested.
+    pub range: Range<BufferAddress>,
+    /// The alignment that [`Self::range`] was placed at.
+    pub alignment: NonZeroBufferAddress,
+    /// The offset, within the heap, of the first byte that did not survive the round trip, if any.
+    pub first_mismatch: Option<BufferAddress>,
+    /// The number of bytes that did not survive the round trip.
+    pub mismatches: BufferAddress,
+}
+
+impl SelfTestCase {
+    pub fn passed(&self) -> bool {
+        self.mismatches == 0
+    }
+}
+
+/// The outcome of [`run`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub cases: Vec<SelfTestCase>,
+}
+
+impl SelfTestReport {
+    /// Whether every tested range survived the round trip.
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(SelfTestCase::passed)
+    }
+
+    /// The cases that did not survive the round trip.
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCase> {
+        self.cases.iter().filter(|case| !case.passed())
+    }
+}
+
+/// Runs the round-trip self-test described by `config`, blocking until the GPU has finished.
+///
+/// # Panics
+///
+/// This function panics if a size or alignment in `config` is not a multiple of the values given
+/// in [`SelfTestConfig`], or if `config` has no sizes or no alignments.
+pub fn run(device: &wgpu::Device, queue: &wgpu::Queue, config: &SelfTestConfig) -> SelfTestReport {
+    assert!(
+        config.sizes.iter().all(|size| size.get().is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)),
+        "self-test sizes must be multiples of `COPY_BUFFER_ALIGNMENT`",
+    );
+    assert!(
+        config.alignments.iter().all(|align| align.get().is_multiple_of(wgpu::MAP_ALIGNMENT)),
+        "self-test alignments must be multiples of `MAP_ALIGNMENT`",
+    );
+
+    // Lay the ranges out back to back, each at the next offset that is a multiple of its
+    // alignment.
+    let mut cases = Vec::new();
+    let mut cursor = 0;
+    for &alignment in &config.alignments {
+        for &size in &config.sizes {
+            let start = match cursor % alignment.get() {
+                0 => cursor,
+                remainder => cursor + (alignment.get() - remainder),
+            };
+            cursor = start + size.get();
+            cases.push(SelfTestCase {
+                range: start..cursor,
+                alignment,
+                first_mismatch: None,
+                mismatches: 0,
+            });
+        }
+    }
+    let heap_size = NonZeroBufferAddress::new(cursor).expect("self-test has no ranges to test");
+
+    let heap = Heap::with_readback(device, heap_size, config.usage);
+    let patterns: Vec<Vec<u8>> = cases
+        .iter()
+        .enumerate()
+        .map(|(index, case)| pattern(index, case.range.end - case.range.start))
+        .collect();
+    for (case, pattern) in cases.iter().zip(&patterns) {
+        heap.write(case.range.clone(), pattern);
+    }
+    heap.unmap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("wgpu-allocators self-test"),
+    });
+    for case in &cases {
+        heap.flush_range(&mut encoder, case.range.clone());
+        heap.mark_gpu_modified(case.range.clone());
+    }
+    heap.sync_back_dirty(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    heap.map_readback_async(0..heap_size.get());
+    device.poll(wgpu::Maintain::Wait);
+    let contents = heap.read_mapped(0..heap_size.get());
+    heap.unmap_readback();
+
+    for (case, pattern) in cases.iter_mut().zip(&patterns) {
+        // Note: these casts can't truncate as the readback buffer was successfully mapped.
+        let actual = &contents[(case.range.start as usize)..(case.range.end as usize)];
+        let mut mismatched = actual
+            .iter()
+            .zip(pattern)
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+            .map(|(index, _)| case.range.start + index as BufferAddress);
+        case.first_mismatch = mismatched.next();
+        case.mismatches = case.first_mismatch.map_or(0, |_| 1 + mismatched.count() as u64);
+    }
+
+    SelfTestReport { cases }
+}
+
+/// A byte pattern of length `len` that differs between cases and never repeats within 251 bytes,
+/// so that misplaced or truncated copies are caught.
+fn pattern(case_index: usize, len: BufferAddress) -> Vec<u8> {
+    (0..len).map(|i| ((i + case_index as u64 * 31) % 251) as u8 + 1).collect()
+}
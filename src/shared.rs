@@ -0,0 +1,185 @@
+//! An arena that can be allocated from by several threads at once.
+//!
+//! A [`HeapArena`] needs `&mut self` to allocate, so sharing one between threads takes a lock
+//! around every allocation, which serializes command recording. A [`SharedHeapArena`] instead
+//! splits its heaps into several independent *shards*, each an ordinary `HeapArena` behind its own
+//! [`Mutex`]. Each thread prefers a shard of its own and falls back to others that aren't busy, so
+//! threads rarely wait on each other.
+//!
+//! # Synchronization
+//!
+//! Allocating, freeing, and writing through `&self` are safe to do from any number of threads.
+//! The following still have to be ordered by the application:
+//!
+//! - [`SharedHeapArena::unmap`] and [`SharedHeapArena::flush_dirty`] must not run while another
+//!   thread is writing, as writes need staging memory to be mapped and flushes must see every
+//!   write that should be part of the submission.
+//! - The command encoder passed to [`SharedHeapArena::flush_dirty`] must be submitted before any
+//!   commands that read the flushed data, and [`SharedHeapArena::remap`] must only be called after
+//!   that submission.
+//! - Configuration, such as the upload policy, is per shard and is changed for all shards at once
+//!   with [`SharedHeapArena::for_each_shard`].
+//!
+//! # Determinism
+//!
+//! Placement within each shard is as deterministic as that of any [`HeapArena`], but the shard
+//! that serves an allocation is not. A thread first tries the shard picked by a hash of its
+//! [`ThreadId`](std::thread::ThreadId), which differs between runs, and moves on to another if
+//! that one is locked. Replaying a recorded sequence of operations therefore only reproduces the
+//! layout of a `SharedHeapArena` if every allocation is made in a chosen shard, through
+//! [`SharedHeapArena::lock`].
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    arena::Allocation,
+    AllocError,
+    Allocator,
+    GrowthPolicy,
+    HeapArena,
+    HeapUsages,
+    NonZeroBufferAddress,
+};
+
+/// An allocation made by a [`SharedHeapArena`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SharedAllocation {
+    /// The index of the shard that the allocation was made in.
+    pub shard: usize,
+    /// The allocation within the [`HeapArena`] of that shard.
+    pub allocation: Allocation,
+}
+
+/// A [`HeapArena`] split into shards that can be used from several threads at once.
+///
+/// See the [module documentation](self) for which operations need to be synchronized.
+#[derive(Debug)]
+pub struct SharedHeapArena<A> {
+    shards: Box<[Mutex<HeapArena<A>>]>,
+}
+
+impl<A> SharedHeapArena<A> {
+    /// Creates a new `SharedHeapArena` of `shard_count` shards, each like one created with
+    /// [`HeapArena::new`].
+    ///
+    /// Each shard gets its own clone of `growth_policy`.
+    pub fn new(
+        usage: HeapUsages,
+        growth_policy: impl GrowthPolicy + Clone + Send + 'static,
+        shard_count: NonZeroUsize,
+    ) -> Self {
+        Self {
+            shards: (0..shard_count.get())
+                .map(|_| Mutex::new(HeapArena::new(usage, growth_policy.clone())))
+                .collect(),
+        }
+    }
+
+    /// The number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks shard `index` for direct access, blocking until no other thread is using it.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `index` is out of bounds.
+    pub fn lock(&self, index: usize) -> MutexGuard<'_, HeapArena<A>> {
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Calls `f` with each shard in turn, locking only one shard at a time.
+    pub fn for_each_shard(&self, mut f: impl FnMut(&mut HeapArena<A>)) {
+        for shard in self.shards.iter() {
+            f(&mut shard.lock().unwrap());
+        }
+    }
+
+    /// The shard that the current thread tries first.
+    fn preferred_shard(&self) -> usize {
+        let mut hasher = DefaultHasher::new();
+        std::thread::current().id().hash(&mut hasher);
+
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Writes `contents` into `allocation`, as with [`HeapArena::write`].
+    pub fn write(&self, allocation: &SharedAllocation, contents: &[u8]) {
+        self.lock(allocation.shard).write(&allocation.allocation, contents);
+    }
+
+    /// Flushes every shard, as with [`HeapArena::flush_dirty`], returning the total number of
+    /// copies recorded.
+    pub fn flush_dirty(&self, encoder: &mut wgpu::CommandEncoder) -> usize {
+        let mut copies = 0;
+        self.for_each_shard(|arena| copies += arena.flush_dirty(encoder));
+
+        copies
+    }
+}
+
+impl<A: Allocator> SharedHeapArena<A> {
+    /// Unmaps the staging memory of every shard, as with [`HeapArena::unmap`].
+    pub fn unmap(&self) {
+        self.for_each_shard(|arena| arena.unmap());
+    }
+
+    /// Requests that the staging memory of every shard be mapped again, as with
+    /// [`HeapArena::remap`].
+    pub fn remap(&self) {
+        self.for_each_shard(|arena| arena.remap());
+    }
+
+    /// Allocates `size` bytes aligned to `alignment`, as with [`HeapArena::alloc`].
+    ///
+    /// The allocation is made in the first shard that isn't locked by another thread, starting
+    /// from the one this thread prefers. If every shard is busy, this blocks until the preferred
+    /// one is free.
+    ///
+    /// # Errors
+    ///
+    /// See [`HeapArena::alloc`]. Other shards aren't tried if the allocation fails.
+    pub fn alloc(
+        &self,
+        device: &wgpu::Device,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<SharedAllocation, AllocError> {
+        let preferred = self.preferred_shard();
+        let shard_count = self.shards.len();
+
+        let available = (0..shard_count)
+            .map(|offset| (preferred + offset) % shard_count)
+            .find_map(|index| Some((index, self.shards[index].try_lock().ok()?)));
+        let (shard, mut arena) = match available {
+            Some(available) => available,
+            None => (preferred, self.lock(preferred)),
+        };
+
+        Ok(SharedAllocation { shard, allocation: arena.alloc(device, size, alignment)? })
+    }
+
+    /// Returns `allocation` to the shard it was made in, as with [`HeapArena::dealloc`].
+    ///
+    /// # Errors
+    ///
+    /// This fails with [`AllocError::NotOwnedByAllocator`] if there is no such shard, or as
+    /// [`HeapArena::dealloc`] does.
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must have been returned by [`Self::alloc`] on this arena, must not have been
+    /// freed already, and must no longer be in use by the GPU.
+    pub unsafe fn dealloc(&self, allocation: SharedAllocation) -> Result<(), AllocError> {
+        let shard = self.shards.get(allocation.shard).ok_or(AllocError::NotOwnedByAllocator)?;
+
+        // SAFETY: The caller upholds the contract of `HeapArena::dealloc`.
+        unsafe { shard.lock().unwrap().dealloc(allocation.allocation) }
+    }
+}
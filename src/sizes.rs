@@ -0,0 +1,94 @@
+//! Size classes, and the power-of-two and alignment rounding that goes with them.
+//!
+//! A [`HeapArena`](crate::HeapArena) keeps a pool of heaps for each size class, which is the
+//! base-2 logarithm of an allocation size, rounded down (see [`classify_size`]). The functions in
+//! this module are those the arena itself classifies and rounds sizes with, so that heap sizes
+//! and pool targets computed ahead of time agree with where the arena puts allocations.
+
+use wgpu::BufferAddress;
+
+use std::ops::RangeInclusive;
+
+use crate::NonZeroBufferAddress;
+
+/// The lowest size class with a pool of its own, that of sizes of 4,096 to 8,191 bytes.
+///
+/// Every lower size class shares the pool of tiny heaps.
+pub const FIRST_POOLED_SIZE_CLASS: usize = 12;
+
+/// The size class of an allocation of `size` bytes.
+///
+/// This is the position of the leftmost 1 bit in `size`, so allocations of 2<sup>n</sup> to
+/// 2<sup>n+1</sup> - 1 bytes are in size class n.
+pub fn classify_size(size: NonZeroBufferAddress) -> usize {
+    // The base-2 logarithm of `size`, rounded down, is the zero-based index of its leftmost 1 bit.
+    // As `size` is nonzero, this can't fail.
+    //
+    // Note: it's OK to cast this to `usize` as it can't possibly overflow `usize` on any
+    // system&mdash;we're not dealing with 512-bit integers here.
+    size.ilog2() as usize
+}
+
+/// The sizes, in bytes, of allocations in `size_class`.
+///
+/// # Panics
+///
+/// This function panics if `size_class` is 64 or more, as no [`BufferAddress`] is that large.
+pub fn size_class_range(size_class: usize) -> RangeInclusive<BufferAddress> {
+    assert!(size_class < 64, "size class {} is too large; must be less than 64", size_class);
+    let start: BufferAddress = 1 << size_class;
+
+    start..=(start | (start - 1))
+}
+
+/// The index of the pool of `size_class` among those past the pool of tiny heaps, or `None` if
+/// it shares that pool (see [`FIRST_POOLED_SIZE_CLASS`]).
+pub fn pool_index(size_class: usize) -> Option<usize> {
+    size_class.checked_sub(FIRST_POOLED_SIZE_CLASS)
+}
+
+/// Whether allocations of `a` and `b` are kept in the same pool of an arena.
+pub fn share_pool(a: usize, b: usize) -> bool {
+    pool_index(a) == pool_index(b)
+}
+
+/// The smallest power of two no less than `size`, or `None` if it would overflow.
+pub fn next_power_of_two(size: NonZeroBufferAddress) -> Option<NonZeroBufferAddress> {
+    size.checked_next_power_of_two()
+}
+
+/// The largest power of two no greater than `size`, which is the smallest size in its size
+/// class.
+pub fn prev_power_of_two(size: NonZeroBufferAddress) -> NonZeroBufferAddress {
+    // Note: shifting 1 left by the logarithm of a nonzero value can't make it zero.
+    NonZeroBufferAddress::new(1 << size.ilog2()).unwrap()
+}
+
+/// Rounds `value` up to the nearest multiple of `alignment`, or returns `None` on overflow.
+pub fn align_up(value: BufferAddress, alignment: NonZeroBufferAddress) -> Option<BufferAddress> {
+    match value % alignment.get() {
+        0 => Some(value),
+        remainder => value.checked_add(alignment.get() - remainder),
+    }
+}
+
+/// Rounds `value` down to the nearest multiple of `alignment`.
+pub fn align_down(value: BufferAddress, alignment: NonZeroBufferAddress) -> BufferAddress {
+    value - value % alignment.get()
+}
+
+/// The least common multiple of two alignments, which satisfies both.
+///
+/// This saturates at the largest [`BufferAddress`] rather than overflowing.
+pub fn combine_alignments(
+    a: NonZeroBufferAddress,
+    b: NonZeroBufferAddress,
+) -> NonZeroBufferAddress {
+    let (mut x, mut y) = (a.get(), b.get());
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+
+    // Note: `x` is the greatest common divisor of `a` and `b`, which divides `a` exactly.
+    b.saturating_mul(NonZeroBufferAddress::new(a.get() / x).unwrap())
+}
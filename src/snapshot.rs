@@ -0,0 +1,86 @@
+//! Serializable snapshots of the logical state of a [`HeapArena`].
+//!
+//! This module only exists with the `snapshot` feature. [`HeapArena::snapshot`] captures which
+//! heaps an arena has, in which pools, and the full state of the allocator of each&mdash;its free
+//! lists, pointers, and the like&mdash;along with the live allocations in every heap, but not the
+//! contents of any buffer. The snapshot can be serialized with serde, such as into a crash report,
+//! and later turned back into an equivalent arena with [`HeapArena::restore`], so that a
+//! fragmentation bug seen in the field can be replayed and analyzed offline.
+//!
+//! Every allocator in this crate can be snapshotted. Other allocators can be too, as long as they
+//! implement [`Clone`], [`serde::Serialize`], and [`serde::Deserialize`].
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::snapshot`]: crate::HeapArena::snapshot
+//! [`HeapArena::restore`]: crate::HeapArena::restore
+
+use serde::{Deserialize, Serialize};
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{HeapUsages, NonZeroBufferAddress, UploadStrategy};
+
+/// The logical state of a [`HeapArena`](crate::HeapArena).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArenaSnapshot<A> {
+    /// The usage of every heap.
+    #[serde(with = "usage_bits")]
+    pub usage: HeapUsages,
+    pub upload_strategy: UploadStrategy,
+    pub min_alignment: NonZeroBufferAddress,
+    /// Every pool that has ever had a heap, from the lowest size class to the highest.
+    pub pools: Vec<PoolSnapshot<A>>,
+}
+
+/// The logical state of the heaps of a single size class.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PoolSnapshot<A> {
+    /// The size class of the pool, where the pool of tiny heaps is reported as size class 0.
+    pub size_class: usize,
+    /// The heaps, in order of [`ArenaKey::index_in_pool`](crate::arena::ArenaKey::index_in_pool).
+    pub heaps: Vec<HeapSnapshot<A>>,
+    /// The generation of every slot that has ever held a heap, including slots that are empty
+    /// now, so that [`ArenaKey`](crate::arena::ArenaKey)s made before the snapshot go stale
+    /// exactly as they would have in the original arena.
+    pub generations: Vec<u32>,
+    /// The generation of the next allocation in the pool (see
+    /// [`Allocation::generation`](crate::arena::Allocation::generation)).
+    pub next_allocation_generation: u32,
+}
+
+/// The logical state of a single heap.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeapSnapshot<A> {
+    /// The size, in bytes, of the heap.
+    pub size: NonZeroBufferAddress,
+    pub allocator: A,
+    /// The range of every live allocation, sorted by offset.
+    pub allocations: Vec<Range<BufferAddress>>,
+    /// The generation of every live allocation, in the same order as [`Self::allocations`].
+    pub generations: Vec<u32>,
+    /// The most bytes that were ever live at once.
+    pub high_water_mark: BufferAddress,
+}
+
+/// Serialization of [`HeapUsages`] as its bits.
+mod usage_bits {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::HeapUsages;
+
+    pub(super) fn serialize<S: Serializer>(
+        usage: &HeapUsages,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(usage.bits())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HeapUsages, D::Error> {
+        let bits = u32::deserialize(deserializer)?;
+
+        HeapUsages::from_bits(bits).ok_or_else(|| D::Error::custom("unknown heap usage bits"))
+    }
+}
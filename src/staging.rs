@@ -0,0 +1,187 @@
+use wgpu::{BufferAddress, BufferUsages};
+
+use std::{cell::RefCell, ops::Range};
+
+use crate::{
+    governor,
+    queue::{InFlight, InFlightRanges, Serial, Staging},
+    AllocError,
+    Allocator,
+    NonZeroBufferAddress,
+};
+
+/// A heap made only of staging memory, with no GPU buffer of its own.
+///
+/// Where a [`Heap`](crate::Heap) pairs its staging buffer with a GPU buffer that it flushes into,
+/// a `StagingHeap` leaves the destination up to the caller: its allocations can be copied into any
+/// buffer or texture. This makes it a building block for upload scratch memory.
+///
+/// Like a `Heap`, a `StagingHeap` is mapped at creation, and must be unmapped before commands that
+/// copy from it are submitted and remapped afterwards (see [`Staging`]).
+#[derive(Debug)]
+pub struct StagingHeap<A> {
+    buffer: wgpu::Buffer,
+    /// Regions of [`Self::buffer`] that are copied from by submissions that may still be
+    /// executing.
+    in_flight: RefCell<InFlightRanges>,
+    size: NonZeroBufferAddress,
+    allocator: A,
+}
+
+impl<A: Allocator> StagingHeap<A> {
+    /// Creates a new `StagingHeap` of `size` bytes, managed by `allocator`, which must manage
+    /// exactly `size` bytes.
+    pub fn new(device: &wgpu::Device, size: NonZeroBufferAddress, allocator: A) -> Self {
+        let reservation = governor::acquire(1);
+        let buffer = crate::create_buffer(
+            device,
+            None,
+            size.get(),
+            BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE,
+            true,
+        );
+        reservation.keep();
+
+        Self {
+            buffer,
+            in_flight: RefCell::default(),
+            size,
+            allocator,
+        }
+    }
+
+    /// The staging buffer.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// The size, in bytes, of this heap.
+    pub fn size(&self) -> NonZeroBufferAddress {
+        self.size
+    }
+
+    /// The allocator that manages this heap.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    /// See [`Allocator::alloc`].
+    pub fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.allocator.alloc(size, alignment)
+    }
+
+    /// See [`Allocator::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `range` must be a valid allocation previously returned by [`Self::alloc`].
+    pub unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        self.allocator.dealloc(range)
+    }
+
+    pub fn slice(&self, range: Range<BufferAddress>) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(range)
+    }
+
+    pub fn write(&self, range: Range<BufferAddress>, contents: &[u8]) {
+        self.buffer.slice(range).get_mapped_range_mut().copy_from_slice(contents);
+    }
+
+    /// See [`Heap::try_write`](crate::Heap::try_write).
+    pub fn try_write(
+        &self,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+        last_completed: Serial,
+    ) -> Result<(), InFlight> {
+        if let Some(serial) = self.busy_until(range.clone(), last_completed) {
+            return Err(InFlight { serial });
+        }
+        self.write(range, contents);
+
+        Ok(())
+    }
+
+    /// See [`Heap::staging_busy_until`](crate::Heap::staging_busy_until).
+    pub fn busy_until(
+        &self,
+        range: Range<BufferAddress>,
+        last_completed: Serial,
+    ) -> Option<Serial> {
+        let mut in_flight = self.in_flight.borrow_mut();
+        in_flight.retire(last_completed);
+
+        in_flight.busy_until(range)
+    }
+
+    /// Records a copy of `range` of this heap into `destination`, starting at byte
+    /// `destination_offset`.
+    pub fn copy_to_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        range: Range<BufferAddress>,
+        destination: &wgpu::Buffer,
+        destination_offset: BufferAddress,
+    ) {
+        encoder.copy_buffer_to_buffer(
+            &self.buffer,
+            range.start,
+            destination,
+            destination_offset,
+            crate::get_range_size(&range),
+        );
+        self.in_flight.borrow_mut().record_copy(range);
+    }
+
+    /// Records a copy of the texel data starting at `offset` of this heap into `destination`.
+    ///
+    /// `layout` describes the data relative to `offset`; its own `offset` is ignored.
+    pub fn copy_to_texture(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        offset: BufferAddress,
+        layout: wgpu::ImageDataLayout,
+        destination: wgpu::ImageCopyTexture,
+        size: wgpu::Extent3d,
+    ) {
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout { offset, ..layout },
+            },
+            destination,
+            size,
+        );
+        // The exact extent of the copy depends on the texture format, so conservatively treat
+        // everything from `offset` onwards as being copied from.
+        self.in_flight.borrow_mut().record_copy(offset..self.size.get());
+    }
+
+    pub fn unmap(&self) {
+        self.buffer.unmap();
+    }
+}
+
+impl<A: Allocator> Staging for StagingHeap<A> {
+    fn unmap(&self) {
+        StagingHeap::unmap(self);
+    }
+
+    fn remap(&self) {
+        self.buffer.slice(..).map_async(wgpu::MapMode::Write, |_| {});
+    }
+
+    fn submitted(&self, serial: Serial) {
+        self.in_flight.borrow_mut().submit(serial);
+    }
+}
+
+impl<A> Drop for StagingHeap<A> {
+    fn drop(&mut self) {
+        governor::release(1);
+    }
+}
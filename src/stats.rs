@@ -0,0 +1,102 @@
+//! Snapshots of how much memory the heaps of a [`HeapArena`](crate::HeapArena) are using.
+//!
+//! Unlike [`Metrics`](crate::Metrics), which count operations over time, [`Stats`] describe the
+//! state of heaps at the moment [`HeapArena::stats`](crate::HeapArena::stats) is called. They are
+//! meant to be shown in debug HUDs and to detect fragmentation.
+
+use wgpu::BufferAddress;
+
+use std::collections::BTreeMap;
+
+use crate::{arena::ArenaKey, NonZeroBufferAddress};
+
+/// The memory usage of a single heap and its allocator, or of several summed together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The total size, in bytes, of live allocations.
+    ///
+    /// This is the sum of the requested sizes, so it doesn't include alignment padding or any
+    /// rounding done by the allocator.
+    pub bytes_allocated: BufferAddress,
+    /// The number of bytes not taken up by live allocations.
+    pub bytes_free: BufferAddress,
+    /// The size, in bytes, of the largest contiguous free region, if the allocator keeps track of
+    /// it (see [`Allocator::largest_free_block`](crate::Allocator::largest_free_block)).
+    pub largest_free_block: Option<BufferAddress>,
+    /// The number of live allocations.
+    pub allocation_count: usize,
+    /// The most bytes that were ever allocated at once.
+    ///
+    /// When stats are summed, this is the sum of the individual high-water marks, which is an
+    /// upper bound on the true high-water mark of the whole.
+    pub high_water_mark: BufferAddress,
+}
+
+impl Stats {
+    /// The fraction of free memory that is not part of the largest free block, from 0 (all free
+    /// memory is in one place) to nearly 1 (free memory is scattered in tiny pieces).
+    ///
+    /// This is `None` if the largest free block is unknown, and 0 if there is no free memory.
+    pub fn fragmentation(&self) -> Option<f64> {
+        let largest_free_block = self.largest_free_block?;
+        if self.bytes_free == 0 {
+            return Some(0.0);
+        }
+
+        Some(1.0 - (largest_free_block as f64 / self.bytes_free as f64))
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self {
+            bytes_allocated: self.bytes_allocated + other.bytes_allocated,
+            bytes_free: self.bytes_free + other.bytes_free,
+            largest_free_block: match (self.largest_free_block, other.largest_free_block) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            },
+            allocation_count: self.allocation_count + other.allocation_count,
+            high_water_mark: self.high_water_mark + other.high_water_mark,
+        }
+    }
+}
+
+/// The [`Stats`] of a single heap in a [`HeapArena`](crate::HeapArena).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The key by which the heap can be looked up in the arena.
+    ///
+    /// Heaps in the pool of tiny heaps, which is shared by size classes 0 to 11, are given size
+    /// class 0.
+    pub arena_key: ArenaKey,
+    /// The size, in bytes, of the heap.
+    pub size: NonZeroBufferAddress,
+    pub stats: Stats,
+}
+
+/// A snapshot of the [`Stats`] of every heap in a [`HeapArena`](crate::HeapArena).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Every heap, ordered by size class and then by position in its pool.
+    pub heaps: Vec<HeapStats>,
+    /// The live allocations made with each tag by
+    /// [`HeapArena::alloc_desc`](crate::HeapArena::alloc_desc), for checking content budgets.
+    ///
+    /// Untagged allocations are not counted here.
+    pub tags: BTreeMap<&'static str, TagStats>,
+}
+
+impl ArenaStats {
+    /// Returns the sum of the stats of every heap.
+    pub fn total(&self) -> Stats {
+        self.heaps.iter().fold(Stats::default(), |total, heap| total.add(&heap.stats))
+    }
+}
+
+/// The live allocations of an [`ArenaStats`] made with the same tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    /// The total size, in bytes, of the allocations, including any guard bands around them.
+    pub bytes_allocated: BufferAddress,
+    /// The number of allocations.
+    pub allocation_count: usize,
+}
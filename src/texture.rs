@@ -0,0 +1,324 @@
+//! Sub-allocation of 2D textures, such as sprite and glyph atlases.
+//!
+//! A [`TextureHeap`] owns a single 2D texture, possibly with several array layers, and hands out
+//! rectangular [`TextureRegion`]s of it through a [`TextureAllocator`]. This is the texture
+//! counterpart of a [`Heap`](crate::Heap): rather than one texture per sprite or glyph, many share
+//! the same texture and can be drawn with the same bind group.
+//!
+//! Unlike buffers, textures are written directly with [`wgpu::Queue::write_texture`] or copied
+//! into from a buffer, such as a [`StagingHeap`](crate::StagingHeap), so a `TextureHeap` has no
+//! staging memory of its own.
+
+use crate::version;
+
+/// A rectangle within one layer of a [`TextureHeap`], in texels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureRegion {
+    /// The array layer that contains this region.
+    pub layer: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TextureRegion {
+    /// The texel at the top-left corner of this region, with the layer as its `z` coordinate.
+    pub fn origin(&self) -> wgpu::Origin3d {
+        wgpu::Origin3d { x: self.x, y: self.y, z: self.layer }
+    }
+
+    /// The size of this region, one layer deep.
+    pub fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 }
+    }
+
+    /// Whether this region shares any texel with `other`.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.layer == other.layer
+            && self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+}
+
+/// An allocator of rectangular regions of a 2D texture.
+///
+/// This is the texture counterpart of [`Allocator`](crate::Allocator).
+pub trait TextureAllocator {
+    /// Creates a new allocator that manages a texture of the given size, where
+    /// [`wgpu::Extent3d::depth_or_array_layers`] is the number of array layers.
+    fn new(size: wgpu::Extent3d) -> Self where Self: Sized;
+
+    /// Allocates a region of `width` by `height` texels, or returns `None` if there is no room.
+    fn alloc(&mut self, width: u32, height: u32) -> Option<TextureRegion>;
+
+    /// # Safety
+    ///
+    /// `region` must be a valid allocation previously returned by this allocator.
+    #[allow(clippy::result_unit_err)]
+    unsafe fn dealloc(&mut self, region: TextureRegion) -> Result<(), ()>;
+}
+
+/// A [`TextureAllocator`] that packs regions into horizontal shelves.
+///
+/// Each layer is divided from top to bottom into shelves, each as tall as the region that opened
+/// it, and regions are placed left to right along the shortest shelf they fit on. This packs
+/// regions of similar heights, such as the glyphs of a font, very tightly. A shelf is emptied once
+/// all of its regions are freed, and the bottommost shelves of a layer are removed once empty so
+/// that their space can be reused for regions of any height.
+#[derive(Clone, Debug)]
+pub struct Shelf {
+    width: u32,
+    height: u32,
+    /// The shelves of each layer, from top to bottom.
+    layers: Vec<Vec<ShelfRow>>,
+}
+
+#[derive(Clone, Debug)]
+struct ShelfRow {
+    y: u32,
+    height: u32,
+    /// The `x` coordinate at which the next region on this shelf is placed.
+    cursor: u32,
+    /// The number of live regions on this shelf.
+    live: usize,
+}
+
+impl TextureAllocator for Shelf {
+    fn new(size: wgpu::Extent3d) -> Self {
+        Self {
+            width: size.width,
+            height: size.height,
+            layers: vec![Vec::new(); size.depth_or_array_layers as usize],
+        }
+    }
+
+    fn alloc(&mut self, width: u32, height: u32) -> Option<TextureRegion> {
+        if width == 0 || height == 0 || width > self.width || height > self.height {
+            return None;
+        }
+
+        // Prefer the shortest existing shelf that the region fits on, on any layer.
+        let existing = self
+            .layers
+            .iter()
+            .enumerate()
+            .flat_map(|(layer, rows)| rows.iter().enumerate().map(move |(i, row)| (layer, i, row)))
+            .filter(|(_, _, row)| row.height >= height && self.width - row.cursor >= width)
+            .min_by_key(|(_, _, row)| row.height)
+            .map(|(layer, index, _)| (layer, index));
+
+        let (layer, index) = match existing {
+            Some(found) => found,
+            None => {
+                // Otherwise, open a new shelf below the last one on the first layer with room.
+                let layer = self.layers.iter().position(|rows| {
+                    let top = rows.last().map_or(0, |row| row.y + row.height);
+                    self.height - top >= height
+                })?;
+                let rows = &mut self.layers[layer];
+                let y = rows.last().map_or(0, |row| row.y + row.height);
+                rows.push(ShelfRow { y, height, cursor: 0, live: 0 });
+
+                (layer, rows.len() - 1)
+            }
+        };
+
+        let row = &mut self.layers[layer][index];
+        let region = TextureRegion { layer: layer as u32, x: row.cursor, y: row.y, width, height };
+        row.cursor += width;
+        row.live += 1;
+
+        Some(region)
+    }
+
+    unsafe fn dealloc(&mut self, region: TextureRegion) -> Result<(), ()> {
+        let rows = self.layers.get_mut(region.layer as usize).ok_or(())?;
+        let row = rows
+            .iter_mut()
+            .find(|row| row.y == region.y)
+            .filter(|row| region.height <= row.height && region.x + region.width <= row.cursor)
+            .ok_or(())?;
+        if row.live == 0 {
+            return Err(());
+        }
+
+        row.live -= 1;
+        if row.live == 0 {
+            row.cursor = 0;
+        }
+        while rows.last().is_some_and(|row| row.live == 0) {
+            rows.pop();
+        }
+
+        Ok(())
+    }
+}
+
+/// A 2D texture whose regions are sub-allocated by a [`TextureAllocator`].
+#[derive(Debug)]
+pub struct TextureHeap<A> {
+    texture: wgpu::Texture,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    allocator: A,
+}
+
+impl<A: TextureAllocator> TextureHeap<A> {
+    /// Creates a new `TextureHeap` with a texture of the given size, where
+    /// [`wgpu::Extent3d::depth_or_array_layers`] is the number of array layers.
+    ///
+    /// [`wgpu::TextureUsages::COPY_DST`] is always added to `usage`, as regions could not be
+    /// written otherwise.
+    pub fn new(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> Self {
+        let texture = version::create_texture(
+            device,
+            size,
+            format,
+            usage | wgpu::TextureUsages::COPY_DST,
+        );
+
+        Self { texture, size, format, allocator: A::new(size) }
+    }
+
+    /// The texture.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// The size of the texture, where [`wgpu::Extent3d::depth_or_array_layers`] is the number of
+    /// array layers.
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.size
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// The allocator that manages this heap.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    /// See [`TextureAllocator::alloc`].
+    pub fn alloc(&mut self, width: u32, height: u32) -> Option<TextureRegion> {
+        self.allocator.alloc(width, height)
+    }
+
+    /// See [`TextureAllocator::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `region` must be a valid allocation previously returned by [`Self::alloc`].
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn dealloc(&mut self, region: TextureRegion) -> Result<(), ()> {
+        self.allocator.dealloc(region)
+    }
+}
+
+impl<A> TextureHeap<A> {
+    /// Creates a view of the whole texture.
+    ///
+    /// The view is a [`wgpu::TextureViewDimension::D2Array`] if the texture has more than one
+    /// layer, and a [`wgpu::TextureViewDimension::D2`] otherwise.
+    pub fn create_view(&self) -> wgpu::TextureView {
+        let dimension = match self.size.depth_or_array_layers {
+            1 => wgpu::TextureViewDimension::D2,
+            _ => wgpu::TextureViewDimension::D2Array,
+        };
+
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
+        })
+    }
+
+    /// Creates a 2D view of a single layer of the texture.
+    pub fn create_layer_view(&self, layer: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: version::optional_u32(1),
+            ..Default::default()
+        })
+    }
+
+    /// Describes `region` as the source or destination of a copy.
+    ///
+    /// This is to be used together with [`TextureRegion::extent`] as the copy size.
+    pub fn image_copy(&self, region: &TextureRegion) -> wgpu::ImageCopyTexture<'_> {
+        wgpu::ImageCopyTexture {
+            texture: &self.texture,
+            mip_level: 0,
+            origin: region.origin(),
+            aspect: wgpu::TextureAspect::All,
+        }
+    }
+
+    /// The texture coordinates of the corners of `region`, as `[min_u, min_v, max_u, max_v]`.
+    pub fn uv_rect(&self, region: &TextureRegion) -> [f32; 4] {
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+
+        [
+            region.x as f32 / width,
+            region.y as f32 / height,
+            (region.x + region.width) as f32 / width,
+            (region.y + region.height) as f32 / height,
+        ]
+    }
+
+    /// Writes `data` into `region` through `queue`, where each row of texels in `data` is
+    /// `bytes_per_row` bytes apart.
+    pub fn write(
+        &self,
+        queue: &wgpu::Queue,
+        region: &TextureRegion,
+        data: &[u8],
+        bytes_per_row: u32,
+    ) {
+        queue.write_texture(
+            self.image_copy(region),
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: version::optional_u32(bytes_per_row),
+                rows_per_image: None,
+            },
+            region.extent(),
+        );
+    }
+
+    /// Records a copy into `region` from `buffer`, starting at `offset`, where each row of texels
+    /// is `bytes_per_row` bytes apart.
+    ///
+    /// `bytes_per_row` must be a multiple of [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+    pub fn copy_from_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        bytes_per_row: u32,
+        region: &TextureRegion,
+    ) {
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset,
+                    bytes_per_row: version::optional_u32(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.image_copy(region),
+            region.extent(),
+        );
+    }
+}
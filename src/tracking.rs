@@ -0,0 +1,141 @@
+//! Bookkeeping of every live allocation, for catching misuse and hunting leaks.
+//!
+//! This module only exists with the `track-allocs` feature, which is meant for debug builds. With
+//! it, a [`HeapArena`] remembers each allocation it makes along with where it was made, and
+//! [`HeapArena::dealloc`] panics instead of freeing a range that isn't a live allocation&mdash;such
+//! as one that was freed already or one that covers only part of an allocation&mdash;which would
+//! otherwise silently corrupt the state of the allocator.
+//!
+//! Whatever is still live when it shouldn't be, such as at shutdown, is reported by
+//! [`HeapArena::dump_live_allocations`].
+//!
+//! Allocations freed directly through an [`Allocator`](crate::Allocator) obtained by indexing
+//! the arena bypass this bookkeeping, and so remain listed as live.
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::dealloc`]: crate::HeapArena::dealloc
+//! [`HeapArena::dump_live_allocations`]: crate::HeapArena::dump_live_allocations
+
+use wgpu::BufferAddress;
+
+use std::{backtrace::Backtrace, collections::BTreeMap, fmt, ops::Range};
+
+use crate::arena::{Allocation, ArenaKey, Relocation};
+
+/// An allocation that has not been freed yet.
+#[derive(Debug)]
+pub struct LiveAllocation {
+    pub allocation: Allocation,
+    /// The tag given with [`HeapArena::tag_allocation`](crate::HeapArena::tag_allocation), if
+    /// any.
+    pub tag: Option<String>,
+    /// Where the allocation was made.
+    ///
+    /// This is only captured if enabled by the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`
+    /// environment variables; see [`Backtrace::capture`].
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for LiveAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Allocation { arena_key, range_in_heap, .. } = &self.allocation;
+        write!(
+            f,
+            "{:?} in heap {} of size class {}",
+            range_in_heap, arena_key.index_in_pool(), arena_key.size_class(),
+        )?;
+        if let Some(tag) = self.tag.as_ref() {
+            write!(f, " ({tag})")?;
+        }
+
+        write!(f, ", allocated at:\n{}", self.backtrace)
+    }
+}
+
+/// The live allocations of an arena, keyed by heap (see [`ArenaKey::heap_key`]) and then by offset
+/// within the heap.
+#[derive(Debug, Default)]
+pub(crate) struct AllocTracker {
+    live: BTreeMap<(ArenaKey, BufferAddress), LiveAllocation>,
+}
+
+impl AllocTracker {
+    pub(crate) fn insert(&mut self, allocation: &Allocation) {
+        let key = (allocation.arena_key.heap_key(), allocation.range_in_heap.start);
+        self.live.insert(key, LiveAllocation {
+            allocation: allocation.clone(),
+            tag: None,
+            backtrace: Backtrace::capture(),
+        });
+    }
+
+    /// Panics unless `range_in_heap` of the heap at `arena_key` is exactly a live allocation.
+    pub(crate) fn check_dealloc(&self, arena_key: ArenaKey, range_in_heap: &Range<BufferAddress>) {
+        let arena_key = arena_key.heap_key();
+        if let Some(live) = self.live.get(&(arena_key, range_in_heap.start)) {
+            if live.allocation.range_in_heap == *range_in_heap {
+                return;
+            }
+        }
+
+        let overlapping = self
+            .live
+            .range((arena_key, 0)..(arena_key, range_in_heap.end))
+            .map(|(_, live)| live)
+            .find(|live| live.allocation.range_in_heap.end > range_in_heap.start);
+        match overlapping {
+            Some(live) => panic!(
+                "out-of-bounds dealloc of {:?}, which does not match the live allocation {}",
+                range_in_heap, live,
+            ),
+            None => panic!(
+                "double free of {:?} in heap {} of size class {}, which is not a live allocation",
+                range_in_heap, arena_key.index_in_pool(), arena_key.size_class(),
+            ),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, arena_key: ArenaKey, range_in_heap: &Range<BufferAddress>) {
+        self.live.remove(&(arena_key.heap_key(), range_in_heap.start));
+    }
+
+    pub(crate) fn tag(&mut self, allocation: &Allocation, tag: String) {
+        let key = (allocation.arena_key.heap_key(), allocation.range_in_heap.start);
+        if let Some(live) = self.live.get_mut(&key) {
+            live.tag = Some(tag);
+        }
+    }
+
+    /// Forgets every allocation in a heap whose key is not kept by `keep`.
+    pub(crate) fn retain_heaps(&mut self, keep: impl Fn(ArenaKey) -> bool) {
+        self.live.retain(|(key, _), _| keep(*key));
+    }
+
+    /// Moves each allocation in `relocations` from its old key and range to its new ones.
+    pub(crate) fn relocate(&mut self, relocations: &[Relocation]) {
+        // Every allocation is taken out before any is put back, as the new key and range of one
+        // allocation may be the old ones of another.
+        let moved: Vec<_> = relocations
+            .iter()
+            .filter_map(|Relocation { from, to }| {
+                let key = (from.arena_key.heap_key(), from.range_in_heap.start);
+                let mut live = self.live.remove(&key)?;
+                live.allocation = to.clone();
+
+                Some(live)
+            })
+            .collect();
+        for live in moved {
+            let key = (live.allocation.arena_key.heap_key(), live.allocation.range_in_heap.start);
+            self.live.insert(key, live);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.live.clear();
+    }
+
+    pub(crate) fn live(&self) -> impl Iterator<Item = &LiveAllocation> {
+        self.live.values()
+    }
+}
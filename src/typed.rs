@@ -0,0 +1,210 @@
+//! Heaps whose usage is known at compile time.
+//!
+//! A [`TypedHeap`] is a thin wrapper around a [`Heap`] whose usage is given by a marker type
+//! rather than by [`HeapUsages`]. Its [`slice`](TypedHeap::slice) and
+//! [`binding`](TypedHeap::binding) methods only exist for usages that allow them, so mistakes such
+//! as binding a vertex heap as a storage buffer are caught by the compiler instead of by wgpu
+//! validation. [`TypedHeap::as_dynamic`] gives access to the full dynamic API where that is too
+//! restrictive.
+
+use wgpu::BufferAddress;
+
+use std::{borrow::Cow, marker::PhantomData, ops::Range};
+
+use crate::{layout::WgslType, mapping::WriteView, Heap, HeapUsages, NonZeroBufferAddress};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A marker type for the usage of a [`TypedHeap`].
+pub trait Usage: sealed::Sealed {
+    /// The usage of the underlying [`Heap`].
+    const USAGES: HeapUsages;
+}
+
+/// A [`Usage`] whose heaps are bound as a whole buffer slice, such as for vertex or index data.
+pub trait SliceUsage: Usage {}
+
+/// A [`Usage`] whose heaps are bound through bind groups.
+pub trait BindingUsage: Usage {}
+
+macro_rules! usages {
+    ($($(#[$meta:meta])* $name:ident = $usages:ident: $kind:ident;)*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+            pub enum $name {}
+
+            impl sealed::Sealed for $name {}
+
+            impl Usage for $name {
+                const USAGES: HeapUsages = HeapUsages::$usages;
+            }
+
+            impl $kind for $name {}
+        )*
+    };
+}
+
+usages! {
+    /// The usage of heaps that hold index buffers.
+    Index = INDEX: SliceUsage;
+    /// The usage of heaps that hold vertex buffers.
+    Vertex = VERTEX: SliceUsage;
+    /// The usage of heaps that hold indirect draw and dispatch arguments.
+    Indirect = INDIRECT: SliceUsage;
+    /// The usage of heaps that hold uniform buffers.
+    Uniform = UNIFORM: BindingUsage;
+    /// The usage of heaps that hold storage buffers.
+    Storage = STORAGE: BindingUsage;
+}
+
+/// A [`Heap`] with the usage `U`.
+#[derive(Debug)]
+pub struct TypedHeap<U> {
+    heap: Heap,
+    usage: PhantomData<U>,
+}
+
+impl<U: Usage> TypedHeap<U> {
+    /// See [`Heap::new`].
+    pub fn new(device: &wgpu::Device, size: NonZeroBufferAddress) -> Self {
+        Self { heap: Heap::new(device, size, U::USAGES), usage: PhantomData }
+    }
+
+    /// See [`Heap::with_readback`].
+    pub fn with_readback(device: &wgpu::Device, size: NonZeroBufferAddress) -> Self {
+        Self { heap: Heap::with_readback(device, size, U::USAGES), usage: PhantomData }
+    }
+
+    /// The underlying heap, with its full dynamic API.
+    pub fn as_dynamic(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Unwraps the underlying heap.
+    pub fn into_dynamic(self) -> Heap {
+        self.heap
+    }
+
+    pub fn size(&self) -> NonZeroBufferAddress {
+        self.heap.size()
+    }
+
+    pub fn write(&self, range: Range<BufferAddress>, contents: &[u8]) {
+        self.heap.write(range, contents);
+    }
+
+    pub fn write_slice<T: bytemuck::Pod>(&self, range: Range<BufferAddress>, contents: &[T]) {
+        self.heap.write_slice(range, contents);
+    }
+
+    pub fn write_struct<T: WgslType>(&self, range: Range<BufferAddress>, value: &T) {
+        self.heap.write_struct(range, value);
+    }
+
+    pub fn get_write_view(&self, range: Range<BufferAddress>) -> WriteView<'_> {
+        self.heap.get_write_view(range)
+    }
+
+    pub fn get_write_view_as<T: bytemuck::Pod>(
+        &self,
+        range: Range<BufferAddress>,
+    ) -> WriteView<'_, T> {
+        self.heap.get_write_view_as(range)
+    }
+
+    pub fn write_and_flush(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        range: Range<BufferAddress>,
+        contents: &[u8],
+    ) {
+        self.heap.write_and_flush(encoder, range, contents);
+    }
+
+    pub fn flush(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.heap.flush(encoder);
+    }
+
+    pub fn flush_range(&self, encoder: &mut wgpu::CommandEncoder, range: Range<BufferAddress>) {
+        self.heap.flush_range(encoder, range);
+    }
+
+    pub fn unmap(&self) {
+        self.heap.unmap();
+    }
+}
+
+impl<U: SliceUsage> TypedHeap<U> {
+    pub fn slice(&self, range: Range<BufferAddress>) -> wgpu::BufferSlice<'_> {
+        self.heap.slice(range)
+    }
+}
+
+impl<U: BindingUsage> TypedHeap<U> {
+    pub fn binding(&self, range: Range<BufferAddress>) -> wgpu::BufferBinding<'_> {
+        self.heap.binding(range)
+    }
+}
+
+/// The layout of an array of values of some type in a heap of a particular usage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ArrayLayout {
+    /// The distance, in bytes, between the starts of consecutive elements.
+    pub stride: BufferAddress,
+    /// The total size, in bytes, of the array.
+    pub size: NonZeroBufferAddress,
+    /// The alignment, in bytes, of the array.
+    pub alignment: NonZeroBufferAddress,
+}
+
+impl ArrayLayout {
+    /// The alignment, in bytes, to which WGSL rounds up arrays and structures in the uniform
+    /// address space.
+    pub const UNIFORM_ALIGNMENT: BufferAddress = 16;
+
+    /// Computes the layout of an array of `count` values of type `T` in a heap with the usage
+    /// `usage`.
+    ///
+    /// Elements are laid out as in a Rust slice, except in heaps with [`HeapUsages::UNIFORM`]
+    /// usage, where both the stride and the alignment are rounded up to
+    /// [`Self::UNIFORM_ALIGNMENT`] as WGSL requires (much like std140 in GLSL).
+    ///
+    /// # Panics
+    ///
+    /// This panics if `T` is zero-sized or the size of the array overflows.
+    pub fn of<T>(usage: HeapUsages, count: NonZeroBufferAddress) -> Self {
+        let mut stride = std::mem::size_of::<T>() as BufferAddress;
+        let mut alignment = std::mem::align_of::<T>() as BufferAddress;
+        assert!(stride != 0, "zero-sized types cannot be allocated");
+        if usage.contains(HeapUsages::UNIFORM) {
+            stride = stride.next_multiple_of(Self::UNIFORM_ALIGNMENT);
+            alignment = alignment.max(Self::UNIFORM_ALIGNMENT);
+        }
+
+        let size = stride.checked_mul(count.get()).expect("array size overflows");
+
+        Self {
+            stride,
+            size: NonZeroBufferAddress::new(size).unwrap(),
+            alignment: NonZeroBufferAddress::new(alignment).unwrap(),
+        }
+    }
+
+    /// The bytes of `contents` laid out with [`Self::stride`], with any padding zeroed.
+    pub(crate) fn bytes_of<'a, T: bytemuck::Pod>(&self, contents: &'a [T]) -> Cow<'a, [u8]> {
+        let element_size = std::mem::size_of::<T>();
+        if self.stride == element_size as BufferAddress {
+            return Cow::Borrowed(bytemuck::cast_slice(contents));
+        }
+
+        let mut bytes = vec![0; contents.len() * self.stride as usize];
+        for (element, chunk) in contents.iter().zip(bytes.chunks_mut(self.stride as usize)) {
+            chunk[..element_size].copy_from_slice(bytemuck::bytes_of(element));
+        }
+
+        Cow::Owned(bytes)
+    }
+}
@@ -0,0 +1,135 @@
+//! Policy for choosing between the different paths by which data can be uploaded to a heap.
+//!
+//! There are three such paths, each suited to a different range of upload sizes:
+//!
+//! - [`UploadPath::QueueWrite`] hands the data to [`wgpu::Queue::write_buffer`], which avoids
+//!   recording a copy command but has wgpu allocate its own staging memory behind the scenes. This
+//!   is cheapest for small uploads.
+//! - [`UploadPath::Staging`] writes the data into the heap's own staging buffer and records a copy
+//!   into the GPU buffer, exactly like [`Heap::write_and_flush`](crate::Heap::write_and_flush).
+//! - [`UploadPath::DedicatedStaging`] creates a temporary staging buffer just for the upload,
+//!   which keeps very large uploads from needing the heap's staging buffer to be mapped.
+//!
+//! An [`UploadPolicy`] decides which path an upload takes, and can be changed at runtime with
+//! [`HeapArena::set_upload_policy`](crate::HeapArena::set_upload_policy).
+
+use wgpu::BufferAddress;
+
+use std::fmt;
+
+use crate::NonZeroBufferAddress;
+
+/// The thresholds that govern which [`UploadPath`] an upload takes.
+///
+/// With the `serde` feature enabled, this can be loaded from a configuration file so that it can
+/// be tuned per platform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UploadPolicy {
+    /// The size, in bytes, of the largest upload that may take [`UploadPath::QueueWrite`].
+    pub write_buffer_max: BufferAddress,
+    /// The size, in bytes, of the smallest upload that must take [`UploadPath::Staging`] (or
+    /// [`UploadPath::DedicatedStaging`]) rather than [`UploadPath::QueueWrite`].
+    pub staging_min: BufferAddress,
+    /// The size, in bytes, of the smallest upload that takes [`UploadPath::DedicatedStaging`].
+    pub dedicated_staging_min: BufferAddress,
+    /// The maximum number of bytes that may be uploaded per frame, or `None` for no limit.
+    ///
+    /// Frames begin with [`HeapArena::begin_frame`](crate::HeapArena::begin_frame).
+    pub per_frame_budget: Option<BufferAddress>,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            write_buffer_max: 64 * 1024,
+            staging_min: 1024,
+            dedicated_staging_min: 16 * 1024 * 1024,
+            per_frame_budget: None,
+        }
+    }
+}
+
+impl UploadPolicy {
+    /// Chooses the path for an upload of `size` bytes.
+    ///
+    /// Uploads of at least [`Self::dedicated_staging_min`] bytes take
+    /// [`UploadPath::DedicatedStaging`]. Of the remaining uploads, those that are both smaller than
+    /// [`Self::staging_min`] and no larger than [`Self::write_buffer_max`] take
+    /// [`UploadPath::QueueWrite`], and all others take [`UploadPath::Staging`].
+    pub fn choose(&self, size: BufferAddress) -> UploadPath {
+        if size >= self.dedicated_staging_min {
+            UploadPath::DedicatedStaging
+        } else if size < self.staging_min && size <= self.write_buffer_max {
+            UploadPath::QueueWrite
+        } else {
+            UploadPath::Staging
+        }
+    }
+}
+
+/// A path by which data can be uploaded to a heap.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UploadPath {
+    QueueWrite,
+    Staging,
+    DedicatedStaging,
+}
+
+/// Whether, and how much, staging memory a [`Heap`](crate::Heap) keeps of its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UploadStrategy {
+    /// The heap has a persistently-mapped staging buffer as large as its GPU buffer, which is
+    /// written on the CPU and then flushed into the GPU buffer.
+    #[default]
+    Staging,
+    /// The heap has no staging buffer, and every upload is handed to
+    /// [`wgpu::Queue::write_buffer`].
+    ///
+    /// This halves the memory used by the heap, which matters on unified-memory platforms and on
+    /// WebGPU, where the staging buffer would otherwise be a second copy of the same data. The
+    /// staging API of such a heap, such as [`Heap::write`](crate::Heap::write), panics; writes go
+    /// through [`Heap::write_via`](crate::Heap::write_via) or
+    /// [`Heap::upload`](crate::Heap::upload) instead.
+    QueueWrite,
+    /// The heap has a persistently-mapped staging buffer of `window_size` bytes (rounded up to
+    /// [`wgpu::MAP_ALIGNMENT`]), through which writes are streamed into the GPU buffer.
+    ///
+    /// This suits heaps of hundreds of megabytes, whose staging buffer would otherwise take as
+    /// much host memory. Writes larger than the free space of the window are staged as it frees
+    /// up, which is when the heap is written or unmapped after the window has been remapped, and
+    /// so reach the GPU buffer over several flushes. Until then, the unstaged data is kept on the
+    /// CPU and its range counts as unflushed.
+    ///
+    /// Writes to such a heap must begin and end on multiples of [`wgpu::COPY_BUFFER_ALIGNMENT`],
+    /// and can be made while the window is not mapped. As the window holds only recent writes,
+    /// there are no views of staging memory, such as
+    /// [`Heap::get_write_view`](crate::Heap::get_write_view).
+    Windowed { window_size: NonZeroBufferAddress },
+}
+
+/// The error returned when an upload would exceed [`UploadPolicy::per_frame_budget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    /// The size, in bytes, of the rejected upload.
+    pub requested: BufferAddress,
+    /// The number of bytes that may still be uploaded this frame.
+    pub remaining: BufferAddress,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "upload of {} bytes exceeds the per-frame budget; only {} bytes remain",
+            self.requested,
+            self.remaining,
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
@@ -0,0 +1,92 @@
+//! Shims over the parts of the wgpu API that differ between the supported versions.
+//!
+//! Everything else in the crate names wgpu types directly; only the handful of fields whose types
+//! changed between releases go through here.
+
+use wgpu::BufferAddress;
+
+/// The value of an optional row pitch, layer count, or similar `u32` field.
+///
+/// wgpu 0.13 wraps these in [`NonZeroU32`](std::num::NonZeroU32); later versions take a plain
+/// `u32`. Zero maps to `None` in either case.
+#[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+pub(crate) fn optional_u32(value: u32) -> Option<std::num::NonZeroU32> {
+    std::num::NonZeroU32::new(value)
+}
+
+#[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+pub(crate) fn optional_u32(value: u32) -> Option<u32> {
+    (value != 0).then_some(value)
+}
+
+/// The size argument of [`wgpu::CommandEncoder::clear_buffer`], where a zero `size` means "to the
+/// end of the buffer".
+#[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+pub(crate) fn clear_size(size: BufferAddress) -> Option<crate::NonZeroBufferAddress> {
+    crate::NonZeroBufferAddress::new(size)
+}
+
+#[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+pub(crate) fn clear_size(size: BufferAddress) -> Option<BufferAddress> {
+    (size != 0).then_some(size)
+}
+
+/// Creates a texture from every field of [`wgpu::TextureDescriptor`] that all supported versions
+/// share.
+///
+/// Later versions add `view_formats`, which is left empty.
+pub(crate) fn create_texture(
+    device: &wgpu::Device,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+        view_formats: &[],
+    })
+}
+
+/// Creates an instance on every backend.
+#[cfg(feature = "test-harness")]
+pub(crate) fn create_instance() -> wgpu::Instance {
+    #[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+    return wgpu::Instance::new(wgpu::Backends::all());
+    #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+    return wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+}
+
+/// Describes a device with no optional features and the downlevel default limits.
+#[cfg(feature = "test-harness")]
+pub(crate) fn device_descriptor(label: &str) -> wgpu::DeviceDescriptor<'_> {
+    wgpu::DeviceDescriptor {
+        label: Some(label),
+        #[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+        features: wgpu::Features::empty(),
+        #[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+        limits: wgpu::Limits::downlevel_defaults(),
+        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+        required_features: wgpu::Features::empty(),
+        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+        required_limits: wgpu::Limits::downlevel_defaults(),
+        #[cfg(feature = "wgpu-22")]
+        memory_hints: wgpu::MemoryHints::default(),
+    }
+}
+
+/// Whether a [`wgpu::BufferUsages::MAP_READ`] buffer may also have
+/// [`wgpu::BufferUsages::COPY_SRC`] without [`wgpu::Features::MAPPABLE_PRIMARY_BUFFERS`].
+///
+/// wgpu 0.13 doesn't check this; later versions raise a validation error.
+pub(crate) const MAP_READ_ALLOWS_COPY_SRC: bool =
+    cfg!(not(any(feature = "wgpu-0_19", feature = "wgpu-22")));
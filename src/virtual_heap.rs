@@ -0,0 +1,82 @@
+use wgpu::BufferAddress;
+
+use std::ops::Range;
+
+use crate::{AllocError, Allocator, NonZeroBufferAddress};
+
+/// An [`Allocator`] running over an address space that no buffer of this crate backs.
+///
+/// A `VirtualHeap` does only the address-space math of a [`Heap`](crate::Heap): it decides where
+/// allocations are placed within `size` units, and nothing else. What those units are is up to the
+/// caller&mdash;bytes of a buffer created by another crate, rows of a texture atlas, or slots of
+/// some table on the CPU. [`RawHeap`](crate::RawHeap) pairs a `VirtualHeap` with a
+/// [`wgpu::Buffer`].
+#[derive(Clone, Debug)]
+pub struct VirtualHeap<A> {
+    size: NonZeroBufferAddress,
+    allocator: A,
+}
+
+impl<A: Allocator> VirtualHeap<A> {
+    /// Creates a `VirtualHeap` of `size` units, managed by a new `A`.
+    pub fn new(size: NonZeroBufferAddress) -> Self {
+        Self { size, allocator: A::with_capacity(size) }
+    }
+
+    /// Creates a `VirtualHeap` of `size` units, managed by `allocator`, which must manage exactly
+    /// `size` units.
+    pub fn with_allocator(size: NonZeroBufferAddress, allocator: A) -> Self {
+        Self { size, allocator }
+    }
+
+    /// The number of units managed by this heap.
+    pub fn size(&self) -> NonZeroBufferAddress {
+        self.size
+    }
+
+    /// The allocator that manages this heap.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    /// Unwraps the allocator that manages this heap.
+    pub fn into_allocator(self) -> A {
+        self.allocator
+    }
+
+    /// See [`Allocator::alloc`].
+    pub fn alloc(
+        &mut self,
+        size: NonZeroBufferAddress,
+        alignment: NonZeroBufferAddress,
+    ) -> Result<Range<BufferAddress>, AllocError> {
+        self.allocator.alloc(size, alignment)
+    }
+
+    /// See [`Allocator::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `range` must be a valid allocation previously returned by [`Self::alloc`].
+    pub unsafe fn dealloc(&mut self, range: Range<BufferAddress>) -> Result<(), AllocError> {
+        self.allocator.dealloc(range)
+    }
+
+    /// Extends this heap to `new_size` units, returning whether its allocator could make use of
+    /// them (see [`Allocator::grow`]).
+    ///
+    /// The size is left as is if not.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `new_size` is smaller than the current size.
+    pub fn grow(&mut self, new_size: NonZeroBufferAddress) -> bool {
+        assert!(new_size >= self.size, "virtual heap cannot shrink");
+        let grew = self.allocator.grow(new_size);
+        if grew {
+            self.size = new_size;
+        }
+
+        grew
+    }
+}
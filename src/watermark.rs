@@ -0,0 +1,141 @@
+//! Callbacks for when the memory allocated from a [`HeapArena`] crosses a threshold.
+//!
+//! Streaming systems, such as those of textures and meshes, can register a watermark with
+//! [`HeapArena::on_usage_above`] to start evicting before allocations begin to fail, and one with
+//! [`HeapArena::on_usage_below`] to stop again once enough has been evicted.
+//!
+//! Usage is the fraction of the budget of an arena (see [`HeapArena::set_budget`]) that is
+//! allocated, or of the memory reserved by its heaps if it has no budget. Allocations are counted
+//! with their alignment padding and guard bands, as in [`HeapArena::stats`].
+//!
+//! [`HeapArena`]: crate::HeapArena
+//! [`HeapArena::on_usage_above`]: crate::HeapArena::on_usage_above
+//! [`HeapArena::on_usage_below`]: crate::HeapArena::on_usage_below
+//! [`HeapArena::set_budget`]: crate::HeapArena::set_budget
+//! [`HeapArena::stats`]: crate::HeapArena::stats
+
+use wgpu::BufferAddress;
+
+use std::fmt;
+
+/// Identifies a watermark, so that it can be removed with
+/// [`HeapArena::remove_watermark`](crate::HeapArena::remove_watermark).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WatermarkId(u64);
+
+/// The usage of an arena as it crossed the threshold of a watermark.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatermarkEvent {
+    /// The threshold of the watermark that was crossed.
+    pub threshold: f64,
+    /// The usage of the arena after crossing it, from 0 upwards.
+    ///
+    /// This can exceed 1 if the heaps of the arena exceed a budget that was lowered after they
+    /// were created.
+    pub usage: f64,
+    /// The number of bytes allocated from the arena.
+    pub allocated_bytes: BufferAddress,
+    /// The number of bytes that usage is measured against: the budget of the arena if it has one,
+    /// or else the memory reserved by its heaps.
+    pub capacity: BufferAddress,
+}
+
+/// Which way usage must cross the threshold of a watermark for its callback to be invoked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Crossing {
+    /// From at or below the threshold to above it.
+    Rising,
+    /// From above the threshold to at or below it.
+    Falling,
+}
+
+/// The watermarks registered with an arena.
+#[derive(Default)]
+pub(crate) struct Watermarks {
+    watermarks: Vec<Watermark>,
+    next_id: u64,
+}
+
+struct Watermark {
+    id: WatermarkId,
+    threshold: f64,
+    crossing: Crossing,
+    callback: Box<dyn FnMut(&WatermarkEvent) + Send>,
+    /// Whether usage was above the threshold when last checked.
+    above: bool,
+}
+
+impl Watermarks {
+    /// Registers a watermark, given the current usage so that it only fires on later crossings.
+    pub(crate) fn insert(
+        &mut self,
+        threshold: f64,
+        crossing: Crossing,
+        callback: Box<dyn FnMut(&WatermarkEvent) + Send>,
+        usage: f64,
+    ) -> WatermarkId {
+        let id = WatermarkId(self.next_id);
+        self.next_id += 1;
+        self.watermarks.push(Watermark {
+            id,
+            threshold,
+            crossing,
+            callback,
+            above: usage > threshold,
+        });
+
+        id
+    }
+
+    /// Removes the watermark `id`, returning whether there was one.
+    pub(crate) fn remove(&mut self, id: WatermarkId) -> bool {
+        let len = self.watermarks.len();
+        self.watermarks.retain(|watermark| watermark.id != id);
+
+        self.watermarks.len() != len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.watermarks.is_empty()
+    }
+
+    /// Invokes the callback of every watermark whose threshold was crossed, in its direction,
+    /// since the last update.
+    pub(crate) fn update(&mut self, allocated_bytes: BufferAddress, capacity: BufferAddress) {
+        let usage = usage(allocated_bytes, capacity);
+        for watermark in self.watermarks.iter_mut() {
+            let above = usage > watermark.threshold;
+            let crossed = match watermark.crossing {
+                Crossing::Rising => above && !watermark.above,
+                Crossing::Falling => !above && watermark.above,
+            };
+            watermark.above = above;
+            if crossed {
+                (watermark.callback)(&WatermarkEvent {
+                    threshold: watermark.threshold,
+                    usage,
+                    allocated_bytes,
+                    capacity,
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Watermarks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let thresholds =
+            self.watermarks.iter().map(|watermark| (watermark.crossing, watermark.threshold));
+
+        f.debug_list().entries(thresholds).finish()
+    }
+}
+
+/// The fraction of `capacity` that `allocated_bytes` make up, or 0 if there is no capacity.
+pub(crate) fn usage(allocated_bytes: BufferAddress, capacity: BufferAddress) -> f64 {
+    if capacity == 0 {
+        return 0.0;
+    }
+
+    allocated_bytes as f64 / capacity as f64
+}
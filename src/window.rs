@@ -0,0 +1,301 @@
+//! Staging memory that is smaller than the heap it stages writes for.
+//!
+//! A heap created with [`UploadStrategy::Windowed`](crate::UploadStrategy::Windowed) has a
+//! staging buffer of a fixed size, used as a ring: each write is staged into the free space after
+//! the previous one, as one or more chunks, and each chunk is copied into the GPU buffer when the
+//! heap is flushed. The space of a chunk can be reused once a mapping of the window that was
+//! requested after the copy was recorded has completed, as that mapping waits for the copy to
+//! execute. Data that doesn't fit is kept on the CPU until it does, which is when the window is
+//! next written or unmapped while mapped, so large writes reach the GPU buffer over several
+//! flushes.
+
+use wgpu::BufferAddress;
+
+use std::{collections::VecDeque, ops::Range};
+
+/// The state of the staging window of a [`Heap`](crate::Heap).
+#[derive(Debug)]
+pub(crate) struct StagingWindow {
+    /// The size, in bytes, of the staging buffer, which is a multiple of [`wgpu::MAP_ALIGNMENT`].
+    size: BufferAddress,
+    /// The offset in the staging buffer at which the next chunk is staged.
+    head: BufferAddress,
+    /// The chunks in the staging buffer, in the order that they were staged.
+    chunks: VecDeque<Chunk>,
+    /// Writes that have yet to be staged, in the order that they were made.
+    pending: VecDeque<PendingWrite>,
+    /// The number of mappings of the staging buffer requested so far.
+    map_requests: u64,
+}
+
+/// A write, or part of one, that has been staged into the window.
+#[derive(Debug)]
+struct Chunk {
+    window_start: BufferAddress,
+    heap_range: Range<BufferAddress>,
+    /// The value of [`StagingWindow::map_requests`] when the chunk was copied into the GPU buffer,
+    /// if it has been.
+    copied_at: Option<u64>,
+}
+
+#[derive(Debug)]
+struct PendingWrite {
+    heap_start: BufferAddress,
+    contents: Vec<u8>,
+    /// The number of leading bytes of `contents` that have been staged.
+    staged: usize,
+}
+
+impl PendingWrite {
+    fn heap_range(&self) -> Range<BufferAddress> {
+        (self.heap_start + self.staged as BufferAddress)
+            ..(self.heap_start + self.contents.len() as BufferAddress)
+    }
+}
+
+impl StagingWindow {
+    /// Creates the state of an empty window of `size` bytes, as returned by [`Self::buffer_size`].
+    pub(crate) fn new(size: BufferAddress) -> Self {
+        Self {
+            size,
+            head: 0,
+            chunks: VecDeque::new(),
+            pending: VecDeque::new(),
+            map_requests: 0,
+        }
+    }
+
+    /// The size of the staging buffer of a window of at least `window_size` bytes.
+    pub(crate) fn buffer_size(window_size: BufferAddress) -> BufferAddress {
+        window_size.next_multiple_of(wgpu::MAP_ALIGNMENT)
+    }
+
+    pub(crate) fn size(&self) -> BufferAddress {
+        self.size
+    }
+
+    /// Notes that a mapping of the staging buffer has been requested.
+    pub(crate) fn note_map_request(&mut self) {
+        self.map_requests += 1;
+    }
+
+    /// Whether every write has been copied into the GPU buffer, or at least recorded to be.
+    pub(crate) fn is_flushed(&self) -> bool {
+        self.pending.is_empty() && self.chunks.iter().all(|chunk| chunk.copied_at.is_some())
+    }
+
+    /// The ranges of the heap that were written but have not been copied into the GPU buffer.
+    pub(crate) fn unflushed_ranges(&self) -> Vec<Range<BufferAddress>> {
+        let staged = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.copied_at.is_none())
+            .map(|chunk| chunk.heap_range.clone());
+        let pending = self.pending.iter().map(PendingWrite::heap_range);
+
+        staged.chain(pending).collect()
+    }
+
+    /// Writes `contents` into the heap at `heap_start`, staging as much as fits into `buffer`, if
+    /// it is mapped, and keeping the rest until there is room.
+    pub(crate) fn write(
+        &mut self,
+        buffer: Option<&wgpu::Buffer>,
+        heap_start: BufferAddress,
+        contents: &[u8],
+    ) {
+        let mut staged = 0;
+        if let Some(buffer) = buffer {
+            self.stage_pending(buffer);
+            // Note: writes must be staged in order, so that later writes win when copied.
+            if self.pending.is_empty() {
+                staged = self.stage(buffer, heap_start, contents);
+            }
+        }
+        if staged < contents.len() {
+            self.pending.push_back(PendingWrite {
+                heap_start: heap_start + staged as BufferAddress,
+                contents: contents[staged..].to_vec(),
+                staged: 0,
+            });
+        }
+    }
+
+    /// Frees the space of chunks whose copies have executed, and then stages as much pending data
+    /// as fits into `buffer`, which must be mapped.
+    pub(crate) fn stage_pending(&mut self, buffer: &wgpu::Buffer) {
+        self.release_copied();
+        while let Some(mut write) = self.pending.pop_front() {
+            let heap_start = write.heap_range().start;
+            write.staged += self.stage(buffer, heap_start, &write.contents[write.staged..]);
+            if write.staged < write.contents.len() {
+                self.pending.push_front(write);
+                break;
+            }
+        }
+    }
+
+    /// Stages as much of `contents` as fits into `buffer`, returning the number of bytes staged.
+    fn stage(
+        &mut self,
+        buffer: &wgpu::Buffer,
+        heap_start: BufferAddress,
+        contents: &[u8],
+    ) -> usize {
+        let mut staged = 0;
+        while staged < contents.len() {
+            let Some(space) = self.free_space() else {
+                break;
+            };
+            let len = (contents.len() - staged).min(size_of(&space) as usize);
+            let window_range = space.start..(space.start + len as BufferAddress);
+            buffer
+                .slice(window_range.clone())
+                .get_mapped_range_mut()
+                .copy_from_slice(&contents[staged..][..len]);
+
+            let heap_start = heap_start + staged as BufferAddress;
+            self.chunks.push_back(Chunk {
+                window_start: window_range.start,
+                heap_range: heap_start..(heap_start + len as BufferAddress),
+                copied_at: None,
+            });
+            // Chunks are viewed as mapped memory, so must begin on a multiple of
+            // `MAP_ALIGNMENT`.
+            self.head = window_range.end.next_multiple_of(wgpu::MAP_ALIGNMENT);
+            staged += len;
+        }
+
+        staged
+    }
+
+    /// The largest contiguous free range of the window after [`Self::head`], wrapping around to
+    /// the start of the window if there is none before its end.
+    fn free_space(&mut self) -> Option<Range<BufferAddress>> {
+        let Some(oldest) = self.chunks.front() else {
+            self.head = 0;
+            return Some(0..self.size);
+        };
+        // Note: a chunk that was trimmed by `Self::discard` may no longer begin on a multiple of
+        // `MAP_ALIGNMENT`, but the space before it up to one is still taken.
+        let tail = oldest.window_start - oldest.window_start % wgpu::MAP_ALIGNMENT;
+        if self.head > tail {
+            if self.head < self.size {
+                return Some(self.head..self.size);
+            }
+            self.head = 0;
+        }
+
+        (self.head < tail).then_some(self.head..tail)
+    }
+
+    /// Frees the space of the oldest chunks, up to the first one that may still be copied from.
+    ///
+    /// The staging buffer must be mapped, which means that every copy recorded before the mapping
+    /// was requested has executed.
+    fn release_copied(&mut self) {
+        while let Some(chunk) = self.chunks.front() {
+            match chunk.copied_at {
+                Some(copied_at) if copied_at < self.map_requests => self.chunks.pop_front(),
+                _ => break,
+            };
+        }
+    }
+
+    /// Records copies into `gpu_buffer` of the chunks that have not been copied yet, up to and
+    /// including the last one that overlaps `range`, returning the ranges of the heap copied.
+    ///
+    /// Earlier chunks are copied as well so that no chunk is copied after a later one that
+    /// overlaps it.
+    pub(crate) fn flush(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+        gpu_buffer: &wgpu::Buffer,
+        range: Range<BufferAddress>,
+    ) -> Vec<Range<BufferAddress>> {
+        let Some(last) = self.chunks.iter().rposition(|chunk| {
+            chunk.copied_at.is_none() && overlaps(&chunk.heap_range, &range)
+        }) else {
+            return Vec::new();
+        };
+
+        let mut copied = Vec::new();
+        for chunk in self.chunks.range_mut(..=last) {
+            if chunk.copied_at.is_some() {
+                continue;
+            }
+            encoder.copy_buffer_to_buffer(
+                buffer,
+                chunk.window_start,
+                gpu_buffer,
+                chunk.heap_range.start,
+                size_of(&chunk.heap_range),
+            );
+            chunk.copied_at = Some(self.map_requests);
+            copied.push(chunk.heap_range.clone());
+        }
+
+        copied
+    }
+
+    /// Drops the parts of writes to `range` of the heap that have not been copied into the GPU
+    /// buffer, as they have been superseded.
+    ///
+    /// `range` must begin and end on multiples of [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub(crate) fn discard(&mut self, range: &Range<BufferAddress>) {
+        let chunks = std::mem::take(&mut self.chunks);
+        for chunk in chunks {
+            if chunk.copied_at.is_some() || !overlaps(&chunk.heap_range, range) {
+                self.chunks.push_back(chunk);
+                continue;
+            }
+            // Note: the space of a trimmed chunk is freed along with what remains of it, which is
+            // never before the space of the chunks staged before it.
+            for heap_range in remainder(&chunk.heap_range, range) {
+                self.chunks.push_back(Chunk {
+                    window_start: chunk.window_start + (heap_range.start - chunk.heap_range.start),
+                    heap_range,
+                    copied_at: None,
+                });
+            }
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        for write in pending {
+            let write_range = write.heap_range();
+            if !overlaps(&write_range, range) {
+                self.pending.push_back(write);
+                continue;
+            }
+            for heap_range in remainder(&write_range, range) {
+                let start = (heap_range.start - write.heap_start) as usize;
+                let end = (heap_range.end - write.heap_start) as usize;
+                self.pending.push_back(PendingWrite {
+                    heap_start: heap_range.start,
+                    contents: write.contents[start..end].to_vec(),
+                    staged: 0,
+                });
+            }
+        }
+    }
+}
+
+fn size_of(range: &Range<BufferAddress>) -> BufferAddress {
+    range.end - range.start
+}
+
+fn overlaps(a: &Range<BufferAddress>, b: &Range<BufferAddress>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// The parts of `range` outside of `removed`.
+fn remainder(
+    range: &Range<BufferAddress>,
+    removed: &Range<BufferAddress>,
+) -> impl Iterator<Item = Range<BufferAddress>> {
+    let before = range.start..removed.start.min(range.end);
+    let after = removed.end.max(range.start)..range.end;
+
+    [before, after].into_iter().filter(|part| !part.is_empty())
+}
@@ -0,0 +1,899 @@
+//! Tests of the allocators on their own, without a device.
+
+use proptest::prelude::*;
+use wgpu_allocators::{
+    arena::{NewHeapSizeContext, TinyAllocPolicy},
+    mapping::NotMapped,
+    growth::{Doubling, Exponential, Fixed, NextPowerOfTwo},
+    texture::{Shelf, TextureAllocator, TextureRegion},
+    AllocError,
+    Aligned,
+    Allocator,
+    CopyError,
+    Buddy,
+    DoubleStack,
+    FitStrategy,
+    FreeList,
+    GpuBacking,
+    HeapArena,
+    HeapBacking,
+    HeapDescriptor,
+    HeapUsages,
+    MockHeap,
+    Offset,
+    NonZeroBufferAddress,
+    Pool,
+    GrowthPolicy,
+    Ring,
+    sizes,
+    Stack,
+    Tlsf,
+    VirtualHeap,
+    wgpu,
+};
+
+use std::{cell::RefCell, ops::Range, sync::{Arc, Mutex}};
+
+fn nonzero(value: u64) -> NonZeroBufferAddress {
+    NonZeroBufferAddress::new(value).unwrap()
+}
+
+#[test]
+fn free_list_chooses_the_best_fit() {
+    let mut allocator = FreeList::with_capacity(nonzero(1024));
+    let a = allocator.alloc(nonzero(256), nonzero(1)).unwrap();
+    let b = allocator.alloc(nonzero(64), nonzero(1)).unwrap();
+    let c = allocator.alloc(nonzero(128), nonzero(1)).unwrap();
+    let _d = allocator.alloc(nonzero(64), nonzero(1)).unwrap();
+
+    unsafe {
+        allocator.dealloc(a.clone()).unwrap();
+        allocator.dealloc(c.clone()).unwrap();
+    }
+
+    // The 128-byte hole left by `c` fits more tightly than the 256-byte hole left by `a` or the
+    // free space at the end.
+    assert_eq!(allocator.alloc(nonzero(100), nonzero(1)), Ok(c.start..(c.start + 100)));
+    assert_eq!(allocator.alloc(nonzero(200), nonzero(1)), Ok(a.start..(a.start + 200)));
+    assert!(b.end <= c.start);
+}
+
+#[test]
+fn free_list_strategies_choose_different_blocks() {
+    // Leaves holes of 256, 64, and 128 bytes, in that order, followed by 448 free bytes.
+    let fragmented = |strategy| {
+        let mut allocator = FreeList::with_strategy(nonzero(1024), strategy);
+        let ranges: Vec<_> = [256, 32, 64, 32, 128, 64]
+            .into_iter()
+            .map(|size| allocator.alloc(nonzero(size), nonzero(1)).unwrap())
+            .collect();
+        unsafe {
+            allocator.dealloc(ranges[0].clone()).unwrap();
+            allocator.dealloc(ranges[2].clone()).unwrap();
+            allocator.dealloc(ranges[4].clone()).unwrap();
+        }
+
+        allocator
+    };
+
+    let mut first_fit = fragmented(FitStrategy::FirstFit);
+    assert_eq!(first_fit.strategy(), FitStrategy::FirstFit);
+    assert_eq!(first_fit.alloc(nonzero(64), nonzero(1)), Ok(0..64));
+    let mut best_fit = fragmented(FitStrategy::BestFit);
+    assert_eq!(best_fit.alloc(nonzero(64), nonzero(1)), Ok(288..352));
+    let mut worst_fit = fragmented(FitStrategy::WorstFit);
+    assert_eq!(worst_fit.alloc(nonzero(64), nonzero(1)), Ok(576..640));
+
+    // Next fit resumes after the previous allocation and wraps around once it reaches the end.
+    let mut next_fit = fragmented(FitStrategy::NextFit);
+    assert_eq!(next_fit.alloc(nonzero(400), nonzero(1)), Ok(576..976));
+    assert_eq!(next_fit.alloc(nonzero(32), nonzero(1)), Ok(976..1008));
+    assert_eq!(next_fit.alloc(nonzero(64), nonzero(1)), Ok(0..64));
+    assert_eq!(next_fit.alloc(nonzero(64), nonzero(1)), Ok(64..128));
+    next_fit.reset();
+    assert_eq!(next_fit.alloc(nonzero(64), nonzero(1)), Ok(0..64));
+}
+
+#[test]
+fn aligned_allocators_honor_their_alignment() {
+    let mut allocator = Aligned::<256, _>::wrap(FreeList::with_capacity(nonzero(1024)));
+    assert_eq!(allocator.alloc(nonzero(4), nonzero(4)), Ok(0..4));
+    assert_eq!(allocator.alloc_aligned(nonzero(4)), Ok(256..260));
+    // Alignments that aren't multiples of `ALIGN` are combined with it.
+    assert_eq!(allocator.alloc(nonzero(4), nonzero(384)), Ok(768..772));
+    unsafe { allocator.dealloc(256..260) }.unwrap();
+    assert_eq!(allocator.alloc(nonzero(4), nonzero(1)), Ok(256..260));
+    assert_eq!(allocator.inner().largest_free_block(), allocator.largest_free_block());
+}
+
+#[test]
+fn reset_allocators_free_everything() {
+    fn check(mut allocator: impl Allocator, size: u64) {
+        let first = allocator.alloc(nonzero(size), nonzero(16)).unwrap();
+        assert_eq!(allocator.alloc(nonzero(size), nonzero(16)), Err(AllocError::OutOfMemory));
+        assert!(allocator.reset());
+        assert_eq!(allocator.alloc(nonzero(size), nonzero(16)), Ok(first));
+    }
+
+    check(Stack::with_capacity(nonzero(1024)), 1024);
+    check(DoubleStack::with_capacity(nonzero(1024)), 1024);
+    check(FreeList::with_capacity(nonzero(1024)), 1024);
+    check(Buddy::with_capacity(nonzero(1024)), 1024);
+    check(Tlsf::with_capacity(nonzero(1024)), 1024);
+    check(Offset::with_capacity(nonzero(1024)), 1024);
+    check(Ring::with_capacity(nonzero(1024)), 1024);
+    // The block size of a pool outlives the reset.
+    check(Pool::with_block_size(nonzero(512), nonzero(512), nonzero(16)), 512);
+    check(Aligned::<64, _>::wrap(FreeList::with_capacity(nonzero(1024))), 1024);
+}
+
+#[test]
+fn free_list_coalesces_adjacent_blocks() {
+    let mut allocator = FreeList::with_capacity(nonzero(1024));
+    let ranges: Vec<_> =
+        (0..4).map(|_| allocator.alloc(nonzero(256), nonzero(256)).unwrap()).collect();
+    assert_eq!(allocator.alloc(nonzero(1), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    for index in [1, 3, 0, 2] {
+        unsafe { allocator.dealloc(ranges[index].clone()).unwrap() };
+    }
+
+    assert_eq!(allocator.free_block_count(), 1);
+    assert_eq!(allocator.free_bytes(), 1024);
+    assert_eq!(allocator.alloc(nonzero(1024), nonzero(1)), Ok(0..1024));
+}
+
+#[test]
+fn free_list_respects_alignment() {
+    let mut allocator = FreeList::with_capacity(nonzero(1024));
+    allocator.alloc(nonzero(3), nonzero(1)).unwrap();
+    let aligned = allocator.alloc(nonzero(16), nonzero(256)).unwrap();
+
+    assert_eq!(aligned, 256..272);
+    // The padding before the aligned allocation is still available.
+    assert_eq!(allocator.alloc(nonzero(200), nonzero(1)), Ok(3..203));
+}
+
+#[test]
+fn free_list_rejects_invalid_deallocations() {
+    let mut allocator = FreeList::with_capacity(nonzero(1024));
+    let range = allocator.alloc(nonzero(64), nonzero(1)).unwrap();
+
+    unsafe {
+        assert_eq!(allocator.dealloc(range.clone()), Ok(()));
+        assert_eq!(allocator.dealloc(range), Err(AllocError::NotOwnedByAllocator));
+        assert_eq!(allocator.dealloc(1000..1100), Err(AllocError::NotOwnedByAllocator));
+    }
+}
+
+#[test]
+fn buddy_splits_and_merges_blocks() {
+    let mut allocator = Buddy::with_capacity(nonzero(1024));
+    let a = allocator.alloc(nonzero(100), nonzero(4)).unwrap();
+    let b = allocator.alloc(nonzero(100), nonzero(4)).unwrap();
+    let c = allocator.alloc(nonzero(256), nonzero(4)).unwrap();
+
+    assert_eq!(a, 0..100);
+    assert_eq!(b, 128..228);
+    assert_eq!(c, 256..512);
+    assert_eq!(allocator.alloc(nonzero(1024), nonzero(4)), Err(AllocError::OutOfMemory));
+
+    unsafe {
+        allocator.dealloc(a).unwrap();
+        allocator.dealloc(c).unwrap();
+        allocator.dealloc(b.clone()).unwrap();
+        assert_eq!(allocator.dealloc(b), Err(AllocError::NotOwnedByAllocator));
+    }
+    assert_eq!(allocator.alloc(nonzero(1024), nonzero(4)), Ok(0..1024));
+}
+
+#[test]
+fn buddy_aligns_blocks_to_their_size() {
+    let mut allocator = Buddy::with_capacity(nonzero(4096));
+    allocator.alloc(nonzero(16), nonzero(1)).unwrap();
+    let aligned = allocator.alloc(nonzero(16), nonzero(1024)).unwrap();
+
+    assert_eq!(aligned.start % 1024, 0);
+    assert_ne!(aligned.start, 0);
+}
+
+#[test]
+fn buddy_manages_sizes_that_are_not_powers_of_two() {
+    // 3,000 bytes are split into top-level blocks of 2,048, 512, 256, 128, 32, and 16 bytes, with
+    // the last 8 bytes going unused.
+    let mut allocator = Buddy::with_capacity(nonzero(3000));
+    let ranges: Vec<_> = [2048, 512, 256, 128, 32, 16]
+        .into_iter()
+        .map(|size| allocator.alloc(nonzero(size), nonzero(1)).unwrap())
+        .collect();
+
+    assert_eq!(ranges.last(), Some(&(2976..2992)));
+    assert_eq!(allocator.alloc(nonzero(16), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    for range in ranges.into_iter().rev() {
+        unsafe { allocator.dealloc(range).unwrap() };
+    }
+    // Top-level blocks are never merged with each other.
+    assert_eq!(allocator.alloc(nonzero(2049), nonzero(1)), Err(AllocError::OutOfMemory));
+    assert_eq!(allocator.alloc(nonzero(2048), nonzero(1)), Ok(0..2048));
+}
+
+#[test]
+fn tlsf_reuses_and_coalesces_blocks() {
+    let mut allocator = Tlsf::with_capacity(nonzero(4096));
+    let a = allocator.alloc(nonzero(100), nonzero(4)).unwrap();
+    let b = allocator.alloc(nonzero(1000), nonzero(4)).unwrap();
+    let c = allocator.alloc(nonzero(100), nonzero(4)).unwrap();
+    assert!(a.end <= b.start && b.end <= c.start);
+
+    unsafe { allocator.dealloc(b.clone()).unwrap() };
+    // A smaller allocation fits in the hole left by `b`.
+    let d = allocator.alloc(nonzero(500), nonzero(4)).unwrap();
+    assert_eq!(d.start, b.start);
+
+    unsafe {
+        allocator.dealloc(a).unwrap();
+        allocator.dealloc(c).unwrap();
+        allocator.dealloc(d.clone()).unwrap();
+        assert_eq!(allocator.dealloc(d), Err(AllocError::NotOwnedByAllocator));
+    }
+    assert_eq!(allocator.largest_free_block(), Some(4096));
+    assert_eq!(allocator.alloc(nonzero(4096), nonzero(4)), Ok(0..4096));
+}
+
+#[test]
+fn tlsf_respects_alignment() {
+    let mut allocator = Tlsf::with_capacity(nonzero(4096));
+    allocator.alloc(nonzero(16), nonzero(1)).unwrap();
+    let aligned = allocator.alloc(nonzero(64), nonzero(1024)).unwrap();
+
+    assert_eq!(aligned, 1024..1088);
+    // The padding before the aligned allocation is still available.
+    assert_eq!(allocator.alloc(nonzero(512), nonzero(16)), Ok(16..528));
+}
+
+#[test]
+fn tlsf_honors_alignments_that_are_not_powers_of_two() {
+    let mut allocator = Tlsf::with_capacity(nonzero(4096));
+    allocator.alloc(nonzero(16), nonzero(1)).unwrap();
+    // These are aligned to 48 and 80 bytes, the least common multiples with the granularity.
+    assert_eq!(allocator.alloc(nonzero(64), nonzero(24)), Ok(48..112));
+    assert_eq!(allocator.alloc(nonzero(64), nonzero(40)), Ok(160..224));
+    // The padding before the second allocation is still available.
+    assert_eq!(allocator.alloc(nonzero(8), nonzero(12)), Ok(144..152));
+
+    unsafe {
+        allocator.dealloc(0..16).unwrap();
+        allocator.dealloc(48..112).unwrap();
+        allocator.dealloc(160..224).unwrap();
+        allocator.dealloc(144..152).unwrap();
+    }
+    assert_eq!(allocator.largest_free_block(), Some(4096));
+}
+
+#[test]
+fn tlsf_fails_when_full() {
+    let mut allocator = Tlsf::with_capacity(nonzero(1024));
+    let ranges: Vec<_> =
+        (0..16).map(|_| allocator.alloc(nonzero(64), nonzero(1)).unwrap()).collect();
+
+    assert_eq!(allocator.alloc(nonzero(1), nonzero(1)), Err(AllocError::OutOfMemory));
+    unsafe { allocator.dealloc(ranges[7].clone()).unwrap() };
+    assert_eq!(allocator.alloc(nonzero(64), nonzero(1)), Ok(ranges[7].clone()));
+}
+
+#[test]
+fn offset_allocator_packs_bytes_and_coalesces_blocks() {
+    let mut allocator = Offset::with_capacity(nonzero(1024));
+    assert_eq!(allocator.alloc(nonzero(3), nonzero(1)), Ok(0..3));
+    assert_eq!(allocator.alloc(nonzero(100), nonzero(12)), Ok(12..112));
+    // The padding before the aligned allocation is still available.
+    assert_eq!(allocator.alloc(nonzero(9), nonzero(1)), Ok(3..12));
+
+    unsafe {
+        allocator.dealloc(12..112).unwrap();
+        allocator.dealloc(0..3).unwrap();
+        assert_eq!(allocator.dealloc(0..3), Err(AllocError::NotOwnedByAllocator));
+        allocator.dealloc(3..12).unwrap();
+    }
+    assert_eq!(allocator.largest_free_block(), Some(1024));
+    assert_eq!(allocator.alloc(nonzero(1024), nonzero(256)), Ok(0..1024));
+}
+
+#[test]
+fn offset_allocator_handles_the_largest_bins() {
+    let mut allocator = Offset::with_capacity(nonzero(1 << 40));
+    assert_eq!(allocator.alloc(nonzero(1 << 33), nonzero(1)), Ok(0..(1 << 33)));
+    assert_eq!(allocator.alloc(nonzero(1 << 35), nonzero(1)), Err(AllocError::OutOfMemory));
+}
+
+#[test]
+fn ring_wraps_around_freed_frames() {
+    let mut allocator = Ring::with_capacity(nonzero(1024));
+    assert_eq!(allocator.alloc(nonzero(400), nonzero(4)), Ok(0..400));
+    allocator.mark_frame(1);
+    assert_eq!(allocator.alloc(nonzero(400), nonzero(4)), Ok(400..800));
+    allocator.mark_frame(2);
+
+    // Frame 1 is still in flight, so there is no room at either end.
+    assert_eq!(allocator.alloc(nonzero(400), nonzero(4)), Err(AllocError::OutOfMemory));
+
+    allocator.free_up_to(1);
+    assert_eq!(allocator.alloc(nonzero(400), nonzero(4)), Ok(0..400));
+    allocator.mark_frame(3);
+    // The 224 bytes skipped at the end count as used until frame 3 is freed.
+    assert_eq!(allocator.used_bytes(), 1024);
+    assert_eq!(allocator.alloc(nonzero(1), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    allocator.free_up_to(3);
+    assert_eq!(allocator.used_bytes(), 0);
+    assert_eq!(allocator.alloc(nonzero(1024), nonzero(4)), Ok(0..1024));
+    unsafe { assert_eq!(allocator.dealloc(0..1024), Err(AllocError::Unsupported)) };
+}
+
+#[test]
+fn pool_hands_out_and_recycles_slots() {
+    let mut allocator = Pool::with_block_size(nonzero(1000), nonzero(200), nonzero(256));
+    assert_eq!(allocator.slot_count(), 3);
+
+    assert_eq!(allocator.alloc(nonzero(200), nonzero(256)), Ok(0..200));
+    assert_eq!(allocator.alloc(nonzero(64), nonzero(16)), Ok(256..320));
+    assert_eq!(allocator.alloc(nonzero(200), nonzero(256)), Ok(512..712));
+    assert_eq!(allocator.alloc(nonzero(1), nonzero(1)), Err(AllocError::OutOfMemory));
+    assert_eq!(allocator.largest_free_block(), Some(0));
+
+    unsafe {
+        allocator.dealloc(256..320).unwrap();
+        assert_eq!(allocator.dealloc(256..320), Err(AllocError::NotOwnedByAllocator));
+        assert_eq!(allocator.dealloc(768..968), Err(AllocError::NotOwnedByAllocator));
+    }
+    assert_eq!(allocator.free_slot_count(), 1);
+
+    // Requests that don't fit a slot fail regardless of free space.
+    assert_eq!(allocator.alloc(nonzero(201), nonzero(1)), Err(AllocError::OutOfMemory));
+    assert_eq!(
+        allocator.alloc(nonzero(4), nonzero(512)),
+        Err(AllocError::AlignmentNotSupported { alignment: nonzero(512) }),
+    );
+    assert_eq!(allocator.alloc(nonzero(4), nonzero(4)), Ok(256..260));
+}
+
+#[test]
+fn shelf_packs_regions_along_shelves() {
+    let size = wgpu::Extent3d { width: 64, height: 32, depth_or_array_layers: 2 };
+    let mut allocator = Shelf::new(size);
+    let region = |layer, x, y, width, height| TextureRegion { layer, x, y, width, height };
+    assert_eq!(allocator.alloc(32, 16), Some(region(0, 0, 0, 32, 16)));
+    assert_eq!(allocator.alloc(16, 8), Some(region(0, 32, 0, 16, 8)));
+    let c = allocator.alloc(32, 12).unwrap();
+    assert_eq!(c, region(0, 0, 16, 32, 12));
+    assert_eq!(allocator.alloc(16, 16), Some(region(0, 48, 0, 16, 16)));
+    // There are only 4 rows left on the first layer.
+    assert_eq!(allocator.alloc(64, 8), Some(region(1, 0, 0, 64, 8)));
+    assert_eq!(allocator.alloc(65, 1), None);
+
+    unsafe {
+        allocator.dealloc(c).unwrap();
+        assert_eq!(allocator.dealloc(c), Err(()));
+    }
+    // The emptied bottom shelf was removed, so its space can hold a taller region.
+    assert_eq!(allocator.alloc(64, 16), Some(region(0, 0, 16, 64, 16)));
+}
+
+#[test]
+fn stack_honors_alignments_that_are_not_powers_of_two() {
+    let mut allocator = Stack::with_capacity(nonzero(100));
+    assert_eq!(allocator.alloc(nonzero(10), nonzero(12)), Ok(84..94));
+    assert_eq!(allocator.alloc(nonzero(80), nonzero(3)), Ok(3..83));
+    assert_eq!(allocator.alloc(nonzero(4), nonzero(4)), Err(AllocError::OutOfMemory));
+
+    // Freeing the allocations frees their padding too.
+    unsafe {
+        allocator.dealloc(3..83).unwrap();
+        allocator.dealloc(84..94).unwrap();
+    }
+    assert_eq!(allocator.largest_free_block(), Some(100));
+}
+
+#[test]
+fn double_stacks_grow_toward_each_other() {
+    let mut allocator = DoubleStack::with_capacity(nonzero(100));
+    assert_eq!(allocator.alloc(nonzero(10), nonzero(1)), Ok(0..10));
+    assert_eq!(allocator.alloc_bottom(nonzero(10), nonzero(12)), Ok(12..22));
+    assert_eq!(allocator.alloc_top(nonzero(10), nonzero(12)), Ok(84..94));
+    let transient = allocator.alloc_top(nonzero(20), nonzero(4)).unwrap();
+    assert_eq!(transient, 64..84);
+    assert_eq!(allocator.largest_free_block(), Some(42));
+    assert_eq!(allocator.alloc_top(nonzero(43), nonzero(1)), Err(AllocError::OutOfMemory));
+    assert_eq!(allocator.alloc_bottom(nonzero(43), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    unsafe {
+        assert_eq!(allocator.dealloc(0..10), Err(AllocError::OutOfOrder));
+        allocator.dealloc(transient).unwrap();
+        allocator.dealloc(12..22).unwrap();
+    }
+    // The alignment padding of freed allocations is freed with them.
+    assert_eq!(allocator.bottom_used(), 10);
+    assert_eq!(allocator.top_used(), 16);
+
+    // Resetting the top leaves the bottom alone.
+    allocator.reset_top();
+    assert_eq!(allocator.top_used(), 0);
+    assert_eq!(allocator.alloc_top(nonzero(88), nonzero(1)), Ok(12..100));
+}
+
+#[test]
+fn allocators_report_why_they_fail() {
+    let mut stack = Stack::with_capacity(nonzero(100));
+    let first = stack.alloc(nonzero(10), nonzero(1)).unwrap();
+    let _second = stack.alloc(nonzero(10), nonzero(1)).unwrap();
+    unsafe { assert_eq!(stack.dealloc(first), Err(AllocError::OutOfOrder)) };
+
+    let mut buddy = Buddy::with_capacity(nonzero(1024));
+    assert_eq!(
+        buddy.alloc(nonzero(16), nonzero(24)),
+        Err(AllocError::AlignmentNotSupported { alignment: nonzero(24) }),
+    );
+    assert_eq!(buddy.alloc(nonzero(2048), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    let error: Box<dyn std::error::Error> = Box::new(AllocError::OutOfMemory);
+    assert_eq!(error.to_string(), "out of memory");
+}
+
+const CAPACITY: u64 = 4096;
+
+/// An operation on an allocator: either an allocation of some size and alignment, or the
+/// deallocation of some live allocation, chosen by index.
+#[derive(Clone, Debug)]
+enum Op {
+    Alloc { size: u64, alignment: u64 },
+    Dealloc { index: usize },
+}
+
+#[test]
+fn growth_policies_size_heaps_from_the_pool_history() {
+    fn context(first_alloc_size: u64, last_heap_size: Option<u64>) -> NewHeapSizeContext {
+        NewHeapSizeContext {
+            first_alloc_size: nonzero(first_alloc_size),
+            heap_count: usize::from(last_heap_size.is_some()),
+            last_heap_size: last_heap_size.map(nonzero),
+        }
+    }
+
+    let doubling = Doubling { initial: nonzero(4096) };
+    assert_eq!(doubling.new_heap_size(context(16, None)), nonzero(4096));
+    assert_eq!(doubling.new_heap_size(context(16, Some(4096))), nonzero(8192));
+    assert_eq!(doubling.new_heap_size(context(65536, Some(4096))), nonzero(65536));
+
+    assert_eq!(Fixed(nonzero(4096)).new_heap_size(context(16, Some(8192))), nonzero(4096));
+    assert_eq!(NextPowerOfTwo.new_heap_size(context(5000, Some(4096))), nonzero(8192));
+
+    let exponential = Exponential { base: 4, cap: nonzero(65536) };
+    assert_eq!(exponential.new_heap_size(context(3000, None)), nonzero(4096));
+    assert_eq!(exponential.new_heap_size(context(16, Some(4096))), nonzero(16384));
+    assert_eq!(exponential.new_heap_size(context(16, Some(32768))), nonzero(65536));
+    assert_eq!(exponential.new_heap_size(context(100_000, Some(65536))), nonzero(100_000));
+
+    let closure = |context: NewHeapSizeContext| context.first_alloc_size;
+    assert_eq!(closure.new_heap_size(context(16, Some(4096))), nonzero(16));
+}
+
+#[cfg(feature = "bench")]
+#[test]
+fn workloads_are_deterministic_and_replayable() {
+    use wgpu_allocators::bench::Workload;
+
+    assert_eq!(Workload::all(7), Workload::all(7));
+    assert_ne!(Workload::random_churn(7, 64), Workload::random_churn(8, 64));
+
+    let stats = Workload::uniform_heavy(7, 16).run::<FreeList>();
+    assert_eq!(stats.failed_allocations + stats.failed_deallocations, 0);
+    assert_eq!(stats.allocations, stats.deallocations);
+
+    // Vertex streaming frees oldest first, which a stack refuses but a free list allows.
+    assert_ne!(Workload::vertex_streaming(7, 16).run::<Stack>().failed_deallocations, 0);
+    let stats = Workload::vertex_streaming(7, 16).run::<FreeList>();
+    assert_eq!(stats.failed_allocations + stats.failed_deallocations, 0);
+}
+
+#[test]
+fn allocators_manage_all_of_a_mock_heap() {
+    fn fill<A: Allocator>(heap: &MockHeap) {
+        let mut allocator = A::new(heap);
+        let range = allocator.alloc(nonzero(CAPACITY), nonzero(1)).unwrap();
+        assert_eq!(range, 0..CAPACITY);
+        assert!(allocator.alloc(nonzero(1), nonzero(1)).is_err());
+    }
+
+    let heap = MockHeap::new(nonzero(CAPACITY), HeapUsages::UNIFORM);
+    fill::<Stack>(&heap);
+    fill::<FreeList>(&heap);
+    fill::<Buddy>(&heap);
+    fill::<Tlsf>(&heap);
+    fill::<Ring>(&heap);
+    assert_eq!(Pool::new(&heap).slot_count() as u64, CAPACITY / Pool::DEFAULT_BLOCK_SIZE);
+}
+
+#[test]
+fn virtual_heaps_sub_allocate_external_memory() {
+    // Rows of a 1,024-row texture atlas.
+    let mut atlas = VirtualHeap::<FreeList>::new(nonzero(1024));
+    let glyphs = atlas.alloc(nonzero(512), nonzero(1)).unwrap();
+    let icons = atlas.alloc(nonzero(512), nonzero(1)).unwrap();
+    assert_eq!(atlas.alloc(nonzero(1), nonzero(1)), Err(AllocError::OutOfMemory));
+
+    assert!(atlas.grow(nonzero(2048)));
+    assert_eq!(atlas.size(), nonzero(2048));
+    assert_eq!(atlas.alloc(nonzero(1024), nonzero(1)).unwrap(), 1024..2048);
+
+    unsafe { atlas.dealloc(glyphs.clone()) }.unwrap();
+    assert_eq!(atlas.alloc(nonzero(256), nonzero(1)).unwrap().start, glyphs.start);
+    assert!(icons.end <= 1024);
+
+    // A stack can't use memory appended to its heap.
+    let mut stack = VirtualHeap::<Stack>::new(nonzero(1024));
+    assert!(!stack.grow(nonzero(2048)));
+    assert_eq!(stack.size(), nonzero(1024));
+}
+
+/// A backing whose heaps are plain memory, standing in for the buffers of another GPU API.
+#[derive(Debug)]
+struct CpuBacking;
+
+#[derive(Debug)]
+struct CpuHeap(RefCell<Vec<u8>>);
+
+impl HeapBacking for CpuHeap {
+    fn size(&self) -> NonZeroBufferAddress {
+        nonzero(self.0.borrow().len() as u64)
+    }
+}
+
+impl GpuBacking for CpuBacking {
+    type Device = ();
+    type Encoder = ();
+    type Heap = CpuHeap;
+
+    fn max_heap_size(_: &()) -> u64 {
+        CAPACITY
+    }
+
+    fn create_heap(_: &(), descriptor: &HeapDescriptor) -> CpuHeap {
+        CpuHeap(RefCell::new(vec![0; descriptor.size.get() as usize]))
+    }
+
+    fn write(heap: &CpuHeap, range: Range<u64>, contents: &[u8]) -> Result<(), NotMapped> {
+        heap.0.borrow_mut()[range.start as usize..range.end as usize].copy_from_slice(contents);
+
+        Ok(())
+    }
+
+    fn map(_: &CpuHeap) {}
+
+    fn unmap(_: &CpuHeap) {}
+
+    fn copy(
+        _: &mut (),
+        source: &CpuHeap,
+        source_range: Range<u64>,
+        destination: &CpuHeap,
+        destination_offset: u64,
+    ) -> Result<(), CopyError> {
+        if std::ptr::eq(source, destination) {
+            return Err(CopyError::SameHeap);
+        }
+        let contents = &source.0.borrow()[source_range.start as usize..source_range.end as usize];
+        let start = destination_offset as usize;
+        destination.0.borrow_mut()[start..(start + contents.len())].copy_from_slice(contents);
+
+        Ok(())
+    }
+}
+
+#[test]
+fn arenas_allocate_in_heaps_of_any_backing() {
+    let mut arena = HeapArena::<FreeList, CpuBacking>::with_backing(
+        HeapUsages::STORAGE,
+        Fixed(nonzero(256)),
+    );
+    let first = arena.alloc(&(), nonzero(192), nonzero(4)).unwrap();
+    let second = arena.alloc(&(), nonzero(128), nonzero(4)).unwrap();
+    assert_ne!(first.arena_key, second.arena_key);
+    assert_eq!(arena.heap_for(&second).size(), nonzero(256));
+
+    assert_eq!(
+        arena.copy_allocation(&mut (), &first, &second),
+        Err(CopyError::SizeMismatch { source_size: 192, destination_size: 128 }),
+    );
+
+    arena.heap_for(&second).0.borrow_mut()[0..128].fill(7);
+    unsafe { arena.dealloc(first) }.unwrap();
+    let third = arena.alloc(&(), nonzero(256), nonzero(4)).unwrap();
+    arena.copy_allocation(&mut (), &second, &third).unwrap();
+    let heap = arena.heap_for(&third).0.borrow();
+    assert_eq!(heap[0..128], [7; 128]);
+    assert_eq!(heap[128..256], [0; 128]);
+    assert_eq!(arena.stats().total().bytes_allocated, 384);
+}
+
+#[test]
+fn watermarks_fire_when_usage_crosses_them() {
+    let mut arena = HeapArena::<FreeList, CpuBacking>::with_backing(
+        HeapUsages::STORAGE,
+        Fixed(nonzero(256)),
+    )
+    .with_budget(1024);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let above = {
+        let events = Arc::clone(&events);
+        arena.on_usage_above(0.5, move |event| events.lock().unwrap().push(("above", event.usage)))
+    };
+    let below = {
+        let events = Arc::clone(&events);
+        arena.on_usage_below(0.5, move |event| events.lock().unwrap().push(("below", event.usage)))
+    };
+
+    let mut allocations: Vec<_> =
+        (0..2).map(|_| arena.alloc(&(), nonzero(256), nonzero(4)).unwrap()).collect();
+    assert!(events.lock().unwrap().is_empty());
+    allocations.push(arena.alloc(&(), nonzero(128), nonzero(4)).unwrap());
+    allocations.push(arena.alloc(&(), nonzero(128), nonzero(4)).unwrap());
+    assert_eq!(arena.allocated_bytes(), 768);
+    assert_eq!(*events.lock().unwrap(), [("above", 0.625)]);
+
+    unsafe { arena.dealloc(allocations.pop().unwrap()) }.unwrap();
+    unsafe { arena.dealloc(allocations.pop().unwrap()) }.unwrap();
+    assert_eq!(*events.lock().unwrap(), [("above", 0.625), ("below", 0.5)]);
+
+    assert!(arena.remove_watermark(above));
+    arena.alloc(&(), nonzero(256), nonzero(4)).unwrap();
+    arena.reset_all();
+    assert_eq!(*events.lock().unwrap(), [("above", 0.625), ("below", 0.5), ("below", 0.0)]);
+    assert!(arena.remove_watermark(below));
+    assert!(!arena.remove_watermark(below));
+}
+
+#[test]
+fn min_heap_size_overrides_the_growth_policy() {
+    let mut arena = HeapArena::<FreeList, CpuBacking>::with_backing(
+        HeapUsages::STORAGE,
+        |context: NewHeapSizeContext| context.first_alloc_size,
+    );
+    arena.set_min_heap_size(Some(nonzero(1024)));
+    let small = arena.alloc(&(), nonzero(64), nonzero(4)).unwrap();
+    assert_eq!(arena.heap_for(&small).size(), nonzero(1024));
+
+    // Heaps larger than the minimum are left as the growth policy sizes them.
+    let large = arena.alloc(&(), nonzero(2048), nonzero(4)).unwrap();
+    assert_eq!(arena.heap_for(&large).size(), nonzero(2048));
+
+    arena.set_min_heap_size(None);
+    let exact = arena.alloc(&(), nonzero(1024), nonzero(4)).unwrap();
+    assert_eq!(arena.heap_for(&exact).size(), nonzero(1024));
+}
+
+#[test]
+fn batched_tiny_allocations_share_heaps() {
+    let mut arena = HeapArena::<FreeList, CpuBacking>::with_backing(
+        HeapUsages::UNIFORM,
+        |context: NewHeapSizeContext| context.first_alloc_size,
+    );
+    for _ in 0..4 {
+        arena.alloc(&(), nonzero(64), nonzero(4)).unwrap();
+    }
+    assert_eq!(arena.heaps().count(), 4);
+
+    arena.set_tiny_alloc_policy(TinyAllocPolicy::Batched { heap_size: nonzero(1024) });
+    let batched: Vec<_> =
+        (0..20).map(|size| arena.alloc(&(), nonzero(32 + size), nonzero(4)).unwrap()).collect();
+    assert_eq!(arena.heaps().count(), 5);
+    assert!(batched.iter().all(|allocation| allocation.arena_key == batched[0].arena_key));
+    assert_eq!(arena.heap_for(&batched[0]).size(), nonzero(1024));
+
+    // Allocations outside the pool of tiny heaps are still sized by the growth policy.
+    let pooled = arena.alloc(&(), nonzero(4096), nonzero(4)).unwrap();
+    assert_eq!(arena.heap_for(&pooled).size(), nonzero(4096));
+}
+
+#[test]
+fn size_classes_span_powers_of_two() {
+    assert_eq!(sizes::classify_size(nonzero(1)), 0);
+    assert_eq!(sizes::classify_size(nonzero(4095)), 11);
+    assert_eq!(sizes::classify_size(nonzero(4096)), 12);
+    assert_eq!(sizes::classify_size(nonzero(u64::MAX)), 63);
+    assert_eq!(sizes::size_class_range(0), 1..=1);
+    assert_eq!(sizes::size_class_range(12), 4096..=8191);
+    assert_eq!(sizes::size_class_range(63), (1 << 63)..=u64::MAX);
+
+    assert_eq!(sizes::pool_index(11), None);
+    assert_eq!(sizes::pool_index(sizes::FIRST_POOLED_SIZE_CLASS), Some(0));
+    assert!(sizes::share_pool(0, 11));
+    assert!(!sizes::share_pool(11, 12));
+}
+
+#[test]
+fn sizes_round_to_powers_of_two_and_alignments() {
+    assert_eq!(sizes::next_power_of_two(nonzero(1)), Some(nonzero(1)));
+    assert_eq!(sizes::next_power_of_two(nonzero(4097)), Some(nonzero(8192)));
+    assert_eq!(sizes::next_power_of_two(nonzero((1 << 63) + 1)), None);
+    assert_eq!(sizes::prev_power_of_two(nonzero(4097)), nonzero(4096));
+    assert_eq!(sizes::prev_power_of_two(nonzero(u64::MAX)), nonzero(1 << 63));
+
+    assert_eq!(sizes::align_up(0, nonzero(256)), Some(0));
+    assert_eq!(sizes::align_up(257, nonzero(256)), Some(512));
+    assert_eq!(sizes::align_up(u64::MAX, nonzero(256)), None);
+    assert_eq!(sizes::align_down(511, nonzero(256)), 256);
+    assert_eq!(sizes::align_up(10, nonzero(3)), Some(12));
+
+    assert_eq!(sizes::combine_alignments(nonzero(4), nonzero(6)), nonzero(12));
+    assert_eq!(sizes::combine_alignments(nonzero(256), nonzero(4)), nonzero(256));
+    assert_eq!(sizes::combine_alignments(nonzero(1 << 63), nonzero(3)), nonzero(u64::MAX));
+}
+
+#[test]
+fn arenas_classify_allocations_as_sizes_does() {
+    let mut arena = HeapArena::<FreeList, CpuBacking>::with_backing(
+        HeapUsages::STORAGE,
+        Fixed(nonzero(CAPACITY)),
+    );
+    for size in [1, 100, 2048, 4095, 4096] {
+        let allocation = arena.alloc(&(), nonzero(size), nonzero(1)).unwrap();
+        assert_eq!(allocation.arena_key.size_class(), sizes::classify_size(nonzero(size)));
+        let size_class = HeapArena::<FreeList>::size_class(nonzero(size));
+        assert_eq!(size_class, allocation.arena_key.size_class());
+    }
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    // Alignments that aren't powers of two come from combining alignments, as the least common
+    // multiple of, say, a 4-byte vertex stride and a 12-byte one.
+    let alignment = prop_oneof![
+        (0..=8u32).prop_map(|log2| 1 << log2),
+        prop::sample::select(vec![3, 6, 12, 20, 24, 40, 48, 80, 96]),
+    ];
+    prop_oneof![
+        (1..=512u64, alignment).prop_map(|(size, alignment)| Op::Alloc { size, alignment }),
+        any::<usize>().prop_map(|index| Op::Dealloc { index }),
+    ]
+}
+
+/// Runs `ops` against `allocator`, checking that every allocation is in bounds, aligned, exactly
+/// as large as requested, and disjoint from every other live allocation, that only alignments
+/// that aren't powers of two are ever rejected as unsupported, and that freeing every allocation
+/// restores the whole capacity.
+fn check_invariants(mut allocator: impl Allocator, ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let capacity = allocator.largest_free_block();
+    let mut live: Vec<Range<u64>> = Vec::new();
+    for op in ops {
+        match op {
+            Op::Alloc { size, alignment } => {
+                let range = match allocator.alloc(nonzero(size), nonzero(alignment)) {
+                    Ok(range) => range,
+                    Err(AllocError::AlignmentNotSupported { .. }) => {
+                        prop_assert!(!alignment.is_power_of_two(), "{alignment} was rejected");
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                prop_assert!(range.end <= CAPACITY, "{range:?} is out of bounds");
+                prop_assert_eq!(range.start % alignment, 0);
+                prop_assert_eq!(range.end - range.start, size);
+                for other in live.iter() {
+                    prop_assert!(
+                        range.end <= other.start || other.end <= range.start,
+                        "{range:?} overlaps {other:?}",
+                    );
+                }
+                live.push(range);
+            }
+            Op::Dealloc { index } if !live.is_empty() => {
+                let index = index % live.len();
+                // Some allocators can only free certain allocations; those that can't be freed
+                // simply stay live.
+                if unsafe { allocator.dealloc(live[index].clone()) }.is_ok() {
+                    live.swap_remove(index);
+                }
+            }
+            Op::Dealloc { .. } => {}
+        }
+    }
+
+    // Once everything is freed, in whatever order the allocator accepts, the whole capacity must
+    // be available again, including any alignment padding. Allocators that can't free
+    // allocations one by one, such as `Ring`, are exempt.
+    loop {
+        let count = live.len();
+        live.retain(|range| unsafe { allocator.dealloc(range.clone()) }.is_err());
+        if live.len() == count {
+            break;
+        }
+    }
+    if live.is_empty() {
+        prop_assert_eq!(allocator.largest_free_block(), capacity);
+        if let Some(capacity) = capacity {
+            prop_assert!(
+                allocator.alloc(nonzero(capacity), nonzero(1)).is_ok(),
+                "capacity was not restored",
+            );
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn stack_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(Stack::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn double_stack_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(DoubleStack::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn free_list_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(FreeList::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn free_list_strategies_make_valid_allocations(
+        strategy in prop::sample::select(vec![
+            FitStrategy::FirstFit,
+            FitStrategy::NextFit,
+            FitStrategy::BestFit,
+            FitStrategy::WorstFit,
+        ]),
+        ops in prop::collection::vec(op(), 1..64),
+    ) {
+        check_invariants(FreeList::with_strategy(nonzero(CAPACITY), strategy), ops)?;
+    }
+
+    #[test]
+    fn buddy_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(Buddy::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn tlsf_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(Tlsf::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn offset_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(Offset::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn ring_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        check_invariants(Ring::with_capacity(nonzero(CAPACITY)), ops)?;
+    }
+
+    #[test]
+    fn sizes_are_in_the_range_of_their_size_class(size in 1..=u64::MAX) {
+        let size_class = sizes::classify_size(nonzero(size));
+        prop_assert!(sizes::size_class_range(size_class).contains(&size));
+        prop_assert_eq!(sizes::prev_power_of_two(nonzero(size)).get(), 1 << size_class);
+        if let Some(next) = sizes::next_power_of_two(nonzero(size)) {
+            prop_assert!(next.get() >= size && next.get().is_power_of_two());
+            prop_assert!(next.get() / 2 < size);
+        }
+    }
+
+    #[test]
+    fn aligned_values_are_the_nearest_multiples(value in 0..(1u64 << 48), alignment in 1..4096u64) {
+        let up = sizes::align_up(value, nonzero(alignment)).unwrap();
+        let down = sizes::align_down(value, nonzero(alignment));
+        prop_assert_eq!(up % alignment, 0);
+        prop_assert_eq!(down % alignment, 0);
+        prop_assert!(down <= value && value <= up);
+        prop_assert!(up - down == 0 || up - down == alignment);
+    }
+
+    #[test]
+    fn pool_allocations_are_valid(ops in prop::collection::vec(op(), 1..64)) {
+        let pool = Pool::with_block_size(nonzero(CAPACITY), nonzero(512), nonzero(256));
+        check_invariants(pool, ops)?;
+    }
+}
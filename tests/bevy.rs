@@ -0,0 +1,106 @@
+//! Tests of the Bevy integration, driving a render app through frames on a real device.
+//!
+//! The render app has a device and a queue but none of the rest of the renderer. These tests are
+//! skipped on machines without a wgpu adapter.
+
+use bevy_app::{App, SubApp};
+use bevy_ecs::{
+    schedule::{IntoSystemConfigs, Schedule, ScheduleLabel},
+    system::{Res, ResMut, Resource},
+};
+use bevy_render::{
+    renderer::{RenderDevice, RenderQueue},
+    ExtractSchedule,
+    Render,
+    RenderApp,
+    RenderSet,
+};
+use wgpu_allocators::{
+    arena::Allocation,
+    bevy::{HeapArenaPlugin, HeapArenaResource},
+    growth::Fixed,
+    harness::TestContext,
+    FreeList,
+    HeapUsages,
+    MapState,
+    NonZeroBufferAddress,
+    wgpu,
+};
+
+use std::sync::Arc;
+
+fn nonzero(value: u64) -> NonZeroBufferAddress {
+    NonZeroBufferAddress::new(value).unwrap()
+}
+
+/// An app whose render app runs its extract and render schedules on `context`.
+fn app(context: TestContext) -> App {
+    let mut render_app = App::empty();
+    render_app.main_schedule_label = Render.intern();
+    render_app
+        .add_schedule(Schedule::new(ExtractSchedule))
+        .add_schedule(Render::base_schedule())
+        .insert_resource(RenderDevice::from(context.device))
+        .insert_resource(RenderQueue(Arc::new(context.queue)));
+
+    let mut app = App::new();
+    app.insert_sub_app(
+        RenderApp,
+        SubApp::new(render_app, |_, render_app| render_app.world.run_schedule(ExtractSchedule)),
+    );
+
+    app
+}
+
+/// The allocations made by [`upload`], one per frame.
+#[derive(Default, Resource)]
+struct Uploads(Vec<Allocation>);
+
+fn upload(
+    mut arena: ResMut<HeapArenaResource<FreeList>>,
+    device: Res<RenderDevice>,
+    mut uploads: ResMut<Uploads>,
+) {
+    let frame = arena.get_mut().frame() as u32;
+    // Note: this panics unless the staging memory written last frame was mapped again.
+    let allocation = arena.upload_uniform(&device, &[frame; 4]).unwrap();
+    uploads.0.push(allocation);
+}
+
+#[test]
+fn heap_arena_plugin_remaps_staging_memory_between_frames() {
+    let Some(context) = TestContext::new() else {
+        return;
+    };
+    let mut app = app(context);
+    app.add_plugins(HeapArenaPlugin::<FreeList>::new());
+    let render_app = app.sub_app_mut(RenderApp);
+    let device = render_app.world.resource::<RenderDevice>().clone();
+    render_app
+        .insert_resource(HeapArenaResource::<FreeList>::for_device(
+            &device,
+            HeapUsages::UNIFORM,
+            Fixed(nonzero(4096)),
+        ))
+        .init_resource::<Uploads>()
+        .add_systems(Render, upload.in_set(RenderSet::PrepareResources));
+    app.finish();
+    app.cleanup();
+
+    for _ in 0..3 {
+        app.update();
+    }
+
+    let world = &mut app.sub_app_mut(RenderApp).world;
+    let uploads = world.remove_resource::<Uploads>().unwrap().0;
+    let arena = world.remove_resource::<HeapArenaResource<FreeList>>().unwrap().into_inner();
+    assert_eq!(arena.frame(), 3);
+    assert_eq!(uploads.len(), 3);
+    for allocation in &uploads {
+        assert!(arena.is_live(allocation));
+    }
+
+    // The mapping requested after the last flush completes without another frame.
+    device.poll(wgpu::Maintain::Wait);
+    assert!(arena.heaps().all(|(heap, _)| heap.map_state() == MapState::Mapped));
+}
@@ -0,0 +1,168 @@
+//! Tests of the process-wide buffer governor against a real device.
+//!
+//! The governor counts the buffers of every heap in the process, so these tests are kept apart
+//! from the others and run one at a time. They are skipped on machines without a wgpu adapter.
+
+use wgpu_allocators::{
+    arena::{Allocation, NewHeapSizeContext},
+    governor,
+    growth::Fixed,
+    harness::{with_context, TestContext},
+    AllocError,
+    FreeList,
+    Heap,
+    HeapArena,
+    HeapUsages,
+    NonZeroBufferAddress,
+    wgpu,
+};
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+fn nonzero(value: u64) -> NonZeroBufferAddress {
+    NonZeroBufferAddress::new(value).unwrap()
+}
+
+/// Runs `f` with a [`TestContext`] and a buffer ceiling of `ceiling`, which is lifted afterwards.
+fn with_ceiling(ceiling: usize, f: impl FnOnce(&TestContext)) {
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    with_context(|context| {
+        assert_eq!(governor::live_buffer_count(), 0);
+        governor::set_buffer_ceiling(Some(ceiling));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(context)));
+        governor::set_buffer_ceiling(None);
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+    });
+}
+
+#[test]
+fn heaps_count_their_buffers_against_the_ceiling() {
+    with_ceiling(3, |context| {
+        // A staging buffer and a GPU buffer.
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        assert_eq!(governor::live_buffer_count(), 2);
+        assert_eq!(governor::buffer_ceiling(), Some(3));
+
+        // Note: with one buffer to spare, the heap would be consolidated to twice its size.
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(256)));
+        assert_eq!(
+            arena.alloc(&context.device, nonzero(256), nonzero(4)),
+            Err(AllocError::HeapCreationFailed { heap_size: nonzero(512) }),
+        );
+        assert_eq!(arena.reserved_bytes(), 0);
+
+        drop(heap);
+        assert_eq!(governor::live_buffer_count(), 0);
+        arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert_eq!(governor::live_buffer_count(), 2);
+    });
+}
+
+#[test]
+fn heaps_that_fail_to_be_created_are_not_counted() {
+    with_ceiling(8, |context| {
+        // No device can create a buffer this large, so wgpu reports an error, which panics.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Heap::new(&context.device, nonzero(u64::MAX / 2), HeapUsages::STORAGE)
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(governor::live_buffer_count(), 0);
+    });
+}
+
+#[test]
+fn heaps_consolidate_under_pressure() {
+    with_ceiling(8, |context| {
+        let heaps = [(); 2].map(|_| Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE));
+        // Half of the ceiling is taken, which doubles the size of new heaps.
+        assert_eq!(governor::live_buffer_count(), 4);
+        assert_eq!(governor::consolidation_factor(), 2);
+
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(1024)));
+        let allocation = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert_eq!(arena[allocation.arena_key].0.size().get(), 2048);
+
+        drop(heaps);
+    });
+}
+
+#[test]
+fn consolidated_heaps_stay_within_the_budget() {
+    with_ceiling(8, |context| {
+        let heaps = [(); 2].map(|_| Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE));
+        assert_eq!(governor::consolidation_factor(), 2);
+
+        // A consolidated heap is shrunk to fit the budget...
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(1024)))
+            .with_budget(1536);
+        let allocation = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert_eq!(arena[allocation.arena_key].0.size().get(), 1536);
+
+        // ...but never below the size that the growth policy asked for.
+        let mut arena = HeapArena::<FreeList>::new(
+            HeapUsages::STORAGE,
+            |context: NewHeapSizeContext| context.first_alloc_size,
+        )
+        .with_budget(1024);
+        let allocation = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        assert_eq!(arena[allocation.arena_key].0.size().get(), 1024);
+
+        drop(heaps);
+    });
+}
+
+#[test]
+fn deterministic_arenas_place_allocations_regardless_of_pressure() {
+    /// Runs the same sequence of allocations and deallocations on a new arena, returning every
+    /// allocation made and the size of every heap.
+    fn replay(device: &wgpu::Device, deterministic: bool) -> (Vec<Allocation>, Vec<u64>) {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(1024)));
+        arena.set_deterministic(deterministic);
+        let mut allocations = Vec::new();
+        let mut live = Vec::new();
+        for i in 0..24u64 {
+            let size = 64 * (1 + i * 7 % 5);
+            let allocation = arena.alloc(device, nonzero(size), nonzero(16)).unwrap();
+            allocations.push(allocation.clone());
+            live.push(allocation);
+            if i % 3 == 2 {
+                let index = (i as usize * 5) % live.len();
+                unsafe { arena.dealloc(live.remove(index)) }.unwrap();
+            }
+        }
+        let heap_sizes = arena.heaps().map(|(heap, _)| heap.size().get()).collect();
+
+        (allocations, heap_sizes)
+    }
+
+    with_ceiling(24, |context| {
+        let expected = replay(&context.device, true);
+
+        // Half of the ceiling is taken, which doubles the size of new heaps.
+        let heaps = [(); 6].map(|_| Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE));
+        assert_eq!(governor::consolidation_factor(), 2);
+        assert_eq!(replay(&context.device, true), expected);
+        assert_ne!(replay(&context.device, false).1, expected.1);
+
+        drop(heaps);
+    });
+
+    let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(1024)));
+    arena.set_deterministic(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        arena.set_eviction_handler(|_, _| false);
+    }));
+    assert!(result.is_err());
+    arena.set_deterministic(false);
+    arena.set_eviction_handler(|_, _| false);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| arena.set_deterministic(true)));
+    assert!(result.is_err());
+}
@@ -0,0 +1,2446 @@
+//! End-to-end tests of the write, flush, and binding paths against a real device.
+//!
+//! These tests are skipped on machines without a wgpu adapter.
+
+use wgpu_allocators::{
+    diagnostics::Suggestion,
+    arena::{
+        AllocDesc, Allocation, AllocationInfo, ArenaKey, Displaced, EmptyHeapPolicy, HeapGrowth,
+        NewHeapSizeContext, Placement, PreemptionPolicy, Relocation,
+    },
+    BindGroupCache,
+    CachedArena,
+    CompactionBudget,
+    CompactionReport,
+    Completion,
+    copy::CopyPlanner,
+    AllocError,
+    AllocationObserver,
+    Allocator,
+    BindingError,
+    CopyError,
+    FrameHeap,
+    FreeList,
+    growth::{Doubling, Fixed},
+    guard::{CanaryViolation, GuardSide},
+    harness::{with_context, TestContext},
+    selftest::{self, SelfTestConfig},
+    SharedHeapArena,
+    texture::Shelf,
+    typed::{Uniform, Vertex},
+    Heap,
+    HeapArena,
+    HeapDescriptor,
+    HeapEvent,
+    HeapEventKind,
+    HeapUsages,
+    indirect::{DrawIndexedIndirect, IndirectArgBuffer},
+    InFlight,
+    layout::{LayoutRules, StructLayout, WgslType},
+    MapState,
+    ManagedQueue,
+    MeshAllocator,
+    NonZeroBufferAddress,
+    Priority,
+    queue::Staging,
+    RawHeap,
+    Stack,
+    StagingHeap,
+    Stats,
+    TextureHeap,
+    TransferContext,
+    TypedHeap,
+    UploadPath,
+    UploadPolicy,
+    UploadStrategy,
+    WriteBatcher,
+    wgpu,
+};
+
+fn nonzero(value: u64) -> NonZeroBufferAddress {
+    NonZeroBufferAddress::new(value).unwrap()
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+fn flush_all(context: &TestContext, heap: &Heap) {
+    heap.unmap();
+    context.submit(|encoder| heap.flush(encoder));
+}
+
+#[test]
+fn write_and_flush_round_trips() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        let contents = pattern(64);
+        let mut encoder = context.device.create_command_encoder(&Default::default());
+        heap.write(64..128, &contents);
+        heap.unmap();
+        heap.flush_range(&mut encoder, 64..128);
+        context.queue.submit(Some(encoder.finish()));
+
+        assert_eq!(context.read_heap(&heap, 64..128), contents);
+    });
+}
+
+#[test]
+fn flush_range_only_copies_the_given_range() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.write(0..256, &[0xff; 256]);
+        heap.unmap();
+        context.submit(|encoder| heap.flush_range(encoder, 128..192));
+
+        let contents = context.read_heap(&heap, 0..256);
+        assert!(contents[..128].iter().all(|&byte| byte == 0));
+        assert!(contents[128..192].iter().all(|&byte| byte == 0xff));
+        assert!(contents[192..].iter().all(|&byte| byte == 0));
+    });
+}
+
+#[test]
+fn binding_covers_the_given_range() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(1024), HeapUsages::UNIFORM);
+        let binding = heap.binding(256..512);
+
+        assert_eq!(binding.offset, 256);
+        assert_eq!(binding.size, Some(nonzero(256)));
+    });
+}
+
+#[test]
+fn typed_heaps_have_the_usage_of_their_marker() {
+    with_context(|context| {
+        let uniforms = TypedHeap::<Uniform>::new(&context.device, nonzero(1024));
+        let vertices = TypedHeap::<Vertex>::new(&context.device, nonzero(1024));
+
+        assert_eq!(uniforms.as_dynamic().usage(), HeapUsages::UNIFORM);
+        assert_eq!(uniforms.binding(256..512).offset, 256);
+        assert_eq!(vertices.as_dynamic().usage(), HeapUsages::VERTEX);
+        let _ = vertices.slice(0..512);
+    });
+}
+
+#[test]
+fn raw_heaps_suballocate_the_given_buffer() {
+    with_context(|context| {
+        let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 1024,
+            usage: wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        let mut heap = RawHeap::new(&buffer, nonzero(1024), Stack::with_capacity(nonzero(1024)));
+        let first = heap.alloc(nonzero(256), nonzero(256)).unwrap();
+        let second = heap.alloc(nonzero(256), nonzero(256)).unwrap();
+
+        assert!(first.end <= second.start || second.end <= first.start);
+        assert_eq!(heap.binding(second.clone()).offset, second.start);
+        assert!(heap.alloc(nonzero(1024), nonzero(4)).is_err());
+    });
+}
+
+#[test]
+fn staging_heaps_copy_into_any_buffer() {
+    with_context(|context| {
+        let mut staging =
+            StagingHeap::new(&context.device, nonzero(256), Stack::with_capacity(nonzero(256)));
+        let destination = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 128,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let range = staging.alloc(nonzero(64), nonzero(4)).unwrap();
+        staging.write(range.clone(), &pattern(64));
+        staging.unmap();
+        context.submit(|encoder| staging.copy_to_buffer(encoder, range, &destination, 32));
+
+        assert_eq!(context.read_buffer(&destination, 32..96), pattern(64));
+    });
+}
+
+#[test]
+fn arena_allocations_round_trip() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        let first = arena.alloc(&context.device, nonzero(16), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(32), nonzero(4)).unwrap();
+        arena.write(&first, &pattern(16));
+        arena.write(&second, &pattern(32));
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_range(encoder, &first);
+            arena.flush_range(encoder, &second);
+        });
+
+        for (allocation, len) in [(first, 16), (second, 32)] {
+            let (heap, _) = &arena[allocation.arena_key];
+            assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone()), pattern(len));
+        }
+    });
+}
+
+#[test]
+fn uniform_arrays_are_padded_to_sixteen_bytes() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::UNIFORM, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        let allocation = arena.alloc_for::<[u32; 2]>(&context.device, nonzero(2)).unwrap();
+        assert_eq!(allocation.range_in_heap.end - allocation.range_in_heap.start, 32);
+        assert_eq!(allocation.range_in_heap.start % 16, 0);
+
+        arena.write_slice(&allocation, &[[1u32, 2], [3, 4]]);
+        arena.unmap();
+        context.submit(|encoder| arena.flush_range(encoder, &allocation));
+
+        let (heap, _) = &arena[allocation.arena_key];
+        let contents = context.read_heap(heap, allocation.range_in_heap.clone());
+        let expected: &[u32] = &[1, 2, 0, 0, 3, 4, 0, 0];
+        assert_eq!(contents, bytemuck::cast_slice::<u32, u8>(expected));
+    });
+}
+
+#[test]
+fn placement_predicts_alloc() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(8192))
+        });
+        assert_eq!(HeapArena::<Stack>::size_class(nonzero(4096)), 12);
+
+        match arena.placement(nonzero(4096), nonzero(4)).unwrap() {
+            Placement::NewHeap { size_class, heap_size } => {
+                assert_eq!(size_class, 12);
+                assert_eq!(heap_size, nonzero(8192));
+            }
+            placement => panic!("expected a new heap, not {:?}", placement),
+        }
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+
+        let placement = arena.placement(nonzero(4096), nonzero(4)).unwrap();
+        let Placement::Existing(predicted) = placement else {
+            panic!("expected an existing heap");
+        };
+        let allocation = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(predicted.arena_key, allocation.arena_key);
+        assert_eq!(predicted.range_in_heap, allocation.range_in_heap);
+    });
+}
+
+#[test]
+fn arenas_allocate_in_the_best_fitting_heap() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let first = arena.alloc(&context.device, nonzero(3072), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(2048), nonzero(4)).unwrap();
+        assert_eq!(first.arena_key.index_in_pool(), 0);
+        assert_eq!(second.arena_key.index_in_pool(), 1);
+
+        // The first heap has 1,024 bytes free and the second 2,048, so the smaller allocation
+        // goes in the first heap and leaves room for the larger one in the second.
+        let Placement::Existing(predicted) = arena.placement(nonzero(1024), nonzero(4)).unwrap()
+        else {
+            panic!("expected an existing heap");
+        };
+        let small = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        assert_eq!(small, predicted);
+        assert_eq!(small.arena_key.index_in_pool(), 0);
+        assert_eq!(small.range_in_heap, 3072..4096);
+
+        let large = arena.alloc(&context.device, nonzero(2048), nonzero(4)).unwrap();
+        assert_eq!(large.arena_key.index_in_pool(), 1);
+        assert_eq!(arena.stats().heaps.len(), 2);
+    });
+}
+
+#[test]
+fn meshes_are_packed_with_their_draw_parameters() {
+    with_context(|context| {
+        let mut meshes = MeshAllocator::<FreeList>::new(
+            nonzero(12),
+            wgpu::IndexFormat::Uint16,
+            Fixed(nonzero(4096)),
+        );
+        let count = |count| std::num::NonZeroU32::new(count).unwrap();
+        let first = meshes.alloc(&context.device, count(3), count(3)).unwrap();
+        let second = meshes.alloc(&context.device, count(4), count(6)).unwrap();
+        assert_eq!(first.allocation.arena_key, second.allocation.arena_key);
+
+        for mesh in [&first, &second] {
+            assert_eq!(mesh.vertex.start % 12, 0);
+            assert_eq!(mesh.vertex.end, mesh.index.start);
+            assert_eq!(mesh.base_vertex as u64, mesh.vertex.start / 12);
+            assert_eq!(mesh.first_index as u64, mesh.index.start / 2);
+        }
+        assert_eq!(first.vertex.end - first.vertex.start, 36);
+        assert_eq!(second.index.end - second.index.start, 12);
+        assert_eq!(second.indices(), second.first_index..(second.first_index + 6));
+
+        let vertices = [[1.0f32, 2.0, 3.0]; 4];
+        let indices = [0u16, 1, 2, 2, 3, 0];
+        meshes.write(&second, &vertices, &indices);
+        let (heap, _) = &meshes.arena()[second.allocation.arena_key];
+        heap.unmap();
+        context.submit(|encoder| meshes.arena().flush_all(encoder));
+
+        assert_eq!(
+            context.read_heap(heap, second.vertex.clone()),
+            bytemuck::cast_slice::<_, u8>(&vertices),
+        );
+        assert_eq!(
+            context.read_heap(heap, second.index.clone()),
+            bytemuck::cast_slice::<_, u8>(&indices),
+        );
+    });
+}
+
+#[test]
+fn indirect_args_are_packed_into_slots() {
+    with_context(|context| {
+        let count = |count| std::num::NonZeroU32::new(count).unwrap();
+        let mut args = IndirectArgBuffer::<DrawIndexedIndirect>::new(&context.device, count(8));
+        assert_eq!(IndirectArgBuffer::<DrawIndexedIndirect>::SLOT_SIZE, 20);
+        assert_eq!(args.alloc_slot(), Ok(0));
+        let slots = args.alloc_slots(count(3)).unwrap();
+        assert_eq!(slots, 1..4);
+        assert_eq!(args.slot_offset(slots.start), 20);
+        assert_eq!(args.alloc_slots(count(5)), Err(AllocError::OutOfMemory));
+
+        #[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+        let draw = |vertex_count| DrawIndexedIndirect {
+            vertex_count,
+            instance_count: 1,
+            base_index: 0,
+            vertex_offset: -1,
+            base_instance: 0,
+        };
+        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+        let draw = |index_count| DrawIndexedIndirect {
+            index_count,
+            instance_count: 1,
+            first_index: 0,
+            base_vertex: -1,
+            first_instance: 0,
+        };
+        // The second slot begins at an offset that is not a multiple of `MAP_ALIGNMENT`.
+        args.write_args(1, &draw(3));
+        args.write_all_args(2, &[draw(6), draw(9)]);
+        args.heap().unmap();
+        context.submit(|encoder| args.heap().flush(encoder));
+
+        let expected: Vec<u8> = [draw(3), draw(6), draw(9)]
+            .iter()
+            .flat_map(|args| args.as_bytes().to_vec())
+            .collect();
+        assert_eq!(context.read_heap(args.heap(), 20..80), expected);
+
+        unsafe { args.dealloc_slots(slots).unwrap() };
+        assert_eq!(args.alloc_slots(count(7)), Ok(1..8));
+    });
+}
+
+#[test]
+fn arenas_stay_within_their_budget() {
+    with_context(|context| {
+        let mut arena =
+            HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096))).with_budget(8192);
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(arena.reserved_bytes(), 8192);
+
+        let exceeded = AllocError::BudgetExceeded { heap_size: nonzero(4096), budget: 8192 };
+        assert_eq!(arena.placement(nonzero(4096), nonzero(4)).err(), Some(exceeded));
+        assert_eq!(arena.alloc(&context.device, nonzero(4096), nonzero(4)), Err(exceeded));
+
+        // The handler evicts the first allocation to make room, then gives up.
+        let evictable = std::sync::Arc::new(std::sync::Mutex::new(vec![first.clone()]));
+        let handler_evictable = evictable.clone();
+        arena.set_eviction_handler(move |arena, heap_size| {
+            assert_eq!(heap_size, nonzero(4096));
+            match handler_evictable.lock().unwrap().pop() {
+                Some(allocation) => {
+                    unsafe { arena.dealloc(allocation).unwrap() };
+                    true
+                }
+                None => false,
+            }
+        });
+        let allocation = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(allocation.range_in_heap, first.range_in_heap);
+        assert_ne!(allocation.generation, first.generation);
+        assert!(evictable.lock().unwrap().is_empty());
+        assert_eq!(arena.alloc(&context.device, nonzero(4096), nonzero(4)), Err(exceeded));
+        assert_eq!(arena.reserved_bytes(), 8192);
+
+        arena.set_budget(None);
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(arena.reserved_bytes(), 12288);
+    });
+}
+
+#[test]
+fn higher_priority_allocations_displace_evictable_ones() {
+    with_context(|context| {
+        let mut arena =
+            HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096))).with_budget(8192);
+        let desc = |priority, evictable| AllocDesc {
+            priority,
+            evictable,
+            ..AllocDesc::new(nonzero(4096), nonzero(4))
+        };
+        let prefetched = arena.alloc_desc(&context.device, desc(Priority::Low, true)).unwrap();
+        arena.alloc_desc(&context.device, desc(Priority::Normal, false)).unwrap();
+
+        // Nothing is displaced until the policy allows it.
+        let exceeded = AllocError::BudgetExceeded { heap_size: nonzero(4096), budget: 8192 };
+        assert_eq!(arena.preemption_policy(), PreemptionPolicy::Deny);
+        assert_eq!(arena.alloc_desc(&context.device, desc(Priority::High, false)), Err(exceeded));
+        unsafe { arena.set_preemption_policy(PreemptionPolicy::Evict) };
+        // An allocation can't displace one of equal priority.
+        assert_eq!(arena.alloc_desc(&context.device, desc(Priority::Low, true)), Err(exceeded));
+        assert!(arena.is_live(&prefetched));
+
+        let visible = arena.alloc_desc(&context.device, desc(Priority::High, false)).unwrap();
+        assert_eq!(visible.range_in_heap, prefetched.range_in_heap);
+        assert!(!arena.is_live(&prefetched));
+        assert_eq!(
+            arena.take_displaced(),
+            [Displaced {
+                allocation: prefetched,
+                priority: Priority::Low,
+                displaced_by: Priority::High,
+            }],
+        );
+        assert!(arena.take_displaced().is_empty());
+
+        // Nothing evictable is left, so the budget holds.
+        assert_eq!(arena.alloc_desc(&context.device, desc(Priority::High, false)), Err(exceeded));
+        assert_eq!(arena.reserved_bytes(), 8192);
+    });
+}
+
+#[test]
+fn arena_allocation_failures_are_reported() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let too_large = AllocError::SizeTooLargeForArena {
+            size: nonzero(8192),
+            heap_size: nonzero(4096),
+        };
+        assert_eq!(arena.placement(nonzero(8192), nonzero(4)).err(), Some(too_large));
+        assert_eq!(arena.alloc(&context.device, nonzero(8192), nonzero(4)), Err(too_large));
+        assert_eq!(arena.stats().heaps.len(), 0);
+
+        let allocation = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        let mut other = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        unsafe {
+            assert_eq!(other.dealloc(allocation.clone()), Err(AllocError::NotOwnedByAllocator));
+            assert_eq!(arena.dealloc(allocation), Ok(()));
+        }
+    });
+}
+
+#[test]
+fn diagnostics_suggest_growing_full_pools() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(8192))
+        });
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+
+        let roomy = arena.diagnose(nonzero(4096), nonzero(1));
+        assert_eq!(roomy.heaps.len(), 1);
+        assert_eq!(roomy.suggestion, Suggestion::None);
+
+        let full = arena.diagnose(nonzero(6000), nonzero(4));
+        assert_eq!(full.heaps[0].largest_free_block, Some(4096));
+        assert_eq!(full.suggestion, Suggestion::Grow);
+        assert!(full.to_string().contains("suggestion: grow"));
+    });
+}
+
+#[test]
+fn arena_keys_point_at_the_heaps_of_their_allocations() {
+    with_context(|context| {
+        let mut arena =
+            HeapArena::<FreeList>::new(HeapUsages::STORAGE, Doubling { initial: nonzero(4096) });
+        let mut allocations: Vec<_> = (0..7)
+            .map(|_| arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap())
+            .collect();
+        // Free one allocation from each heap and refill the gaps, so that later allocations land
+        // in earlier heaps.
+        for index in [6, 2, 0] {
+            unsafe { arena.dealloc(allocations.remove(index)) }.unwrap();
+        }
+        for _ in 0..3 {
+            allocations.push(arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap());
+        }
+        assert_eq!(arena.metrics().total().heaps_created, 3);
+
+        for (index, allocation) in allocations.iter().enumerate() {
+            arena.write(allocation, &[index as u8; 4096]);
+        }
+        arena.unmap();
+        // Every heap is full, so its writes coalesce into a single copy.
+        context.submit(|encoder| assert_eq!(arena.flush_dirty(encoder), 3));
+        arena.remap();
+        context.device.poll(wgpu::Maintain::Wait);
+
+        let diagnostics = arena.diagnose(nonzero(4096), nonzero(4));
+        for (index, allocation) in allocations.iter().enumerate() {
+            let (heap, _) = &arena[allocation.arena_key];
+            assert!(allocation.range_in_heap.end <= heap.size().get());
+            let contents = context.read_heap(heap, allocation.range_in_heap.clone());
+            assert!(contents.iter().all(|&byte| byte == index as u8));
+            let heap_diagnostics = &diagnostics.heaps[allocation.arena_key.index_in_pool()];
+            assert_eq!(heap_diagnostics.size, heap.size());
+        }
+    });
+}
+
+#[test]
+fn tagged_allocations_are_accounted_per_tag() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(1 << 16)));
+        let tagged = |size, tag| AllocDesc {
+            tag: Some(tag),
+            ..AllocDesc::new(nonzero(size), nonzero(4))
+        };
+        let mesh = arena.alloc_desc(&context.device, tagged(4096, "meshes")).unwrap();
+        arena.alloc_desc(&context.device, tagged(1024, "meshes")).unwrap();
+        arena.alloc_desc(&context.device, tagged(256, "uniforms")).unwrap();
+        let desc = AllocDesc::new(nonzero(512), nonzero(4));
+        arena.alloc_desc(&context.device, desc).unwrap();
+
+        let tags = arena.stats().tags;
+        assert_eq!(tags.keys().copied().collect::<Vec<_>>(), ["meshes", "uniforms"]);
+        assert_eq!(tags["meshes"].bytes_allocated, 5120);
+        assert_eq!(tags["meshes"].allocation_count, 2);
+        assert_eq!(tags["uniforms"].bytes_allocated, 256);
+
+        unsafe { arena.dealloc(mesh) }.unwrap();
+        assert_eq!(arena.stats().tags["meshes"].bytes_allocated, 1024);
+
+        arena.reset_all();
+        assert!(arena.stats().tags.is_empty());
+    });
+}
+
+#[test]
+fn pools_are_created_in_any_order() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        // No pool exists for any size class yet, so every pool up to this one is created.
+        let large = arena.alloc(&context.device, nonzero(1 << 20), nonzero(4)).unwrap();
+        let small = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(large.arena_key.size_class(), 20);
+        assert_eq!(arena.metrics().size_pools.len(), 9);
+        assert!(arena.get(large.arena_key).is_some());
+        assert!(arena.get(small.arena_key).is_some());
+
+        // Keys and allocations of pools that this arena doesn't have are rejected, not panicked on.
+        let mut other = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        let larger = other.alloc(&context.device, nonzero(1 << 22), nonzero(4)).unwrap();
+        assert!(arena.get(larger.arena_key).is_none());
+        assert!(arena.get_mut(larger.arena_key).is_none());
+        assert!(arena.diagnose(nonzero(1 << 22), nonzero(4)).heaps.is_empty());
+        assert_eq!(unsafe { arena.dealloc(larger) }, Err(AllocError::NotOwnedByAllocator));
+    });
+}
+
+#[test]
+fn segmented_allocations_span_several_heaps() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let allocation = arena
+            .alloc_segmented(&context.device, nonzero(10000), nonzero(4096), nonzero(4))
+            .unwrap();
+        let sizes: Vec<_> = allocation.segments().iter().map(|segment| segment.size()).collect();
+        assert_eq!(sizes, [4096, 4096, 1808]);
+        let offsets: Vec<_> = allocation.iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [0, 4096, 8192]);
+        assert_eq!(allocation.locate(5000), Some((1, 904)));
+        assert_eq!(allocation.locate(10000), None);
+        assert_eq!(arena.segment_bindings(&allocation).count(), 3);
+
+        // This write straddles the boundary between the first two segments.
+        arena.write_segmented(&allocation, 4000, &pattern(200));
+        arena.unmap();
+        context.submit(|encoder| assert_eq!(arena.flush_dirty(encoder), 2));
+        arena.remap();
+        context.device.poll(wgpu::Maintain::Wait);
+        let [first, second, _] = allocation.segments() else { unreachable!() };
+        let (heap, _) = &arena[first.arena_key];
+        assert_eq!(context.read_heap(heap, 4000..4096), pattern(200)[..96]);
+        let (heap, _) = &arena[second.arena_key];
+        assert_eq!(context.read_heap(heap, 0..104), pattern(200)[96..]);
+
+        // The budget leaves room for only one more heap, so the second segment can't be
+        // allocated, and the first is freed again.
+        let bytes_allocated = arena.stats().total().bytes_allocated;
+        arena.set_budget(Some(arena.reserved_bytes() + 4096));
+        let result =
+            arena.alloc_segmented(&context.device, nonzero(8192), nonzero(4096), nonzero(4));
+        assert!(matches!(result, Err(AllocError::BudgetExceeded { .. })));
+        assert_eq!(arena.stats().total().bytes_allocated, bytes_allocated);
+
+        unsafe { arena.dealloc_segmented(allocation) }.unwrap();
+        assert_eq!(arena.stats().total().bytes_allocated, 0);
+    });
+}
+
+#[test]
+fn reset_arenas_reuse_their_heaps() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        let reserved_bytes = arena.reserved_bytes();
+
+        arena.reset_all();
+        let second = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+
+        assert_eq!(arena.reserved_bytes(), reserved_bytes);
+        assert_eq!(second.range_in_heap, first.range_in_heap);
+        let total = arena.metrics().total();
+        assert_eq!(total.heaps_created, 1);
+        assert_eq!(total.bytes_allocated - total.bytes_freed, 4096);
+    });
+}
+
+#[test]
+fn reset_pools_leave_other_pools_alone() {
+    with_context(|context| {
+        let mut arena =
+            HeapArena::<FreeList>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+                context.first_alloc_size.max(nonzero(4096))
+            });
+        let small = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        let large = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        let owned = arena.alloc_owned(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_ne!(small.arena_key, large.arena_key);
+        // Nothing happens to pools that don't exist.
+        arena.reset_pool(40);
+
+        arena.reset_pool(large.arena_key.size_class());
+        // The released allocation was freed by the reset, so it must not be freed again.
+        drop(owned);
+        assert_eq!(arena.reclaim(), 0);
+        // Both heaps of the pool are empty again, so neither allocation needs a new one.
+        for _ in 0..2 {
+            let again = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+            assert_eq!(again.range_in_heap, large.range_in_heap);
+        }
+        assert_eq!(arena.metrics().total().heaps_created, 3);
+
+        // The allocation in the other pool is still live, so freeing it succeeds.
+        unsafe { arena.dealloc(small) }.unwrap();
+    });
+}
+
+#[test]
+fn dealloc_releases_trailing_empty_heaps() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        arena.set_empty_heap_policy(EmptyHeapPolicy::ReleaseTrailing);
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(arena.reserved_bytes(), 8192);
+
+        // The first heap is empty but followed by a live one, so it must be kept.
+        unsafe { arena.dealloc(first).unwrap() };
+        assert_eq!(arena.reserved_bytes(), 8192);
+
+        unsafe { arena.dealloc(second).unwrap() };
+        assert_eq!(arena.reserved_bytes(), 0);
+        assert_eq!(arena.frame_counters().deallocations, 2);
+        let total = arena.metrics().total();
+        assert_eq!(total.heaps_destroyed, 2);
+        assert_eq!(total.bytes_allocated, total.bytes_freed);
+    });
+}
+
+#[test]
+fn keys_of_destroyed_heaps_go_stale() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        arena.set_empty_heap_policy(EmptyHeapPolicy::ReleaseTrailing);
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        unsafe { arena.dealloc(first.clone()).unwrap() };
+
+        // The new heap takes the place of the destroyed one, but not its key.
+        let second = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(second.arena_key.index_in_pool(), first.arena_key.index_in_pool());
+        assert_ne!(second.arena_key, first.arena_key);
+        assert!(arena.get(first.arena_key).is_none());
+        assert!(arena.try_heap_for(&first).is_none());
+        assert!(arena.try_heap_for(&second).is_some());
+        assert_eq!(unsafe { arena.dealloc(first) }, Err(AllocError::StaleKey));
+        unsafe { arena.dealloc(second).unwrap() };
+    });
+}
+
+#[test]
+fn cached_arenas_evict_the_least_recently_used_entries() {
+    with_context(|context| {
+        let arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        })
+        .with_budget(8192);
+        let mut cache = CachedArena::new(arena);
+        let device = &context.device;
+        cache.get_or_alloc(device, "a", nonzero(4096), 1, || [1; 4096]).unwrap();
+        cache.get_or_alloc(device, "b", nonzero(4096), 2, || [2; 4096]).unwrap();
+        // Hits don't produce the data again.
+        let miss = || -> [u8; 0] { unreachable!() };
+        cache.get_or_alloc(device, "a", nonzero(4096), 3, miss).unwrap();
+
+        // Both entries may still be in use, so neither can be evicted.
+        let result = cache.get_or_alloc(device, "c", nonzero(4096), 4, || [3; 4096]);
+        assert!(matches!(result, Err(AllocError::BudgetExceeded { .. })));
+
+        // "b" was used less recently than "a", so it makes room for "c".
+        assert_eq!(cache.retire_completed(3), 0);
+        let c = cache.get_or_alloc(device, "c", nonzero(4096), 4, || [3; 4096]).unwrap().clone();
+        assert!(cache.contains_key(&"a"));
+        assert!(!cache.contains_key(&"b"));
+        assert_eq!(cache.get(&"c", 4), Some(&c));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.arena().reserved_bytes(), 8192);
+
+        // "c" is still in use, so only "a" can be evicted.
+        assert_eq!(cache.evict_lru(), Some("a"));
+        assert_eq!(cache.evict_lru(), None);
+    });
+}
+
+#[test]
+fn cached_arenas_only_evict_entries_that_make_room() {
+    with_context(|context| {
+        let arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        })
+        .with_budget(8192);
+        let mut cache = CachedArena::new(arena);
+        let device = &context.device;
+        cache.get_or_alloc(device, "tiny", nonzero(256), 1, || [1; 256]).unwrap();
+        cache.get_or_alloc(device, "a", nonzero(4096), 1, || [2; 4096]).unwrap();
+        cache.retire_completed(1);
+
+        // Nothing is in the pool of this allocation, so evicting wouldn't help.
+        let result = cache.get_or_alloc(device, "huge", nonzero(8192), 2, || [3; 8192]);
+        assert!(matches!(result, Err(AllocError::BudgetExceeded { .. })));
+        assert_eq!(cache.len(), 2);
+
+        // "tiny" was used least recently, but its heap can't hold this allocation.
+        cache.get_or_alloc(device, "b", nonzero(4096), 2, || [4; 4096]).unwrap();
+        assert!(cache.contains_key(&"tiny"));
+        assert!(!cache.contains_key(&"a"));
+        assert_eq!(cache.arena().reserved_bytes(), 4096 + 256);
+    });
+}
+
+#[test]
+fn cached_arenas_write_data_shorter_than_entries() {
+    with_context(|context| {
+        let arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let mut cache = CachedArena::new(arena);
+        let entry = cache
+            .get_or_alloc(&context.device, "short", nonzero(256), 1, || pattern(10))
+            .unwrap()
+            .clone();
+        cache.arena().unmap();
+        context.submit(|encoder| cache.arena().flush_range(encoder, &entry));
+
+        let (heap, _) = &cache.arena()[entry.arena_key];
+        let start = entry.range_in_heap.start;
+        assert_eq!(context.read_heap(heap, start..(start + 10)), pattern(10));
+    });
+}
+
+#[test]
+fn heaps_grow_instead_of_multiplying() {
+    with_context(|context| {
+        // The GPU buffer is carried over by a copy.
+        let mut heap = Heap::with_upload_strategy(
+            &context.device,
+            nonzero(256),
+            HeapUsages::STORAGE,
+            UploadStrategy::QueueWrite,
+        );
+        heap.write_via(&context.queue, 0..256, &pattern(256));
+        context.submit(|encoder| heap.grow(&context.device, encoder, nonzero(1024)));
+        assert_eq!(heap.size().get(), 1024);
+        assert_eq!(context.read_heap(&heap, 0..256), pattern(256));
+
+        // Unflushed staging memory is carried over on the CPU.
+        let mut arena = HeapArena::<FreeList>::new(
+            HeapUsages::STORAGE,
+            |context: NewHeapSizeContext| context.first_alloc_size,
+        );
+        arena.set_heap_growth(HeapGrowth::UpTo(nonzero(16384)));
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        arena.write(&first, &pattern(4096));
+        let mut second = None;
+        context.submit(|encoder| {
+            second = arena.alloc_or_grow(&context.device, encoder, nonzero(4096), nonzero(4)).ok();
+        });
+        let second = second.unwrap();
+        arena.write(&second, &[0xff; 4096]);
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_range(encoder, &first);
+            arena.flush_range(encoder, &second);
+        });
+
+        assert_eq!(first.arena_key, second.arena_key);
+        assert_eq!(arena.reserved_bytes(), 8192);
+        let total = arena.metrics().total();
+        assert_eq!((total.heaps_created, total.heaps_grown), (1, 1));
+        let (heap, _) = &arena[first.arena_key];
+        assert_eq!(context.read_heap(heap, first.range_in_heap.clone()), pattern(4096));
+        assert!(context.read_heap(heap, second.range_in_heap).iter().all(|&byte| byte == 0xff));
+    });
+}
+
+#[test]
+fn heaps_are_labeled_by_arena_and_pool() {
+    with_context(|context| {
+        let heap = Heap::with_descriptor(&context.device, &HeapDescriptor {
+            label: Some("sprites"),
+            ..HeapDescriptor::new(nonzero(256), HeapUsages::VERTEX)
+        });
+        assert_eq!(heap.label(), Some("sprites"));
+
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::UNIFORM, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        let unlabeled = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        arena.set_heap_label_prefix("arena-uniform");
+        let tiny = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+
+        let label = |allocation: &Allocation| arena[allocation.arena_key].0.label();
+        assert_eq!(label(&unlabeled), None);
+        assert_eq!(label(&tiny), Some("arena-uniform-tiny-heap1"));
+        assert_eq!(label(&first), Some("arena-uniform-pool12-heap0"));
+        assert_eq!(label(&second), Some("arena-uniform-pool12-heap1"));
+    });
+}
+
+#[test]
+fn stats_report_usage_and_fragmentation() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        unsafe { arena.dealloc(second).unwrap() };
+
+        let stats = arena.stats();
+        assert_eq!(stats.heaps.len(), 1);
+        assert_eq!(
+            stats.total(),
+            Stats {
+                bytes_allocated: 2048,
+                bytes_free: 2048,
+                largest_free_block: Some(1024),
+                allocation_count: 2,
+                high_water_mark: 3072,
+            },
+        );
+        assert_eq!(stats.total().fragmentation(), Some(0.5));
+
+        arena.reset_all();
+        let total = arena.stats().total();
+        assert_eq!((total.allocation_count, total.high_water_mark), (0, 3072));
+        assert_eq!(total.fragmentation(), Some(0.0));
+    });
+}
+
+#[test]
+fn compaction_moves_allocations_out_of_sparse_heaps() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(8192)));
+        let first = arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        // This alignment keeps the second allocation out of the first heap.
+        let second = arena.alloc(&context.device, nonzero(4096), nonzero(8192)).unwrap();
+        assert_ne!(first.arena_key, second.arena_key);
+        arena.write(&first, &pattern(4096));
+        arena.write(&second, &[0xff; 4096]);
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_range(encoder, &first);
+            arena.flush_range(encoder, &second);
+        });
+        arena.remap();
+        context.device.poll(wgpu::Maintain::Wait);
+
+        let mut report = CompactionReport::default();
+        context.submit(|encoder| report = unsafe { arena.compact(encoder) });
+        assert_eq!(report.relocations.len(), 2);
+        assert_eq!(arena.reserved_bytes(), 8192);
+        assert_eq!(arena.metrics().total().heaps_destroyed, 1);
+        assert_eq!((report.heaps_destroyed, report.bytes_reclaimed), (1, 8192));
+        assert_eq!((report.allocations_moved, report.bytes_moved), (1, 4096));
+        assert_eq!(report.copies_recorded, 1);
+        assert_eq!(report.remaining.bytes_allocated, 8192);
+        assert_eq!(report.fragmentation(), Some(0.0));
+
+        let mut relocated = report.relocations.into_iter().map(|relocation| relocation.to);
+        let (heap, _) = &arena[relocated.next().unwrap().arena_key];
+        let mut contents = context.read_heap(heap, 0..8192);
+        assert!(contents.drain(..4096).all(|byte| byte == 0xff));
+        assert_eq!(contents, pattern(4096));
+        assert!(relocated.all(|allocation| allocation.range_in_heap == (4096..8192)));
+    });
+}
+
+#[test]
+fn incremental_compaction_stays_within_its_budget() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let allocations: Vec<_> = (0..16)
+            .map(|_| arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap())
+            .collect();
+        // Leave one allocation in each of the four heaps.
+        for allocation in allocations.into_iter().filter(|allocation| allocation.offset() != 0) {
+            unsafe { arena.dealloc(allocation) }.unwrap();
+        }
+        assert_eq!(arena.reserved_bytes(), 4 * 4096);
+        assert_eq!(arena.compaction_progress(), None);
+
+        // Heaps whose evacuation exceeds the whole budget are left alone.
+        let mut reports = Vec::new();
+        let budget = CompactionBudget::bytes(512);
+        context.submit(|encoder| {
+            reports.push(unsafe { arena.compact_incremental(encoder, budget) });
+        });
+        let report = reports.pop().unwrap();
+        assert_eq!(report.allocations_moved, 0);
+        assert!(report.is_complete());
+
+        let budget = CompactionBudget::allocations(1);
+        for _ in 0..3 {
+            context.submit(|encoder| {
+                reports.push(unsafe { arena.compact_incremental(encoder, budget) });
+            });
+        }
+
+        for report in reports.iter() {
+            assert_eq!((report.allocations_moved, report.heaps_destroyed), (1, 1));
+        }
+        let progress: Vec<_> = reports.iter().map(|report| report.progress).collect();
+        assert_eq!(progress, [1.0 / 3.0, 2.0 / 3.0, 1.0]);
+        assert!(reports[2].is_complete());
+        assert_eq!(arena.compaction_progress(), None);
+        assert_eq!(arena.reserved_bytes(), 4096);
+        assert_eq!(reports[2].remaining.bytes_allocated, 4096);
+    });
+}
+
+#[test]
+fn allocations_can_be_stored_and_looked_up_repeatedly() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let allocation = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        let stored = std::collections::HashSet::from([allocation.clone()]);
+        assert!(stored.contains(&allocation));
+
+        let key = allocation.arena_key;
+        assert_eq!(arena[key].0.size().get(), 4096);
+        assert_eq!(arena[key].0.usage(), HeapUsages::STORAGE);
+        assert_eq!((key.size_class(), key.index_in_pool()), (8, 0));
+        assert_eq!((allocation.offset(), allocation.size()), (3840, 256));
+
+        assert_eq!(arena.heap_for(&allocation).id(), arena[key].0.id());
+        let info = arena.allocation_info(&allocation).unwrap();
+        assert_eq!(
+            info,
+            AllocationInfo {
+                heap_size: nonzero(4096),
+                size_class: 8,
+                heap_index: 0,
+                offset: 3840,
+                size: 256,
+            },
+        );
+        let other = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        assert_eq!(other.allocation_info(&allocation), None);
+    });
+}
+
+#[test]
+fn dropped_owned_allocations_are_reclaimed() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        let first = arena.alloc_owned(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let second = arena.alloc_owned(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let kept = arena.alloc_owned(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let kept = kept.into_inner();
+        arena.write(&first, &pattern(1024));
+
+        // A stack can only free its top allocation, so the second must be freed before the first.
+        drop(first);
+        drop(second);
+        assert_eq!(arena.reclaim(), 0);
+
+        unsafe { arena.dealloc(kept).unwrap() };
+        assert_eq!(arena.reclaim(), 2);
+        let total = arena.metrics().total();
+        assert_eq!(total.bytes_allocated, total.bytes_freed);
+    });
+}
+
+#[test]
+fn structs_are_written_with_wgsl_padding() {
+    wgpu_allocators::wgsl_struct! {
+        struct Inner {
+            value: f32,
+        }
+    }
+    wgpu_allocators::wgsl_struct! {
+        struct Outer {
+            scale: f32,
+            inner: Inner,
+            color: [f32; 3],
+        }
+    }
+
+    let storage = StructLayout::new(LayoutRules::Std430).field::<f32>().field::<Inner>();
+    assert_eq!(storage.field::<[f32; 3]>().offsets(), [0, 4, 16]);
+    assert_eq!(Outer::layout(LayoutRules::Std430).size, 32);
+    let uniform = StructLayout::new(LayoutRules::Std140).field::<f32>().field::<Inner>();
+    assert_eq!(uniform.field::<[f32; 3]>().offsets(), [0, 16, 32]);
+    assert_eq!(Outer::layout(LayoutRules::Std140).size, 48);
+
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::UNIFORM);
+        let value = Outer { scale: 1.0, inner: Inner { value: 2.0 }, color: [3.0, 4.0, 5.0] };
+        heap.write_struct(0..48, &value);
+        heap.unmap();
+        context.submit(|encoder| heap.flush(encoder));
+
+        let mut expected = vec![0; 48];
+        for (offset, float) in [(0, 1.0f32), (16, 2.0), (32, 3.0), (36, 4.0), (40, 5.0)] {
+            expected[offset..(offset + 4)].copy_from_slice(&float.to_ne_bytes());
+        }
+        assert_eq!(context.read_heap(&heap, 0..48), expected);
+    });
+}
+
+#[test]
+fn frame_heaps_rotate_between_copies() {
+    with_context(|context| {
+        let descriptor = HeapDescriptor {
+            label: Some("frames"),
+            ..HeapDescriptor::new(nonzero(256), HeapUsages::STORAGE)
+        };
+        let mut heap = FrameHeap::<2>::with_descriptor(&context.device, &descriptor);
+        assert_eq!(heap.heaps()[1].label(), Some("frames-frame1"));
+
+        for frame in 0..2 {
+            heap.begin_frame(frame);
+            assert_eq!(heap.frame_index(), frame);
+            heap.write(0..8, &[frame as u8 + 1; 8]);
+            heap.unmap();
+            context.submit(|encoder| heap.flush(encoder));
+        }
+        assert_eq!(context.read_heap(&heap.heaps()[0], 0..8), [1; 8]);
+        assert_eq!(context.read_heap(&heap.heaps()[1], 0..8), [2; 8]);
+
+        // Frame 2 reuses the heap of frame 0, which was remapped when frame 1 began.
+        heap.begin_frame(2);
+        assert_eq!(heap.frame_index(), 0);
+        context.device.poll(wgpu::Maintain::Wait);
+        assert_eq!(heap.map_state(), MapState::Mapped);
+        heap.write(0..8, &[3; 8]);
+    });
+}
+
+#[test]
+fn bind_groups_are_cached_until_their_heap_is_destroyed() {
+    with_context(|context| {
+        let layout = context.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let mut cache = BindGroupCache::new();
+        let layout = cache.add_layout(layout);
+
+        let mut arena = HeapArena::<FreeList>::new(
+            HeapUsages::UNIFORM,
+            |context: NewHeapSizeContext| context.first_alloc_size.max(nonzero(4096)),
+        );
+        arena.set_empty_heap_policy(EmptyHeapPolicy::ReleaseTrailing);
+        let first = arena.alloc(&context.device, nonzero(64), nonzero(256)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(64), nonzero(256)).unwrap();
+
+        let bind_group: *const wgpu::BindGroup =
+            cache.get_for(&context.device, layout, &arena, &first);
+        assert_eq!(bind_group, cache.get_for(&context.device, layout, &arena, &first) as *const _);
+        cache.get_for(&context.device, layout, &arena, &second);
+        assert_eq!(cache.len(), 2);
+
+        cache.prune(&arena);
+        assert_eq!(cache.len(), 2);
+        unsafe {
+            arena.dealloc(first).unwrap();
+            arena.dealloc(second).unwrap();
+        }
+        cache.prune(&arena);
+        assert!(cache.is_empty());
+    });
+}
+
+#[test]
+fn arenas_with_limits_align_for_dynamic_offsets() {
+    with_context(|context| {
+        let limits = context.device.limits();
+        let mut arena =
+            HeapArena::<FreeList>::with_limits(HeapUsages::UNIFORM, Fixed(nonzero(4096)), &limits);
+        let alignment = u64::from(limits.min_uniform_buffer_offset_alignment);
+        assert_eq!(arena.min_alignment().get(), alignment);
+
+        let first = arena.alloc(&context.device, nonzero(32), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(64), nonzero(4)).unwrap();
+        assert_eq!(u64::from(arena.dynamic_offset(&second)), second.offset());
+        assert_eq!(second.offset() % alignment, 0);
+        assert_eq!(arena.dynamic_binding(&first).offset, 0);
+        assert_eq!(arena.dynamic_binding(&second).size, Some(nonzero(64)));
+
+        // Requested alignments are combined with the minimum rather than replaced by it.
+        arena.set_min_alignment(nonzero(12));
+        let third = arena.alloc(&context.device, nonzero(64), nonzero(8)).unwrap();
+        assert_eq!(third.offset() % 24, 0);
+    });
+}
+
+#[test]
+fn shared_arenas_allocate_from_many_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedHeapArena<FreeList>>();
+
+    with_context(|context| {
+        let shard_count = std::num::NonZeroUsize::new(2).unwrap();
+        let arena = SharedHeapArena::<FreeList>::new(
+            HeapUsages::STORAGE,
+            |context: NewHeapSizeContext| context.first_alloc_size.max(nonzero(4096)),
+            shard_count,
+        );
+
+        let allocations: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4u8)
+                .map(|thread| {
+                    let arena = &arena;
+                    scope.spawn(move || {
+                        (0..8)
+                            .map(|_| {
+                                let allocation =
+                                    arena.alloc(&context.device, nonzero(64), nonzero(8)).unwrap();
+                                arena.write(&allocation, &[thread; 64]);
+
+                                (thread, allocation)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+        });
+
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_dirty(encoder);
+        });
+        for (thread, allocation) in allocations.iter() {
+            let shard = arena.lock(allocation.shard);
+            let (heap, _) = &shard[allocation.allocation.arena_key];
+            let range = allocation.allocation.range_in_heap.clone();
+            assert_eq!(context.read_heap(heap, range), [*thread; 64]);
+        }
+        for (_, allocation) in allocations {
+            unsafe { arena.dealloc(allocation).unwrap() };
+        }
+    });
+}
+
+#[test]
+fn arenas_visit_every_heap() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(
+            HeapUsages::STORAGE,
+            |context: NewHeapSizeContext| context.first_alloc_size.max(nonzero(4096)),
+        );
+        let small = arena.alloc(&context.device, nonzero(16), nonzero(4)).unwrap();
+        let large = arena.alloc(&context.device, nonzero(8192), nonzero(4)).unwrap();
+        arena.write(&small, &pattern(16));
+        arena.write(&large, &pattern(8192));
+
+        let mut pools = Vec::new();
+        arena.for_each_pool(|size_class, heaps| pools.push((size_class, heaps.len())));
+        assert_eq!(pools, [(0, 1), (13, 1)]);
+        assert_eq!(arena.heaps().count(), 2);
+        assert_eq!(arena.heaps_mut().count(), 2);
+
+        for (heap, _) in arena.heaps() {
+            heap.unmap();
+        }
+        context.submit(|encoder| arena.flush_all(encoder));
+        assert_eq!(arena.frame_counters().flush_commands, 2);
+
+        let (heap, _) = &arena[large.arena_key];
+        assert_eq!(context.read_heap(heap, large.range_in_heap.clone()), pattern(8192));
+    });
+}
+
+#[test]
+fn deferred_deallocations_wait_for_their_fence() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        let first = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        unsafe {
+            arena.dealloc_deferred(first.clone(), 1);
+            arena.dealloc_deferred(second, 2);
+        }
+
+        assert_eq!(arena.retire_completed(0), 0);
+        // `first` is due, but the stack can't free it while `second` is on top.
+        assert_eq!(arena.retire_completed(1), 0);
+        assert_eq!(arena.retiring_count(), 2);
+        assert_eq!(arena.retire_completed(2), 2);
+        assert_eq!(arena.retiring_count(), 0);
+
+        let reused = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        assert_eq!(reused.arena_key, first.arena_key);
+        assert_eq!(reused.range_in_heap, first.range_in_heap);
+        assert_ne!(reused.generation, first.generation);
+    });
+}
+
+#[test]
+fn freed_allocations_are_told_apart_from_reused_ones() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let freed = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert!(arena.is_live(&freed));
+        unsafe { arena.dealloc(freed.clone()) }.unwrap();
+        assert!(!arena.is_live(&freed));
+
+        let reused = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert_eq!(reused.range_in_heap, freed.range_in_heap);
+        assert!(arena.is_live(&reused));
+        assert!(!arena.is_live(&freed));
+        let start = reused.range_in_heap.start;
+        let part = Allocation { range_in_heap: start..(start + 16), ..reused.clone() };
+        assert!(arena.is_live(&part));
+        assert_eq!(unsafe { arena.dealloc(freed.clone()) }, Err(AllocError::NotOwnedByAllocator));
+        // With `track-allocs`, freeing part of an allocation panics instead (see
+        // `tracked_arenas_catch_invalid_deallocations`).
+        if !cfg!(feature = "track-allocs") {
+            let result = unsafe { arena.dealloc(part.clone()) };
+            assert_eq!(result, Err(AllocError::NotOwnedByAllocator));
+            assert!(arena.is_live(&reused));
+            assert_eq!(arena.allocated_bytes(), 256);
+        }
+
+        if cfg!(debug_assertions) {
+            let write = catch_unwind(AssertUnwindSafe(|| arena.write(&freed, &pattern(256))));
+            assert!(write.is_err());
+            let binding = catch_unwind(AssertUnwindSafe(|| {
+                let _ = arena.binding(&freed);
+            }));
+            assert!(binding.is_err());
+            let slice = catch_unwind(AssertUnwindSafe(|| {
+                let _ = arena.slice(&freed);
+            }));
+            assert!(slice.is_err());
+        }
+        arena.write(&reused, &pattern(256));
+        arena.unmap();
+        context.submit(|encoder| arena.flush_range(encoder, &reused));
+        let _ = arena.binding(&part);
+    });
+}
+
+#[cfg(feature = "track-allocs")]
+#[test]
+fn tracked_arenas_catch_invalid_deallocations() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let first = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        arena.tag_allocation(&second, "leaked");
+
+        let start = first.range_in_heap.start;
+        let partial = Allocation { range_in_heap: start..(start + 16), ..first.clone() };
+        let result = catch_unwind(AssertUnwindSafe(|| unsafe { arena.dealloc(partial) }));
+        assert!(result.is_err());
+
+        unsafe { arena.dealloc(first.clone()) }.unwrap();
+        let result = catch_unwind(AssertUnwindSafe(|| unsafe { arena.dealloc(first) }));
+        assert!(result.is_err());
+
+        let live = arena.dump_live_allocations();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].allocation, second);
+        assert_eq!(live[0].tag.as_deref(), Some("leaked"));
+    });
+}
+
+#[cfg(feature = "snapshot")]
+#[test]
+fn restored_snapshots_allocate_like_the_original() {
+    use wgpu_allocators::{snapshot::ArenaSnapshot, Tlsf};
+
+    with_context(|context| {
+        let mut arena = HeapArena::<Tlsf>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let mut allocations: Vec<_> = (0..12)
+            .map(|i| arena.alloc(&context.device, nonzero(256 + 64 * i), nonzero(16)).unwrap())
+            .collect();
+        let mut freed = Vec::new();
+        for index in (0..12).step_by(2).rev() {
+            let allocation = allocations.remove(index);
+            unsafe { arena.dealloc(allocation.clone()) }.unwrap();
+            freed.push(allocation);
+        }
+
+        let json = serde_json::to_string(&arena.snapshot()).unwrap();
+        let snapshot: ArenaSnapshot<Tlsf> = serde_json::from_str(&json).unwrap();
+        let mut restored = HeapArena::restore(snapshot, &context.device, Fixed(nonzero(4096)));
+        assert_eq!(restored.reserved_bytes(), arena.reserved_bytes());
+        assert_eq!(restored.stats(), arena.stats());
+
+        // Allocations made before the snapshot are as valid, or as stale, as in the original.
+        assert!(allocations.iter().all(|allocation| restored.is_live(allocation)));
+        assert!(freed.iter().all(|allocation| !restored.is_live(allocation)));
+        let last = allocations.pop().unwrap();
+        unsafe { arena.dealloc(last.clone()) }.unwrap();
+        unsafe { restored.dealloc(last) }.unwrap();
+
+        for size in [64, 300, 700, 1000, 2000] {
+            let expected = arena.alloc(&context.device, nonzero(size), nonzero(16)).unwrap();
+            let actual = restored.alloc(&context.device, nonzero(size), nonzero(16)).unwrap();
+            assert_eq!(actual, expected);
+        }
+    });
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn allocations_are_written_from_memory_mapped_files() {
+    use std::{
+        fs::{self, File},
+        panic::{catch_unwind, AssertUnwindSafe},
+        process,
+    };
+    use wgpu_allocators::MMAP_CHUNK_SIZE;
+
+    with_context(|context| {
+        // The copy spans more than one chunk, and starts partway into the file.
+        let len = MMAP_CHUNK_SIZE + 4096;
+        let contents = pattern(len + 100);
+        let path = std::env::temp_dir().join(format!("wgpu-allocators-mmap-{}", process::id()));
+        fs::write(&path, &contents).unwrap();
+        let mmap = unsafe { memmap2::Mmap::map(&File::open(&path).unwrap()) }.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4 << 20)));
+        let allocation = arena.alloc(&context.device, nonzero(len as u64), nonzero(256)).unwrap();
+        arena.write_from_mmap(&allocation, &mmap, 100);
+
+        // The file is too short to fill the allocation from this far in.
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            arena.write_from_mmap(&allocation, &mmap, 101);
+        }));
+        assert!(result.is_err());
+
+        arena.unmap();
+        context.submit(|encoder| assert_eq!(arena.flush_dirty(encoder), 1));
+        let (heap, _) = &arena[allocation.arena_key];
+        assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone()), contents[100..]);
+    });
+}
+
+/// A WGSL module with one uniform binding and one storage binding that ends in a runtime-sized
+/// array.
+#[cfg(feature = "naga")]
+const LIGHTING_WGSL: &str = "
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+};
+
+struct Light {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+};
+
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(0) @binding(1) var<storage, read> lights: Lights;
+
+@compute @workgroup_size(1)
+fn main() {
+    let light = lights.lights[0];
+    let x = camera.position + light.color * f32(lights.count);
+}
+";
+
+#[cfg(feature = "naga")]
+#[test]
+fn binding_layouts_are_reflected_from_wgsl() {
+    use wgpu_allocators::reflect::{BindingLayout, ReflectError};
+
+    let module = naga::front::wgsl::parse_str(LIGHTING_WGSL).unwrap();
+
+    // The `vec3` after the matrix ends at 76 bytes, and the struct is padded to its alignment.
+    let camera = BindingLayout::reflect(&module, "camera").unwrap();
+    assert_eq!(camera, BindingLayout {
+        fixed_size: 80,
+        runtime_array_stride: None,
+        alignment: nonzero(16),
+    });
+    assert_eq!(BindingLayout::reflect(&module, "Camera").unwrap(), camera);
+    assert_eq!(camera.size_with_len(0), 80);
+
+    // The array is aligned to its 16-byte elements, each of which is padded to 32 bytes.
+    let lights = BindingLayout::reflect(&module, "lights").unwrap();
+    assert_eq!(lights, BindingLayout {
+        fixed_size: 16,
+        runtime_array_stride: Some(nonzero(32)),
+        alignment: nonzero(16),
+    });
+    assert_eq!(lights.size_with_len(0), 16);
+    assert_eq!(lights.size_with_len(3), 112);
+
+    assert_eq!(
+        BindingLayout::reflect(&module, "shadows"),
+        Err(ReflectError::NotFound("shadows".to_owned())),
+    );
+}
+
+#[cfg(feature = "naga")]
+#[test]
+fn reflected_bindings_are_allocated_at_bindable_offsets() {
+    use wgpu_allocators::reflect::ReflectError;
+
+    with_context(|context| {
+        let module = naga::front::wgsl::parse_str(LIGHTING_WGSL).unwrap();
+        let limits = context.device.limits();
+        let offset_alignment: wgpu::BufferAddress = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .into();
+        let mut arena = HeapArena::<FreeList>::new(
+            HeapUsages::UNIFORM | HeapUsages::STORAGE,
+            Fixed(nonzero(4096)),
+        );
+
+        let camera = arena.alloc_for_binding(&context.device, &module, "camera").unwrap();
+        assert_eq!(camera.size(), 80);
+        let lights = arena.alloc_for_binding_with_len(&context.device, &module, "lights", 3);
+        let lights = lights.unwrap();
+        assert_eq!(lights.size(), 112);
+        for allocation in [&camera, &lights] {
+            assert_eq!(allocation.range_in_heap.start % offset_alignment, 0);
+        }
+
+        assert_eq!(
+            arena.alloc_for_binding(&context.device, &module, "lights"),
+            Err(ReflectError::RuntimeSized),
+        );
+        assert!(matches!(
+            arena.alloc_for_binding(&context.device, &module, "shadows"),
+            Err(ReflectError::NotFound(_)),
+        ));
+    });
+}
+
+#[cfg(feature = "compat")]
+#[test]
+fn compat_allocators_free_through_their_arenas() {
+    use wgpu_allocators::compat::{
+        self,
+        AllocationCreateDesc,
+        AllocationError,
+        MemoryLocation,
+        MemoryRequirements,
+    };
+
+    with_context(|context| {
+        let mut allocator = compat::Allocator::<FreeList>::new(|_| nonzero(4096));
+        let desc = AllocationCreateDesc {
+            name: "vertices",
+            requirements: MemoryRequirements { size: 256, alignment: 4 },
+            location: MemoryLocation::CpuToGpu,
+            usage: HeapUsages::VERTEX,
+        };
+        let allocated_bytes = |allocator: &compat::Allocator<FreeList>| {
+            allocator.arena(HeapUsages::VERTEX).unwrap().allocated_bytes()
+        };
+
+        let first = allocator.allocate(&context.device, &desc).unwrap();
+        let offset = first.offset();
+        assert_eq!(allocated_bytes(&allocator), 256);
+        allocator.free(first).unwrap();
+        assert_eq!(allocated_bytes(&allocator), 0);
+
+        // The freed range is reused.
+        let second = allocator.allocate(&context.device, &desc).unwrap();
+        assert_eq!(second.offset(), offset);
+        assert_eq!(allocated_bytes(&allocator), 256);
+
+        let mut other = compat::Allocator::<FreeList>::new(|_| nonzero(4096));
+        assert_eq!(other.free(second), Err(AllocationError::NotOwned));
+        assert_eq!(allocated_bytes(&allocator), 256);
+    });
+}
+
+#[test]
+fn guard_bands_catch_writes_past_allocations() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        arena.set_guard_size(Some(nonzero(16)));
+        let first = arena.alloc(&context.device, nonzero(10), nonzero(1)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(64), nonzero(64)).unwrap();
+        assert_eq!(first.range_in_heap.end - first.range_in_heap.start, 10);
+        assert_eq!(second.range_in_heap.start % 64, 0);
+
+        // Overrun the first allocation by 2 bytes and underrun the second by 1.
+        let overrun = first.range_in_heap.start..(first.range_in_heap.end + 2);
+        arena[first.arena_key].0.write(overrun, &[1; 12]);
+        let underrun = (second.range_in_heap.start - 1)..second.range_in_heap.start;
+        arena[second.arena_key].0.write(underrun, &[2]);
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_dirty(encoder);
+        });
+        arena.remap();
+        context.device.poll(wgpu::Maintain::Wait);
+
+        let violations = arena.verify_canaries(&context.device, &context.queue);
+        assert_eq!(violations, [
+            CanaryViolation {
+                allocation: first.clone(),
+                side: GuardSide::Back,
+                first_corrupted: first.range_in_heap.end,
+                corrupted_bytes: 2,
+            },
+            CanaryViolation {
+                allocation: second.clone(),
+                side: GuardSide::Front,
+                first_corrupted: second.range_in_heap.start - 1,
+                corrupted_bytes: 1,
+            },
+        ]);
+
+        unsafe { arena.dealloc(first) }.unwrap();
+        unsafe { arena.dealloc(second) }.unwrap();
+        assert_eq!(arena.stats().total().allocation_count, 0);
+        assert!(arena.verify_canaries(&context.device, &context.queue).is_empty());
+    });
+}
+
+#[test]
+fn unmapped_heaps_refuse_writes_until_remapped() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        assert_eq!(heap.map_state(), MapState::Mapped);
+        heap.unmap();
+        let error = heap.checked_write(0..4, &[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(error.state, MapState::Unmapped);
+
+        let mapping = heap.remap();
+        assert_eq!(heap.map_state(), MapState::Pending);
+        context.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).unwrap();
+
+        assert_eq!(heap.map_state(), MapState::Mapped);
+        heap.checked_write(0..4, &[1, 2, 3, 4]).unwrap();
+    });
+}
+
+#[test]
+fn async_writes_wait_for_the_staging_buffer_to_be_mapped() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.unmap();
+        pollster::block_on(heap.write_async(&context.device, 0..8, &pattern(8))).unwrap();
+        assert_eq!(heap.map_state(), MapState::Mapped);
+
+        // A mapping that was already requested is waited on rather than requested again.
+        heap.unmap();
+        let _mapping = heap.remap();
+        pollster::block_on(heap.write_async(&context.device, 8..16, &pattern(8))).unwrap();
+        heap.unmap();
+        context.submit(|encoder| heap.flush(encoder));
+        assert_eq!(context.read_heap(&heap, 0..16), [pattern(8), pattern(8)].concat());
+
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let first = arena.alloc(&context.device, nonzero(32), nonzero(4)).unwrap();
+        arena.unmap();
+        let second = pollster::block_on(arena.alloc_and_write_async(
+            &context.device,
+            &context.queue,
+            &pattern(30),
+            nonzero(4),
+        ))
+        .unwrap();
+        assert_eq!(second.arena_key, first.arena_key);
+        assert_eq!(second.size(), 32);
+        assert_eq!(arena.frame_counters().bytes_uploaded, 32);
+        arena.unmap();
+        context.submit(|encoder| {
+            arena.flush_dirty(encoder);
+        });
+        let (heap, _) = &arena[second.arena_key];
+        assert_eq!(context.read_heap(heap, second.range_in_heap.clone())[..30], pattern(30));
+    });
+}
+
+#[test]
+fn write_views_serialize_into_staging_memory() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.get_write_view(4..12).copy_from_slice(&pattern(8));
+        {
+            let mut floats = heap.get_write_view_as::<f32>(64..80);
+            assert_eq!(floats.len(), 4);
+            for (i, float) in floats.iter_mut().enumerate() {
+                *float = i as f32;
+            }
+        }
+        heap.unmap();
+        assert!(heap.checked_get_write_view(0..4).is_err());
+        context.submit(|encoder| {
+            heap.flush_dirty(encoder);
+        });
+
+        assert_eq!(context.read_heap(&heap, 4..12), pattern(8));
+        assert_eq!(
+            context.read_heap(&heap, 64..80),
+            bytemuck::cast_slice::<_, u8>(&[0.0f32, 1.0, 2.0, 3.0]),
+        );
+    });
+}
+
+#[test]
+fn invalid_bindings_are_reported() {
+    with_context(|context| {
+        let limits = context.device.limits();
+        let max_size = u64::from(limits.max_uniform_buffer_binding_size);
+        let alignment = u64::from(limits.min_uniform_buffer_offset_alignment);
+        let heap = Heap::new(&context.device, nonzero(2 * max_size), HeapUsages::UNIFORM);
+
+        heap.write(0..16, &pattern(16));
+        assert_eq!(
+            heap.checked_binding(0..64).err(),
+            Some(BindingError::Unflushed { range: 0..64, unflushed: 0..16 }),
+        );
+        heap.unmap();
+        context.submit(|encoder| heap.flush_range(encoder, 0..16));
+        assert!(heap.checked_binding(0..64).is_ok());
+
+        assert_eq!(
+            heap.checked_binding(4..68).err(),
+            Some(BindingError::Misaligned { range: 4..68, alignment }),
+        );
+        let too_large = 0..(max_size + alignment);
+        assert_eq!(
+            heap.checked_binding(too_large.clone()).err(),
+            Some(BindingError::TooLarge { range: too_large, max_size }),
+        );
+        assert!(heap.checked_binding(alignment..(alignment + max_size)).is_ok());
+    });
+}
+
+#[test]
+fn bindings_and_slices_check_the_usage_of_their_heap() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::VERTEX);
+        let uniform = wgpu::BufferBindingType::Uniform;
+        assert_eq!(
+            heap.checked_binding(0..64).err(),
+            Some(BindingError::MissingUsage {
+                required: HeapUsages::UNIFORM | HeapUsages::STORAGE,
+                usage: HeapUsages::VERTEX,
+            }),
+        );
+        assert_eq!(
+            heap.checked_binding_as(0..64, uniform).err(),
+            Some(BindingError::MissingUsage {
+                required: HeapUsages::UNIFORM,
+                usage: HeapUsages::VERTEX,
+            }),
+        );
+        assert!(heap.checked_slice(0..64, HeapUsages::VERTEX).is_ok());
+        assert_eq!(
+            heap.checked_slice(0..64, HeapUsages::INDEX).err(),
+            Some(BindingError::MissingUsage {
+                required: HeapUsages::INDEX,
+                usage: HeapUsages::VERTEX,
+            }),
+        );
+        assert_eq!(
+            heap.checked_slice(128..512, HeapUsages::VERTEX).err(),
+            Some(BindingError::OutOfBounds { range: 128..512, heap_size: nonzero(256) }),
+        );
+
+        // Storage bindings are checked against the storage limits only.
+        let heap = Heap::new(
+            &context.device,
+            nonzero(256),
+            HeapUsages::UNIFORM | HeapUsages::STORAGE,
+        );
+        let storage = wgpu::BufferBindingType::Storage { read_only: true };
+        let alignment = u64::from(context.device.limits().min_storage_buffer_offset_alignment);
+        assert!(heap.checked_binding_as(0..64, uniform).is_ok());
+        assert!(heap.checked_binding_as(0..64, storage).is_ok());
+        assert_eq!(
+            heap.checked_binding_as(4..68, storage).err(),
+            Some(BindingError::Misaligned { range: 4..68, alignment }),
+        );
+    });
+}
+
+#[test]
+fn flushing_a_whole_heap_round_trips() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(512), HeapUsages::VERTEX);
+        heap.write(0..512, &pattern(512));
+        flush_all(context, &heap);
+
+        assert_eq!(context.read_heap(&heap, 0..512), pattern(512));
+    });
+}
+
+#[test]
+fn flush_dirty_coalesces_writes() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.write(0..8, &pattern(8));
+        heap.write(8..16, &pattern(8));
+        heap.write(8..12, &[9; 4]);
+        heap.write(128..132, &[1, 2, 3, 4]);
+        heap.unmap();
+
+        let mut ranges = Vec::new();
+        context.submit(|encoder| ranges = heap.flush_dirty(encoder));
+        assert_eq!(ranges, [0..16, 128..132]);
+        assert!(heap.flush_dirty(&mut context.device.create_command_encoder(&Default::default()))
+            .is_empty());
+
+        let mut expected = pattern(8);
+        expected.extend(pattern(8));
+        expected[8..12].copy_from_slice(&[9; 4]);
+        assert_eq!(context.read_heap(&heap, 0..16), expected);
+        assert_eq!(context.read_heap(&heap, 128..132), [1, 2, 3, 4]);
+    });
+}
+
+#[test]
+fn clear_ranges_zeroes_only_aligned_subranges() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(64), HeapUsages::STORAGE);
+        heap.write(0..64, &pattern(64));
+        flush_all(context, &heap);
+
+        let mut ranges = vec![8..16, 4..8, 33..42];
+        context.submit(|encoder| heap.clear_ranges(encoder, &mut ranges));
+        assert_eq!(ranges, [4..16, 36..40]);
+
+        let mut expected = pattern(64);
+        expected[4..16].fill(0);
+        expected[36..40].fill(0);
+        assert_eq!(context.read_heap(&heap, 0..64), expected);
+    });
+}
+
+#[test]
+fn cleared_ranges_stay_zeroed_after_flushing() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(64), HeapUsages::STORAGE);
+        heap.write(0..64, &pattern(64));
+        flush_all(context, &heap);
+        pollster::block_on(heap.ensure_mapped(&context.device)).unwrap();
+
+        // The unflushed write is superseded by the clear.
+        heap.write(0..8, &[0xff; 8]);
+        context.submit(|encoder| {
+            heap.clear(encoder, 0..16);
+            assert!(heap.flush_dirty(encoder).is_empty());
+        });
+        let mut expected = pattern(64);
+        expected[0..16].fill(0);
+        assert_eq!(context.read_heap(&heap, 0..64), expected);
+
+        heap.clear_staging(0..16).unwrap();
+        flush_all(context, &heap);
+        assert_eq!(context.read_heap(&heap, 0..64), expected);
+        assert!(heap.clear_staging(0..16).is_err());
+    });
+}
+
+#[test]
+fn copy_planner_merges_contiguous_copies() {
+    with_context(|context| {
+        let source = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        let destination = Heap::new(&context.device, nonzero(256), HeapUsages::STORAGE);
+        source.write(0..256, &pattern(256));
+        flush_all(context, &source);
+
+        let mut planner = CopyPlanner::new();
+        planner.push_heap_copy(&source, 32..64, &destination, 96);
+        planner.push_heap_copy(&source, 0..32, &destination, 64);
+        planner.push_heap_copy(&source, 64..128, &destination, 128);
+        // This reads what the first copies wrote, so it must stay after them.
+        planner.push_heap_copy(&destination, 64..192, &source, 128);
+
+        assert_eq!(planner.plan().len(), 2);
+        context.submit(|encoder| assert_eq!(planner.record(encoder), 2));
+
+        let expected = pattern(256)[..128].to_vec();
+        assert_eq!(context.read_heap(&destination, 64..192), expected);
+        assert_eq!(context.read_heap(&source, 128..256), expected);
+    });
+}
+
+#[test]
+fn allocations_are_copied_between_heaps() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(256)));
+        let source = arena.alloc(&context.device, nonzero(192), nonzero(4)).unwrap();
+        let destination = arena.alloc(&context.device, nonzero(128), nonzero(4)).unwrap();
+        let small = arena.alloc(&context.device, nonzero(64), nonzero(4)).unwrap();
+        assert_ne!(source.arena_key, destination.arena_key);
+        arena.write(&source, &pattern(192));
+        arena.write(&small, &pattern(64));
+        arena.unmap();
+
+        context.submit(|encoder| {
+            arena.flush_range(encoder, &source);
+            arena.flush_range(encoder, &small);
+            assert_eq!(
+                arena.copy_allocation(encoder, &source, &destination),
+                Err(CopyError::SizeMismatch { source_size: 192, destination_size: 128 }),
+            );
+            // `small` went in the free space at the end of the first heap.
+            assert_eq!(
+                arena.copy_allocation(encoder, &small, &source),
+                Err(CopyError::SameHeap),
+            );
+            arena.copy_allocation(encoder, &small, &destination).unwrap();
+        });
+        let heap = arena.heap_for(&destination);
+        assert_eq!(context.read_heap(heap, destination.range_in_heap.start..64), pattern(64));
+
+        let source_heap = arena.heap_for(&source);
+        context.submit(|encoder| {
+            assert_eq!(
+                source_heap.copy_to(encoder, 0..6, heap, 0),
+                Err(CopyError::Misaligned { range: 0..6 }),
+            );
+            assert_eq!(
+                source_heap.copy_to(encoder, 0..128, heap, 192),
+                Err(CopyError::OutOfBounds { range: 192..320, heap_size: nonzero(256) }),
+            );
+            source_heap.copy_to(encoder, 64..128, heap, 64).unwrap();
+        });
+        assert_eq!(context.read_heap(heap, 64..128), pattern(192)[64..128].to_vec());
+    });
+}
+
+#[test]
+fn batched_writes_are_flushed_together() {
+    with_context(|context| {
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(256)));
+        let first = arena.alloc(&context.device, nonzero(64), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(64), nonzero(4)).unwrap();
+        let third = arena.alloc(&context.device, nonzero(256), nonzero(4)).unwrap();
+        assert_eq!(first.range_in_heap.end, second.range_in_heap.start);
+
+        let mut batcher = WriteBatcher::new();
+        batcher.write(&arena, &second, &pattern(64));
+        batcher.write(&arena, &third, &pattern(256));
+        batcher.write(&arena, &first, &pattern(64));
+        assert_eq!(batcher.len(), 3);
+        assert_eq!(batcher.bytes_written(), 384);
+
+        arena.unmap();
+        context.submit(|encoder| {
+            // The first two writes are adjacent, so they are flushed with a single copy.
+            assert_eq!(batcher.finish(&arena, encoder), 2);
+            assert_eq!(arena.flush_dirty(encoder), 0);
+        });
+        assert!(batcher.is_empty());
+
+        for (allocation, len) in [(first, 64), (second, 64), (third, 256)] {
+            let heap = arena.heap_for(&allocation);
+            assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone()), pattern(len));
+        }
+    });
+}
+
+#[test]
+fn sync_back_dirty_copies_only_marked_ranges() {
+    with_context(|context| {
+        let heap = Heap::with_readback(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.write(0..256, &pattern(256));
+        flush_all(context, &heap);
+
+        heap.mark_gpu_modified(16..32);
+        heap.mark_gpu_modified(30..40);
+        heap.mark_gpu_modified(128..130);
+        let mut ranges = Vec::new();
+        context.submit(|encoder| ranges = heap.sync_back_dirty(encoder));
+        assert_eq!(ranges, vec![16..40, 128..132]);
+
+        heap.map_readback_async(0..256);
+        context.device.poll(wgpu::Maintain::Wait);
+        let contents = heap.read_mapped(0..256);
+        assert_eq!(contents[16..40], pattern(256)[16..40]);
+        assert_eq!(contents[128..132], pattern(256)[128..132]);
+        assert!(contents[..16].iter().all(|&byte| byte == 0));
+        assert!(contents[40..128].iter().all(|&byte| byte == 0));
+    });
+}
+
+#[test]
+fn fetch_reads_back_copied_ranges() {
+    with_context(|context| {
+        let heap = Heap::with_readback(&context.device, nonzero(256), HeapUsages::STORAGE);
+        heap.write(0..256, &pattern(256));
+        heap.unmap();
+        context.submit(|encoder| {
+            heap.flush(encoder);
+            heap.read_range(encoder, 13..42);
+        });
+
+        let contents = heap.fetch(13..42);
+        context.device.poll(wgpu::Maintain::Wait);
+        assert_eq!(pollster::block_on(contents).unwrap(), &pattern(256)[13..42]);
+    });
+}
+
+#[test]
+fn managed_queue_remaps_staging_between_submissions() {
+    let Some(TestContext { device, queue }) = TestContext::new() else { return };
+    let heap = Heap::with_readback(&device, nonzero(256), HeapUsages::STORAGE);
+    let mut managed_queue = ManagedQueue::new(queue);
+
+    for (index, byte) in [0x11, 0x22].into_iter().enumerate() {
+        // The second write would panic if the staging buffer had not been remapped.
+        heap.write(0..256, &[byte; 256]);
+        heap.mark_gpu_modified(0..256);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        heap.flush(&mut encoder);
+        heap.sync_back_dirty(&mut encoder);
+        let serial = managed_queue.submit(&[&heap], Some(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+
+        assert_eq!(serial, index as u64 + 1);
+        assert!(managed_queue.is_completed(serial));
+
+        heap.map_readback_async(0..256);
+        device.poll(wgpu::Maintain::Wait);
+        assert_eq!(heap.read_mapped(0..256), vec![byte; 256]);
+        heap.unmap_readback();
+    }
+}
+
+#[test]
+fn staging_ranges_are_in_flight_until_their_submission_completes() {
+    let Some(TestContext { device, queue }) = TestContext::new() else { return };
+    let heap = Heap::new(&device, nonzero(256), HeapUsages::STORAGE);
+    let mut managed_queue = ManagedQueue::new(queue);
+
+    heap.write(0..64, &pattern(64));
+    let mut encoder = device.create_command_encoder(&Default::default());
+    heap.flush_range(&mut encoder, 0..64);
+    let serial = managed_queue.submit(&[&heap], Some(encoder.finish()));
+
+    assert_eq!(heap.staging_busy_until(32..128, 0), Some(serial));
+    assert_eq!(heap.staging_busy_until(64..128, 0), None);
+    assert_eq!(heap.try_write(0..64, &pattern(64), 0), Err(InFlight { serial }));
+
+    device.poll(wgpu::Maintain::Wait);
+    let last_completed = managed_queue.last_completed();
+    assert_eq!(heap.try_write(0..64, &pattern(64), last_completed), Ok(()));
+}
+
+#[test]
+fn allocations_used_by_unfinished_submissions_are_not_freed() {
+    let Some(TestContext { device, queue }) = TestContext::new() else { return };
+    let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+    arena.enable_hazard_tracking();
+    let [flushed, bound, unused] =
+        [(); 3].map(|_| arena.alloc(&device, nonzero(256), nonzero(4)).unwrap());
+
+    arena.write(&flushed, &pattern(256));
+    let mut encoder = device.create_command_encoder(&Default::default());
+    arena.flush_range(&mut encoder, &flushed);
+    let _ = arena.binding(&bound);
+    // Note: the GPU may finish a real submission before it can be observed as unfinished, so a
+    // submission is simulated with a completion that never advances.
+    Staging::submitted_to(&arena, 1, &Completion::default());
+
+    assert_eq!(arena.busy_until(&flushed), Some(1));
+    assert_eq!(arena.busy_until(&bound), Some(1));
+    assert_eq!(arena.busy_until(&unused), None);
+    assert_eq!(unsafe { arena.dealloc(flushed.clone()) }, Err(AllocError::InUse { serial: 1 }));
+    assert_eq!(unsafe { arena.dealloc(unused) }, Ok(()));
+    unsafe { arena.dealloc_deferred(bound, 0) };
+    assert_eq!(arena.retire_completed(0), 0);
+
+    // A real submission through a queue that completes it retires the simulated one too.
+    let mut managed_queue = ManagedQueue::new(queue);
+    let serial = managed_queue.submit(&[&arena], Some(encoder.finish()));
+    device.poll(wgpu::Maintain::Wait);
+    assert!(managed_queue.is_completed(serial));
+    assert_eq!(arena.busy_until(&flushed), None);
+    assert_eq!(unsafe { arena.dealloc(flushed) }, Ok(()));
+    assert_eq!(arena.retire_completed(0), 1);
+}
+
+#[test]
+fn transfer_contexts_upload_ahead_of_the_main_encoder() {
+    let Some(TestContext { device, queue }) = TestContext::new() else { return };
+    let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+    let allocation = arena.alloc(&device, nonzero(256), nonzero(4)).unwrap();
+    let mut managed_queue = ManagedQueue::new(queue);
+
+    arena.write(&allocation, &pattern(256));
+    let transfer = TransferContext::new(&device, Some("transfer"));
+    assert_eq!(arena.flush_with(&transfer), 1);
+    let token = transfer.submit(&mut managed_queue, &[&arena]);
+    let encoder = device.create_command_encoder(&Default::default());
+    let serial = managed_queue.submit(&[], Some(encoder.finish()));
+    assert_eq!((token.serial(), serial), (1, 2));
+
+    token.wait(&device, &managed_queue);
+    assert!(token.is_completed(&managed_queue));
+    let context = TestContext { device, queue: managed_queue.into_inner() };
+    let (heap, _) = &arena[allocation.arena_key];
+    assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone()), pattern(256));
+}
+
+#[test]
+fn heaps_and_allocations_can_be_created_with_data() {
+    with_context(|context| {
+        let heap = Heap::with_data(&context.device, &context.queue, HeapUsages::VERTEX, &[7; 6]);
+        assert_eq!(heap.size().get(), 8);
+        assert_eq!(heap.upload_strategy(), UploadStrategy::QueueWrite);
+        context.submit(|_| {});
+        assert_eq!(context.read_heap(&heap, 0..8), [7, 7, 7, 7, 7, 7, 0, 0]);
+
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        let mut allocation = None;
+        context.submit(|encoder| {
+            let contents = pattern(30);
+            let device = &context.device;
+            allocation = arena
+                .alloc_with_data(device, &context.queue, encoder, &contents, nonzero(4))
+                .ok();
+            arena.unmap();
+        });
+        let allocation = allocation.unwrap();
+        assert_eq!(allocation.size(), 32);
+        assert_eq!(arena.frame_counters().bytes_uploaded, 32);
+        let (heap, _) = &arena[allocation.arena_key];
+        assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone())[..30], pattern(30));
+    });
+}
+
+#[test]
+fn uploads_take_the_path_chosen_by_the_policy() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(8192))
+        });
+        arena.set_upload_policy(UploadPolicy {
+            write_buffer_max: 64,
+            staging_min: 64,
+            dedicated_staging_min: 1024,
+            per_frame_budget: Some(2048),
+        });
+
+        let mut encoder = context.device.create_command_encoder(&Default::default());
+        let mut uploads = Vec::new();
+        for (len, expected_path) in [
+            (16, UploadPath::QueueWrite),
+            (256, UploadPath::Staging),
+            (1024, UploadPath::DedicatedStaging),
+        ] {
+            let allocation = arena.alloc(&context.device, nonzero(len), nonzero(4)).unwrap();
+            let contents = pattern(len as usize);
+            let path = arena
+                .upload(&context.device, &context.queue, &mut encoder, &allocation, &contents)
+                .unwrap();
+            assert_eq!(path, expected_path);
+            uploads.push((allocation, len));
+        }
+
+        let allocation = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let error = arena
+            .upload(&context.device, &context.queue, &mut encoder, &allocation, &pattern(1024))
+            .unwrap_err();
+        assert_eq!(error.remaining, 2048 - 16 - 256 - 1024);
+
+        arena.unmap();
+        context.queue.submit(Some(encoder.finish()));
+        for (allocation, len) in uploads {
+            let (heap, _) = &arena[allocation.arena_key];
+            assert_eq!(
+                context.read_heap(heap, allocation.range_in_heap.clone()),
+                pattern(len as usize),
+            );
+        }
+    });
+}
+
+#[test]
+fn queue_write_heaps_have_no_staging_memory() {
+    with_context(|context| {
+        let heap = Heap::with_upload_strategy(
+            &context.device,
+            nonzero(64),
+            HeapUsages::STORAGE,
+            UploadStrategy::QueueWrite,
+        );
+        assert_eq!(heap.upload_strategy(), UploadStrategy::QueueWrite);
+        heap.write_via(&context.queue, 0..32, &pattern(32));
+        // Flushing and unmapping do nothing, so heaps of both strategies can be driven alike.
+        flush_all(context, &heap);
+
+        assert_eq!(context.read_heap(&heap, 0..32), pattern(32));
+    });
+}
+
+#[test]
+fn windowed_heaps_stream_large_writes_over_several_flushes() {
+    with_context(|context| {
+        let strategy = UploadStrategy::Windowed { window_size: nonzero(256) };
+        let heap = Heap::with_upload_strategy(
+            &context.device,
+            nonzero(1024),
+            HeapUsages::STORAGE,
+            strategy,
+        );
+        assert_eq!(heap.upload_strategy(), strategy);
+        heap.write(0..1024, &pattern(1024));
+        assert!(heap.checked_slice(768..1024, HeapUsages::STORAGE).is_err());
+
+        let mut flushes = 0;
+        loop {
+            heap.unmap();
+            let mut flushed = Vec::new();
+            context.submit(|encoder| flushed = heap.flush_dirty(encoder));
+            heap.remap();
+            context.device.poll(wgpu::Maintain::Wait);
+            if flushed.is_empty() {
+                break;
+            }
+            flushes += 1;
+        }
+
+        assert_eq!(flushes, 4);
+        assert!(heap.checked_slice(0..1024, HeapUsages::STORAGE).is_ok());
+        assert_eq!(context.read_heap(&heap, 0..1024), pattern(1024));
+    });
+}
+
+#[test]
+fn pending_uploads_are_performed_in_priority_order() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        arena.set_upload_policy(UploadPolicy {
+            write_buffer_max: 1024,
+            staging_min: 1024,
+            dedicated_staging_min: 4096,
+            per_frame_budget: Some(256),
+        });
+
+        let background = arena.alloc(&context.device, nonzero(128), nonzero(4)).unwrap();
+        let visible = arena.alloc(&context.device, nonzero(128), nonzero(4)).unwrap();
+        let also_visible = arena.alloc(&context.device, nonzero(128), nonzero(4)).unwrap();
+        arena.defer_upload(&background, pattern(128), 0);
+        arena.defer_upload(&visible, pattern(128), 1);
+        arena.defer_upload(&also_visible, pattern(128), 1);
+
+        let mut encoder = context.device.create_command_encoder(&Default::default());
+        assert_eq!(arena.upload_pending(&context.device, &context.queue, &mut encoder), 2);
+        assert_eq!(arena.pending_upload_count(), 1);
+        assert_eq!(arena.pending_upload_bytes(), 128);
+        context.queue.submit(Some(encoder.finish()));
+
+        for allocation in [&visible, &also_visible] {
+            let (heap, _) = &arena[allocation.arena_key];
+            assert_eq!(context.read_heap(heap, allocation.range_in_heap.clone()), pattern(128));
+        }
+
+        arena.begin_frame();
+        let mut encoder = context.device.create_command_encoder(&Default::default());
+        assert_eq!(arena.upload_pending(&context.device, &context.queue, &mut encoder), 1);
+        assert_eq!(arena.pending_upload_count(), 0);
+    });
+}
+
+#[test]
+fn cold_allocations_are_those_left_untouched() {
+    with_context(|context| {
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::UNIFORM, |context: NewHeapSizeContext| {
+            context.first_alloc_size.max(nonzero(4096))
+        });
+        arena.enable_aging();
+        let hot = arena.alloc(&context.device, nonzero(64), nonzero(4)).unwrap();
+        let cold = arena.alloc(&context.device, nonzero(128), nonzero(4)).unwrap();
+        arena.set_label(&cold, "cold");
+
+        for _ in 0..3 {
+            arena.begin_frame();
+            arena.write(&hot, &pattern(64));
+        }
+        let _ = arena.slice(&hot);
+
+        let report = arena.cold_allocations(3);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].range_in_heap, cold.range_in_heap);
+        assert_eq!(report[0].age.label.as_deref(), Some("cold"));
+        assert_eq!(report[0].size(), 128);
+        assert_eq!(arena.age(&hot).unwrap().last_bound, Some(3));
+
+        arena.forget_age(&cold);
+        let report = arena.cold_allocations(0);
+        assert!(report.iter().all(|entry| entry.range_in_heap != cold.range_in_heap));
+    });
+}
+
+#[test]
+fn heap_usages_convert_to_and_from_buffer_usages() {
+    let usage = HeapUsages::STORAGE | HeapUsages::COPY_SRC | HeapUsages::COPY_DST;
+    let buffer_usages = wgpu::BufferUsages::from(usage);
+    assert_eq!(
+        buffer_usages,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+    );
+    assert_eq!(HeapUsages::from_buffer_usages(buffer_usages), Some(usage));
+    assert_eq!(HeapUsages::from_buffer_usages(wgpu::BufferUsages::all()), Some(HeapUsages::all()));
+    assert_eq!(wgpu::BufferUsages::from(HeapUsages::all()), wgpu::BufferUsages::all());
+    #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+    {
+        let usage = HeapUsages::QUERY_RESOLVE | HeapUsages::COPY_SRC;
+        let buffer_usages = wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC;
+        assert_eq!(wgpu::BufferUsages::from(usage), buffer_usages);
+        assert_eq!(HeapUsages::from_buffer_usages(buffer_usages), Some(usage));
+    }
+}
+
+#[test]
+fn map_read_heaps_can_be_read_directly() {
+    with_context(|context| {
+        let heap = Heap::new(&context.device, nonzero(256), HeapUsages::MAP_READ);
+        assert_eq!(heap.upload_strategy(), UploadStrategy::QueueWrite);
+        heap.write_via(&context.queue, 0..256, &pattern(256));
+        context.submit(|_| {});
+
+        heap.map_read_async(0..256, |result| result.unwrap());
+        context.device.poll(wgpu::Maintain::Wait);
+        assert_eq!(*heap.gpu_mapped_range(0..256), pattern(256));
+        heap.unmap_gpu();
+    });
+}
+
+#[test]
+fn allocation_observers_see_every_event() {
+    #[derive(Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl AllocationObserver for Recorder {
+        fn heap_event(&mut self, event: &HeapEvent) {
+            self.0.lock().unwrap().push(format!("{:?} {}", event.kind, event.size));
+        }
+
+        fn allocated(&mut self, allocation: &Allocation) {
+            self.0.lock().unwrap().push(format!("alloc {:?}", allocation.range_in_heap));
+        }
+
+        fn deallocated(&mut self, allocation: &Allocation) {
+            self.0.lock().unwrap().push(format!("dealloc {:?}", allocation.range_in_heap));
+        }
+
+        fn flushed(&mut self, _: ArenaKey, range: std::ops::Range<u64>) {
+            self.0.lock().unwrap().push(format!("flush {range:?}"));
+        }
+
+        fn compacted(&mut self, relocations: &[Relocation]) {
+            self.0.lock().unwrap().push(format!("compact {}", relocations.len()));
+        }
+    }
+
+    with_context(|context| {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+        let mut arena = HeapArena::<FreeList>::new(HeapUsages::STORAGE, Fixed(nonzero(4096)));
+        arena.set_allocation_observer(recorder);
+
+        let first = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        let second = arena.alloc(&context.device, nonzero(1024), nonzero(4)).unwrap();
+        arena.unmap();
+        context.submit(|encoder| arena.flush_range(encoder, &second));
+        arena.remap();
+        context.device.poll(wgpu::Maintain::Wait);
+        unsafe { arena.dealloc(first) }.unwrap();
+        context.submit(|encoder| drop(unsafe { arena.compact(encoder) }));
+        drop(arena);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "Created 4096",
+                "alloc 0..1024",
+                "alloc 1024..2048",
+                "flush 1024..2048",
+                "dealloc 0..1024",
+                "compact 0",
+                "Destroyed 4096",
+            ],
+        );
+    });
+}
+
+#[test]
+fn heap_observer_sees_creation_and_destruction() {
+    with_context(|context| {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut arena = HeapArena::<Stack>::new(HeapUsages::STORAGE, |context: NewHeapSizeContext| {
+            context.first_alloc_size
+        });
+        arena.set_heap_observer({
+            let events = events.clone();
+            move |event: &HeapEvent| events.lock().unwrap().push(*event)
+        });
+
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        arena.alloc(&context.device, nonzero(4096), nonzero(4)).unwrap();
+        assert_eq!(arena.reserved_bytes(), 8192);
+        drop(arena);
+
+        let events = events.lock().unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| (event.kind, event.size.get(), event.size_class, event.total_reserved))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (HeapEventKind::Created, 4096, 12, 4096),
+                (HeapEventKind::Created, 4096, 12, 8192),
+                (HeapEventKind::Destroyed, 4096, 12, 4096),
+                (HeapEventKind::Destroyed, 4096, 12, 0),
+            ],
+        );
+    });
+}
+
+#[test]
+fn texture_regions_round_trip() {
+    with_context(|context| {
+        let size = wgpu::Extent3d { width: 64, height: 64, depth_or_array_layers: 2 };
+        let mut heap = TextureHeap::<Shelf>::new(
+            &context.device,
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        );
+        let _first = heap.alloc(16, 8).unwrap();
+        let region = heap.alloc(8, 4).unwrap();
+        assert_eq!(heap.uv_rect(&region), [0.25, 0.0, 0.375, 0.0625]);
+        heap.write(&context.queue, &region, &pattern(8 * 4 * 4), 8 * 4);
+
+        // Rows copied into a buffer must be `COPY_BYTES_PER_ROW_ALIGNMENT` bytes apart.
+        let row_pitch = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (row_pitch * 4) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        context.submit(|encoder| {
+            encoder.copy_texture_to_buffer(
+                heap.image_copy(&region),
+                wgpu::ImageCopyBuffer {
+                    buffer: &buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        #[cfg(not(any(feature = "wgpu-0_19", feature = "wgpu-22")))]
+                        bytes_per_row: std::num::NonZeroU32::new(row_pitch),
+                        #[cfg(any(feature = "wgpu-0_19", feature = "wgpu-22"))]
+                        bytes_per_row: Some(row_pitch),
+                        rows_per_image: None,
+                    },
+                },
+                region.extent(),
+            );
+        });
+
+        let contents = context.read_buffer(&buffer, 0..(row_pitch * 4) as u64);
+        for (row, expected) in contents.chunks(row_pitch as usize).zip(pattern(128).chunks(32)) {
+            assert_eq!(&row[..32], expected);
+        }
+    });
+}
+
+#[test]
+fn self_test_passes() {
+    with_context(|context| {
+        let report = selftest::run(&context.device, &context.queue, &SelfTestConfig::default());
+
+        assert_eq!(report.cases.len(), 15);
+        assert!(report.passed(), "{:?}", report.failures().collect::<Vec<_>>());
+    });
+}